@@ -75,15 +75,21 @@ impl KvClient {
     }
 
     /// Compacts the event history in the etcd key-value store. The key-value
-    /// store should be periodically compacted or the event history will continue to grow
-    /// indefinitely.
+    /// store should be periodically compacted or the event history will continue to grow.
+    /// The simulated store keeps at most the last 10000 events in any case.
     #[inline]
     pub async fn compact(
         &mut self,
-        _revision: i64,
+        revision: i64,
         _options: Option<CompactionOptions>,
     ) -> Result<CompactionResponse> {
-        todo!()
+        let req = Request::Compact { revision };
+        let (tx, mut rx) = self.ep.connect1(self.server_addr).await?;
+        tx.send(Box::new(req)).await?;
+        *rx.recv()
+            .await?
+            .downcast::<Result<CompactionResponse>>()
+            .unwrap()
     }
 
     /// Processes multiple operations in a single transaction.
@@ -241,7 +247,17 @@ pub struct CompactionOptions();
 
 /// Response for `Compact` operation.
 #[derive(Debug, Clone)]
-pub struct CompactionResponse();
+pub struct CompactionResponse {
+    pub(crate) header: ResponseHeader,
+}
+
+impl CompactionResponse {
+    /// Get response header.
+    #[inline]
+    pub fn header(&self) -> Option<&ResponseHeader> {
+        Some(&self.header)
+    }
+}
 
 /// Transaction of multiple operations.
 #[derive(Debug, Default, Clone)]
@@ -1,13 +1,14 @@
 use futures_util::{select_biased, FutureExt};
 use madsim::net::{Endpoint, Payload};
-use std::{io::Result, net::SocketAddr, sync::Arc};
+use std::{io::Result, net::SocketAddr, sync::Arc, time::Duration};
 
-use super::{election::*, kv::*, service::EtcdService, Bytes};
+use super::{election::*, kv::*, service::EtcdService, watch::*, Bytes};
 
 /// A simulated etcd server.
 #[derive(Default, Clone)]
 pub struct SimServer {
     timeout_rate: f32,
+    op_latency: Duration,
     load: Option<String>,
 }
 
@@ -24,6 +25,15 @@ impl SimServer {
         self
     }
 
+    /// Set the latency of processing each request.
+    ///
+    /// The server waits for the latency in logical time after receiving a request, before
+    /// executing it. The request is still executed if the client times out in the meantime.
+    pub fn op_latency(mut self, latency: Duration) -> Self {
+        self.op_latency = latency;
+        self
+    }
+
     /// Load data from dump.
     pub fn load(mut self, data: String) -> Self {
         self.load = Some(data);
@@ -34,11 +44,15 @@ impl SimServer {
     pub async fn serve(self, addr: SocketAddr) -> Result<()> {
         let ep = Endpoint::bind(addr).await?;
         let service = Arc::new(EtcdService::new(self.timeout_rate, self.load));
+        let op_latency = self.op_latency;
         loop {
             let (tx, mut rx, _) = ep.accept1().await?;
             let service = service.clone();
             madsim::task::spawn(async move {
                 let request = *rx.recv().await?.downcast::<Request>().unwrap();
+                if !op_latency.is_zero() {
+                    madsim::time::sleep(op_latency).await;
+                }
                 let response: Payload = match request {
                     Request::Put {
                         key,
@@ -50,6 +64,7 @@ impl SimServer {
                         Box::new(service.delete(key, options).await)
                     }
                     Request::Txn { txn } => Box::new(service.txn(txn).await),
+                    Request::Compact { revision } => Box::new(service.compact(revision).await),
                     Request::LeaseGrant { ttl, id } => Box::new(service.lease_grant(ttl, id).await),
                     Request::LeaseRevoke { id } => Box::new(service.lease_revoke(id).await),
                     Request::LeaseKeepAlive { id } => loop {
@@ -89,9 +104,75 @@ impl SimServer {
                             tx.send(Box::new(response)).await?;
                         },
                     },
+                    Request::Watch { key, options } => {
+                        let start_revision = options.start_revision;
+                        match service.watch(key, options.prefix, start_revision).await {
+                            Err(e) => Box::new(Err(e) as super::Result<WatchResponse>),
+                            Ok((header, start)) => {
+                                let response = |response: WatchResponse| {
+                                    Box::new(Ok(response) as super::Result<WatchResponse>)
+                                        as Payload
+                                };
+                                let created = WatchResponse {
+                                    created: true,
+                                    ..WatchResponse::new(header.clone())
+                                };
+                                tx.send(response(created)).await?;
+                                let (past, mut events) = match start {
+                                    Ok(start) => start,
+                                    Err(compact_revision) => {
+                                        let canceled = WatchResponse {
+                                            canceled: true,
+                                            compact_revision,
+                                            cancel_reason:
+                                                "mvcc: required revision has been compacted".into(),
+                                            ..WatchResponse::new(header)
+                                        };
+                                        _ = tx.send(response(canceled)).await;
+                                        return Ok(());
+                                    }
+                                };
+                                if !past.is_empty() {
+                                    let events = past.into_iter().map(Event::from).collect();
+                                    let past = WatchResponse {
+                                        events,
+                                        ..WatchResponse::new(header)
+                                    };
+                                    tx.send(response(past)).await?;
+                                }
+                                loop {
+                                    select_biased! {
+                                        // canceled, or the client has dropped the watcher
+                                        _ = rx.recv().fuse() => {
+                                            let canceled = WatchResponse {
+                                                canceled: true,
+                                                ..WatchResponse::new(service._header())
+                                            };
+                                            _ = tx.send(response(canceled)).await;
+                                            return Ok(());
+                                        }
+                                        event = events.recv().fuse() => {
+                                            let Some(event) = event else {
+                                                return Ok(());
+                                            };
+                                            let rsp = WatchResponse {
+                                                events: vec![event.into()],
+                                                ..WatchResponse::new(service._header())
+                                            };
+                                            tx.send(response(rsp)).await?;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    // only valid on the stream of a watch
+                    Request::WatchCancel { .. } => return Ok(()),
                     Request::Resign { leader } => Box::new(service.resign(leader).await),
                     Request::Status => Box::new(service.status().await),
                     Request::Dump => Box::new(service.dump().await),
+                    Request::Snapshot => Box::new(service.snapshot().await),
+                    Request::Restore { snapshot } => Box::new(service.restore(snapshot).await),
                 };
                 tx.send(response).await?;
                 Ok(()) as Result<()>
@@ -120,6 +201,9 @@ pub(crate) enum Request {
     Txn {
         txn: Txn,
     },
+    Compact {
+        revision: i64,
+    },
 
     // lease API
     LeaseGrant {
@@ -159,9 +243,23 @@ pub(crate) enum Request {
         leader: LeaderKey,
     },
 
+    // watch API
+    Watch {
+        key: Bytes,
+        options: WatchOptions,
+    },
+    WatchCancel {
+        #[allow(dead_code)]
+        watch_id: i64,
+    },
+
     // maintenance API
     Status,
 
     // internal API
     Dump,
+    Snapshot,
+    Restore {
+        snapshot: Vec<u8>,
+    },
 }
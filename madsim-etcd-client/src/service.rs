@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use spin::Mutex;
 use std::collections::btree_map::Entry;
-use std::collections::{btree_map::Range, BTreeMap, HashMap, HashSet};
+use std::collections::{btree_map::Range, BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
@@ -18,11 +18,16 @@ pub struct EtcdService {
 
 impl EtcdService {
     pub fn new(timeout_rate: f32, data: Option<String>) -> Self {
-        let inner = Arc::new(Mutex::new(
-            data.map_or_else(ServiceInner::default, |data| {
-                toml::from_str(&data).expect("failed to deserialize dump")
-            }),
-        ));
+        let inner = Arc::new(Mutex::new(data.map_or_else(
+            ServiceInner::default,
+            |data| {
+                let mut inner: ServiceInner =
+                    toml::from_str(&data).expect("failed to deserialize dump");
+                // the history is not part of the dump
+                inner.watcher.compact_revision = inner.revision;
+                inner
+            },
+        )));
         let weak = Arc::downgrade(&inner);
         madsim::task::spawn(async move {
             while let Some(inner) = weak.upgrade() {
@@ -59,6 +64,11 @@ impl EtcdService {
         Ok(rsp)
     }
 
+    pub async fn compact(&self, revision: i64) -> Result<CompactionResponse> {
+        self.timeout().await?;
+        self.inner.lock().compact(revision)
+    }
+
     pub async fn txn(&self, txn: Txn) -> Result<TxnResponse> {
         self.assert_request_size(txn.size())?;
         self.timeout().await?;
@@ -140,12 +150,31 @@ impl EtcdService {
         self.inner.lock().leader(name)
     }
 
-    pub async fn observe(&self, name: Key) -> Result<(LeaderResponse, mpsc::Receiver<Event>)> {
+    pub async fn observe(
+        &self,
+        name: Key,
+    ) -> Result<(LeaderResponse, mpsc::UnboundedReceiver<Event>)> {
         self.assert_request_size(name.len())?;
         self.timeout().await?;
         self.inner.lock().observe(name)
     }
 
+    pub async fn watch(
+        &self,
+        key: Key,
+        prefix: bool,
+        start_revision: i64,
+    ) -> Result<(ResponseHeader, WatchStart)> {
+        self.assert_request_size(key.len())?;
+        self.timeout().await?;
+        Ok(self.inner.lock().watch(key, prefix, start_revision))
+    }
+
+    // A sync version of header, for use in watch.
+    pub fn _header(&self) -> ResponseHeader {
+        self.inner.lock().header()
+    }
+
     pub async fn resign(&self, leader: LeaderKey) -> Result<ResignResponse> {
         self.assert_request_size(leader.size())?;
         self.timeout().await?;
@@ -162,6 +191,34 @@ impl EtcdService {
         Ok(toml::to_string(inner).expect("failed to serialize dump"))
     }
 
+    pub async fn snapshot(&self) -> Result<Vec<u8>> {
+        self.timeout().await?;
+        let inner = &*self.inner.lock();
+        Ok(toml::to_string(inner)
+            .expect("failed to serialize snapshot")
+            .into_bytes())
+    }
+
+    pub async fn restore(&self, snapshot: Vec<u8>) -> Result<()> {
+        self.timeout().await?;
+        let restored: ServiceInner = std::str::from_utf8(&snapshot)
+            .ok()
+            .and_then(|data| toml::from_str(data).ok())
+            .ok_or_else(|| Error::InvalidArgs("invalid snapshot".into()))?;
+        let mut inner = self.inner.lock();
+        tracing::debug!(revision = restored.revision, "restore");
+        // watchers are not part of the snapshot, keep them subscribed
+        let mut watcher = std::mem::take(&mut inner.watcher);
+        // and the history since the snapshot is lost
+        watcher.history.clear();
+        watcher.compact_revision = restored.revision;
+        *inner = ServiceInner {
+            watcher,
+            ..restored
+        };
+        Ok(())
+    }
+
     async fn timeout(&self) -> Result<()> {
         if thread_rng().gen_bool(self.timeout_rate as f64) {
             let t = thread_rng().gen_range(Duration::from_secs(5)..Duration::from_secs(15));
@@ -197,35 +254,58 @@ struct ServiceInner {
     watcher: EventBus,
 }
 
+/// The maximum number of events kept in the history.
+///
+/// Older events are compacted automatically, so that the history doesn't grow indefinitely if
+/// the client never compacts.
+const HISTORY_LIMIT: usize = 10_000;
+
 #[derive(Debug, Default)]
 struct EventBus {
-    list: Vec<(EventPattern, mpsc::Sender<Event>)>,
+    list: Vec<(EventPattern, mpsc::UnboundedSender<Event>)>,
+    /// Events since the compact revision, in the order of revisions.
+    history: VecDeque<Event>,
+    /// Events before the revision have been compacted.
+    compact_revision: i64,
 }
 
 #[derive(Debug)]
 enum EventPattern {
+    Key(Key),
     Prefix(Key),
 }
 
 impl EventPattern {
     fn is_match(&self, event: &Event) -> bool {
+        let (Event::Put(kv) | Event::Delete(kv)) = event;
         match self {
-            Self::Prefix(prefix) => match event {
-                Event::Put(kv) | Event::Delete(kv) => kv.key.starts_with(prefix),
-            },
+            Self::Key(key) => kv.key == *key,
+            Self::Prefix(prefix) => kv.key.starts_with(prefix),
         }
     }
 }
 
+/// An event of a key. The revision of the event is the modify revision of the key-value.
 #[derive(Debug, Clone)]
 pub enum Event {
     Put(KeyValue),
     Delete(KeyValue),
 }
 
+impl Event {
+    fn revision(&self) -> i64 {
+        let (Event::Put(kv) | Event::Delete(kv)) = self;
+        kv.modify_revision
+    }
+}
+
+/// The start of a watch: the past events since the start revision and the receiver of future
+/// events, or the compact revision if the past events have been compacted.
+pub type WatchStart = std::result::Result<(Vec<Event>, mpsc::UnboundedReceiver<Event>), i64>;
+
 impl EventBus {
     /// Subscribe a watcher.
-    fn subscribe(&mut self, pattern: EventPattern, tx: mpsc::Sender<Event>) {
+    fn subscribe(&mut self, pattern: EventPattern, tx: mpsc::UnboundedSender<Event>) {
         tracing::trace!(?pattern, "subscribe");
         self.list.push((pattern, tx));
     }
@@ -233,14 +313,29 @@ impl EventBus {
     /// Publish an event.
     fn publish(&mut self, event: Event) {
         tracing::trace!(?event, "new event");
+        self.history.push_back(event.clone());
+        if self.history.len() > HISTORY_LIMIT {
+            // compact at a revision boundary, so that events of a txn are kept or dropped together
+            let revision = self.history[self.history.len() - HISTORY_LIMIT].revision();
+            self.compact(revision);
+        }
         self.list.retain(|(pattern, tx)| {
             if pattern.is_match(&event) {
-                tx.try_send(event.clone()).is_ok()
+                tx.send(event.clone()).is_ok()
             } else {
                 true
             }
         });
     }
+
+    /// Drop the events before the revision.
+    fn compact(&mut self, revision: i64) {
+        tracing::trace!(revision, "compact history");
+        self.compact_revision = revision;
+        while matches!(self.history.front(), Some(e) if e.revision() < revision) {
+            self.history.pop_front();
+        }
+    }
 }
 
 type LeaseId = i64;
@@ -344,13 +439,14 @@ impl ServiceInner {
         tracing::trace!(?key, "delete");
         let prev_kv = self.kv.remove(&key);
         let deleted = prev_kv.is_some() as i64;
-        if let Some(kv) = prev_kv {
+        if let Some(mut kv) = prev_kv {
             self.revision += 1;
             // remove key from the lease
             if kv.lease != 0 {
                 let lease = self.lease.get_mut(&kv.lease).expect("no lease");
                 lease.keys.remove(&key);
             }
+            kv.modify_revision = self.revision;
             self.watcher.publish(Event::Delete(kv));
         }
         DeleteResponse {
@@ -374,6 +470,8 @@ impl ServiceInner {
         let revision = self.revision;
         let mut op_responses = vec![];
         for op in if succeeded { txn.success } else { txn.failure } {
+            // all operations of a txn share the next revision
+            self.revision = revision;
             let response = match op {
                 TxnOp::Get { key, options } => TxnOpResponse::Get(self.get(key, options)),
                 TxnOp::Put {
@@ -416,12 +514,13 @@ impl ServiceInner {
     fn lease_revoke(&mut self, id: i64) -> Result<LeaseRevokeResponse> {
         tracing::trace!(id, "lease_revoke");
         let lease = self.lease.remove(&id).ok_or_else(lease_not_found)?;
+        self.revision += 1;
         for key in lease.keys {
             tracing::trace!(?key, "delete");
-            let kv = self.kv.remove(&key).expect("no key");
+            let mut kv = self.kv.remove(&key).expect("no key");
+            kv.modify_revision = self.revision;
             self.watcher.publish(Event::Delete(kv));
         }
-        self.revision += 1;
         Ok(LeaseRevokeResponse {
             header: self.header(),
         })
@@ -463,15 +562,19 @@ impl ServiceInner {
     }
 
     /// Clears expired lease. This should be called every seconds.
+    ///
+    /// A lease expires when it has not been kept alive for its TTL. Keys attached to it are
+    /// deleted in a new revision, and watchers receive the delete events.
     fn tick(&mut self) {
-        let origin_len = self.lease.len();
         self.lease.retain(|id, lease| {
             lease.ttl -= 1;
             if lease.ttl <= 0 {
                 tracing::trace!(id, "lease expired");
+                self.revision += 1;
                 for key in &lease.keys {
                     tracing::trace!(?key, "delete");
-                    let kv = self.kv.remove(key).expect("no key");
+                    let mut kv = self.kv.remove(key).expect("no key");
+                    kv.modify_revision = self.revision;
                     self.watcher.publish(Event::Delete(kv));
                 }
                 false
@@ -479,9 +582,6 @@ impl ServiceInner {
                 true
             }
         });
-        if self.lease.len() != origin_len {
-            self.revision += 1;
-        }
     }
 
     fn campaign(
@@ -489,7 +589,8 @@ impl ServiceInner {
         name: &Key,
         value: &Value,
         lease: i64,
-    ) -> Result<std::result::Result<CampaignResponse, (Bytes, mpsc::Receiver<Event>)>> {
+    ) -> Result<std::result::Result<CampaignResponse, (Bytes, mpsc::UnboundedReceiver<Event>)>>
+    {
         tracing::trace!(?name, ?value, lease, "campaign");
 
         // key = format!("{name}/{lease:016x}")
@@ -527,7 +628,7 @@ impl ServiceInner {
                 }),
             }))
         } else {
-            let (tx, rx) = mpsc::channel(4);
+            let (tx, rx) = mpsc::unbounded_channel();
             self.watcher
                 .subscribe(EventPattern::Prefix(name.clone()), tx);
             Ok(Err((key, rx)))
@@ -562,21 +663,69 @@ impl ServiceInner {
 
     // NOTE: This function returns an event stream of the prefix `name`.
     //       Caller should check the leader on each event, since the leader may not change.
-    fn observe(&mut self, name: Key) -> Result<(LeaderResponse, mpsc::Receiver<Event>)> {
+    fn observe(&mut self, name: Key) -> Result<(LeaderResponse, mpsc::UnboundedReceiver<Event>)> {
         tracing::trace!(?name, "observe");
-        let (tx, rx) = mpsc::channel(4);
+        let (tx, rx) = mpsc::unbounded_channel();
         self.watcher
             .subscribe(EventPattern::Prefix(name.clone()), tx);
         Ok((self.leader(name), rx))
     }
 
+    /// Subscribes a watcher to the events since `start_revision`, or future events if it is 0.
+    fn watch(
+        &mut self,
+        key: Key,
+        prefix: bool,
+        start_revision: i64,
+    ) -> (ResponseHeader, WatchStart) {
+        tracing::trace!(?key, prefix, start_revision, "watch");
+        if start_revision > 0 && start_revision < self.watcher.compact_revision {
+            return (self.header(), Err(self.watcher.compact_revision));
+        }
+        let pattern = if prefix {
+            EventPattern::Prefix(key)
+        } else {
+            EventPattern::Key(key)
+        };
+        let past = if start_revision > 0 {
+            (self.watcher.history.iter())
+                .filter(|e| e.revision() >= start_revision && pattern.is_match(e))
+                .cloned()
+                .collect()
+        } else {
+            vec![]
+        };
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.watcher.subscribe(pattern, tx);
+        (self.header(), Ok((past, rx)))
+    }
+
+    /// Compacts the event history before the revision.
+    fn compact(&mut self, revision: i64) -> Result<CompactionResponse> {
+        tracing::trace!(revision, "compact");
+        if revision <= self.watcher.compact_revision {
+            return Err(compacted());
+        }
+        if revision > self.revision {
+            return Err(Error::GRpcStatus(tonic::Status::new(
+                tonic::Code::OutOfRange,
+                "etcdserver: mvcc: required revision is a future revision",
+            )));
+        }
+        self.watcher.compact(revision);
+        Ok(CompactionResponse {
+            header: self.header(),
+        })
+    }
+
     fn resign(&mut self, leader: LeaderKey) -> Result<ResignResponse> {
         tracing::trace!(name = ?leader.name, "resign");
-        let kv = self.kv.remove(&leader.key).ok_or_else(session_expired)?;
+        let mut kv = self.kv.remove(&leader.key).ok_or_else(session_expired)?;
         let lease = self.lease.get_mut(&kv.lease).expect("no lease");
         lease.keys.remove(&leader.key);
-        self.watcher.publish(Event::Delete(kv));
         self.revision += 1;
+        kv.modify_revision = self.revision;
+        self.watcher.publish(Event::Delete(kv));
         Ok(ResignResponse {
             header: self.header(),
         })
@@ -597,6 +746,13 @@ fn lease_not_found() -> Error {
     ))
 }
 
+fn compacted() -> Error {
+    Error::GRpcStatus(tonic::Status::new(
+        tonic::Code::OutOfRange,
+        "etcdserver: mvcc: required revision has been compacted",
+    ))
+}
+
 fn session_expired() -> Error {
     Error::ElectError("session expired".into())
 }
@@ -60,6 +60,22 @@ impl Client {
         ElectionClient::new(self.ep.clone())
     }
 
+    /// Gets a watch client.
+    #[inline]
+    pub fn watch_client(&self) -> WatchClient {
+        WatchClient::new(self.ep.clone())
+    }
+
+    /// Watches for events happening or that have happened.
+    #[inline]
+    pub async fn watch(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        options: Option<WatchOptions>,
+    ) -> Result<(Watcher, WatchStream)> {
+        self.watch_client().watch(key, options).await
+    }
+
     /// Gets a maintenance client.
     #[inline]
     pub fn maintenance_client(&self) -> MaintenanceClient {
@@ -74,6 +90,26 @@ impl Client {
         tx.send(Box::new(req)).await?;
         *rx.recv().await?.downcast::<Result<String>>().unwrap()
     }
+
+    /// Take a snapshot of the whole keyspace of the etcd server, including leases and revision.
+    ///
+    /// The snapshot can be restored by [`Client::restore`].
+    pub async fn snapshot(&mut self) -> Result<Vec<u8>> {
+        let req = Request::Snapshot;
+        let (tx, mut rx) = self.ep.connect1(self.server_addr).await?;
+        tx.send(Box::new(req)).await?;
+        *rx.recv().await?.downcast::<Result<Vec<u8>>>().unwrap()
+    }
+
+    /// Restore the etcd server from a snapshot, replacing all of its current data.
+    ///
+    /// Watchers are kept but not notified of the changes.
+    pub async fn restore(&mut self, snapshot: Vec<u8>) -> Result<()> {
+        let req = Request::Restore { snapshot };
+        let (tx, mut rx) = self.ep.connect1(self.server_addr).await?;
+        tx.send(Box::new(req)).await?;
+        *rx.recv().await?.downcast::<Result<()>>().unwrap()
+    }
 }
 
 /// Options for [`Connect`] operation.
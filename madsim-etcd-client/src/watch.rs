@@ -1,3 +1,85 @@
+use super::{server::Request, service, KeyValue, ResponseHeader, Result};
+use futures_util::stream::{Stream, StreamExt};
+use madsim::net::{Endpoint, Receiver, Sender};
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Client for watch operations.
+#[derive(Clone)]
+pub struct WatchClient {
+    ep: Endpoint,
+    server_addr: SocketAddr,
+}
+
+impl WatchClient {
+    /// Create a new [`WatchClient`].
+    pub(crate) fn new(ep: Endpoint) -> Self {
+        WatchClient {
+            server_addr: ep.peer_addr().unwrap(),
+            ep,
+        }
+    }
+
+    /// Watches for events happening or that have happened. Both input and output
+    /// are streams; the input stream is for creating and canceling watcher and the output
+    /// stream sends events. The entire event history can be watched starting from the
+    /// last compaction revision.
+    #[inline]
+    pub async fn watch(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        options: Option<WatchOptions>,
+    ) -> Result<(Watcher, WatchStream)> {
+        let req = Request::Watch {
+            key: key.into().into(),
+            options: options.unwrap_or_default(),
+        };
+        let (tx, rx) = self.ep.connect1(self.server_addr).await?;
+        tx.send(Box::new(req)).await?;
+        let mut stream = WatchStream { rx };
+        let watch_id = match stream.message().await? {
+            Some(rsp) if rsp.created() => rsp.watch_id(),
+            _ => return Err(super::Error::WatchError("failed to create watch".into())),
+        };
+        Ok((Watcher { watch_id, tx }, stream))
+    }
+}
+
+/// Options for `Watch` operation.
+#[derive(Debug, Default, Clone)]
+pub struct WatchOptions {
+    pub(crate) prefix: bool,
+    pub(crate) start_revision: i64,
+}
+
+impl WatchOptions {
+    /// Creates a new `WatchOptions`.
+    #[inline]
+    pub const fn new() -> Self {
+        WatchOptions {
+            prefix: false,
+            start_revision: 0,
+        }
+    }
+
+    /// Sets the revision to watch from (inclusive). If it is 0, only future events are watched.
+    #[inline]
+    pub const fn with_start_revision(mut self, revision: i64) -> Self {
+        self.start_revision = revision;
+        self
+    }
+
+    /// Watches all keys prefixed with key.
+    #[inline]
+    pub const fn with_prefix(mut self) -> Self {
+        self.prefix = true;
+        self
+    }
+}
+
 /// The kind of event.
 #[repr(i32)]
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -6,3 +88,180 @@ pub enum EventType {
     Put = 0,
     Delete = 1,
 }
+
+/// Every change to every key is represented with Event messages.
+#[derive(Debug, Clone)]
+pub struct Event {
+    event_type: EventType,
+    kv: KeyValue,
+}
+
+impl Event {
+    /// The kind of event. If type is a `Put`, it indicates
+    /// new data has been stored to the key. If type is a `Delete`,
+    /// it indicates the key was deleted.
+    #[inline]
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    /// The KeyValue for the event.
+    /// A `Put` event contains current kv pair.
+    /// A `Delete` event contains the deleted kv pair.
+    #[inline]
+    pub fn kv(&self) -> Option<&KeyValue> {
+        Some(&self.kv)
+    }
+}
+
+impl From<service::Event> for Event {
+    fn from(event: service::Event) -> Self {
+        match event {
+            service::Event::Put(kv) => Event {
+                event_type: EventType::Put,
+                kv,
+            },
+            service::Event::Delete(kv) => Event {
+                event_type: EventType::Delete,
+                kv,
+            },
+        }
+    }
+}
+
+/// Response for `Watch` operation.
+#[derive(Debug, Clone)]
+pub struct WatchResponse {
+    pub(crate) header: ResponseHeader,
+    pub(crate) watch_id: i64,
+    pub(crate) created: bool,
+    pub(crate) canceled: bool,
+    pub(crate) compact_revision: i64,
+    pub(crate) cancel_reason: String,
+    pub(crate) events: Vec<Event>,
+}
+
+impl WatchResponse {
+    /// Creates an empty response of the watch. Each watch has its own stream, where watch IDs
+    /// start from 0.
+    pub(crate) fn new(header: ResponseHeader) -> Self {
+        WatchResponse {
+            header,
+            watch_id: 0,
+            created: false,
+            canceled: false,
+            compact_revision: 0,
+            cancel_reason: String::new(),
+            events: vec![],
+        }
+    }
+
+    /// Get response header.
+    #[inline]
+    pub fn header(&self) -> Option<&ResponseHeader> {
+        Some(&self.header)
+    }
+
+    /// The ID of the watcher that corresponds to the response.
+    #[inline]
+    pub const fn watch_id(&self) -> i64 {
+        self.watch_id
+    }
+
+    /// created is set to true if the response is for a create watch request.
+    /// The client should record the watch_id and expect to receive events for
+    /// the created watcher from the same stream.
+    /// All events sent to the created watcher will attach with the same watch_id.
+    #[inline]
+    pub const fn created(&self) -> bool {
+        self.created
+    }
+
+    /// canceled is set to true if the response is for a cancel watch request.
+    /// No further events will be sent to the canceled watcher.
+    #[inline]
+    pub const fn canceled(&self) -> bool {
+        self.canceled
+    }
+
+    /// The minimum historical revision available to the watcher.
+    /// It is set if the watcher tries to watch from a compacted revision,
+    /// and the watcher is canceled.
+    #[inline]
+    pub const fn compact_revision(&self) -> i64 {
+        self.compact_revision
+    }
+
+    /// Indicates the reason for canceling the watcher.
+    #[inline]
+    pub fn cancel_reason(&self) -> &str {
+        &self.cancel_reason
+    }
+
+    /// Events of the watch, in the order of revisions.
+    #[inline]
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+}
+
+/// The watching handle.
+#[derive(Debug)]
+pub struct Watcher {
+    watch_id: i64,
+    tx: Sender,
+}
+
+impl Watcher {
+    /// The ID of the watcher.
+    #[inline]
+    pub const fn watch_id(&self) -> i64 {
+        self.watch_id
+    }
+
+    /// Cancels watch by the ID of the watcher.
+    ///
+    /// The stream receives a canceled response and ends.
+    #[inline]
+    pub async fn cancel(&mut self) -> Result<()> {
+        self.tx
+            .send(Box::new(Request::WatchCancel {
+                watch_id: self.watch_id,
+            }))
+            .await?;
+        Ok(())
+    }
+}
+
+/// The watch response stream.
+#[derive(Debug)]
+pub struct WatchStream {
+    rx: Receiver,
+}
+
+impl WatchStream {
+    /// Fetches the next message from this stream.
+    #[inline]
+    pub async fn message(&mut self) -> Result<Option<WatchResponse>> {
+        let rsp = *(self.rx.recv().await?)
+            .downcast::<Result<WatchResponse>>()
+            .unwrap();
+        rsp.map(Some)
+    }
+}
+
+impl Stream for WatchStream {
+    type Item = Result<WatchResponse>;
+
+    #[inline]
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.rx.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(payload))) => {
+                Poll::Ready(Some(*payload.downcast::<Result<WatchResponse>>().unwrap()))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e.into()))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -1,8 +1,14 @@
 #![cfg(madsim)]
 
-use madsim::{net::NetSim, runtime::Handle, time::sleep};
+use madsim::time::Instant;
+use madsim::{
+    net::NetSim,
+    runtime::Handle,
+    time::{sleep, timeout},
+};
 use madsim_etcd_client::{
-    Client, GetOptions, ProclaimOptions, PutOptions, ResignOptions, SimServer,
+    Client, EventType, GetOptions, ProclaimOptions, PutOptions, ResignOptions, SimServer,
+    WatchOptions,
 };
 use std::time::Duration;
 
@@ -61,6 +67,50 @@ async fn kv() {
     task1.await.unwrap();
 }
 
+#[madsim::test]
+async fn op_latency() {
+    let handle = Handle::current();
+    let ip1 = "10.0.0.1".parse().unwrap();
+    let ip2 = "10.0.0.2".parse().unwrap();
+    let server = handle.create_node().name("server").ip(ip1).build();
+    let client = handle.create_node().name("client").ip(ip2).build();
+
+    server.spawn(async move {
+        SimServer::builder()
+            .op_latency(Duration::from_secs(2))
+            .serve("10.0.0.1:2379".parse().unwrap())
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let task1 = client.spawn(async move {
+        let client = Client::connect(["10.0.0.1:2379"], None).await.unwrap();
+        let mut client = client.kv_client();
+        // the client gives up before the response
+        let t0 = Instant::now();
+        timeout(Duration::from_secs(1), client.put("foo", "bar", None))
+            .await
+            .unwrap_err();
+        let elapsed = t0.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(1) && elapsed < Duration::from_millis(1010),
+            "{elapsed:?}"
+        );
+
+        // but the server still executes the request
+        let t0 = Instant::now();
+        let resp = client.get("foo", None).await.unwrap();
+        let elapsed = t0.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(2) && elapsed < Duration::from_millis(2100),
+            "{elapsed:?}"
+        );
+        assert_eq!(resp.kvs()[0].value(), b"bar");
+    });
+    task1.await.unwrap();
+}
+
 #[madsim::test]
 async fn lease() {
     let handle = Handle::current();
@@ -126,6 +176,188 @@ async fn lease() {
     task1.await.unwrap();
 }
 
+#[madsim::test]
+async fn lease_expiry() {
+    let handle = Handle::current();
+    let ip1 = "10.0.0.1".parse().unwrap();
+    let ip2 = "10.0.0.2".parse().unwrap();
+    let server = handle.create_node().name("server").ip(ip1).build();
+    let client = handle.create_node().name("client").ip(ip2).build();
+
+    server.spawn(async move {
+        SimServer::builder()
+            .serve("10.0.0.1:2379".parse().unwrap())
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let task1 = client.spawn(async move {
+        let mut client = Client::connect(["10.0.0.1:2379"], None).await.unwrap();
+        let mut kv_client = client.kv_client();
+        let mut lease_client = client.lease_client();
+        let lease = lease_client.grant(10, None).await.unwrap();
+        let opt = PutOptions::new().with_lease(lease.id());
+        kv_client.put("leader", "me", Some(opt)).await.unwrap();
+        let (_watcher, mut stream) = client.watch("leader", None).await.unwrap();
+
+        // the key outlives the TTL while the lease is kept alive
+        let (mut keeper, mut responses) = lease_client.keep_alive(lease.id()).await.unwrap();
+        for _ in 0..6 {
+            sleep(Duration::from_secs(5)).await;
+            keeper.keep_alive().await.unwrap();
+            responses.message().await.unwrap().unwrap();
+        }
+        let resp = kv_client.get("leader", None).await.unwrap();
+        assert_eq!(resp.kvs().len(), 1);
+
+        // and is deleted once keepalives stop for the TTL
+        let t0 = Instant::now();
+        drop((keeper, responses));
+        let resp = stream.message().await.unwrap().unwrap();
+        let elapsed = t0.elapsed();
+        assert!(
+            elapsed > Duration::from_secs(8) && elapsed <= Duration::from_secs(11),
+            "{elapsed:?}"
+        );
+        assert_eq!(resp.events().len(), 1);
+        let event = &resp.events()[0];
+        assert_eq!(event.event_type(), EventType::Delete);
+        assert_eq!(event.kv().unwrap().key(), b"leader");
+        let resp = kv_client.get("leader", None).await.unwrap();
+        assert!(resp.kvs().is_empty());
+        lease_client
+            .time_to_live(lease.id(), None)
+            .await
+            .unwrap_err();
+    });
+    task1.await.unwrap();
+}
+
+#[madsim::test]
+async fn watch_order_and_compaction() {
+    let handle = Handle::current();
+    let ip1 = "10.0.0.1".parse().unwrap();
+    let ip2 = "10.0.0.2".parse().unwrap();
+    let server = handle.create_node().name("server").ip(ip1).build();
+    let client = handle.create_node().name("client").ip(ip2).build();
+
+    server.spawn(async move {
+        SimServer::builder()
+            .serve("10.0.0.1:2379".parse().unwrap())
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let task1 = client.spawn(async move {
+        let mut client = Client::connect(["10.0.0.1:2379"], None).await.unwrap();
+        let mut kv_client = client.kv_client();
+        let opt = WatchOptions::new().with_prefix();
+        let (_watcher, mut stream) = client.watch("k", Some(opt)).await.unwrap();
+        let mut revisions = vec![];
+        for i in 0..10 {
+            let key = format!("k{}", i % 3);
+            let rsp = kv_client.put(key, i.to_string(), None).await.unwrap();
+            revisions.push(rsp.header().unwrap().revision());
+        }
+        kv_client.delete("k0", None).await.unwrap();
+        let last = client
+            .get("k", None)
+            .await
+            .unwrap()
+            .header()
+            .unwrap()
+            .revision();
+        revisions.push(last);
+
+        // events arrive in the order of the writes
+        let mut events = vec![];
+        while events.len() < 11 {
+            let rsp = stream.message().await.unwrap().unwrap();
+            events.extend(rsp.events().iter().cloned());
+        }
+        let mod_revisions: Vec<_> = (events.iter())
+            .map(|e| e.kv().unwrap().mod_revision())
+            .collect();
+        assert_eq!(mod_revisions, revisions);
+        for (i, event) in events[..10].iter().enumerate() {
+            assert_eq!(event.event_type(), EventType::Put);
+            assert_eq!(event.kv().unwrap().value(), i.to_string().as_bytes());
+        }
+        assert_eq!(events[10].event_type(), EventType::Delete);
+        assert_eq!(events[10].kv().unwrap().key(), b"k0");
+
+        // the history is replayed from the start revision
+        let opt = WatchOptions::new().with_start_revision(revisions[5]);
+        let (_watcher, mut stream) = client.watch("k2", Some(opt)).await.unwrap();
+        let rsp = stream.message().await.unwrap().unwrap();
+        let values: Vec<_> = (rsp.events().iter())
+            .map(|e| e.kv().unwrap().value().to_vec())
+            .collect();
+        assert_eq!(values, [b"5", b"8"]);
+
+        // watching below the compact revision is canceled
+        let compact = revisions[5];
+        kv_client.compact(compact, None).await.unwrap();
+        kv_client.compact(compact, None).await.unwrap_err();
+        let opt = WatchOptions::new().with_start_revision(revisions[2]);
+        let (_watcher, mut stream) = client.watch("k2", Some(opt)).await.unwrap();
+        let rsp = stream.message().await.unwrap().unwrap();
+        assert!(rsp.canceled());
+        assert_eq!(rsp.compact_revision(), compact);
+        assert!(rsp.events().is_empty());
+
+        // while the history since the compact revision is kept
+        let opt = WatchOptions::new().with_start_revision(compact);
+        let (_watcher, mut stream) = client.watch("k2", Some(opt)).await.unwrap();
+        let rsp = stream.message().await.unwrap().unwrap();
+        assert!(!rsp.canceled());
+        assert_eq!(rsp.events().len(), 2);
+    });
+    task1.await.unwrap();
+}
+
+#[madsim::test]
+async fn watch_history_limit() {
+    let handle = Handle::current();
+    let ip1 = "10.0.0.1".parse().unwrap();
+    let ip2 = "10.0.0.2".parse().unwrap();
+    let server = handle.create_node().name("server").ip(ip1).build();
+    let client = handle.create_node().name("client").ip(ip2).build();
+
+    server.spawn(async move {
+        SimServer::builder()
+            .serve("10.0.0.1:2379".parse().unwrap())
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let task1 = client.spawn(async move {
+        let mut client = Client::connect(["10.0.0.1:2379"], None).await.unwrap();
+        let mut revisions = vec![];
+        for i in 0..10_001 {
+            let rsp = client.put("k", i.to_string(), None).await.unwrap();
+            revisions.push(rsp.header().unwrap().revision());
+        }
+
+        // the oldest event is compacted without an explicit compaction
+        let opt = WatchOptions::new().with_start_revision(revisions[0]);
+        let (_watcher, mut stream) = client.watch("k", Some(opt)).await.unwrap();
+        let rsp = stream.message().await.unwrap().unwrap();
+        assert!(rsp.canceled());
+        assert_eq!(rsp.compact_revision(), revisions[1]);
+
+        let opt = WatchOptions::new().with_start_revision(revisions[1]);
+        let (_watcher, mut stream) = client.watch("k", Some(opt)).await.unwrap();
+        let rsp = stream.message().await.unwrap().unwrap();
+        assert!(!rsp.canceled());
+        assert_eq!(rsp.events().len(), 10_000);
+    });
+    task1.await.unwrap();
+}
+
 #[madsim::test]
 async fn election() {
     // tracing_subscriber::fmt::init();
@@ -312,3 +544,54 @@ async fn load_dump() {
         .await
         .unwrap();
 }
+
+#[madsim::test]
+async fn snapshot_restore() {
+    let handle = Handle::current();
+    let ip1 = "10.0.0.1".parse().unwrap();
+    let ip2 = "10.0.0.2".parse().unwrap();
+    let server = handle.create_node().name("server").ip(ip1).build();
+    let client = handle.create_node().name("client").ip(ip2).build();
+
+    server.spawn(async move {
+        SimServer::builder()
+            .serve("10.0.0.1:2379".parse().unwrap())
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let task1 = client.spawn(async move {
+        let mut client = Client::connect(["10.0.0.1:2379"], None).await.unwrap();
+        let mut kv = client.kv_client();
+        kv.put("k/foo", "1", None).await.unwrap();
+        kv.put("k/bar", "2", None).await.unwrap();
+        let all = Some(GetOptions::new().with_prefix());
+        let before = kv.get("k/", all.clone()).await.unwrap();
+        let snapshot = client.snapshot().await.unwrap();
+
+        // mutate
+        kv.put("k/foo", "3", None).await.unwrap();
+        kv.delete("k/bar", None).await.unwrap();
+        kv.put("k/baz", "4", None).await.unwrap();
+
+        client.restore(snapshot).await.unwrap();
+        let after = kv.get("k/", all).await.unwrap();
+        assert_eq!(after.kvs(), before.kvs());
+        assert_eq!(
+            after.header().unwrap().revision(),
+            before.header().unwrap().revision()
+        );
+
+        // the revision continues from the snapshot
+        let resp = kv.put("k/foo", "5", None).await.unwrap();
+        assert_eq!(
+            resp.header().unwrap().revision(),
+            before.header().unwrap().revision() + 1
+        );
+
+        // invalid snapshots are rejected
+        client.restore(b"invalid".to_vec()).await.unwrap_err();
+    });
+    task1.await.unwrap();
+}
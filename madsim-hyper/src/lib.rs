@@ -0,0 +1,25 @@
+//! The `hyper` runtime adapters on madsim.
+//!
+//! This crate re-exports [`hyper`]. HTTP connections of `hyper` run on any I/O type, so they
+//! can be served over the simulated TCP streams of madsim, and are subject to the latency,
+//! partitions and node failures of the simulated network. The executor and the timer that
+//! `hyper-util` provides for tokio are replaced in simulation by those in [`rt`].
+
+#[cfg(madsim)]
+#[path = "sim.rs"]
+mod sim;
+
+pub use hyper::*;
+
+/// Runtime components of `hyper`, and the adapters to run it on tokio.
+///
+/// In simulation, [`TokioExecutor`](rt::TokioExecutor) spawns tasks on the current node and
+/// [`TokioTimer`](rt::TokioTimer) sleeps in simulated time.
+pub mod rt {
+    pub use hyper::rt::*;
+
+    #[cfg(madsim)]
+    pub use crate::sim::{TokioExecutor, TokioIo, TokioTimer};
+    #[cfg(not(madsim))]
+    pub use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+}
@@ -0,0 +1,66 @@
+use hyper::rt::{Executor, Sleep, Timer};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// The adapter of tokio I/O types, which also works with the simulated streams.
+pub use hyper_util::rt::TokioIo;
+
+/// An executor that spawns tasks on the current node.
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub struct TokioExecutor {}
+
+impl TokioExecutor {
+    /// Create a new executor.
+    pub fn new() -> Self {
+        TokioExecutor {}
+    }
+}
+
+impl<F> Executor<F> for TokioExecutor
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn execute(&self, fut: F) {
+        madsim::task::spawn(fut);
+    }
+}
+
+/// A timer that sleeps in simulated time.
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub struct TokioTimer {}
+
+impl TokioTimer {
+    /// Create a new timer.
+    pub fn new() -> Self {
+        TokioTimer {}
+    }
+}
+
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Sleep>> {
+        Box::pin(SimSleep(madsim::time::sleep(duration)))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Sleep>> {
+        Box::pin(SimSleep(madsim::time::sleep_until(deadline)))
+    }
+}
+
+struct SimSleep(madsim::time::Sleep);
+
+impl Future for SimSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl Sleep for SimSleep {}
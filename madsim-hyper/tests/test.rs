@@ -0,0 +1,126 @@
+#![cfg(madsim)]
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use madsim::{
+    net::{NetSim, TcpListener, TcpStream},
+    runtime::Handle,
+    time::{sleep, timeout, Instant},
+};
+use madsim_hyper::{
+    body::Incoming,
+    client::conn::http1 as client_http1,
+    header,
+    rt::{Executor, TokioExecutor, TokioIo, TokioTimer},
+    server::conn::http1 as server_http1,
+    service::service_fn,
+    Request, Response, StatusCode,
+};
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+/// A REST handler that reports the status of the node.
+async fn admin(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let rsp = match req.uri().path() {
+        "/status" => Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-node", "server")
+            .body(Full::from(r#"{"healthy":true}"#)),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::default()),
+    };
+    Ok(rsp.unwrap())
+}
+
+async fn serve(addr: SocketAddr) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        madsim::task::spawn(async move {
+            let conn = server_http1::Builder::new()
+                .timer(TokioTimer::new())
+                .serve_connection(TokioIo::new(stream), service_fn(admin));
+            _ = conn.await;
+        });
+    }
+}
+
+/// Issues a GET request on a new connection.
+async fn get(
+    addr: SocketAddr,
+    path: &str,
+) -> Result<Response<Bytes>, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = TcpStream::connect(addr).await?;
+    let (mut sender, conn) = client_http1::handshake(TokioIo::new(stream)).await?;
+    TokioExecutor::new().execute(async move {
+        _ = conn.await;
+    });
+    let req = Request::get(path)
+        .header(header::HOST, "server")
+        .body(Full::<Bytes>::default())?;
+    let rsp = sender.send_request(req).await?;
+    let (parts, body) = rsp.into_parts();
+    let body = body.collect().await?.to_bytes();
+    Ok(Response::from_parts(parts, body))
+}
+
+#[madsim::test]
+async fn get_status() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle
+        .create_node()
+        .name("client")
+        .ip("10.0.0.2".parse().unwrap())
+        .build();
+    node0.spawn(serve(addr0));
+
+    let task = node1.spawn(async move {
+        sleep(Duration::from_secs(1)).await;
+        let rsp = get(addr0, "/status").await.unwrap();
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(rsp.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(rsp.headers()["x-node"], "server");
+        assert_eq!(rsp.body().as_ref(), br#"{"healthy":true}"#);
+
+        let rsp = get(addr0, "/unknown").await.unwrap();
+        assert_eq!(rsp.status(), StatusCode::NOT_FOUND);
+        assert!(rsp.body().is_empty());
+    });
+    task.await.unwrap();
+}
+
+#[madsim::test]
+async fn partition() {
+    let handle = Handle::current();
+    let net = NetSim::current();
+    let addr0 = "10.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle
+        .create_node()
+        .name("client")
+        .ip("10.0.0.2".parse().unwrap())
+        .build();
+    node0.spawn(serve(addr0));
+    sleep(Duration::from_secs(1)).await;
+
+    // the server is unreachable during the partition
+    net.disconnect2(node0.id(), node1.id());
+    let task = node1.spawn(async move {
+        let res = timeout(Duration::from_secs(5), get(addr0, "/status")).await;
+        assert!(!matches!(res, Ok(Ok(_))));
+    });
+    task.await.unwrap();
+
+    // and serves requests again after the partition heals
+    net.connect2(node0.id(), node1.id());
+    let task = node1.spawn(async move {
+        let t0 = Instant::now();
+        let rsp = get(addr0, "/status").await.unwrap();
+        assert_eq!(rsp.status(), StatusCode::OK);
+        t0.elapsed()
+    });
+    let elapsed = task.await.unwrap();
+    assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+}
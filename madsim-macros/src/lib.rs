@@ -51,6 +51,39 @@ pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
 /// }
 /// ```
 ///
+/// # Time limit
+///
+/// A test can limit the simulated time it takes, so that a simulation running forever
+/// (e.g. retrying with backoff in a loop) fails fast instead of hanging until the CI timeout:
+///
+/// ```ignore
+/// #[madsim::test(time_limit = "60s")]
+/// async fn my_test() {
+///     // ...
+/// }
+/// ```
+///
+/// The test panics once the simulated clock reaches the limit, with a dump of pending tasks.
+/// The limit is a decimal number with a unit of `ns`, `us`, `ms`, `s`, `m` or `h`.
+/// `MADSIM_TEST_TIME_LIMIT` takes precedence over this option. The option is ignored when
+/// running on tokio.
+///
+/// # Step limit
+///
+/// Tasks spinning in a busy loop (e.g. `loop { yield_now().await }`) don't wait for timers, so
+/// the time limit never triggers. A test can limit the number of task polls instead:
+///
+/// ```ignore
+/// #[madsim::test(step_limit = 1_000_000)]
+/// async fn my_test() {
+///     // ...
+/// }
+/// ```
+///
+/// The test panics once the limit is exceeded, with the last polled task and a dump of pending
+/// tasks. `MADSIM_TEST_STEP_LIMIT` takes precedence over this option. The option is ignored
+/// when running on tokio.
+///
 /// # Configuration
 ///
 /// Test can be configured using the following environment variables:
@@ -79,6 +112,12 @@ pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
 ///
 ///     By default, there is no time limit.
 ///
+/// - `MADSIM_TEST_STEP_LIMIT`: Set the limit of task polls for the test.
+///
+///     The test will panic if tasks are polled more times than the limit, e.g. by busy loops.
+///
+///     By default, there is no step limit.
+///
 /// - `MADSIM_TEST_CHECK_DETERMINISM`: Enable determinism check.
 ///
 ///     The test will be run at least twice with the same seed.
@@ -93,6 +132,25 @@ pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
     parse(input, args, true, false).unwrap_or_else(|e| e.to_compile_error().into())
 }
 
+// This macro is used by madsim outside simulation.
+#[doc(hidden)]
+#[proc_macro_attribute]
+pub fn std_test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(args as syn::AttributeArgs);
+    let item = proc_macro2::TokenStream::from(item);
+
+    // options of the simulation are ignored by tokio
+    let tokio = match parse_args(args) {
+        Ok(args) => args.tokio,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let result = quote! {
+        #[::madsim::export::tokio::test(#(#tokio),*)]
+        #item
+    };
+    result.into()
+}
+
 // This macro is used by madsim-tokio.
 #[doc(hidden)]
 #[proc_macro_attribute]
@@ -113,12 +171,70 @@ pub fn tokio_test(args: TokenStream, item: TokenStream) -> TokenStream {
     parse(input, args, true, true).unwrap_or_else(|e| e.to_compile_error().into())
 }
 
+/// Options of the macros.
+struct Args {
+    time_limit: Option<u64>,
+    step_limit: Option<u64>,
+    /// Options of `tokio::test` and `tokio::main`, which are ignored in simulation.
+    tokio: Vec<syn::NestedMeta>,
+}
+
+/// Options accepted by `tokio::test` and `tokio::main`.
+const TOKIO_OPTIONS: &[&str] = &[
+    "flavor",
+    "worker_threads",
+    "start_paused",
+    "crate",
+    "unhandled_panic",
+];
+
+fn parse_args(args: syn::AttributeArgs) -> Result<Args, syn::Error> {
+    let mut time_limit = None;
+    let mut step_limit = None;
+    let mut tokio = vec![];
+    for arg in args {
+        match arg {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("time_limit") => {
+                let syn::Lit::Str(lit) = &nv.lit else {
+                    return Err(syn::Error::new_spanned(&nv.lit, "expected a string"));
+                };
+                let nanos = parse_duration(&lit.value())
+                    .ok_or_else(|| syn::Error::new_spanned(lit, "invalid duration"))?;
+                time_limit = Some(nanos);
+            }
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("step_limit") => {
+                let syn::Lit::Int(lit) = &nv.lit else {
+                    return Err(syn::Error::new_spanned(&nv.lit, "expected an integer"));
+                };
+                step_limit = Some(lit.base10_parse::<u64>()?);
+            }
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv))
+                if TOKIO_OPTIONS.iter().any(|name| nv.path.is_ident(name)) =>
+            {
+                tokio.push(syn::NestedMeta::Meta(syn::Meta::NameValue(nv)));
+            }
+            arg => return Err(syn::Error::new_spanned(arg, "unknown option")),
+        }
+    }
+    Ok(Args {
+        time_limit,
+        step_limit,
+        tokio,
+    })
+}
+
 fn parse(
     mut input: syn::ItemFn,
-    _args: syn::AttributeArgs,
+    args: syn::AttributeArgs,
     is_test: bool,
     is_tokio: bool,
 ) -> Result<TokenStream, syn::Error> {
+    let Args {
+        time_limit,
+        step_limit,
+        ..
+    } = parse_args(args)?;
+
     if input.sig.asyncness.take().is_none() {
         let msg = "the `async` keyword is missing from the function declaration";
         return Err(syn::Error::new_spanned(input.sig.fn_token, msg));
@@ -131,9 +247,25 @@ fn parse(
     } else {
         quote! {}
     };
+    let mut builder = quote! { #tokio::madsim::runtime::Builder::from_env() };
+    if let Some(nanos) = time_limit {
+        // the environment variable takes precedence
+        builder = quote! {{
+            let mut builder = #builder;
+            builder.time_limit.get_or_insert(::std::time::Duration::from_nanos(#nanos));
+            builder
+        }};
+    }
+    if let Some(steps) = step_limit {
+        builder = quote! {{
+            let mut builder = #builder;
+            builder.step_limit.get_or_insert(#steps);
+            builder
+        }};
+    }
     input.block = syn::parse2(quote! {
         {
-            #tokio::madsim::runtime::Builder::from_env().run(|| async #body)
+            #builder.run(|| async #body)
         }
     })
     .expect("Parsing failure");
@@ -151,3 +283,21 @@ fn parse(
     };
     Ok(result.into())
 }
+
+/// Parses a duration like `60s` or `1.5m` into nanoseconds.
+fn parse_duration(s: &str) -> Option<u64> {
+    let pos = s.find(|c: char| c.is_ascii_alphabetic())?;
+    let (value, unit) = s.split_at(pos);
+    let value: f64 = value.trim().parse().ok()?;
+    let scale = match unit {
+        "ns" => 1.0,
+        "us" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        "m" => 60e9,
+        "h" => 3600e9,
+        _ => return None,
+    };
+    let nanos = value * scale;
+    (nanos.is_finite() && nanos > 0.0).then_some(nanos as u64)
+}
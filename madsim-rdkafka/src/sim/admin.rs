@@ -101,6 +101,20 @@ where
         Ok(results)
     }
 
+    /// Triggers a rebalance of the consumer group.
+    ///
+    /// This is only available in simulation. Members of the group revoke their partitions and
+    /// receive a new assignment the next time they poll, with the rebalance callbacks of their
+    /// [`ConsumerContext`](crate::consumer::ConsumerContext).
+    pub async fn rebalance_group(&self, group_name: &str) -> KafkaResult<()> {
+        let req = Request::RebalanceGroup {
+            group: group_name.to_string(),
+        };
+        let (tx, mut rx) = self.ep.connect1(self.addr).await?;
+        tx.send(Box::new(req)).await?;
+        *rx.recv().await?.downcast().unwrap()
+    }
+
     /// Deletes the named groups.
     pub fn delete_groups(
         &self,
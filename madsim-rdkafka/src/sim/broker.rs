@@ -6,12 +6,17 @@ use crate::{
     metadata::{Metadata, MetadataPartition, MetadataTopic},
     Message, Offset, TopicPartitionList,
 };
-use std::collections::HashMap;
+use madsim::time::Instant;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
 use tracing::*;
 
 #[derive(Debug, Default)]
 pub struct Broker {
     topics: HashMap<String, Topic>,
+    groups: HashMap<String, Group>,
 }
 
 #[derive(Debug)]
@@ -30,6 +35,32 @@ struct Partition {
     msgs: Vec<OwnedMessage>,
 }
 
+/// A consumer group.
+#[derive(Debug, Default)]
+struct Group {
+    generation: i32,
+    next_member_id: u64,
+    members: BTreeMap<u64, Member>,
+}
+
+#[derive(Debug)]
+struct Member {
+    topics: Vec<String>,
+    /// The latest generation joined by the member, after revoking its partitions.
+    generation: Option<i32>,
+    session_timeout: Duration,
+    last_seen: Instant,
+}
+
+/// The membership of a consumer in a group.
+#[derive(Debug)]
+pub struct Membership {
+    pub member_id: u64,
+    pub generation: i32,
+    /// The partitions assigned to the member, or `None` if the group is rebalancing.
+    pub assignment: Option<TopicPartitionList>,
+}
+
 impl Partition {
     /// Creates a new partition.
     fn new(id: i32) -> Self {
@@ -188,6 +219,80 @@ impl Broker {
         Ok(ret)
     }
 
+    /// Joins a consumer group, or sends a heartbeat to it.
+    ///
+    /// `generation` is the latest generation the member has seen, after revoking the partitions
+    /// of the previous generation. The group moves to a new generation when a member joins,
+    /// changes its subscription, misses the session timeout or a rebalance is triggered. The new
+    /// assignment is returned only after all members have joined the generation, so partitions
+    /// are always revoked before they are assigned to another member.
+    pub fn sync_group(
+        &mut self,
+        group: &str,
+        member_id: Option<u64>,
+        topics: Vec<String>,
+        generation: Option<i32>,
+        session_timeout: Duration,
+    ) -> Result<Membership> {
+        let now = Instant::now();
+        let group = self.groups.entry(group.to_string()).or_default();
+
+        let len = group.members.len();
+        (group.members).retain(|_, m| now - m.last_seen <= m.session_timeout);
+        if group.members.len() != len {
+            debug!("{} members left the group", len - group.members.len());
+            group.generation += 1;
+        }
+        let member_id = match member_id.filter(|id| group.members.contains_key(id)) {
+            Some(id) => id,
+            None => {
+                let id = group.next_member_id;
+                group.next_member_id += 1;
+                group.generation += 1;
+                debug!(member_id = id, ?topics, "join group");
+                group.members.insert(
+                    id,
+                    Member {
+                        topics: topics.clone(),
+                        generation: None,
+                        session_timeout,
+                        last_seen: now,
+                    },
+                );
+                id
+            }
+        };
+        let member = group.members.get_mut(&member_id).unwrap();
+        if member.topics != topics {
+            member.topics = topics;
+            group.generation += 1;
+        }
+        member.generation = generation;
+        member.session_timeout = session_timeout;
+        member.last_seen = now;
+
+        let generation = group.generation;
+        let assignment = (group.members.values())
+            .all(|m| m.generation == Some(generation))
+            .then(|| group.assignment(member_id, &self.topics));
+        Ok(Membership {
+            member_id,
+            generation,
+            assignment,
+        })
+    }
+
+    /// Triggers a rebalance of a consumer group.
+    pub fn rebalance_group(&mut self, group: &str) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group)
+            .ok_or(Error::AdminOp(ErrorCode::GroupIdNotFound))?;
+        group.generation += 1;
+        debug!(generation = group.generation, "rebalance group");
+        Ok(())
+    }
+
     fn get_partition(
         &self,
         topic: &str,
@@ -202,6 +307,33 @@ impl Broker {
     }
 }
 
+impl Group {
+    /// Returns the partitions assigned to a member by the range assignor.
+    ///
+    /// The partitions of each topic are divided into ranges over the subscribed members in the
+    /// order of their IDs. If they don't divide evenly, the first members get one more.
+    fn assignment(&self, member_id: u64, topics: &HashMap<String, Topic>) -> TopicPartitionList {
+        let mut tpl = TopicPartitionList::new();
+        for name in &self.members[&member_id].topics {
+            let Some(topic) = topics.get(name) else {
+                continue;
+            };
+            let subscribers = (self.members.iter())
+                .filter(|(_, m)| m.topics.contains(name))
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            let idx = subscribers.iter().position(|id| *id == member_id).unwrap();
+            let (n, m) = (topic.partitions.len(), subscribers.len());
+            let start = idx * (n / m) + idx.min(n % m);
+            let len = n / m + usize::from(idx < n % m);
+            for partition in start..start + len {
+                tpl.add_partition(name, partition as _);
+            }
+        }
+        tpl
+    }
+}
+
 impl Topic {
     /// Create a new [`Topic`].
     fn new(name: String, partitions: usize) -> Self {
@@ -14,7 +14,7 @@ use std::{
 };
 
 use crate::{
-    broker::FetchOptions,
+    broker::{FetchOptions, Membership},
     client::ClientContext,
     config::{FromClientConfig, FromClientConfigAndContext},
     error::{KafkaError, KafkaResult},
@@ -32,8 +32,29 @@ where
 {
 }
 
+/// Rebalance information.
+#[derive(Clone, Debug)]
+pub enum Rebalance<'a> {
+    /// A new partition assignment is received.
+    Assign(&'a TopicPartitionList),
+    /// A new partition revocation is received.
+    Revoke(&'a TopicPartitionList),
+    /// Unexpected error from Kafka.
+    Error(KafkaError),
+}
+
 /// Consumer-specific context.
-pub trait ConsumerContext: ClientContext {}
+pub trait ConsumerContext: ClientContext {
+    /// Pre-rebalance callback. This method will run before the rebalance and
+    /// should terminate its execution quickly.
+    #[allow(unused_variables)]
+    fn pre_rebalance<'a>(&self, rebalance: &Rebalance<'a>) {}
+
+    /// Post-rebalance callback. This method will run after the rebalance and
+    /// should terminate its execution quickly.
+    #[allow(unused_variables)]
+    fn post_rebalance<'a>(&self, rebalance: &Rebalance<'a>) {}
+}
 
 /// An inert [`ConsumerContext`] that can be used when no customizations are needed.
 #[derive(Clone, Debug, Default)]
@@ -50,12 +71,24 @@ pub struct BaseConsumer<C = DefaultConsumerContext>
 where
     C: ConsumerContext,
 {
-    _context: C,
+    context: C,
     config: ConsumerConfig,
     ep: Endpoint,
     addr: SocketAddr,
     tpl: Mutex<TopicPartitionList>,
     msgs: Mutex<VecDeque<OwnedMessage>>,
+    subscription: Mutex<Option<Subscription>>,
+}
+
+/// The subscription of a consumer and its state in the group.
+#[derive(Debug, Clone, Default)]
+struct Subscription {
+    topics: Vec<String>,
+    member_id: Option<u64>,
+    /// The latest generation of the group seen by the consumer.
+    generation: Option<i32>,
+    /// The generation of the current assignment.
+    assigned: Option<i32>,
 }
 
 #[async_trait::async_trait]
@@ -70,7 +103,7 @@ impl FromClientConfig for BaseConsumer {
 impl<C: ConsumerContext> FromClientConfigAndContext<C> for BaseConsumer<C> {
     async fn from_config_and_context(
         config: &ClientConfig,
-        _context: C,
+        context: C,
     ) -> KafkaResult<BaseConsumer<C>> {
         let config_json = serde_json::to_string(&config.conf_map)
             .map_err(|e| KafkaError::ClientCreation(e.to_string()))?;
@@ -82,16 +115,13 @@ impl<C: ConsumerContext> FromClientConfigAndContext<C> for BaseConsumer<C> {
         if config.enable_partition_eof {
             warn!("partition eof is not supported yet");
         }
-        if config.group_id.is_some() {
-            warn!("group id is ignored");
-        }
         let addr: SocketAddr = madsim::net::lookup_host(&config.bootstrap_servers)
             .await
             .map_err(|e| KafkaError::ClientCreation(e.to_string()))?
             .next()
             .ok_or_else(|| KafkaError::ClientCreation("invalid host or ip".into()))?;
         let p = BaseConsumer {
-            _context,
+            context,
             config,
             ep: Endpoint::bind("0.0.0.0:0")
                 .await
@@ -99,6 +129,7 @@ impl<C: ConsumerContext> FromClientConfigAndContext<C> for BaseConsumer<C> {
             addr,
             tpl: Mutex::new(TopicPartitionList::new()),
             msgs: Mutex::new(VecDeque::new()),
+            subscription: Mutex::new(None),
         };
         Ok(p)
     }
@@ -124,6 +155,33 @@ where
         Ok(())
     }
 
+    /// Subscribes the consumer to a list of topics.
+    ///
+    /// The consumer joins the group of `group.id` the next time it polls, and the partitions of
+    /// the topics are assigned to the members of the group by the range assignor. The group
+    /// rebalances when a member joins, changes its subscription or misses `session.timeout.ms`,
+    /// and the consumer is notified by the rebalance callbacks of its [`ConsumerContext`].
+    /// Partitions are always revoked from a member before they are assigned to another.
+    ///
+    /// Committed offsets are not supported yet, so assigned partitions are consumed from
+    /// `auto.offset.reset`.
+    pub fn subscribe(&self, topics: &[&str]) -> KafkaResult<()> {
+        if self.config.group_id.is_none() {
+            return Err(KafkaError::Subscription(
+                "group.id must be set to subscribe".into(),
+            ));
+        }
+        let mut subscription = self.subscription.lock();
+        let subscription = subscription.get_or_insert_with(Default::default);
+        subscription.topics = topics.iter().map(|s| s.to_string()).collect();
+        Ok(())
+    }
+
+    /// Returns the current partition assignment.
+    pub fn assignment(&self) -> KafkaResult<TopicPartitionList> {
+        Ok(self.tpl.lock().clone())
+    }
+
     /// Returns the low and high watermarks for a specific topic and partition.
     pub async fn fetch_watermarks(
         &self,
@@ -183,6 +241,7 @@ where
     async fn poll_internal(&self) -> KafkaResult<Option<OwnedMessage>> {
         // FIXME: concurrent call
         if self.msgs.lock().is_empty() {
+            self.sync_group().await?;
             let tpl = self.tpl.lock().clone();
             if tpl.count() == 0 {
                 return Ok(None);
@@ -208,6 +267,50 @@ where
         }
         Ok(self.msgs.lock().pop_front())
     }
+
+    /// Joins the group of the subscription or sends a heartbeat to it, and runs the rebalance
+    /// callbacks if the group has moved to a new generation.
+    async fn sync_group(&self) -> KafkaResult<()> {
+        let Some(subscription) = self.subscription.lock().clone() else {
+            return Ok(());
+        };
+        let req = Request::SyncGroup {
+            group: self.config.group_id.clone().unwrap(),
+            member_id: subscription.member_id,
+            topics: subscription.topics,
+            generation: subscription.generation,
+            session_timeout: Duration::from_millis(self.config.session_timeout_ms),
+        };
+        let (tx, mut rx) = self.ep.connect1(self.addr).await?;
+        tx.send(Box::new(req)).await?;
+        let rsp = *(rx.recv().await?)
+            .downcast::<KafkaResult<Membership>>()
+            .unwrap();
+        let membership = rsp?;
+
+        let mut assigned = subscription.assigned;
+        if assigned.is_some() && assigned != Some(membership.generation) {
+            let revoked = self.tpl.lock().clone();
+            debug!(generation = membership.generation, list = ?revoked, "revoke");
+            self.context.pre_rebalance(&Rebalance::Revoke(&revoked));
+            *self.tpl.lock() = TopicPartitionList::new();
+            self.context.post_rebalance(&Rebalance::Revoke(&revoked));
+            assigned = None;
+        }
+        if let (None, Some(assignment)) = (assigned, &membership.assignment) {
+            debug!(generation = membership.generation, list = ?assignment, "assign");
+            self.context.pre_rebalance(&Rebalance::Assign(assignment));
+            self.assign(assignment)?;
+            self.context.post_rebalance(&Rebalance::Assign(assignment));
+            assigned = Some(membership.generation);
+        }
+        if let Some(subscription) = self.subscription.lock().as_mut() {
+            subscription.member_id = Some(membership.member_id);
+            subscription.generation = Some(membership.generation);
+            subscription.assigned = assigned;
+        }
+        Ok(())
+    }
 }
 
 /// A high-level consumer with a [`Stream`](futures::Stream) interface.
@@ -263,6 +366,16 @@ where
         self.base.assign(assignment)
     }
 
+    /// Subscribes the consumer to a list of topics. See [`BaseConsumer::subscribe`].
+    pub fn subscribe(&self, topics: &[&str]) -> KafkaResult<()> {
+        self.base.subscribe(topics)
+    }
+
+    /// Returns the current partition assignment.
+    pub fn assignment(&self) -> KafkaResult<TopicPartitionList> {
+        self.base.assignment()
+    }
+
     pub async fn fetch_watermarks(
         &self,
         topic: &str,
@@ -332,6 +445,14 @@ struct ConsumerConfig {
     #[serde(rename = "group.id")]
     group_id: Option<String>,
 
+    /// Client group session and failure detection timeout.
+    #[serde(
+        rename = "session.timeout.ms",
+        deserialize_with = "super::from_str",
+        default = "default_session_timeout_ms"
+    )]
+    session_timeout_ms: u64,
+
     /// If true the consumer's offset will be periodically committed in the background.
     #[serde(
         rename = "enable.auto.commit",
@@ -380,6 +501,9 @@ enum AutoOffsetResetStrategy {
 const fn default_enable_auto_commit() -> bool {
     true
 }
+const fn default_session_timeout_ms() -> u64 {
+    45000
+}
 const fn default_fetch_max_bytes() -> u32 {
     52428800
 }
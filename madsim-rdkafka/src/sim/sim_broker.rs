@@ -6,20 +6,35 @@ use crate::{
 };
 use madsim::net::{Endpoint, Payload};
 use spin::Mutex;
-use std::{io::Result, net::SocketAddr, sync::Arc};
+use std::{io::Result, net::SocketAddr, sync::Arc, time::Duration};
 
 #[derive(Default)]
-pub struct SimBroker {}
+pub struct SimBroker {
+    op_latency: Duration,
+}
 
 impl SimBroker {
+    /// Set the latency of processing each request.
+    ///
+    /// The broker waits for the latency in logical time after receiving a request, before
+    /// executing it.
+    pub fn op_latency(mut self, latency: Duration) -> Self {
+        self.op_latency = latency;
+        self
+    }
+
     pub async fn serve(self, addr: SocketAddr) -> Result<()> {
         let ep = Endpoint::bind(addr).await?;
         let service = Arc::new(Mutex::new(Broker::default()));
+        let op_latency = self.op_latency;
         loop {
             let (tx, mut rx, _) = ep.accept1().await?;
             let service = service.clone();
             madsim::task::spawn(async move {
                 let request = *rx.recv().await?.downcast::<Request>().unwrap();
+                if !op_latency.is_zero() {
+                    madsim::time::sleep(op_latency).await;
+                }
                 let response: Payload = match request {
                     Request::CreateTopic { name, partitions } => {
                         Box::new(service.lock().create_topic(name, partitions))
@@ -42,6 +57,22 @@ impl SimBroker {
                     Request::OffsetsForTimes { tpl } => {
                         Box::new(service.lock().offsets_for_times(&tpl))
                     }
+                    Request::SyncGroup {
+                        group,
+                        member_id,
+                        topics,
+                        generation,
+                        session_timeout,
+                    } => Box::new(service.lock().sync_group(
+                        &group,
+                        member_id,
+                        topics,
+                        generation,
+                        session_timeout,
+                    )),
+                    Request::RebalanceGroup { group } => {
+                        Box::new(service.lock().rebalance_group(&group))
+                    }
                 };
                 tx.send(response).await?;
                 Ok(()) as Result<()>
@@ -74,4 +105,14 @@ pub enum Request {
     OffsetsForTimes {
         tpl: TopicPartitionList,
     },
+    SyncGroup {
+        group: String,
+        member_id: Option<u64>,
+        topics: Vec<String>,
+        generation: Option<i32>,
+        session_timeout: Duration,
+    },
+    RebalanceGroup {
+        group: String,
+    },
 }
@@ -5,7 +5,9 @@ use madsim::net::NetSim;
 use madsim::runtime::Handle;
 use madsim_rdkafka::{
     admin::*,
-    consumer::{BaseConsumer, StreamConsumer},
+    client::ClientContext,
+    consumer::{BaseConsumer, ConsumerContext, Rebalance, StreamConsumer},
+    error::{KafkaError, RDKafkaErrorCode},
     producer::{BaseProducer, BaseRecord, FutureProducer, FutureRecord},
     ClientConfig, Message, SimBroker, TopicPartitionList,
 };
@@ -13,7 +15,7 @@ use std::{
     net::SocketAddr,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     time::Duration,
 };
@@ -174,3 +176,229 @@ async fn test() {
     madsim::time::sleep(Duration::from_secs(10)).await;
     assert_eq!(sum.load(Ordering::Relaxed), (1..=30).sum::<usize>() * 2);
 }
+
+type RebalanceEvents = Arc<Mutex<Vec<(&'static str, &'static str, Vec<i32>)>>>;
+
+/// Records the rebalance events of a consumer.
+struct RebalanceRecorder {
+    name: &'static str,
+    events: RebalanceEvents,
+}
+
+impl ClientContext for RebalanceRecorder {}
+
+impl ConsumerContext for RebalanceRecorder {
+    fn pre_rebalance<'a>(&self, rebalance: &Rebalance<'a>) {
+        let (kind, tpl) = match rebalance {
+            Rebalance::Assign(tpl) => ("assign", tpl),
+            Rebalance::Revoke(tpl) => ("revoke", tpl),
+            Rebalance::Error(e) => panic!("rebalance error: {e}"),
+        };
+        let partitions = tpl
+            .elements_for_topic("topic")
+            .iter()
+            .map(|e| e.partition())
+            .collect();
+        self.events
+            .lock()
+            .unwrap()
+            .push((self.name, kind, partitions));
+    }
+}
+
+/// Spawns a consumer of the group on a new node, which polls forever.
+fn spawn_group_consumer(name: &'static str, ip: &str, events: RebalanceEvents) {
+    Handle::current()
+        .create_node()
+        .name(name)
+        .ip(ip.parse().unwrap())
+        .build()
+        .spawn(async move {
+            let consumer = ClientConfig::new()
+                .set("bootstrap.servers", "broker:50051")
+                .set("group.id", "group")
+                .set("enable.auto.commit", "false")
+                .set("auto.offset.reset", "earliest")
+                .create_with_context::<_, BaseConsumer<_>>(RebalanceRecorder { name, events })
+                .await
+                .expect("failed to create consumer");
+            consumer.subscribe(&["topic"]).unwrap();
+            loop {
+                match consumer.poll(None).await {
+                    None => madsim::time::sleep(Duration::from_millis(100)).await,
+                    Some(res) => _ = res.unwrap(),
+                }
+            }
+        });
+}
+
+#[madsim::test]
+async fn rebalance() {
+    let handle = Handle::current();
+    let broker_addr = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    NetSim::current().add_dns_record("broker", broker_addr.ip());
+    handle
+        .create_node()
+        .name("broker")
+        .ip(broker_addr.ip())
+        .build()
+        .spawn(async move {
+            SimBroker::default().serve(broker_addr).await.unwrap();
+        });
+    madsim::time::sleep(Duration::from_secs(1)).await;
+
+    let admin_node = handle
+        .create_node()
+        .name("admin")
+        .ip("10.0.0.2".parse().unwrap())
+        .build();
+    let admin = || async {
+        ClientConfig::new()
+            .set("bootstrap.servers", "broker:50051")
+            .create::<AdminClient<_>>()
+            .await
+            .expect("failed to create admin client")
+    };
+    admin_node
+        .spawn(async move {
+            admin()
+                .await
+                .create_topics(
+                    &[NewTopic::new("topic", 4, TopicReplication::Fixed(1))],
+                    &AdminOptions::new(),
+                )
+                .await
+                .expect("failed to create topic");
+        })
+        .await
+        .unwrap();
+
+    // consumers join the group one by one
+    let events = RebalanceEvents::default();
+    for (name, ip) in [("c1", "10.0.2.1"), ("c2", "10.0.2.2"), ("c3", "10.0.2.3")] {
+        spawn_group_consumer(name, ip, events.clone());
+        madsim::time::sleep(Duration::from_secs(2)).await;
+    }
+    // and reassignment is triggered by the admin
+    admin_node
+        .spawn(async move {
+            admin().await.rebalance_group("group").await.unwrap();
+        })
+        .await
+        .unwrap();
+    madsim::time::sleep(Duration::from_secs(2)).await;
+
+    let events = events.lock().unwrap().clone();
+    let events_of = |name| {
+        (events.iter())
+            .filter(|(n, _, _)| *n == name)
+            .map(|(_, kind, partitions)| (*kind, partitions.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        events_of("c1"),
+        [
+            ("assign", vec![0, 1, 2, 3]),
+            ("revoke", vec![0, 1, 2, 3]),
+            ("assign", vec![0, 1]),
+            ("revoke", vec![0, 1]),
+            ("assign", vec![0, 1]),
+            ("revoke", vec![0, 1]),
+            ("assign", vec![0, 1]),
+        ]
+    );
+    assert_eq!(
+        events_of("c2"),
+        [
+            ("assign", vec![2, 3]),
+            ("revoke", vec![2, 3]),
+            ("assign", vec![2]),
+            ("revoke", vec![2]),
+            ("assign", vec![2]),
+        ]
+    );
+    assert_eq!(
+        events_of("c3"),
+        [
+            ("assign", vec![3]),
+            ("revoke", vec![3]),
+            ("assign", vec![3])
+        ]
+    );
+    // partitions are revoked before they are assigned to another consumer
+    let position = |event: (&str, &str, Vec<i32>)| events.iter().position(|e| *e == event).unwrap();
+    assert!(position(("c1", "revoke", vec![0, 1, 2, 3])) < position(("c2", "assign", vec![2, 3])));
+    assert!(position(("c2", "revoke", vec![2, 3])) < position(("c3", "assign", vec![3])));
+}
+
+#[madsim::test]
+async fn op_latency() {
+    let handle = Handle::current();
+    let broker_addr = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    NetSim::current().add_dns_record("broker", broker_addr.ip());
+    handle
+        .create_node()
+        .name("broker")
+        .ip(broker_addr.ip())
+        .build()
+        .spawn(async move {
+            SimBroker::default()
+                .op_latency(Duration::from_secs(2))
+                .serve(broker_addr)
+                .await
+                .unwrap();
+        });
+    madsim::time::sleep(Duration::from_secs(1)).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.0.2".parse().unwrap())
+        .build()
+        .spawn(async move {
+            let admin = ClientConfig::new()
+                .set("bootstrap.servers", "broker:50051")
+                .create::<AdminClient<_>>()
+                .await
+                .expect("failed to create admin client");
+            admin
+                .create_topics(
+                    &[NewTopic::new("topic", 1, TopicReplication::Fixed(1))],
+                    &AdminOptions::new(),
+                )
+                .await
+                .expect("failed to create topic");
+
+            // the producer gives up before the response
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", "broker:50051")
+                .create::<BaseProducer>()
+                .await
+                .expect("failed to create producer");
+            let record = BaseRecord::to("topic").key("key").payload("value");
+            producer.send(record).expect("failed to send message");
+            let t0 = madsim::time::Instant::now();
+            let err = producer.flush(Duration::from_secs(1)).await.unwrap_err();
+            assert!(
+                matches!(err, KafkaError::Flush(RDKafkaErrorCode::RequestTimedOut)),
+                "{err}"
+            );
+            assert!(t0.elapsed() < Duration::from_millis(1010));
+
+            // but the broker still executes the request
+            let consumer = ClientConfig::new()
+                .set("bootstrap.servers", "broker:50051")
+                .create::<BaseConsumer>()
+                .await
+                .expect("failed to create consumer");
+            let t0 = madsim::time::Instant::now();
+            let watermarks = consumer
+                .fetch_watermarks("topic", 0, None)
+                .await
+                .expect("failed to fetch watermarks");
+            assert!(t0.elapsed() >= Duration::from_secs(2));
+            assert_eq!(watermarks, (0, 1));
+        })
+        .await
+        .unwrap();
+}
@@ -7,6 +7,8 @@ pub use self::sim::*;
 mod sim {
     // simulated API
     pub use madsim::net;
+    #[cfg(feature = "process")]
+    pub use madsim::process;
     #[cfg(feature = "signal")]
     pub use madsim::signal;
     #[cfg(feature = "rt")]
@@ -39,10 +41,15 @@ mod sim {
     // TODO: simulate `fs`
     #[cfg(feature = "fs")]
     pub use tokio::fs;
-    #[cfg(feature = "process")]
-    pub use tokio::process;
+    // `Semaphore` and `Notify` queue their waiters in FIFO order, and the woken tasks are then
+    // scheduled by the seeded madsim executor, so the wake order is reproducible for a given seed.
+    // `watch` is replaced because tokio spreads its waiters over randomly picked lists.
+    // `mpsc` is replaced so that the growth guard of unbounded channels is available.
     #[cfg(feature = "sync")]
-    pub use tokio::sync;
+    pub mod sync {
+        pub use madsim::sync::{mpsc, watch};
+        pub use tokio::sync::*;
+    }
     #[cfg(feature = "rt")]
     pub use tokio::task_local;
     pub use tokio::{io, pin};
@@ -12,7 +12,7 @@ pub fn generate<T: Service>(
     emit_package: bool,
     proto_path: &str,
     compile_well_known_types: bool,
-    _attributes: &Attributes,
+    attributes: &Attributes,
 ) -> TokenStream {
     let service_ident = quote::format_ident!("{}Client", service.name());
     let client_mod = quote::format_ident!("{}_client", naive_snake_case(service.name()));
@@ -21,20 +21,20 @@ pub fn generate<T: Service>(
     let connect = generate_connect(&service_ident);
     // let service_doc = generate_doc_comments(service.comment());
 
-    // let package = if emit_package { service.package() } else { "" };
-    // let path = format!(
-    //     "{}{}{}",
-    //     package,
-    //     if package.is_empty() { "" } else { "." },
-    //     service.identifier()
-    // );
+    let package = if emit_package { service.package() } else { "" };
+    let path = format!(
+        "{}{}{}",
+        package,
+        if package.is_empty() { "" } else { "." },
+        service.identifier()
+    );
 
-    // let mod_attributes = attributes.for_mod(package);
-    // let struct_attributes = attributes.for_struct(&path);
+    let mod_attributes = attributes.for_mod(package);
+    let struct_attributes = attributes.for_struct(&path);
 
     quote! {
         /// Generated client implementations.
-        // #(#mod_attributes)*
+        #(#mod_attributes)*
         pub mod #client_mod {
             #![allow(
                 unused_variables,
@@ -43,29 +43,29 @@ pub fn generate<T: Service>(
                 // will trigger if compression is disabled
                 clippy::let_unit_value,
             )]
-            use tonic::codegen::*;
+            use ::tonic::codegen::*;
 
             // #service_doc
-            // #(#struct_attributes)*
-            #[derive(Debug, Clone)]
+            #(#struct_attributes)*
+            #[derive(::core::fmt::Debug, ::core::clone::Clone)]
             pub struct #service_ident<T, F = IdentityInterceptor> {
-                inner: tonic::client::Grpc<T, F>,
+                inner: ::tonic::client::Grpc<T, F>,
             }
 
             #connect
 
-            impl #service_ident<tonic::transport::Channel> {
-                pub fn new(inner: tonic::transport::Channel) -> Self {
-                    let inner = tonic::client::Grpc::new(inner);
+            impl #service_ident<::tonic::transport::Channel> {
+                pub fn new(inner: ::tonic::transport::Channel) -> Self {
+                    let inner = ::tonic::client::Grpc::new(inner);
                     Self { inner }
                 }
             }
 
-            impl<F: tonic::service::Interceptor> #service_ident<tonic::transport::Channel, F> {
-                pub fn with_interceptor(inner: tonic::transport::Channel, interceptor: F)
-                    -> #service_ident<tonic::transport::Channel, F>
+            impl<F: ::tonic::service::Interceptor> #service_ident<::tonic::transport::Channel, F> {
+                pub fn with_interceptor(inner: ::tonic::transport::Channel, interceptor: F)
+                    -> #service_ident<::tonic::transport::Channel, F>
                 {
-                    let inner = tonic::client::Grpc::with_interceptor(inner, interceptor);
+                    let inner = ::tonic::client::Grpc::with_interceptor(inner, interceptor);
                     Self { inner }
                 }
 
@@ -74,27 +74,27 @@ pub fn generate<T: Service>(
                 /// This requires the server to support it otherwise it might respond with an
                 /// error.
                 #[must_use]
-                pub fn send_compressed(self, encoding: CompressionEncoding) -> Self {
-                    // self.inner = self.inner.send_compressed(encoding);
+                pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+                    self.inner = self.inner.send_compressed(encoding);
                     self
                 }
                 /// Enable decompressing responses.
                 #[must_use]
-                pub fn accept_compressed(self, encoding: CompressionEncoding) -> Self {
-                    // self.inner = self.inner.accept_compressed(encoding);
+                pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+                    self.inner = self.inner.accept_compressed(encoding);
                     self
                 }
                 /// Limits the maximum size of a decoded message.
                 #[must_use]
-                pub fn max_decoding_message_size(self, limit: usize) -> Self {
-                    // self.inner = self.inner.max_decoding_message_size(limit);
+                pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+                    self.inner = self.inner.max_decoding_message_size(limit);
                     self
                 }
 
                 /// Limits the maximum size of an encoded message.
                 #[must_use]
-                pub fn max_encoding_message_size(self, limit: usize) -> Self {
-                    // self.inner = self.inner.max_encoding_message_size(limit);
+                pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+                    self.inner = self.inner.max_encoding_message_size(limit);
                     self
                 }
 
@@ -107,15 +107,15 @@ pub fn generate<T: Service>(
 #[cfg(feature = "transport")]
 fn generate_connect(service_ident: &syn::Ident) -> TokenStream {
     quote! {
-        impl #service_ident<tonic::transport::Channel> {
+        impl #service_ident<::tonic::transport::Channel> {
             /// Attempt to create a new client by connecting to a given endpoint.
-            pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+            pub async fn connect<D>(dst: D) -> ::core::result::Result<Self, ::tonic::transport::Error>
             where
-                D: std::convert::TryInto<tonic::transport::Endpoint>,
-                D::Error: Into<StdError>,
+                D: ::core::convert::TryInto<::tonic::transport::Endpoint>,
+                D::Error: ::core::convert::Into<StdError>,
             {
-                let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
-                Ok(Self::new(conn))
+                let conn = ::tonic::transport::Endpoint::new(dst)?.connect().await?;
+                ::core::result::Result::Ok(Self::new(conn))
             }
         }
     }
@@ -169,20 +169,19 @@ fn generate_unary<T: Method>(
     compile_well_known_types: bool,
     path: String,
 ) -> TokenStream {
-    // let codec_name = syn::parse_str::<syn::Path>(method.codec_path()).unwrap();
     let ident = format_ident!("{}", method.name());
     let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
         pub async fn #ident(
             &mut self,
-            request: impl tonic::IntoRequest<#request>,
-        ) -> Result<tonic::Response<#response>, tonic::Status> {
+            request: impl ::tonic::IntoRequest<#request>,
+        ) -> ::core::result::Result<::tonic::Response<#response>, ::tonic::Status> {
            self.inner.ready().await.map_err(|e| {
-               tonic::Status::new(tonic::Code::Unknown, format!("Service was not ready: {e}"))
+               ::tonic::Status::new(::tonic::Code::Unknown, ::std::format!("Service was not ready: {e}"))
            })?;
-           // let codec = #codec_name::default();
-           let codec = ();
+           let codec = #codec;
            let path = http::uri::PathAndQuery::from_static(#path);
            self.inner.unary(request.into_request(), path, codec).await
         }
@@ -195,21 +194,20 @@ fn generate_server_streaming<T: Method>(
     compile_well_known_types: bool,
     path: String,
 ) -> TokenStream {
-    // let codec_name = syn::parse_str::<syn::Path>(method.codec_path()).unwrap();
     let ident = format_ident!("{}", method.name());
 
     let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
         pub async fn #ident(
             &mut self,
-            request: impl tonic::IntoRequest<#request>,
-        ) -> Result<tonic::Response<tonic::codec::Streaming<#response>>, tonic::Status> {
+            request: impl ::tonic::IntoRequest<#request>,
+        ) -> ::core::result::Result<::tonic::Response<::tonic::codec::Streaming<#response>>, ::tonic::Status> {
             self.inner.ready().await.map_err(|e| {
-                tonic::Status::new(tonic::Code::Unknown, format!("Service was not ready: {e}"))
+                ::tonic::Status::new(::tonic::Code::Unknown, ::std::format!("Service was not ready: {e}"))
             })?;
-            // let codec = #codec_name::default();
-            let codec = ();
+            let codec = #codec;
             let path = http::uri::PathAndQuery::from_static(#path);
             self.inner.server_streaming(request.into_request(), path, codec).await
         }
@@ -222,21 +220,20 @@ fn generate_client_streaming<T: Method>(
     compile_well_known_types: bool,
     path: String,
 ) -> TokenStream {
-    // let codec_name = syn::parse_str::<syn::Path>(method.codec_path()).unwrap();
     let ident = format_ident!("{}", method.name());
 
     let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
         pub async fn #ident(
             &mut self,
-            request: impl tonic::IntoStreamingRequest<Message = #request>
-        ) -> Result<tonic::Response<#response>, tonic::Status> {
+            request: impl ::tonic::IntoStreamingRequest<Message = #request>
+        ) -> ::core::result::Result<::tonic::Response<#response>, ::tonic::Status> {
             self.inner.ready().await.map_err(|e| {
-                tonic::Status::new(tonic::Code::Unknown, format!("Service was not ready: {e}"))
+                ::tonic::Status::new(::tonic::Code::Unknown, ::std::format!("Service was not ready: {e}"))
             })?;
-            // let codec = #codec_name::default();
-            let codec = ();
+            let codec = #codec;
             let path = http::uri::PathAndQuery::from_static(#path);
             self.inner.client_streaming(request.into_streaming_request(), path, codec).await
         }
@@ -249,23 +246,71 @@ fn generate_streaming<T: Method>(
     compile_well_known_types: bool,
     path: String,
 ) -> TokenStream {
-    // let codec_name = syn::parse_str::<syn::Path>(method.codec_path()).unwrap();
     let ident = format_ident!("{}", method.name());
 
     let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
         pub async fn #ident(
             &mut self,
-            request: impl tonic::IntoStreamingRequest<Message = #request>
-        ) -> Result<tonic::Response<tonic::codec::Streaming<#response>>, tonic::Status> {
+            request: impl ::tonic::IntoStreamingRequest<Message = #request>
+        ) -> ::core::result::Result<::tonic::Response<::tonic::codec::Streaming<#response>>, ::tonic::Status> {
             self.inner.ready().await.map_err(|e| {
-                tonic::Status::new(tonic::Code::Unknown, format!("Service was not ready: {e}"))
+                ::tonic::Status::new(::tonic::Code::Unknown, ::std::format!("Service was not ready: {e}"))
             })?;
-            // let codec = #codec_name::default();
-            let codec = ();
+            let codec = #codec;
             let path = http::uri::PathAndQuery::from_static(#path);
             self.inner.streaming(request.into_streaming_request(), path, codec).await
         }
     }
 }
+
+#[cfg(all(test, feature = "prost"))]
+mod tests {
+    use super::*;
+    use crate::test_util::TestService;
+    use quote::ToTokens;
+
+    /// Returns the attributes of the struct `name` in any module.
+    fn struct_attrs(file: &syn::File, name: &str) -> Vec<String> {
+        file.items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Mod(m) => m.content.as_ref(),
+                _ => None,
+            })
+            .flat_map(|(_, items)| items)
+            .filter_map(|item| match item {
+                syn::Item::Struct(s) if s.ident == name => Some(&s.attrs),
+                _ => None,
+            })
+            .flatten()
+            .map(|attr| attr.to_token_stream().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn all_client_attribute() {
+        let builder = crate::configure()
+            .all_client_attribute("#[allow(missing_docs)]")
+            .client_attribute("helloworld.Greeter", "#[allow(unused)]");
+        let services = [
+            TestService::new("helloworld", "Greeter"),
+            TestService::new("echo", "Echo"),
+        ];
+        let tokens: TokenStream = (services.iter())
+            .map(|service| generate(service, true, "super", false, &builder.client_attributes))
+            .collect();
+        let file: syn::File = syn::parse2(tokens).unwrap();
+
+        let allow_missing_docs = quote!(#[allow(missing_docs)]).to_string();
+        let allow_unused = quote!(#[allow(unused)]).to_string();
+        let attrs = struct_attrs(&file, "GreeterClient");
+        assert!(attrs.contains(&allow_missing_docs));
+        assert!(attrs.contains(&allow_unused));
+        let attrs = struct_attrs(&file, "EchoClient");
+        assert!(attrs.contains(&allow_missing_docs));
+        assert!(!attrs.contains(&allow_unused));
+    }
+}
@@ -1,9 +1,16 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 pub mod client;
+#[cfg(feature = "prost")]
+mod method_list;
 pub mod server;
+#[cfg(test)]
+mod test_util;
+
+pub use tonic_build::{Method, Service};
 
-pub use tonic_build::{Attributes, Method, Service};
+use proc_macro2::TokenStream;
+use quote::quote;
 
 /// Prost generator
 #[cfg(feature = "prost")]
@@ -14,6 +21,27 @@ mod prost;
 #[cfg_attr(docsrs, doc(cfg(feature = "prost")))]
 pub use prost::{compile_protos, configure, Builder};
 
+/// Returns the `MessageLen` of a method, which the simulated gRPC client and server check the
+/// message size limits against.
+///
+/// Only prost messages have a known encoded length. Messages of other codecs are never limited.
+fn message_len<T: Method>(
+    method: &T,
+    request: &TokenStream,
+    response: &TokenStream,
+) -> TokenStream {
+    if method.codec_path().ends_with("ProstCodec") {
+        quote! {
+            ::tonic::codegen::EncodedLen::<#request, #response> {
+                request: <#request as ::prost::Message>::encoded_len,
+                response: <#response as ::prost::Message>::encoded_len,
+            }
+        }
+    } else {
+        quote! { () }
+    }
+}
+
 fn naive_snake_case(name: &str) -> String {
     let mut s = String::new();
     let mut it = name.chars().peekable();
@@ -29,3 +57,92 @@ fn naive_snake_case(name: &str) -> String {
 
     s
 }
+
+/// Attributes that will be added to `mod` and `struct` items.
+#[derive(Debug, Default, Clone)]
+pub struct Attributes {
+    /// `mod` attributes.
+    module: Vec<(String, String)>,
+    /// `struct` attributes.
+    structure: Vec<(String, String)>,
+}
+
+impl Attributes {
+    fn for_mod(&self, name: &str) -> Vec<syn::Attribute> {
+        generate_attributes(name, &self.module)
+    }
+
+    fn for_struct(&self, name: &str) -> Vec<syn::Attribute> {
+        generate_attributes(name, &self.structure)
+    }
+
+    /// Add an attribute that will be added to `mod` items matching the given pattern.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use madsim_tonic_build::*;
+    /// let mut attributes = Attributes::default();
+    /// attributes.push_mod("my.proto.package", r#"#[cfg(feature = "server")]"#);
+    /// ```
+    pub fn push_mod(&mut self, pattern: impl Into<String>, attr: impl Into<String>) {
+        self.module.push((pattern.into(), attr.into()));
+    }
+
+    /// Add an attribute that will be added to `struct` items matching the given pattern.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use madsim_tonic_build::*;
+    /// let mut attributes = Attributes::default();
+    /// attributes.push_struct("EchoService", "#[derive(PartialEq)]");
+    /// ```
+    pub fn push_struct(&mut self, pattern: impl Into<String>, attr: impl Into<String>) {
+        self.structure.push((pattern.into(), attr.into()));
+    }
+}
+
+// Generates attributes given a list of (`pattern`, `attribute`) pairs. If `pattern` matches `name`, `attribute` will be included.
+fn generate_attributes<'a>(
+    name: &str,
+    attrs: impl IntoIterator<Item = &'a (String, String)>,
+) -> Vec<syn::Attribute> {
+    attrs
+        .into_iter()
+        .filter(|(matcher, _)| match_name(matcher, name))
+        .flat_map(|(_, attr)| {
+            // attributes cannot be parsed directly, so we pretend they're on a struct
+            syn::parse_str::<syn::DeriveInput>(&format!("{attr}\nstruct fake;"))
+                .unwrap()
+                .attrs
+        })
+        .collect::<Vec<_>>()
+}
+
+// Checks whether a path pattern matches a given path.
+// `.` matches all paths.
+fn match_name(pattern: &str, path: &str) -> bool {
+    if pattern.is_empty() {
+        false
+    } else if pattern == "." || pattern == path {
+        true
+    } else {
+        let pattern_segments = pattern.split('.').collect::<Vec<_>>();
+        let path_segments = path.split('.').collect::<Vec<_>>();
+
+        if &pattern[..1] == "." {
+            // prefix match
+            if pattern_segments.len() > path_segments.len() {
+                false
+            } else {
+                pattern_segments[..] == path_segments[..pattern_segments.len()]
+            }
+        // suffix match
+        } else if pattern_segments.len() > path_segments.len() {
+            false
+        } else {
+            pattern_segments[..] == path_segments[path_segments.len() - pattern_segments.len()..]
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::{Method, Service};
+use crate::naive_snake_case;
+use proc_macro2::TokenStream;
+use quote::quote;
+
+/// Generate the method list of a service.
+///
+/// The list doesn't depend on tonic, so the same module is generated for the simulated and the
+/// original code.
+pub(crate) fn generate<T: Service>(service: &T, emit_package: bool) -> TokenStream {
+    let methods_mod = quote::format_ident!("{}_methods", naive_snake_case(service.name()));
+    let package = if emit_package { service.package() } else { "" };
+    let service_name = format!(
+        "{}{}{}",
+        package,
+        if package.is_empty() { "" } else { "." },
+        service.identifier()
+    );
+    let service_doc = format!(" The methods of the `{service_name}` service.");
+    let methods = service.methods().iter().map(|method| {
+        let name = method.identifier();
+        let path = format!("/{}/{}", service_name, method.identifier());
+        let client_streaming = method.client_streaming();
+        let server_streaming = method.server_streaming();
+        quote! {
+            MethodInfo {
+                name: #name,
+                path: #path,
+                client_streaming: #client_streaming,
+                server_streaming: #server_streaming,
+            }
+        }
+    });
+
+    quote! {
+        #[doc = #service_doc]
+        pub mod #methods_mod {
+            /// The description of a RPC method.
+            #[derive(::core::fmt::Debug, ::core::clone::Clone, ::core::marker::Copy, ::core::cmp::PartialEq, ::core::cmp::Eq)]
+            pub struct MethodInfo {
+                /// The name of the method in the proto file.
+                pub name: &'static str,
+                /// The full path of the method, e.g. `/helloworld.Greeter/SayHello`.
+                pub path: &'static str,
+                /// Whether the requests are a stream.
+                pub client_streaming: bool,
+                /// Whether the responses are a stream.
+                pub server_streaming: bool,
+            }
+
+            /// The full name of the service.
+            pub const SERVICE_NAME: &str = #service_name;
+
+            /// The methods of the service, in the order of the proto file.
+            pub const METHODS: &[MethodInfo] = &[#(#methods),*];
+        }
+    }
+}
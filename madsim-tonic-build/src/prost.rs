@@ -1,9 +1,9 @@
-use super::{client, server, Attributes};
+use super::{client, method_list, server, Attributes};
 use proc_macro2::TokenStream;
-use prost_build::{Config, Method, Service};
+use prost_build::{Config, Method, Module, Service};
 use quote::ToTokens;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     ffi::OsString,
     io,
     path::{Path, PathBuf},
@@ -20,6 +20,7 @@ pub fn configure() -> Builder {
         file_descriptor_set_path: None,
         skip_protoc_run: false,
         out_dir: None,
+        sim_subdir: "sim".to_string(),
         extern_path: Vec::new(),
         field_attributes: Vec::new(),
         message_attributes: Vec::new(),
@@ -39,6 +40,10 @@ pub fn configure() -> Builder {
         disable_comments: HashSet::default(),
         use_arc_self: false,
         generate_default_stubs: false,
+        boxed_stream: Vec::new(),
+        use_native_async: false,
+        format: true,
+        build_method_list: false,
         builder: tonic_build::configure(),
     }
 }
@@ -70,13 +75,14 @@ struct TonicBuildService {
 }
 
 impl TonicBuildService {
-    fn new(prost_service: Service) -> Self {
+    fn new(prost_service: Service, extern_path: &[(String, String)]) -> Self {
         Self {
             methods: prost_service
                 .methods
                 .iter()
                 .map(|prost_method| TonicBuildMethod {
                     prost_method: prost_method.clone(),
+                    extern_path: extern_path.to_vec(),
                 })
                 .collect(),
             prost_service,
@@ -87,6 +93,8 @@ impl TonicBuildService {
 /// Newtype wrapper for prost to add tonic-specific extensions
 struct TonicBuildMethod {
     prost_method: Method,
+    /// Protobuf paths and Rust paths configured by `extern_path`.
+    extern_path: Vec<(String, String)>,
 }
 
 impl crate::Service for TonicBuildService {
@@ -135,7 +143,7 @@ impl crate::Method for TonicBuildMethod {
     /// Though ProstCodec implements Default, it is currently only required that
     /// the function match the Default trait's function spec.
     fn codec_path(&self) -> &str {
-        unreachable!("codec_path is not used in madsim-tonic-build")
+        "tonic::codec::ProstCodec"
     }
 
     fn client_streaming(&self) -> bool {
@@ -156,7 +164,12 @@ impl crate::Method for TonicBuildMethod {
         compile_well_known_types: bool,
     ) -> (TokenStream, TokenStream) {
         let convert_type = |proto_type: &str, rust_type: &str| -> TokenStream {
-            if (is_google_type(proto_type) && !compile_well_known_types)
+            if is_extern_type(&self.extern_path, proto_type) {
+                // prost has resolved the type to the user's Rust path
+                syn::parse_str::<syn::Path>(rust_type)
+                    .unwrap()
+                    .to_token_stream()
+            } else if (is_google_type(proto_type) && !compile_well_known_types)
                 || rust_type.starts_with("::")
                 || NON_PATH_TYPE_ALLOWLIST.contains(&rust_type)
             {
@@ -188,6 +201,14 @@ fn is_google_type(ty: &str) -> bool {
     ty.starts_with(".google.protobuf")
 }
 
+/// Returns whether the fully qualified Protobuf type is in a path declared by `extern_path`.
+fn is_extern_type(extern_path: &[(String, String)], ty: &str) -> bool {
+    extern_path.iter().any(|(proto_path, _)| {
+        (ty.strip_prefix(proto_path.as_str()))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
 struct ServiceGenerator {
     builder: Builder,
     clients: TokenStream,
@@ -206,15 +227,24 @@ impl ServiceGenerator {
 
 impl prost_build::ServiceGenerator for ServiceGenerator {
     fn generate(&mut self, service: prost_build::Service, _buf: &mut String) {
-        let service = TonicBuildService::new(service);
+        let service = TonicBuildService::new(service, &self.builder.extern_path);
 
         if self.builder.build_server {
+            // like `tonic-build`, box the streams of all methods with default stubs
+            let all = [".".to_string()];
+            let boxed_stream = if self.builder.generate_default_stubs {
+                &all[..]
+            } else {
+                &self.builder.boxed_stream[..]
+            };
             let server = server::generate(
                 &service,
                 self.builder.emit_package,
                 &self.builder.proto_path,
                 self.builder.compile_well_known_types,
                 &self.builder.server_attributes,
+                boxed_stream,
+                self.builder.use_native_async,
             );
             self.servers.extend(server);
         }
@@ -239,9 +269,7 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 #clients
             };
 
-            let ast: syn::File = syn::parse2(client_service).expect("not a valid tokenstream");
-            let code = prettyplease::unparse(&ast);
-            buf.push_str(&code);
+            buf.push_str(&render(client_service, self.builder.format));
 
             self.clients = TokenStream::default();
         }
@@ -253,15 +281,86 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 #servers
             };
 
-            let ast: syn::File = syn::parse2(server_service).expect("not a valid tokenstream");
-            let code = prettyplease::unparse(&ast);
-            buf.push_str(&code);
+            buf.push_str(&render(server_service, self.builder.format));
 
             self.servers = TokenStream::default();
         }
     }
 }
 
+/// Generates both the simulated and the original services, gated by `cfg(madsim)`.
+struct CombinedGenerator {
+    sim: Box<dyn prost_build::ServiceGenerator>,
+    origin: Box<dyn prost_build::ServiceGenerator>,
+    format: bool,
+}
+
+impl CombinedGenerator {
+    /// Runs `f` on both generators and appends their outputs to `buf`.
+    fn both(
+        &mut self,
+        buf: &mut String,
+        mut f: impl FnMut(&mut dyn prost_build::ServiceGenerator, &mut String),
+    ) {
+        let (mut sim, mut origin) = (String::new(), String::new());
+        f(&mut *self.sim, &mut sim);
+        f(&mut *self.origin, &mut origin);
+        buf.push_str(&gate(&sim, quote::quote!(madsim), self.format));
+        buf.push_str(&gate(&origin, quote::quote!(not(madsim)), self.format));
+    }
+}
+
+impl prost_build::ServiceGenerator for CombinedGenerator {
+    fn generate(&mut self, service: prost_build::Service, buf: &mut String) {
+        self.both(buf, |generator, buf| {
+            generator.generate(service.clone(), buf)
+        });
+    }
+
+    fn finalize(&mut self, buf: &mut String) {
+        self.both(buf, |generator, buf| generator.finalize(buf));
+    }
+}
+
+/// Appends the method list of each service to the code of another generator.
+struct MethodListGenerator {
+    inner: Box<dyn prost_build::ServiceGenerator>,
+    emit_package: bool,
+    format: bool,
+}
+
+impl prost_build::ServiceGenerator for MethodListGenerator {
+    fn generate(&mut self, service: prost_build::Service, buf: &mut String) {
+        self.inner.generate(service.clone(), buf);
+        let service = TonicBuildService::new(service, &[]);
+        let methods = method_list::generate(&service, self.emit_package);
+        buf.push_str(&render(methods, self.format));
+    }
+
+    fn finalize(&mut self, buf: &mut String) {
+        self.inner.finalize(buf);
+    }
+}
+
+/// Adds `#[cfg(#predicate)]` to each item of the code.
+fn gate(code: &str, predicate: TokenStream, format: bool) -> String {
+    if code.trim().is_empty() {
+        return String::new();
+    }
+    let file = syn::parse_file(code).expect("invalid generated code");
+    let items = file.items.iter();
+    render(quote::quote! { #( #[cfg(#predicate)] #items )* }, format)
+}
+
+/// Renders the generated code, formatted with `prettyplease` if `format` is true.
+fn render(tokens: TokenStream, format: bool) -> String {
+    if !format {
+        return tokens.to_string() + "\n";
+    }
+    let ast: syn::File = syn::parse2(tokens).expect("not a valid tokenstream");
+    prettyplease::unparse(&ast)
+}
+
 /// Service generator builder.
 #[derive(Debug, Clone)]
 pub struct Builder {
@@ -289,8 +388,13 @@ pub struct Builder {
     pub(crate) disable_comments: HashSet<String>,
     pub(crate) use_arc_self: bool,
     pub(crate) generate_default_stubs: bool,
+    pub(crate) boxed_stream: Vec<String>,
+    pub(crate) use_native_async: bool,
+    pub(crate) format: bool,
+    pub(crate) build_method_list: bool,
 
     out_dir: Option<PathBuf>,
+    sim_subdir: String,
 
     // The original builder.
     builder: tonic_build::Builder,
@@ -345,6 +449,23 @@ impl Builder {
         self
     }
 
+    /// Set the subdirectory of the output directory to generate the simulated code to.
+    ///
+    /// Defaults to `sim`, which is where `tonic::include_proto!` looks for the code under
+    /// madsim. Compilations into the same output directory can use distinct subdirectories to
+    /// avoid overwriting each other's files, and include them with
+    /// `include!(concat!(env!("OUT_DIR"), "/<subdir>/<package>.rs"))` instead of
+    /// `tonic::include_proto!`, which only knows the default subdirectory. The file of
+    /// [`include_file`](Self::include_file) includes its siblings, so it works with any name.
+    pub fn sim_subdir(mut self, name: impl AsRef<str>) -> Self {
+        assert!(
+            !name.as_ref().is_empty(),
+            "the sim subdirectory must not be empty"
+        );
+        self.sim_subdir = name.as_ref().to_string();
+        self
+    }
+
     /// Declare externally provided Protobuf package or type.
     ///
     /// Passed directly to `prost_build::Config.extern_path`.
@@ -494,6 +615,16 @@ impl Builder {
         self
     }
 
+    /// Add additional attribute to all service servers.
+    pub fn all_server_attribute<A: AsRef<str>>(self, attribute: A) -> Self {
+        self.server_attribute(".", attribute)
+    }
+
+    /// Add additional attribute to all service clients.
+    pub fn all_client_attribute<A: AsRef<str>>(self, attribute: A) -> Self {
+        self.client_attribute(".", attribute)
+    }
+
     /// Set the path to where tonic will search for the Request/Response proto structs
     /// live relative to the module where you call `include_proto!`.
     ///
@@ -577,6 +708,7 @@ impl Builder {
     /// When this is false all gRPC methods must be explicitly implemented.
     /// When this is true any unimplemented service methods will return 'unimplemented' gRPC error code.
     /// When this is true all streaming server request RPC types explicitly use tonic::codegen::BoxStream type.
+    /// Simulated servers use `BoxStream` in the same way.
     ///
     /// This defaults to `false`.
     pub fn generate_default_stubs(mut self, enable: bool) -> Self {
@@ -585,6 +717,101 @@ impl Builder {
         self
     }
 
+    /// Use `tonic::codegen::BoxStream` as the response stream type of matched server streaming
+    /// methods, instead of an associated type of the server trait.
+    ///
+    /// Matches on the method path, e.g. `.helloworld.Greeter.LotsOfReplies`.
+    /// A service or package path matches all methods in it.
+    ///
+    /// `tonic-build` has no such option, and only uses `BoxStream` for all server streaming
+    /// methods with [`generate_default_stubs`](Self::generate_default_stubs). So the patterns
+    /// must match either all server streaming methods, in which case the original code is
+    /// generated with default stubs, or none of them. Otherwise compiling fails, as the
+    /// simulated and the original traits would differ.
+    pub fn boxed_stream<P: AsRef<str>>(mut self, path: P) -> Self {
+        self.boxed_stream.push(path.as_ref().to_string());
+        self
+    }
+
+    /// Declare the methods of simulated server traits natively instead of with `#[async_trait]`.
+    ///
+    /// The methods return `impl Future + Send`, and can be implemented with `async fn` without
+    /// boxing the futures. This requires Rust 1.75.
+    ///
+    /// This only applies to the simulated code. `tonic-build` 0.12 still generates
+    /// `#[async_trait]` traits, so implementations built both ways should be annotated with
+    /// `#[cfg_attr(not(madsim), tonic::async_trait)]`.
+    ///
+    /// This defaults to `false`.
+    pub fn use_native_async(mut self, enable: bool) -> Self {
+        self.use_native_async = enable;
+        self
+    }
+
+    /// Enable or disable formatting the simulated code with `prettyplease`.
+    ///
+    /// Formatting takes a large part of the build time for big protos. When disabled, the code
+    /// is emitted unformatted on a single line, which compiles the same.
+    ///
+    /// This defaults to `true`.
+    pub fn format(mut self, enable: bool) -> Self {
+        self.format = enable;
+        self
+    }
+
+    /// Enable or disable generating the method list of each service.
+    ///
+    /// A module `<service>_methods` is generated next to the client and server, with the full
+    /// name of the service in `SERVICE_NAME`, and the name, path and streaming flags of each
+    /// method in `METHODS`. The list is the same in the simulated and the original code, so it
+    /// can be used to route requests by method without parsing the file descriptors.
+    ///
+    /// This defaults to `false`.
+    pub fn build_method_list(mut self, enable: bool) -> Self {
+        self.build_method_list = enable;
+        self
+    }
+
+    /// Returns whether [`boxed_stream`](Self::boxed_stream) matches all server streaming
+    /// methods, or `false` if it matches none.
+    ///
+    /// Returns an error if it matches some of them, as `tonic-build` can only box all.
+    fn boxes_all_streams(&self, fds: &prost_types::FileDescriptorSet) -> io::Result<bool> {
+        let mut paths = vec![];
+        for file in &fds.file {
+            let package = if self.emit_package {
+                file.package()
+            } else {
+                ""
+            };
+            for service in &file.service {
+                for method in service.method.iter().filter(|m| m.server_streaming()) {
+                    paths.push(format!(
+                        "{}{}{}.{}",
+                        package,
+                        if package.is_empty() { "" } else { "." },
+                        service.name(),
+                        method.name()
+                    ));
+                }
+            }
+        }
+        let matched = (paths.iter())
+            .filter(|path| {
+                (self.boxed_stream.iter()).any(|pattern| server::match_path(pattern, path))
+            })
+            .count();
+        match matched {
+            0 => Ok(false),
+            n if n == paths.len() => Ok(true),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`boxed_stream` must match all server streaming methods or none, \
+                 as `tonic-build` can only use `BoxStream` for all of them",
+            )),
+        }
+    }
+
     /// Compile the .proto files and execute code generation.
     pub fn compile_protos(
         self,
@@ -602,18 +829,130 @@ impl Builder {
         protos: &[impl AsRef<Path>],
         includes: &[impl AsRef<Path>],
     ) -> io::Result<()> {
-        let builder = std::mem::replace(&mut self.builder, tonic_build::configure());
+        let mut builder = std::mem::replace(&mut self.builder, tonic_build::configure());
+        if !self.boxed_stream.is_empty() {
+            let mut config = Config::new();
+            self.apply_config(&mut config);
+            if self.boxes_all_streams(&config.load_fds(protos, includes)?)? {
+                builder = builder.generate_default_stubs(true);
+            }
+        }
 
         let out_dir = if let Some(out_dir) = self.out_dir.as_ref() {
             out_dir.clone()
         } else {
             PathBuf::from(std::env::var("OUT_DIR").unwrap())
         };
-        // generate the simulated version in an internal `sim` directory
-        let out_dir_sim = out_dir.join("sim");
+        // generate the simulated version in an internal directory
+        let out_dir_sim = out_dir.join(&self.sim_subdir);
         std::fs::create_dir_all(&out_dir_sim)?;
 
         config.out_dir(out_dir_sim);
+        self.apply_config(&mut config);
+
+        if self.emit_rerun_if_changed {
+            for path in protos.iter() {
+                println!("cargo:rerun-if-changed={}", path.as_ref().display())
+            }
+
+            for path in includes.iter() {
+                // Cargo will watch the **entire** directory recursively. If we
+                // could figure out which files are imported by our protos we
+                // could specify only those files instead.
+                println!("cargo:rerun-if-changed={}", path.as_ref().display())
+            }
+        }
+
+        let (build_method_list, emit_package, format) =
+            (self.build_method_list, self.emit_package, self.format);
+        let disable_comments = self.disable_comments.clone();
+        config.service_generator(self.service_generator());
+
+        config.compile_protos(protos, includes)?;
+
+        // generate origin
+        config.out_dir(out_dir);
+        if build_method_list {
+            // `tonic-build` doesn't take a wrapped generator, so run it on the same config
+            config.disable_comments(disable_comments);
+            config.service_generator(Box::new(MethodListGenerator {
+                inner: builder.service_generator(),
+                emit_package,
+                format,
+            }));
+            config.compile_protos(protos, includes)?;
+        } else {
+            builder.compile_protos_with_config(config, protos, includes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compile the .proto files and return the generated code instead of writing it to files.
+    ///
+    /// The code of all packages is combined into one string, each package in nested modules
+    /// of its path. Messages are shared, while the simulated and the original clients and
+    /// servers are gated by `#[cfg(madsim)]` and `#[cfg(not(madsim))]` respectively, so the
+    /// string can be `include!`d in either mode.
+    pub fn compile_to_string(
+        mut self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+    ) -> io::Result<String> {
+        let mut builder = std::mem::replace(&mut self.builder, tonic_build::configure());
+        let (build_method_list, emit_package, format) =
+            (self.build_method_list, self.emit_package, self.format);
+
+        let mut config = Config::new();
+        self.apply_config(&mut config);
+        let fds = config.load_fds(protos, includes)?;
+        if self.boxes_all_streams(&fds)? {
+            builder = builder.generate_default_stubs(true);
+        }
+        let requests = (fds.file.into_iter())
+            .map(|file| (Module::from_protobuf_package_name(file.package()), file))
+            .collect();
+        let mut generator: Box<dyn prost_build::ServiceGenerator> = Box::new(CombinedGenerator {
+            sim: Box::new(ServiceGenerator::new(self)),
+            origin: builder.service_generator(),
+            format,
+        });
+        // a single copy of the method list for both versions
+        if build_method_list {
+            generator = Box::new(MethodListGenerator {
+                inner: generator,
+                emit_package,
+                format,
+            });
+        }
+        config.service_generator(generator);
+        let modules: BTreeMap<_, _> = config.generate(requests)?.into_iter().collect();
+
+        let mut code = String::new();
+        let mut path: Vec<&str> = vec![];
+        for (module, content) in modules.iter() {
+            let parts: Vec<&str> = module.parts().collect();
+            // close the modules not containing this one and open the rest
+            let common = (path.iter().zip(&parts))
+                .take_while(|(a, b)| a == b)
+                .count();
+            for _ in common..path.len() {
+                code.push_str("}\n");
+            }
+            for part in &parts[common..] {
+                code.push_str(&format!("pub mod {part} {{\n"));
+            }
+            code.push_str(content);
+            path = parts;
+        }
+        for _ in 0..path.len() {
+            code.push_str("}\n");
+        }
+        Ok(code)
+    }
+
+    /// Apply the options of prost to `config`.
+    fn apply_config(&self, config: &mut Config) {
         if let Some(path) = self.file_descriptor_set_path.as_ref() {
             config.file_descriptor_set_path(path);
         }
@@ -654,34 +993,406 @@ impl Builder {
         for arg in self.protoc_args.iter() {
             config.protoc_arg(arg);
         }
+    }
 
-        if self.emit_rerun_if_changed {
-            for path in protos.iter() {
-                println!("cargo:rerun-if-changed={}", path.as_ref().display())
-            }
+    /// Turn the builder into a `ServiceGenerator` ready to be passed to `prost-build`s
+    /// `Config::service_generator`.
+    pub fn service_generator(self) -> Box<dyn prost_build::ServiceGenerator> {
+        let (build_method_list, emit_package, format) =
+            (self.build_method_list, self.emit_package, self.format);
+        let generator = Box::new(ServiceGenerator::new(self));
+        if !build_method_list {
+            return generator;
+        }
+        Box::new(MethodListGenerator {
+            inner: generator,
+            emit_package,
+            format,
+        })
+    }
+}
 
-            for path in includes.iter() {
-                // Cargo will watch the **entire** directory recursively. If we
-                // could figure out which files are imported by our protos we
-                // could specify only those files instead.
-                println!("cargo:rerun-if-changed={}", path.as_ref().display())
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Method as _;
+
+    fn test_method(input_proto_type: &str, input_type: &str) -> TonicBuildMethod {
+        TonicBuildMethod {
+            prost_method: Method {
+                name: "now".into(),
+                proto_name: "Now".into(),
+                comments: Default::default(),
+                input_type: input_type.into(),
+                output_type: "Reply".into(),
+                input_proto_type: input_proto_type.into(),
+                output_proto_type: ".clock.Reply".into(),
+                options: Default::default(),
+                client_streaming: false,
+                server_streaming: false,
+            },
+            extern_path: vec![(
+                ".google.protobuf.Timestamp".into(),
+                "my_types::Timestamp".into(),
+            )],
         }
+    }
 
-        config.service_generator(self.service_generator());
+    #[test]
+    fn extern_well_known_type() {
+        // prost resolves the request type through `extern_path`
+        let method = test_method(".google.protobuf.Timestamp", "my_types::Timestamp");
+        for compile_well_known_types in [false, true] {
+            let (request, response) =
+                method.request_response_name("super", compile_well_known_types);
+            assert_eq!(
+                request.to_string(),
+                quote::quote!(my_types::Timestamp).to_string()
+            );
+            assert_eq!(
+                response.to_string(),
+                quote::quote!(super::Reply).to_string()
+            );
+        }
 
-        config.compile_protos(protos, includes)?;
+        // other well-known types are not affected
+        let method = test_method(".google.protobuf.Duration", "::prost_types::Duration");
+        let (request, _) = method.request_response_name("super", false);
+        assert_eq!(
+            request.to_string(),
+            quote::quote!(::prost_types::Duration).to_string()
+        );
+    }
 
-        // generate origin
-        config.out_dir(out_dir);
-        builder.compile_protos_with_config(config, protos, includes)?;
+    #[test]
+    fn extern_type_matches_whole_segments() {
+        let extern_path = [(".google.protobuf.Timestamp".to_string(), String::new())];
+        assert!(is_extern_type(&extern_path, ".google.protobuf.Timestamp"));
+        assert!(is_extern_type(
+            &extern_path,
+            ".google.protobuf.Timestamp.Nested"
+        ));
+        assert!(!is_extern_type(
+            &extern_path,
+            ".google.protobuf.TimestampExt"
+        ));
+        assert!(!is_extern_type(&extern_path, ".google.protobuf.Duration"));
+    }
 
-        Ok(())
+    /// Returns the code generated for a service with a single method.
+    fn generate_code(builder: Builder) -> String {
+        let service = prost_build::Service {
+            name: "Clock".into(),
+            proto_name: "Clock".into(),
+            package: "clock".into(),
+            comments: Default::default(),
+            methods: vec![test_method(".clock.Request", "Request").prost_method],
+            options: Default::default(),
+        };
+        let mut generator = builder.service_generator();
+        let mut buf = String::new();
+        generator.generate(service, &mut buf);
+        generator.finalize(&mut buf);
+        buf
     }
 
-    /// Turn the builder into a `ServiceGenerator` ready to be passed to `prost-build`s
-    /// `Config::service_generator`.
-    pub fn service_generator(self) -> Box<dyn prost_build::ServiceGenerator> {
-        Box::new(ServiceGenerator::new(self))
+    #[test]
+    fn native_async_trait() {
+        let code = generate_code(crate::configure().use_native_async(true));
+        let file = syn::parse_file(&code).unwrap();
+        let trait_ = find_trait(&file.items, "Clock").expect("no server trait");
+        assert!((trait_.attrs.iter()).all(|attr| !attr.path().is_ident("async_trait")));
+        let syn::TraitItem::Fn(method) = &trait_.items[0] else {
+            panic!("not a method");
+        };
+        assert_eq!(method.sig.ident, "now");
+        assert!(method.sig.asyncness.is_none());
+        let output = &method.sig.output;
+        let output = quote::quote!(#output).to_string();
+        assert!(
+            output.contains("impl :: core :: future :: Future"),
+            "{output}"
+        );
+        assert!(output.contains(":: core :: marker :: Send"), "{output}");
+
+        // `#[async_trait]` by default
+        let code = generate_code(crate::configure());
+        let file = syn::parse_file(&code).unwrap();
+        let trait_ = find_trait(&file.items, "Clock").expect("no server trait");
+        assert!((trait_.attrs.iter()).any(|attr| attr.path().is_ident("async_trait")));
+    }
+
+    /// Finds a module in the items.
+    fn find_mod<'a>(items: &'a [syn::Item], name: &str) -> Option<&'a syn::ItemMod> {
+        items.iter().find_map(|item| match item {
+            syn::Item::Mod(m) if m.ident == name => Some(m),
+            _ => None,
+        })
+    }
+
+    /// Finds a trait in the items or the modules in them.
+    fn find_trait<'a>(items: &'a [syn::Item], name: &str) -> Option<&'a syn::ItemTrait> {
+        items.iter().find_map(|item| match item {
+            syn::Item::Trait(t) if t.ident == name => Some(t),
+            syn::Item::Mod(m) => find_trait(&m.content.as_ref()?.1, name),
+            _ => None,
+        })
+    }
+
+    /// Writes the proto files into a new directory of the test.
+    ///
+    /// The directory is unique to the process, so concurrent runs of the tests don't clash.
+    fn proto_dir(test: &str, protos: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("madsim-tonic-build-{test}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, content) in protos {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn boxed_stream_matches_original() {
+        let dir = proto_dir(
+            "boxed-stream",
+            &[(
+                "ticker.proto",
+                r#"
+                syntax = "proto3";
+                package ticker;
+                service Ticker {
+                    rpc Ticks (Request) returns (stream Tick);
+                    rpc Tocks (Request) returns (stream Tick);
+                }
+                message Request {}
+                message Tick {}
+                "#,
+            )],
+        );
+        let proto = dir.join("ticker.proto");
+        let compile = |builder: Builder| builder.compile_to_string(&[&proto], &[&dir]);
+        let streams = |code: &str| code.matches("type TicksStream").count();
+
+        // both versions have associated stream types
+        let code = compile(crate::configure()).unwrap();
+        assert_eq!(streams(&code), 2, "{code}");
+        // the original code can't box the streams of some methods
+        let err = compile(crate::configure().boxed_stream(".ticker.Ticker.Ticks")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // both versions use `BoxStream` for all methods
+        let code = compile(crate::configure().boxed_stream(".ticker")).unwrap();
+        assert_eq!(streams(&code), 0, "{code}");
+        assert!(!code.contains("type TocksStream"), "{code}");
+        let code = compile(crate::configure().generate_default_stubs(true)).unwrap();
+        assert_eq!(streams(&code), 0, "{code}");
+        assert!(!code.contains("type TocksStream"), "{code}");
+    }
+
+    #[test]
+    fn compile_to_string() {
+        let dir = proto_dir(
+            "compile-to-string",
+            &[(
+                "clock.proto",
+                r#"
+                syntax = "proto3";
+                package time.clock;
+                service Clock {
+                    rpc Now (Request) returns (Reply);
+                }
+                message Request {}
+                message Reply { uint64 nanos = 1; }
+                "#,
+            )],
+        );
+        let proto = dir.join("clock.proto");
+        let code = crate::configure()
+            .compile_to_string(&[&proto], &[&dir])
+            .unwrap();
+        let file = syn::parse_file(&code).unwrap();
+        let clock = find_mod(&file.items, "time")
+            .and_then(|time| find_mod(&time.content.as_ref()?.1, "clock"))
+            .expect("no package module");
+        let items = &clock.content.as_ref().unwrap().1;
+        // both versions of the client and server
+        for name in ["clock_client", "clock_server"] {
+            let cfgs: Vec<_> = (items.iter())
+                .filter_map(|item| match item {
+                    syn::Item::Mod(m) if m.ident == name => Some(&m.attrs),
+                    _ => None,
+                })
+                .map(|attrs| {
+                    let cfg = attrs
+                        .iter()
+                        .find(|attr| attr.path().is_ident("cfg"))
+                        .unwrap();
+                    cfg.meta.require_list().unwrap().tokens.to_string()
+                })
+                .collect();
+            assert_eq!(cfgs, ["madsim", "not (madsim)"], "{code}");
+        }
+        assert!(find_trait(items, "Clock").is_some());
+        // and a single copy of messages
+        assert_eq!(code.matches("pub struct Request").count(), 1, "{code}");
+    }
+
+    #[test]
+    fn method_list() {
+        let dir = proto_dir(
+            "method-list",
+            &[(
+                "greeter.proto",
+                r#"
+                syntax = "proto3";
+                package hello;
+                service Greeter {
+                    rpc SayHello (Request) returns (Reply);
+                    rpc LotsOfReplies (Request) returns (stream Reply);
+                    rpc LotsOfGreetings (stream Request) returns (Reply);
+                    rpc BidiHello (stream Request) returns (stream Reply);
+                }
+                message Request {}
+                message Reply {}
+                "#,
+            )],
+        );
+        let proto = dir.join("greeter.proto");
+        let code = crate::configure()
+            .build_method_list(true)
+            .compile_to_string(&[&proto], &[&dir])
+            .unwrap();
+        let file = syn::parse_file(&code).unwrap();
+        let hello = find_mod(&file.items, "hello").expect("no package module");
+        let methods = find_mod(&hello.content.as_ref().unwrap().1, "greeter_methods")
+            .expect("no method list");
+        // a single copy for both versions
+        assert_eq!(code.matches("pub mod greeter_methods").count(), 1, "{code}");
+        assert!((methods.attrs.iter()).all(|attr| !attr.path().is_ident("cfg")));
+
+        let mut service_name = None;
+        let mut list = vec![];
+        for item in &methods.content.as_ref().unwrap().1 {
+            let syn::Item::Const(item) = item else {
+                continue;
+            };
+            let lit_str = |expr: &syn::Expr| match expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) => s.value(),
+                _ => panic!("not a string"),
+            };
+            let lit_bool = |expr: &syn::Expr| match expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Bool(b),
+                    ..
+                }) => b.value,
+                _ => panic!("not a bool"),
+            };
+            match item.ident.to_string().as_str() {
+                "SERVICE_NAME" => service_name = Some(lit_str(&item.expr)),
+                "METHODS" => {
+                    let syn::Expr::Reference(r) = &*item.expr else {
+                        panic!("not a reference");
+                    };
+                    let syn::Expr::Array(array) = &*r.expr else {
+                        panic!("not an array");
+                    };
+                    for elem in &array.elems {
+                        let syn::Expr::Struct(info) = elem else {
+                            panic!("not a struct");
+                        };
+                        let field = |name: &str| {
+                            let field = info.fields.iter().find(
+                                |f| matches!(&f.member, syn::Member::Named(ident) if ident == name),
+                            );
+                            &field.unwrap().expr
+                        };
+                        list.push((
+                            lit_str(field("name")),
+                            lit_str(field("path")),
+                            lit_bool(field("client_streaming")),
+                            lit_bool(field("server_streaming")),
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(service_name.as_deref(), Some("hello.Greeter"));
+        let expected = [
+            ("SayHello", false, false),
+            ("LotsOfReplies", false, true),
+            ("LotsOfGreetings", true, false),
+            ("BidiHello", true, true),
+        ]
+        .map(|(name, client, server)| {
+            let path = format!("/hello.Greeter/{name}");
+            (name.to_string(), path, client, server)
+        });
+        assert_eq!(list, expected);
+
+        // not generated by default
+        let code = crate::configure()
+            .compile_to_string(&[&proto], &[&dir])
+            .unwrap();
+        assert!(!code.contains("greeter_methods"), "{code}");
+    }
+
+    #[test]
+    fn sim_subdir() {
+        // two groups of protos with the same package
+        let proto = |service: &str| {
+            format!(
+                r#"
+                syntax = "proto3";
+                package common;
+                service {service} {{
+                    rpc Call (Request) returns (Request);
+                }}
+                message Request {{}}
+                "#
+            )
+        };
+        let (alpha, beta) = (proto("Alpha"), proto("Beta"));
+        let dir = proto_dir("sim-subdir", &[("a.proto", &alpha), ("b.proto", &beta)]);
+        for group in ["a", "b"] {
+            let proto = dir.join(format!("{group}.proto"));
+            crate::configure()
+                .out_dir(&dir)
+                .sim_subdir(format!("sim_{group}"))
+                .include_file("mod.rs")
+                .emit_rerun_if_changed(false)
+                .compile_protos(&[&proto], &[&dir])
+                .unwrap();
+        }
+        // both simulated versions coexist
+        for (group, service) in [("a", "alpha"), ("b", "beta")] {
+            let subdir = dir.join(format!("sim_{group}"));
+            let code = std::fs::read_to_string(subdir.join("common.rs")).unwrap();
+            assert!(
+                code.contains(&format!("pub mod {service}_client")),
+                "{code}"
+            );
+            let include = std::fs::read_to_string(subdir.join("mod.rs")).unwrap();
+            assert!(include.contains("common.rs"), "{include}");
+            assert!(!include.contains("/sim/"), "{include}");
+        }
+        assert!(!dir.join("sim").exists());
+    }
+
+    #[test]
+    fn disable_format() {
+        let formatted = generate_code(crate::configure());
+        let code = generate_code(crate::configure().format(false));
+        // `prettyplease` would break the client and server modules into many lines
+        assert_eq!(code.lines().count(), 2, "{code}");
+        assert!(formatted.lines().count() > 2);
+        // the unformatted code parses to the same code
+        let file = syn::parse_file(&code).unwrap();
+        let squash = |code: &str| code.split_whitespace().collect::<String>();
+        assert_eq!(squash(&prettyplease::unparse(&file)), squash(&formatted));
     }
 }
@@ -13,21 +13,26 @@ pub fn generate<T: Service>(
     emit_package: bool,
     proto_path: &str,
     compile_well_known_types: bool,
-    _attributes: &Attributes,
+    attributes: &Attributes,
+    boxed_stream: &[String],
+    native_async: bool,
 ) -> TokenStream {
     let methods = generate_methods(service, proto_path, compile_well_known_types);
 
     let server_service = quote::format_ident!("{}Server", service.name());
     let server_trait = quote::format_ident!("{}", service.name());
     let server_mod = quote::format_ident!("{}_server", naive_snake_case(service.name()));
+    let package = if emit_package { service.package() } else { "" };
     let generated_trait = generate_trait(
         service,
+        package,
         proto_path,
         compile_well_known_types,
         server_trait.clone(),
+        boxed_stream,
+        native_async,
     );
     // let service_doc = generate_doc_comments(service.comment());
-    let package = if emit_package { service.package() } else { "" };
     // Transport based implementations
     let path = format!(
         "{}{}{}",
@@ -36,55 +41,45 @@ pub fn generate<T: Service>(
         service.identifier()
     );
     let transport = generate_transport(&server_service, &server_trait, &path);
-    // let mod_attributes = attributes.for_mod(package);
-    // let struct_attributes = attributes.for_struct(&path);
-
-    let compression_enabled = cfg!(feature = "compression");
-
-    // let compression_config_ty = if compression_enabled {
-    //     quote! { EnabledCompressionEncodings }
-    // } else {
-    //     quote! { () }
-    // };
-
-    let configure_compression_methods = if compression_enabled {
-        quote! {
-            /// Enable decompressing requests with the given encoding.
-            #[must_use]
-            pub fn accept_compressed(self, _encoding: CompressionEncoding) -> Self {
-                // self.accept_compression_encodings.enable(encoding);
-                self
-            }
-            /// Compress responses with the given encoding, if the client supports it.
-            #[must_use]
-            pub fn send_compressed(self, _encoding: CompressionEncoding) -> Self {
-                // self.send_compression_encodings.enable(encoding);
-                self
-            }
+    let mod_attributes = attributes.for_mod(package);
+    let struct_attributes = attributes.for_struct(&path);
+
+    let configure_compression_methods = quote! {
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.push(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.push(encoding);
+            self
         }
-    } else {
-        quote! {}
     };
 
     let configure_max_message_size_methods = quote! {
         /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
         #[must_use]
-        pub fn max_decoding_message_size(self, _limit: usize) -> Self {
-            // self.max_decoding_message_size = Some(limit);
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = ::core::option::Option::Some(limit);
             self
         }
 
         /// Limits the maximum size of an encoded message.
         #[must_use]
-        pub fn max_encoding_message_size(self, _limit: usize) -> Self {
-            // self.max_encoding_message_size = Some(limit);
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = ::core::option::Option::Some(limit);
             self
         }
     };
 
     quote! {
         /// Generated server implementations.
-        // #(#mod_attributes)*
+        #(#mod_attributes)*
         pub mod #server_mod {
             #![allow(
                 unused_imports,
@@ -95,16 +90,22 @@ pub fn generate<T: Service>(
                 // will trigger if compression is disabled
                 clippy::let_unit_value,
             )]
-            use tonic::codegen::{http::uri::PathAndQuery, futures::{stream::{self, Stream, StreamExt}, future::FutureExt}, *};
+            use ::tonic::codegen::{http::uri::PathAndQuery, futures::{stream::{self, Stream, StreamExt}, future::FutureExt}, *};
+            // `#[async_trait]` expands to an unqualified `Box`
+            use ::std::boxed::Box;
 
             #generated_trait
 
             // #service_doc
-            // #(#struct_attributes)*
-            #[derive(Debug)]
+            #(#struct_attributes)*
+            #[derive(::core::fmt::Debug)]
             pub struct #server_service<T: #server_trait, F> {
                 inner: Arc<T>,
                 interceptor: F,
+                accept_compression_encodings: ::std::vec::Vec<CompressionEncoding>,
+                send_compression_encodings: ::std::vec::Vec<CompressionEncoding>,
+                max_decoding_message_size: ::core::option::Option<usize>,
+                max_encoding_message_size: ::core::option::Option<usize>,
             }
 
             impl<T: #server_trait> #server_service<T, IdentityInterceptor> {
@@ -115,19 +116,27 @@ pub fn generate<T: Service>(
                 pub fn from_arc(inner: Arc<T>) -> Self {
                     Self {
                         inner,
-                        interceptor: Ok,
+                        interceptor: ::core::result::Result::Ok,
+                        accept_compression_encodings: ::core::default::Default::default(),
+                        send_compression_encodings: ::core::default::Default::default(),
+                        max_decoding_message_size: ::core::option::Option::Some(::tonic::codegen::DEFAULT_MAX_DECODING_MESSAGE_SIZE),
+                        max_encoding_message_size: ::core::option::Option::None,
                     }
                 }
             }
 
             impl<T: #server_trait, F> #server_service<T, F>
             where
-                F: tonic::service::Interceptor,
+                F: ::tonic::service::Interceptor,
             {
                 pub fn with_interceptor(inner: T, interceptor: F) -> Self {
                     Self {
                         inner: Arc::new(inner),
                         interceptor,
+                        accept_compression_encodings: ::core::default::Default::default(),
+                        send_compression_encodings: ::core::default::Default::default(),
+                        max_decoding_message_size: ::core::option::Option::Some(::tonic::codegen::DEFAULT_MAX_DECODING_MESSAGE_SIZE),
+                        max_encoding_message_size: ::core::option::Option::None,
                     }
                 }
 
@@ -135,43 +144,54 @@ pub fn generate<T: Service>(
                 #configure_max_message_size_methods
             }
 
-            impl<T, F> tonic::codegen::Service<(PathAndQuery, tonic::Request<BoxMessageStream>)> for #server_service<T, F>
+            impl<T, F> ::tonic::codegen::Service<(PathAndQuery, ::tonic::Request<BoxMessageStream>)> for #server_service<T, F>
             where
                 T: #server_trait,
-                F: tonic::service::Interceptor,
+                F: ::tonic::service::Interceptor,
             {
-                type Response = tonic::Response<BoxMessageStream>;
-                type Error = tonic::Status;
+                type Response = ::tonic::Response<BoxMessageStream>;
+                type Error = ::tonic::Status;
                 type Future = BoxFuture<Self::Response, Self::Error>;
 
-                fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-                    Poll::Ready(Ok(()))
+                fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<::core::result::Result<(), Self::Error>> {
+                    Poll::Ready(::core::result::Result::Ok(()))
                 }
 
-                fn call(&mut self, (path, request): (PathAndQuery, tonic::Request<BoxMessageStream>)) -> Self::Future {
-                    let inner = self.inner.clone();
+                fn call(&mut self, (path, request): (PathAndQuery, ::tonic::Request<BoxMessageStream>)) -> Self::Future {
+                    let inner = ::core::clone::Clone::clone(&self.inner);
                     let mut request = match request.intercept(&mut self.interceptor) {
-                        Ok(r) => r,
-                        Err(e) => return Box::pin(async move { Err(e) }),
+                        ::core::result::Result::Ok(r) => r,
+                        ::core::result::Result::Err(e) => return ::std::boxed::Box::pin(async move { ::core::result::Result::Err(e) }),
                     };
+                    if let ::core::result::Result::Err(e) = request.check_encoding(&self.accept_compression_encodings) {
+                        return ::std::boxed::Box::pin(async move { ::core::result::Result::Err(e) });
+                    }
+                    let send_encoding = request.response_encoding(&self.send_compression_encodings);
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
 
-                    match path.path() {
+                    let future: Self::Future = match path.path() {
                         #methods
 
-                        _ => Box::pin(async move { Err(tonic::Status::invalid_argument(format!("no path: {path}"))) }),
-                    }
+                        _ => ::std::boxed::Box::pin(async move { ::core::result::Result::Err(::tonic::Status::invalid_argument(::std::format!("no path: {path}"))) }),
+                    };
+                    with_response_encoding(future, send_encoding)
                 }
             }
 
-            impl<T, F> Clone for #server_service<T, F>
+            impl<T, F> ::core::clone::Clone for #server_service<T, F>
             where
                 T: #server_trait,
-                F: Clone,
+                F: ::core::clone::Clone,
             {
                 fn clone(&self) -> Self {
                     Self {
-                        inner: self.inner.clone(),
-                        interceptor: self.interceptor.clone(),
+                        inner: ::core::clone::Clone::clone(&self.inner),
+                        interceptor: ::core::clone::Clone::clone(&self.interceptor),
+                        accept_compression_encodings: ::core::clone::Clone::clone(&self.accept_compression_encodings),
+                        send_compression_encodings: ::core::clone::Clone::clone(&self.send_compression_encodings),
+                        max_decoding_message_size: self.max_decoding_message_size,
+                        max_encoding_message_size: self.max_encoding_message_size,
                     }
                 }
             }
@@ -183,20 +203,36 @@ pub fn generate<T: Service>(
 
 fn generate_trait<T: Service>(
     service: &T,
+    package: &str,
     proto_path: &str,
     compile_well_known_types: bool,
     server_trait: Ident,
+    boxed_stream: &[String],
+    native_async: bool,
 ) -> TokenStream {
-    let methods = generate_trait_methods(service, proto_path, compile_well_known_types);
+    let methods = generate_trait_methods(
+        service,
+        package,
+        proto_path,
+        compile_well_known_types,
+        boxed_stream,
+        native_async,
+    );
     // let trait_doc = generate_doc_comment(&format!(
     //     "Generated trait containing gRPC methods that should be implemented for use with {}Server.",
     //     service.name()
     // ));
 
+    let async_trait = if native_async {
+        quote! {}
+    } else {
+        quote! { #[async_trait] }
+    };
+
     quote! {
         // #trait_doc
-        #[async_trait]
-        pub trait #server_trait : Send + Sync + 'static {
+        #async_trait
+        pub trait #server_trait : ::core::marker::Send + ::core::marker::Sync + 'static {
             #methods
         }
     }
@@ -204,8 +240,11 @@ fn generate_trait<T: Service>(
 
 fn generate_trait_methods<T: Service>(
     service: &T,
+    package: &str,
     proto_path: &str,
     compile_well_known_types: bool,
+    boxed_stream: &[String],
+    native_async: bool,
 ) -> TokenStream {
     let mut stream = TokenStream::new();
 
@@ -215,54 +254,58 @@ fn generate_trait_methods<T: Service>(
         let (req_message, res_message) =
             method.request_response_name(proto_path, compile_well_known_types);
 
+        let method_path = format!(
+            "{}{}{}.{}",
+            package,
+            if package.is_empty() { "" } else { "." },
+            service.identifier(),
+            method.identifier()
+        );
+        let boxed = boxed_stream
+            .iter()
+            .any(|pattern| match_path(pattern, &method_path));
+
         // let method_doc = generate_doc_comments(method.comment());
 
-        let method = match (method.client_streaming(), method.server_streaming()) {
-            (false, false) => {
-                quote! {
-                    // #method_doc
-                    async fn #name(&self, request: tonic::Request<#req_message>)
-                        -> Result<tonic::Response<#res_message>, tonic::Status>;
-                }
-            }
-            (true, false) => {
-                quote! {
-                    // #method_doc
-                    async fn #name(&self, request: tonic::Request<tonic::Streaming<#req_message>>)
-                        -> Result<tonic::Response<#res_message>, tonic::Status>;
-                }
-            }
-            (false, true) => {
+        let request = if method.client_streaming() {
+            quote! { ::tonic::Streaming<#req_message> }
+        } else {
+            quote! { #req_message }
+        };
+        let (response, stream_type) = match method.server_streaming() {
+            false => (quote! { #res_message }, quote! {}),
+            true if boxed => (quote! { BoxStream<#res_message> }, quote! {}),
+            true => {
                 let stream = quote::format_ident!("{}Stream", method.identifier());
                 // let stream_doc = generate_doc_comment(&format!(
                 //     "Server streaming response type for the {} method.",
                 //     method.identifier()
                 // ));
-
-                quote! {
+                let stream_type = quote! {
                     // #stream_doc
-                    type #stream: Stream<Item = Result<#res_message, tonic::Status>> + Send + 'static;
-
-                    // #method_doc
-                    async fn #name(&self, request: tonic::Request<#req_message>)
-                        -> Result<tonic::Response<Self::#stream>, tonic::Status>;
-                }
+                    type #stream: Stream<Item = ::core::result::Result<#res_message, ::tonic::Status>> + ::core::marker::Send + 'static;
+                };
+                (quote! { Self::#stream }, stream_type)
             }
-            (true, true) => {
-                let stream = quote::format_ident!("{}Stream", method.identifier());
-                // let stream_doc = generate_doc_comment(&format!(
-                //     "Server streaming response type for the {} method.",
-                //     method.identifier()
-                // ));
+        };
+        let output =
+            quote! { ::core::result::Result<::tonic::Response<#response>, ::tonic::Status> };
 
-                quote! {
-                    // #stream_doc
-                    type #stream: Stream<Item = Result<#res_message, tonic::Status>> + Send + 'static;
+        let method = if native_async {
+            // the server spawns the futures, so they must be `Send`, which `async fn` can't declare
+            quote! {
+                #stream_type
 
-                    // #method_doc
-                    async fn #name(&self, request: tonic::Request<tonic::Streaming<#req_message>>)
-                        -> Result<tonic::Response<Self::#stream>, tonic::Status>;
-                }
+                // #method_doc
+                fn #name(&self, request: ::tonic::Request<#request>)
+                    -> impl ::core::future::Future<Output = #output> + ::core::marker::Send;
+            }
+        } else {
+            quote! {
+                #stream_type
+
+                // #method_doc
+                async fn #name(&self, request: ::tonic::Request<#request>) -> #output;
             }
         };
 
@@ -272,6 +315,18 @@ fn generate_trait_methods<T: Service>(
     stream
 }
 
+/// Returns whether the method path `package.Service.Method` matches the pattern.
+///
+/// The pattern matches the path itself or any of its prefix segments,
+/// e.g. `.helloworld.Greeter` matches all methods of the service.
+pub(crate) fn match_path(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_start_matches('.');
+    match path.strip_prefix(pattern) {
+        Some(rest) => pattern.is_empty() || rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
 #[cfg(feature = "transport")]
 fn generate_transport(
     server_service: &syn::Ident,
@@ -281,10 +336,10 @@ fn generate_transport(
     let service_name = syn::LitStr::new(service_name, proc_macro2::Span::call_site());
 
     quote! {
-        impl<T, F> tonic::transport::NamedService for #server_service<T, F>
+        impl<T, F> ::tonic::transport::NamedService for #server_service<T, F>
         where
             T: #server_trait,
-            F: tonic::service::Interceptor,
+            F: ::tonic::service::Interceptor,
         {
             const NAME: &'static str = #service_name;
         }
@@ -374,17 +429,27 @@ fn generate_unary<T: Method>(
     method_ident: Ident,
     _server_trait: Ident,
 ) -> TokenStream {
-    let (request, _) = method.request_response_name(proto_path, compile_well_known_types);
+    let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
-        let inner = self.inner.clone();
-        Box::pin(async move {
+        let inner = ::core::clone::Clone::clone(&self.inner);
+        ::std::boxed::Box::pin(async move {
             let request = request.map(|mut stream| {
                 let first = stream.next().now_or_never().unwrap().unwrap();
                 *first.unwrap().downcast::<#request>().unwrap()
             });
-            let response: tonic::Response<_> = (*inner).#method_ident(request).await?;
-            Ok(response.map(|msg| stream::once(async move { Ok(Box::new(msg) as BoxMessage) }).boxed()))
+            let codec = #codec;
+            if let ::core::option::Option::Some(len) = ::tonic::codegen::MessageLen::<#request, #response>::request_len(&codec, request.get_ref()) {
+                ::tonic::codegen::check_decoded_len(len, max_decoding_message_size)?;
+            }
+            let response: ::tonic::Response<_> = (*inner).#method_ident(request).await?;
+            let len = ::tonic::codegen::MessageLen::<#request, #response>::response_len(&codec, response.get_ref());
+            if let ::core::option::Option::Some(len) = len {
+                ::tonic::codegen::check_encoded_len(len, max_encoding_message_size)?;
+            }
+            let len = len.unwrap_or_default();
+            ::core::result::Result::Ok(response.map(|msg| stream::once(async move { ::core::result::Result::Ok(::tonic::codegen::encoded(len, msg)) }).boxed()))
         })
     }
 }
@@ -396,17 +461,28 @@ fn generate_server_streaming<T: Method>(
     method_ident: Ident,
     _server_trait: Ident,
 ) -> TokenStream {
-    let (request, _) = method.request_response_name(proto_path, compile_well_known_types);
+    let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
-        let inner = self.inner.clone();
-        Box::pin(async move {
+        let inner = ::core::clone::Clone::clone(&self.inner);
+        ::std::boxed::Box::pin(async move {
             let request = request.map(|mut stream| {
                 let first = stream.next().now_or_never().unwrap().unwrap();
                 *first.unwrap().downcast::<#request>().unwrap()
             });
-            let response: tonic::Response<_> = (*inner).#method_ident(request).await?;
-            Ok(response.map(|stream| stream.map(|res| res.map(|msg| Box::new(msg) as BoxMessage)).boxed()))
+            let codec = #codec;
+            if let ::core::option::Option::Some(len) = ::tonic::codegen::MessageLen::<#request, #response>::request_len(&codec, request.get_ref()) {
+                ::tonic::codegen::check_decoded_len(len, max_decoding_message_size)?;
+            }
+            let response: ::tonic::Response<_> = (*inner).#method_ident(request).await?;
+            ::core::result::Result::Ok(response.map(|stream| stream.map(move |res| res.and_then(|msg| {
+                let len = ::tonic::codegen::MessageLen::<#request, #response>::response_len(&codec, &msg);
+                if let ::core::option::Option::Some(len) = len {
+                    ::tonic::codegen::check_encoded_len(len, max_encoding_message_size)?;
+                }
+                ::core::result::Result::Ok(::tonic::codegen::encoded(len.unwrap_or_default(), msg))
+            })).boxed()))
         })
     }
 }
@@ -418,18 +494,32 @@ fn generate_client_streaming<T: Method>(
     method_ident: Ident,
     _server_trait: Ident,
 ) -> TokenStream {
-    let (request, _) = method.request_response_name(proto_path, compile_well_known_types);
+    let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
-        let inner = self.inner.clone();
-        Box::pin(async move {
+        let inner = ::core::clone::Clone::clone(&self.inner);
+        ::std::boxed::Box::pin(async move {
+            let codec = #codec;
+            let request_codec = ::core::clone::Clone::clone(&codec);
             let request = request.map(|stream| {
-                tonic::Streaming::from_stream(
-                    stream.map(|res| res.map(|msg| *msg.downcast::<#request>().unwrap())).boxed()
+                ::tonic::Streaming::from_stream(
+                    stream.map(move |res| res.and_then(|msg| {
+                        let msg = *msg.downcast::<#request>().unwrap();
+                        if let ::core::option::Option::Some(len) = ::tonic::codegen::MessageLen::<#request, #response>::request_len(&request_codec, &msg) {
+                            ::tonic::codegen::check_decoded_len(len, max_decoding_message_size)?;
+                        }
+                        ::core::result::Result::Ok(msg)
+                    })).boxed()
                 )
             });
-            let response: tonic::Response<_> = (*inner).#method_ident(request).await?;
-            Ok(response.map(|msg| stream::once(async move { Ok(Box::new(msg) as BoxMessage) }).boxed()))
+            let response: ::tonic::Response<_> = (*inner).#method_ident(request).await?;
+            let len = ::tonic::codegen::MessageLen::<#request, #response>::response_len(&codec, response.get_ref());
+            if let ::core::option::Option::Some(len) = len {
+                ::tonic::codegen::check_encoded_len(len, max_encoding_message_size)?;
+            }
+            let len = len.unwrap_or_default();
+            ::core::result::Result::Ok(response.map(|msg| stream::once(async move { ::core::result::Result::Ok(::tonic::codegen::encoded(len, msg)) }).boxed()))
         })
     }
 }
@@ -441,18 +531,70 @@ fn generate_streaming<T: Method>(
     method_ident: Ident,
     _server_trait: Ident,
 ) -> TokenStream {
-    let (request, _) = method.request_response_name(proto_path, compile_well_known_types);
+    let (request, response) = method.request_response_name(proto_path, compile_well_known_types);
+    let codec = crate::message_len(method, &request, &response);
 
     quote! {
-        let inner = self.inner.clone();
-        Box::pin(async move {
+        let inner = ::core::clone::Clone::clone(&self.inner);
+        ::std::boxed::Box::pin(async move {
+            let codec = #codec;
+            let request_codec = ::core::clone::Clone::clone(&codec);
             let request = request.map(|stream| {
-                tonic::Streaming::from_stream(
-                    stream.map(|res| res.map(|msg| *msg.downcast::<#request>().unwrap())).boxed()
+                ::tonic::Streaming::from_stream(
+                    stream.map(move |res| res.and_then(|msg| {
+                        let msg = *msg.downcast::<#request>().unwrap();
+                        if let ::core::option::Option::Some(len) = ::tonic::codegen::MessageLen::<#request, #response>::request_len(&request_codec, &msg) {
+                            ::tonic::codegen::check_decoded_len(len, max_decoding_message_size)?;
+                        }
+                        ::core::result::Result::Ok(msg)
+                    })).boxed()
                 )
             });
-            let response: tonic::Response<_> = (*inner).#method_ident(request).await?;
-            Ok(response.map(|stream| stream.map(|res| res.map(|msg| Box::new(msg) as BoxMessage)).boxed()))
+            let response: ::tonic::Response<_> = (*inner).#method_ident(request).await?;
+            ::core::result::Result::Ok(response.map(|stream| stream.map(move |res| res.and_then(|msg| {
+                let len = ::tonic::codegen::MessageLen::<#request, #response>::response_len(&codec, &msg);
+                if let ::core::option::Option::Some(len) = len {
+                    ::tonic::codegen::check_encoded_len(len, max_encoding_message_size)?;
+                }
+                ::core::result::Result::Ok(::tonic::codegen::encoded(len.unwrap_or_default(), msg))
+            })).boxed()))
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestService;
+
+    #[test]
+    fn boxed_stream() {
+        let service = TestService::new("helloworld", "Greeter")
+            .method("lots_of_replies", "LotsOfReplies")
+            .method("more_replies", "MoreReplies");
+        let tokens = generate(
+            &service,
+            true,
+            "super",
+            false,
+            &Attributes::default(),
+            &[".helloworld.Greeter.LotsOfReplies".into()],
+            false,
+        );
+        let code = prettyplease::unparse(&syn::parse2(tokens).unwrap());
+        assert!(!code.contains("type LotsOfRepliesStream"));
+        assert!(code.contains("tonic::Response<BoxStream<super::HelloReply>>"));
+        assert!(code.contains("type MoreRepliesStream"));
+        assert!(code.contains("tonic::Response<Self::MoreRepliesStream>"));
+    }
+
+    #[test]
+    fn match_method_path() {
+        let path = "helloworld.Greeter.LotsOfReplies";
+        assert!(match_path(".helloworld.Greeter.LotsOfReplies", path));
+        assert!(match_path("helloworld.Greeter", path));
+        assert!(match_path(".", path));
+        assert!(!match_path(".helloworld.Greet", path));
+        assert!(!match_path(".helloworld.Greeter.LotsOfReplie", path));
+    }
+}
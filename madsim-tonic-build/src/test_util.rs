@@ -0,0 +1,82 @@
+//! A service and methods for testing the code generation.
+
+use crate::{Method, Service};
+use proc_macro2::TokenStream;
+use quote::quote;
+
+/// A service of `HelloRequest`s and `HelloReply`s.
+pub(crate) struct TestService {
+    pub package: &'static str,
+    pub name: &'static str,
+    pub methods: Vec<TestMethod>,
+}
+
+/// A server streaming method.
+pub(crate) struct TestMethod {
+    pub name: &'static str,
+    pub identifier: &'static str,
+}
+
+impl TestService {
+    /// Creates a service without methods.
+    pub fn new(package: &'static str, name: &'static str) -> Self {
+        TestService {
+            package,
+            name,
+            methods: vec![],
+        }
+    }
+
+    /// Adds a method.
+    pub fn method(mut self, name: &'static str, identifier: &'static str) -> Self {
+        self.methods.push(TestMethod { name, identifier });
+        self
+    }
+}
+
+impl Service for TestService {
+    type Comment = String;
+    type Method = TestMethod;
+
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn package(&self) -> &str {
+        self.package
+    }
+    fn identifier(&self) -> &str {
+        self.name
+    }
+    fn methods(&self) -> &[Self::Method] {
+        &self.methods
+    }
+    fn comment(&self) -> &[Self::Comment] {
+        &[]
+    }
+}
+
+impl Method for TestMethod {
+    type Comment = String;
+
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn identifier(&self) -> &str {
+        self.identifier
+    }
+    fn codec_path(&self) -> &str {
+        "tonic::codec::ProstCodec"
+    }
+    fn client_streaming(&self) -> bool {
+        false
+    }
+    fn server_streaming(&self) -> bool {
+        true
+    }
+    fn comment(&self) -> &[Self::Comment] {
+        &[]
+    }
+    fn request_response_name(&self, _: &str, _: bool) -> (TokenStream, TokenStream) {
+        (quote!(super::HelloRequest), quote!(super::HelloReply))
+    }
+}
@@ -1,6 +1,7 @@
 //! Generic client implementation.
 
 use std::future::Future;
+use std::sync::Arc;
 use std::time::Duration;
 
 use futures_util::{pin_mut, Stream, StreamExt};
@@ -8,9 +9,14 @@ use tonic::codegen::http::uri::PathAndQuery;
 use tracing::{debug, instrument};
 
 use crate::{
-    codegen::{BoxMessage, IdentityInterceptor, RequestExt},
+    codec::{encoding_name, CompressionEncoding},
+    codegen::{
+        check_decoded_len, check_encoded_len, BoxMessage, IdentityInterceptor, MessageLen,
+        RequestExt, DEFAULT_MAX_DECODING_MESSAGE_SIZE,
+    },
     service::Interceptor,
     sim::AppendMetadata,
+    transport::circuit_breaker::CircuitBreaker,
     Request, Response, Status, Streaming,
 };
 
@@ -18,6 +24,10 @@ use crate::{
 pub struct Grpc<T, F> {
     inner: T,
     interceptor: F,
+    send_compression: Option<CompressionEncoding>,
+    accept_compression: Vec<CompressionEncoding>,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
 }
 
 impl<T> Grpc<T, IdentityInterceptor> {
@@ -26,6 +36,10 @@ impl<T> Grpc<T, IdentityInterceptor> {
         Grpc {
             inner,
             interceptor: Ok,
+            send_compression: None,
+            accept_compression: vec![],
+            max_decoding_message_size: Some(DEFAULT_MAX_DECODING_MESSAGE_SIZE),
+            max_encoding_message_size: None,
         }
     }
 }
@@ -39,7 +53,60 @@ impl<T> Grpc<T, IdentityInterceptor> {
 impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
     /// Creates a new gRPC client with the provided `GrpcService` and interceptor.
     pub fn with_interceptor(inner: crate::transport::Channel, interceptor: F) -> Self {
-        Grpc { inner, interceptor }
+        Grpc {
+            inner,
+            interceptor,
+            send_compression: None,
+            accept_compression: vec![],
+            max_decoding_message_size: Some(DEFAULT_MAX_DECODING_MESSAGE_SIZE),
+            max_encoding_message_size: None,
+        }
+    }
+
+    /// Compress requests with the given encoding.
+    ///
+    /// The encoding is sent in the `grpc-encoding` header. Messages are not actually compressed,
+    /// but the server responds with `Unimplemented` if it doesn't accept the encoding.
+    #[must_use]
+    pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.send_compression = Some(encoding);
+        self
+    }
+
+    /// Enable accepting compressed responses.
+    ///
+    /// Accepted encodings are sent in the `grpc-accept-encoding` header.
+    #[must_use]
+    pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        if !self.accept_compression.contains(&encoding) {
+            self.accept_compression.push(encoding);
+        }
+        self
+    }
+
+    /// Append compression headers to the request.
+    fn append_compression<M>(&self, request: &mut Request<M>) {
+        let metadata = request.metadata_mut();
+        // unsupported encodings are neither used nor advertised
+        if let Some(name) = self.send_compression.and_then(encoding_name) {
+            metadata.insert("grpc-encoding", name.parse().unwrap());
+        }
+        let names: Vec<_> = (self.accept_compression.iter())
+            .filter_map(|e| encoding_name(*e))
+            .collect();
+        if !names.is_empty() {
+            metadata.insert("grpc-accept-encoding", names.join(",").parse().unwrap());
+        }
+    }
+
+    /// Returns the timeout of the request, or the timeout of the channel.
+    ///
+    /// The timeout is sent in the `grpc-timeout` header, so the server can see the deadline and
+    /// cancel the call when it passes.
+    fn propagate_timeout<M>(&self, request: &mut Request<M>) -> Option<Duration> {
+        let timeout = request.timeout().or(self.inner.timeout)?;
+        request.set_timeout(timeout);
+        Some(timeout)
     }
 
     /// Check if the inner GrpcService is able to accept a new request.
@@ -53,17 +120,26 @@ impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
         &mut self,
         mut request: Request<M1>,
         path: PathAndQuery,
-        _codec: C,
+        codec: C,
     ) -> Result<Response<M2>, Status>
     where
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
+        C: MessageLen<M1, M2> + Clone + Send + 'static,
     {
-        let timeout = request.timeout().or(self.inner.timeout);
+        let timeout = self.propagate_timeout(&mut request);
+        let circuit_breaker = self.inner.circuit_breaker.clone();
         let future = async move {
             request.append_metadata();
-            let request = request.intercept(&mut self.interceptor)?.boxed();
-            let (tx, mut rx) = self.inner.ep.connect1().await?;
+            self.append_compression(&mut request);
+            let limits = self.limits();
+            limits.check_request(&codec, request.get_ref())?;
+            let mut request = request.intercept(&mut self.interceptor)?.boxed();
+            let (tx, mut rx, window, lease) = self.inner.ep.connect1().await?;
+            request.extensions_mut().insert(window);
+            if let Some(lease) = lease {
+                request.extensions_mut().insert(lease);
+            }
             // send request
             tx.send(Box::new((path, false, request))).await?;
             // receive response
@@ -72,9 +148,10 @@ impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
                 .downcast::<Result<Response<BoxMessage>, Status>>()
                 .expect("message type mismatch");
             let rsp = rsp?.map(|msg| *msg.downcast().expect("message type mismatch"));
+            limits.check_response(&codec, rsp.get_ref())?;
             Ok(rsp)
         };
-        with_timeout(timeout, future).await
+        with_circuit_breaker(circuit_breaker, with_timeout(timeout, future)).await
     }
 
     /// Send a client side streaming gRPC request.
@@ -83,28 +160,37 @@ impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
         &mut self,
         mut request: Request<impl Stream<Item = M1> + Send + 'static>,
         path: PathAndQuery,
-        _codec: C,
+        codec: C,
     ) -> Result<Response<M2>, Status>
     where
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
+        C: MessageLen<M1, M2> + Clone + Send + 'static,
     {
-        let timeout = request.timeout().or(self.inner.timeout);
+        let timeout = self.propagate_timeout(&mut request);
+        let circuit_breaker = self.inner.circuit_breaker.clone();
         let future = async move {
             request.append_metadata();
-            let request = request.intercept(&mut self.interceptor)?;
-            let (tx, mut rx) = self.inner.ep.connect1().await?;
+            self.append_compression(&mut request);
+            let limits = self.limits();
+            let mut request = request.intercept(&mut self.interceptor)?;
+            let (tx, mut rx, window, lease) = self.inner.ep.connect1().await?;
+            request.extensions_mut().insert(window);
+            if let Some(lease) = lease {
+                request.extensions_mut().insert(lease);
+            }
             // send requests
-            Self::send_request_stream(request, tx, path, false).await?;
+            Self::send_request_stream(request, tx, path, false, limits, codec.clone()).await?;
             // receive response
             let rsp = rx.recv().await?;
             let rsp = *rsp
                 .downcast::<Result<Response<BoxMessage>, Status>>()
                 .expect("message type mismatch");
             let rsp = rsp?.map(|msg| *msg.downcast().expect("message type mismatch"));
+            limits.check_response(&codec, rsp.get_ref())?;
             Ok(rsp)
         };
-        with_timeout(timeout, future).await
+        with_circuit_breaker(circuit_breaker, with_timeout(timeout, future)).await
     }
 
     /// Send a server side streaming gRPC request.
@@ -113,65 +199,89 @@ impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
         &mut self,
         mut request: Request<M1>,
         path: PathAndQuery,
-        _codec: C,
+        codec: C,
     ) -> Result<Response<Streaming<M2>>, Status>
     where
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
+        C: MessageLen<M1, M2> + Clone + Send + 'static,
     {
-        let timeout = request.timeout().or(self.inner.timeout);
+        let timeout = self.propagate_timeout(&mut request);
+        let circuit_breaker = self.inner.circuit_breaker.clone();
         let future = async move {
             request.append_metadata();
-            let request = request.intercept(&mut self.interceptor)?.boxed();
-            let (tx, mut rx) = self.inner.ep.connect1().await?;
+            self.append_compression(&mut request);
+            let limits = self.limits();
+            limits.check_request(&codec, request.get_ref())?;
+            let mut request = request.intercept(&mut self.interceptor)?.boxed();
+            let (tx, mut rx, window, lease) = self.inner.ep.connect1().await?;
+            request.extensions_mut().insert(window);
+            if let Some(lease) = lease {
+                request.extensions_mut().insert(lease);
+            }
             // send request
             tx.send(Box::new((path, true, request))).await?;
             // receive responses
             let res = *(rx.recv().await?)
                 .downcast::<Result<Response<()>, Status>>()
                 .unwrap();
-            let response = res?.map(move |_| Streaming::new(rx, None));
+            let response = res?.map(move |_| limits.check_stream(codec, Streaming::new(rx, None)));
             Ok(response)
         };
-        with_timeout(timeout, future).await
+        with_circuit_breaker(circuit_breaker, with_timeout(timeout, future)).await
     }
 
     /// Send a bi-directional streaming gRPC request.
+    ///
+    /// Requests are sent from a background task, so the server can send responses at any time,
+    /// even before the first request, and each direction has its own latency.
     #[instrument(name = "rpc", skip_all, fields(?path))]
     pub async fn streaming<M1, M2, C>(
         &mut self,
         mut request: Request<impl Stream<Item = M1> + Send + 'static>,
         path: PathAndQuery,
-        _codec: C,
+        codec: C,
     ) -> Result<Response<Streaming<M2>>, Status>
     where
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
+        C: MessageLen<M1, M2> + Clone + Send + 'static,
     {
-        let timeout = request.timeout().or(self.inner.timeout);
+        let timeout = self.propagate_timeout(&mut request);
+        let circuit_breaker = self.inner.circuit_breaker.clone();
         let future = async move {
             request.append_metadata();
-            let request = request.intercept(&mut self.interceptor)?;
-            let (tx, mut rx) = self.inner.ep.connect1().await?;
+            self.append_compression(&mut request);
+            let limits = self.limits();
+            let mut request = request.intercept(&mut self.interceptor)?;
+            let (tx, mut rx, window, lease) = self.inner.ep.connect1().await?;
+            request.extensions_mut().insert(window);
+            if let Some(lease) = lease {
+                request.extensions_mut().insert(lease);
+            }
             // send requests in a background task
+            let send_codec = codec.clone();
             let task = madsim::task::spawn(async move {
-                _ = Self::send_request_stream(request, tx, path, true).await;
+                _ = Self::send_request_stream(request, tx, path, true, limits, send_codec).await;
             });
             // receive responses
             let res = *(rx.recv().await?)
                 .downcast::<Result<Response<()>, Status>>()
                 .unwrap();
-            let response = res?.map(move |_| Streaming::new(rx, Some(task)));
+            let response =
+                res?.map(move |_| limits.check_stream(codec, Streaming::new(rx, Some(task))));
             Ok(response)
         };
-        with_timeout(timeout, future).await
+        with_circuit_breaker(circuit_breaker, with_timeout(timeout, future)).await
     }
 
-    async fn send_request_stream<M1>(
+    async fn send_request_stream<M1, M2>(
         request: Request<impl Stream<Item = M1> + Send + 'static>,
         tx: madsim::net::Sender,
         path: PathAndQuery,
         server_streaming: bool,
+        limits: Limits,
+        codec: impl MessageLen<M1, M2>,
     ) -> Result<(), Status>
     where
         M1: Send + Sync + 'static,
@@ -183,6 +293,8 @@ impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
         // send requests
         pin_mut!(stream);
         while let Some(item) = stream.next().await {
+            // an oversized request fails the call and ends the stream
+            limits.check_request(&codec, &item)?;
             // allows the server to prematurely close the stream
             if tx.send(Box::new(item)).await.is_err() {
                 debug!("send stream unexpectedly closed");
@@ -193,16 +305,77 @@ impl<F: Interceptor> Grpc<crate::transport::Channel, F> {
     }
 
     /// Limits the maximum size of a decoded message.
-    pub fn max_decoding_message_size(self, _limit: usize) -> Self {
-        // self.config.max_decoding_message_size = Some(limit);
+    ///
+    /// A larger response fails the call with `OutOfRange`. Default is 4MB, as in tonic.
+    #[must_use]
+    pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
         self
     }
 
     /// Limits the maximum size of an encoded message.
-    pub fn max_encoding_message_size(self, _limit: usize) -> Self {
-        // self.config.max_encoding_message_size = Some(limit);
+    ///
+    /// A larger request is not sent and fails the call with `OutOfRange`. Default is unlimited.
+    #[must_use]
+    pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
         self
     }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            decoding: self.max_decoding_message_size,
+            encoding: self.max_encoding_message_size,
+        }
+    }
+}
+
+/// The message size limits of a client, checked against the encoded length of messages.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    decoding: Option<usize>,
+    encoding: Option<usize>,
+}
+
+impl Limits {
+    fn check_request<M1, M2>(
+        &self,
+        codec: &impl MessageLen<M1, M2>,
+        msg: &M1,
+    ) -> Result<(), Status> {
+        match codec.request_len(msg) {
+            Some(len) => check_encoded_len(len, self.encoding),
+            None => Ok(()),
+        }
+    }
+
+    fn check_response<M1, M2>(
+        &self,
+        codec: &impl MessageLen<M1, M2>,
+        msg: &M2,
+    ) -> Result<(), Status> {
+        match codec.response_len(msg) {
+            Some(len) => check_decoded_len(len, self.decoding),
+            None => Ok(()),
+        }
+    }
+
+    /// Check each message of a response stream.
+    fn check_stream<M1, M2: Send + 'static>(
+        self,
+        codec: impl MessageLen<M1, M2> + Send + 'static,
+        stream: Streaming<M2>,
+    ) -> Streaming<M2> {
+        if self.decoding.is_none() {
+            return stream;
+        }
+        let stream = stream.map(move |res| {
+            let msg = res?;
+            self.check_response(&codec, &msg)?;
+            Ok(msg)
+        });
+        Streaming::from_stream(stream.boxed())
+    }
 }
 
 async fn with_timeout<T>(
@@ -217,3 +390,16 @@ async fn with_timeout<T>(
         future.await
     }
 }
+
+async fn with_circuit_breaker<T>(
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    future: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+    let Some(circuit_breaker) = circuit_breaker else {
+        return future.await;
+    };
+    let call = circuit_breaker.check()?;
+    let result = future.await;
+    call.finish(&result);
+    result
+}
@@ -73,3 +73,17 @@ impl<T> Stream for Streaming<T> {
         self.stream.poll_next_unpin(cx)
     }
 }
+
+/// Returns the name of the encoding used in `grpc-encoding` headers.
+///
+/// Returns `None` if the encoding is not supported, e.g. its feature of tonic is enabled by
+/// another crate. Such encodings are never sent or accepted.
+pub(crate) fn encoding_name(encoding: CompressionEncoding) -> Option<&'static str> {
+    match encoding {
+        #[cfg(feature = "gzip")]
+        CompressionEncoding::Gzip => Some("gzip"),
+        #[cfg(feature = "zstd")]
+        CompressionEncoding::Zstd => Some("zstd"),
+        _ => None,
+    }
+}
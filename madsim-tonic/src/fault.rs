@@ -0,0 +1,320 @@
+//! Fault injection for calls.
+//!
+//! Faults are injected on the server side: a matching call is rejected before it reaches the
+//! service, as if the backend was degraded. Faults are stored in the current runtime, so they
+//! apply to all servers in the simulation and are never shared between runtimes.
+//!
+//! To corrupt calls instead of failing them, an [interceptor](set_interceptor) can mutate the
+//! frames of calls in flight.
+//!
+//! # Example
+//!
+//! ```ignore
+//! // fail calls of tenant "foo"
+//! let id = tonic::fault::inject(
+//!     |metadata| metadata.get("tenant").is_some_and(|v| v == "foo"),
+//!     Status::unavailable("injected"),
+//! );
+//! // ...
+//! tonic::fault::remove(id);
+//!
+//! // drop 30% of calls to `PutKey`
+//! tonic::fault::drop_calls("/kv.Store/PutKey", 0.3);
+//!
+//! // truncate replies of `GetKey`
+//! tonic::fault::set_interceptor(|frame| {
+//!     if frame.is_response() && frame.path() == "/kv.Store/GetKey" {
+//!         if let Some(reply) = frame.message_mut::<GetKeyReply>() {
+//!             reply.value.truncate(1);
+//!         }
+//!     }
+//! });
+//! ```
+
+use crate::{codegen::BoxMessage, metadata::MetadataMap, Response, Status};
+use madsim::{
+    plugin::{self, Simulator},
+    rand::{thread_rng, GlobalRng, Rng},
+    time::TimeHandle,
+    Config,
+};
+use std::{fmt, sync::Mutex};
+use tracing::debug;
+
+/// An identifier of an injected fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FaultId(u64);
+
+/// A predicate on the method path and request metadata.
+type Predicate = Box<dyn Fn(&str, &MetadataMap) -> bool + Send + Sync>;
+
+/// Inject a fault: calls whose request metadata matches `predicate` fail with `status`.
+///
+/// If a call matches multiple faults, the earliest injected one takes effect.
+pub fn inject(
+    predicate: impl Fn(&MetadataMap) -> bool + Send + Sync + 'static,
+    status: Status,
+) -> FaultId {
+    add(Box::new(move |_, metadata| predicate(metadata)), status)
+}
+
+/// Drop a fraction of calls to the method at `path` (e.g. `/helloworld.Greeter/SayHello`).
+///
+/// Each call is dropped with probability `rate` and fails with `Unavailable`.
+/// Whether a call is dropped is decided by the random generator of the runtime, so it is
+/// reproducible under the same seed.
+///
+/// # Panics
+///
+/// Panics if `rate` is not in `[0, 1]`.
+pub fn drop_calls(path: impl Into<String>, rate: f64) -> FaultId {
+    assert!((0.0..=1.0).contains(&rate), "invalid drop rate: {rate}");
+    let path = path.into();
+    let status = Status::unavailable(format!("call dropped: {path}"));
+    add(
+        Box::new(move |p, _| p == path && thread_rng().gen_bool(rate)),
+        status,
+    )
+}
+
+fn add(predicate: Predicate, status: Status) -> FaultId {
+    let sim = plugin::simulator::<FaultSim>();
+    let mut inner = sim.inner.lock().unwrap();
+    let id = FaultId(inner.next_id);
+    inner.next_id += 1;
+    debug!(?id, ?status, "inject fault");
+    inner.faults.push((id, predicate, status));
+    id
+}
+
+/// Remove an injected fault.
+pub fn remove(id: FaultId) {
+    let sim = plugin::simulator::<FaultSim>();
+    let mut inner = sim.inner.lock().unwrap();
+    inner.faults.retain(|(fid, _, _)| *fid != id);
+}
+
+/// Remove all injected faults and the interceptor.
+pub fn clear() {
+    let sim = plugin::simulator::<FaultSim>();
+    sim.inner.lock().unwrap().faults.clear();
+    *sim.interceptor.lock().unwrap() = None;
+}
+
+/// A frame of a call in flight, passed to the [interceptor](set_interceptor).
+///
+/// A frame is the header of a request or response, with the message of unary calls, or a message
+/// of a stream. Messages are not encoded in simulation, so they are mutated as decoded values.
+pub struct Frame<'a> {
+    path: &'a str,
+    response: bool,
+    metadata: Option<&'a mut MetadataMap>,
+    message: Option<&'a mut BoxMessage>,
+    status: Option<Status>,
+}
+
+impl Frame<'_> {
+    /// Returns the path of the method, e.g. `/helloworld.Greeter/SayHello`.
+    pub fn path(&self) -> &str {
+        self.path
+    }
+
+    /// Returns true if the frame is sent by the server.
+    pub fn is_response(&self) -> bool {
+        self.response
+    }
+
+    /// Returns the metadata of a header frame.
+    pub fn metadata_mut(&mut self) -> Option<&mut MetadataMap> {
+        self.metadata.as_deref_mut()
+    }
+
+    /// Returns the message of the frame if it is of type `M`.
+    pub fn message_mut<M: 'static>(&mut self) -> Option<&mut M> {
+        self.message.as_deref_mut()?.downcast_mut()
+    }
+
+    /// Returns the error of the frame.
+    pub fn status(&self) -> Option<&Status> {
+        self.status.as_ref()
+    }
+
+    /// Replace the frame with an error, which fails the call.
+    pub fn set_status(&mut self, status: Status) {
+        self.status = Some(status);
+    }
+}
+
+impl fmt::Debug for Frame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("path", &self.path)
+            .field("response", &self.response)
+            .field("metadata", &self.metadata)
+            .field("status", &self.status)
+            .finish_non_exhaustive()
+    }
+}
+
+type Interceptor = Box<dyn FnMut(&mut Frame<'_>) + Send>;
+
+/// Set the interceptor called with each frame of calls received by servers, replacing the
+/// previous one.
+///
+/// Requests are intercepted before the server checks them, and responses right before they are
+/// sent, so the interceptor is called in the deterministic order of events. It does not draw
+/// random numbers unless it does so itself. Responses of calls rejected by the server before
+/// reaching the service are not intercepted.
+///
+/// The interceptor must not call functions of this module.
+pub fn set_interceptor(interceptor: impl FnMut(&mut Frame<'_>) + Send + 'static) {
+    let sim = plugin::simulator::<FaultSim>();
+    *sim.interceptor.lock().unwrap() = Some(Box::new(interceptor));
+}
+
+/// Remove the interceptor.
+pub fn clear_interceptor() {
+    let sim = plugin::simulator::<FaultSim>();
+    *sim.interceptor.lock().unwrap() = None;
+}
+
+/// Pass the frame to the interceptor and returns its error.
+fn intercept(mut frame: Frame<'_>) -> Option<Status> {
+    let sim = plugin::simulator::<FaultSim>();
+    if let Some(interceptor) = sim.interceptor.lock().unwrap().as_mut() {
+        interceptor(&mut frame);
+        if let Some(status) = &frame.status {
+            debug!(path = frame.path, ?status, "frame intercepted");
+        }
+    }
+    frame.status
+}
+
+/// Intercept a request header or a message of a request stream.
+pub(crate) fn intercept_request(
+    path: &str,
+    metadata: Option<&mut MetadataMap>,
+    message: Option<&mut BoxMessage>,
+) -> Result<(), Status> {
+    let frame = Frame {
+        path,
+        response: false,
+        metadata,
+        message,
+        status: None,
+    };
+    match intercept(frame) {
+        Some(status) => Err(status),
+        None => Ok(()),
+    }
+}
+
+/// Intercept a response header or a message of a response stream.
+pub(crate) fn intercept_response<T: ResponseFrame>(
+    path: &str,
+    rsp: Result<T, Status>,
+) -> Result<T, Status> {
+    match rsp {
+        Ok(rsp) => rsp.intercept(path),
+        Err(status) => {
+            let frame = Frame {
+                path,
+                response: true,
+                metadata: None,
+                message: None,
+                status: Some(status),
+            };
+            Err(intercept(frame).expect("error frame without status"))
+        }
+    }
+}
+
+/// A successful response frame.
+pub(crate) trait ResponseFrame: Sized {
+    fn intercept(self, path: &str) -> Result<Self, Status>;
+}
+
+impl ResponseFrame for Response<BoxMessage> {
+    fn intercept(self, path: &str) -> Result<Self, Status> {
+        let (mut metadata, mut message, extensions) = self.into_parts();
+        let frame = Frame {
+            path,
+            response: true,
+            metadata: Some(&mut metadata),
+            message: Some(&mut message),
+            status: None,
+        };
+        match intercept(frame) {
+            Some(status) => Err(status),
+            None => Ok(Response::from_parts(metadata, message, extensions)),
+        }
+    }
+}
+
+impl ResponseFrame for Response<()> {
+    fn intercept(mut self, path: &str) -> Result<Self, Status> {
+        let frame = Frame {
+            path,
+            response: true,
+            metadata: Some(self.metadata_mut()),
+            message: None,
+            status: None,
+        };
+        match intercept(frame) {
+            Some(status) => Err(status),
+            None => Ok(self),
+        }
+    }
+}
+
+impl ResponseFrame for BoxMessage {
+    fn intercept(mut self, path: &str) -> Result<Self, Status> {
+        let frame = Frame {
+            path,
+            response: true,
+            metadata: None,
+            message: Some(&mut self),
+            status: None,
+        };
+        match intercept(frame) {
+            Some(status) => Err(status),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Returns the error of the first fault matching the call.
+pub(crate) fn check(path: &str, metadata: &MetadataMap) -> Result<(), Status> {
+    let sim = plugin::simulator::<FaultSim>();
+    let inner = sim.inner.lock().unwrap();
+    let fault = inner
+        .faults
+        .iter()
+        .find(|(_, predicate, _)| predicate(path, metadata));
+    match fault {
+        Some((id, _, status)) => {
+            debug!(?id, "fault injected");
+            Err(status.clone())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Injected faults of a runtime.
+#[derive(Default)]
+struct FaultSim {
+    inner: Mutex<Inner>,
+    interceptor: Mutex<Option<Interceptor>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    faults: Vec<(FaultId, Predicate, Status)>,
+}
+
+impl Simulator for FaultSim {
+    fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &Config) -> Self {
+        FaultSim::default()
+    }
+}
@@ -0,0 +1,39 @@
+//! A simulated implementation of the [gRPC health checking protocol].
+//!
+//! This mirrors the API of [`tonic-health`](https://docs.rs/tonic-health).
+//!
+//! [gRPC health checking protocol]: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+
+pub mod pb;
+pub mod server;
+
+/// An enumeration of values representing gRPC service health.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ServingStatus {
+    /// Unknown status
+    Unknown,
+    /// The service is currently up and serving requests.
+    Serving,
+    /// The service is currently down and not serving requests.
+    NotServing,
+}
+
+impl std::fmt::Display for ServingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServingStatus::Unknown => f.write_str("Unknown"),
+            ServingStatus::Serving => f.write_str("Serving"),
+            ServingStatus::NotServing => f.write_str("NotServing"),
+        }
+    }
+}
+
+impl From<ServingStatus> for pb::health_check_response::ServingStatus {
+    fn from(s: ServingStatus) -> Self {
+        match s {
+            ServingStatus::Unknown => pb::health_check_response::ServingStatus::Unknown,
+            ServingStatus::Serving => pb::health_check_response::ServingStatus::Serving,
+            ServingStatus::NotServing => pb::health_check_response::ServingStatus::NotServing,
+        }
+    }
+}
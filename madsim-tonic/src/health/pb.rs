@@ -0,0 +1,270 @@
+//! Messages and services of `grpc.health.v1`.
+//!
+//! Messages are passed through the simulated network as they are, so they are plain structs
+//! instead of protobuf messages.
+
+/// A health check request.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct HealthCheckRequest {
+    /// The name of the service to check. Empty for the overall health of the server.
+    pub service: String,
+}
+
+/// A health check response.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct HealthCheckResponse {
+    /// The value of [`ServingStatus`](health_check_response::ServingStatus).
+    pub status: i32,
+}
+
+impl HealthCheckResponse {
+    /// Returns the enum value of `status`, or the default if the field is set to an invalid enum
+    /// value.
+    pub fn status(&self) -> health_check_response::ServingStatus {
+        health_check_response::ServingStatus::try_from(self.status).unwrap_or_default()
+    }
+
+    /// Sets `status` to the provided enum value.
+    pub fn set_status(&mut self, value: health_check_response::ServingStatus) {
+        self.status = value as i32;
+    }
+}
+
+/// Nested message and enum types in `HealthCheckResponse`.
+pub mod health_check_response {
+    /// The serving status of a service.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+    #[repr(i32)]
+    pub enum ServingStatus {
+        /// Unknown status.
+        #[default]
+        Unknown = 0,
+        /// The service is serving requests.
+        Serving = 1,
+        /// The service is not serving requests.
+        NotServing = 2,
+        /// Used only by the `Watch` method.
+        ServiceUnknown = 3,
+    }
+
+    impl ServingStatus {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                ServingStatus::Unknown => "UNKNOWN",
+                ServingStatus::Serving => "SERVING",
+                ServingStatus::NotServing => "NOT_SERVING",
+                ServingStatus::ServiceUnknown => "SERVICE_UNKNOWN",
+            }
+        }
+    }
+
+    impl TryFrom<i32> for ServingStatus {
+        type Error = i32;
+
+        fn try_from(value: i32) -> Result<Self, i32> {
+            match value {
+                0 => Ok(ServingStatus::Unknown),
+                1 => Ok(ServingStatus::Serving),
+                2 => Ok(ServingStatus::NotServing),
+                3 => Ok(ServingStatus::ServiceUnknown),
+                _ => Err(value),
+            }
+        }
+    }
+}
+
+/// Generated client implementations.
+pub mod health_client {
+    use super::{HealthCheckRequest, HealthCheckResponse};
+    use crate::{
+        client::Grpc,
+        codegen::{http::uri::PathAndQuery, IdentityInterceptor, StdError},
+        service::Interceptor,
+        transport::{Channel, Endpoint, Error},
+        IntoRequest, Response, Status, Streaming,
+    };
+
+    /// A client of the health service.
+    #[derive(Debug, Clone)]
+    pub struct HealthClient<T, F = IdentityInterceptor> {
+        inner: Grpc<T, F>,
+    }
+
+    impl HealthClient<Channel> {
+        /// Attempt to create a new client by connecting to a given endpoint.
+        pub async fn connect<D>(dst: D) -> Result<Self, Error>
+        where
+            D: TryInto<Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+
+        /// Create a new client with the channel.
+        pub fn new(inner: Channel) -> Self {
+            let inner = Grpc::new(inner);
+            Self { inner }
+        }
+    }
+
+    impl<F: Interceptor> HealthClient<Channel, F> {
+        /// Create a new client with the channel and interceptor.
+        pub fn with_interceptor(inner: Channel, interceptor: F) -> Self {
+            let inner = Grpc::with_interceptor(inner, interceptor);
+            Self { inner }
+        }
+
+        /// Check the health of a service.
+        ///
+        /// Returns `NotFound` if the service is unknown to the server.
+        pub async fn check(
+            &mut self,
+            request: impl IntoRequest<HealthCheckRequest>,
+        ) -> Result<Response<HealthCheckResponse>, Status> {
+            let path = PathAndQuery::from_static("/grpc.health.v1.Health/Check");
+            self.inner.unary(request.into_request(), path, ()).await
+        }
+
+        /// Watch the health of a service.
+        ///
+        /// The current status is sent immediately, followed by a message on every change.
+        pub async fn watch(
+            &mut self,
+            request: impl IntoRequest<HealthCheckRequest>,
+        ) -> Result<Response<Streaming<HealthCheckResponse>>, Status> {
+            let path = PathAndQuery::from_static("/grpc.health.v1.Health/Watch");
+            (self.inner)
+                .server_streaming(request.into_request(), path, ())
+                .await
+        }
+    }
+}
+
+/// Generated server implementations.
+pub mod health_server {
+    use super::{HealthCheckRequest, HealthCheckResponse};
+    use crate::{
+        codegen::{
+            async_trait,
+            futures::{future::FutureExt, stream, Stream, StreamExt},
+            http::uri::PathAndQuery,
+            Arc, BoxFuture, BoxMessage, BoxMessageStream, Context, IdentityInterceptor, Poll,
+            RequestExt, Service,
+        },
+        service::Interceptor,
+        transport::NamedService,
+        Request, Response, Status,
+    };
+
+    /// The health service.
+    #[async_trait]
+    pub trait Health: Send + Sync + 'static {
+        /// Check the health of a service.
+        async fn check(
+            &self,
+            request: Request<HealthCheckRequest>,
+        ) -> Result<Response<HealthCheckResponse>, Status>;
+
+        /// Server streaming response type for the `Watch` method.
+        type WatchStream: Stream<Item = Result<HealthCheckResponse, Status>> + Send + 'static;
+
+        /// Watch the health of a service.
+        async fn watch(
+            &self,
+            request: Request<HealthCheckRequest>,
+        ) -> Result<Response<Self::WatchStream>, Status>;
+    }
+
+    /// A server of the health service.
+    #[derive(Debug)]
+    pub struct HealthServer<T: Health, F = IdentityInterceptor> {
+        inner: Arc<T>,
+        interceptor: F,
+    }
+
+    impl<T: Health> HealthServer<T> {
+        /// Create a new server with the service.
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+
+        /// Create a new server with the shared service.
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                interceptor: Ok,
+            }
+        }
+    }
+
+    impl<T: Health, F: Interceptor> HealthServer<T, F> {
+        /// Create a new server with the service and interceptor.
+        pub fn with_interceptor(inner: T, interceptor: F) -> Self {
+            Self {
+                inner: Arc::new(inner),
+                interceptor,
+            }
+        }
+    }
+
+    impl<T, F> Service<(PathAndQuery, Request<BoxMessageStream>)> for HealthServer<T, F>
+    where
+        T: Health,
+        F: Interceptor,
+    {
+        type Response = Response<BoxMessageStream>;
+        type Error = Status;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(
+            &mut self,
+            (path, request): (PathAndQuery, Request<BoxMessageStream>),
+        ) -> Self::Future {
+            let inner = self.inner.clone();
+            let request = match request.intercept(&mut self.interceptor) {
+                Ok(r) => r,
+                Err(e) => return Box::pin(async move { Err(e) }),
+            };
+            let request = request.map(|mut stream| {
+                let first = stream.next().now_or_never().unwrap().unwrap();
+                *first.unwrap().downcast::<HealthCheckRequest>().unwrap()
+            });
+            match path.path() {
+                "/grpc.health.v1.Health/Check" => Box::pin(async move {
+                    let response = inner.check(request).await?;
+                    Ok(response.map(|msg| {
+                        stream::once(async move { Ok(Box::new(msg) as BoxMessage) }).boxed()
+                    }))
+                }),
+                "/grpc.health.v1.Health/Watch" => Box::pin(async move {
+                    let response = inner.watch(request).await?;
+                    Ok(response.map(|stream| {
+                        (stream.map(|res| res.map(|msg| Box::new(msg) as BoxMessage))).boxed()
+                    }))
+                }),
+                _ => Box::pin(
+                    async move { Err(Status::invalid_argument(format!("no path: {path}"))) },
+                ),
+            }
+        }
+    }
+
+    impl<T: Health, F: Clone> Clone for HealthServer<T, F> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                interceptor: self.interceptor.clone(),
+            }
+        }
+    }
+
+    impl<T: Health, F: Interceptor> NamedService for HealthServer<T, F> {
+        const NAME: &'static str = "grpc.health.v1.Health";
+    }
+}
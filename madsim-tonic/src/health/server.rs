@@ -0,0 +1,152 @@
+//! Contains all healthcheck based server utilities.
+
+use super::{
+    pb::{
+        self,
+        health_server::{Health, HealthServer},
+        HealthCheckRequest, HealthCheckResponse,
+    },
+    ServingStatus,
+};
+use crate::{codegen::BoxStream, transport::NamedService, Request, Response, Status};
+use madsim::sync::watch;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::debug;
+
+/// Creates a `HealthReporter` and a linked `HealthServer` pair. Together,
+/// these types can be used to serve the gRPC Health Checking service.
+///
+/// A `HealthReporter` is used to update the state of gRPC services.
+///
+/// A `HealthServer` is a Tonic gRPC server for the `grpc.health.v1.Health`,
+/// which can be added to a Tonic runtime using `add_service` on the runtime
+/// builder.
+pub fn health_reporter() -> (HealthReporter, HealthServer<impl Health>) {
+    let reporter = HealthReporter::new();
+    let service = HealthService::new(reporter.statuses.clone());
+    let server = HealthServer::new(service);
+    (reporter, server)
+}
+
+type StatusPair = (watch::Sender<ServingStatus>, watch::Receiver<ServingStatus>);
+
+/// A handle providing methods to update the health status of gRPC services. A
+/// `HealthReporter` is connected to a `HealthServer` which serves the statuses
+/// over the `grpc.health.v1.Health` service.
+#[derive(Clone, Debug)]
+pub struct HealthReporter {
+    statuses: Arc<Mutex<HashMap<String, StatusPair>>>,
+}
+
+impl HealthReporter {
+    fn new() -> Self {
+        // According to the gRPC Health Check specification, the empty service "" corresponds to
+        // the overall server health
+        let server_status = ("".to_string(), watch::channel(ServingStatus::Serving));
+        let statuses = Arc::new(Mutex::new(HashMap::from([server_status])));
+        HealthReporter { statuses }
+    }
+
+    /// Sets the status of the service implemented by `S` to `Serving`. This notifies any watchers
+    /// if there is a change in status.
+    pub async fn set_serving<S: NamedService>(&mut self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving)
+            .await;
+    }
+
+    /// Sets the status of the service implemented by `S` to `NotServing`. This notifies any
+    /// watchers if there is a change in status.
+    pub async fn set_not_serving<S: NamedService>(&mut self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing)
+            .await;
+    }
+
+    /// Sets the status of the service with `service_name` to `status`. This notifies any watchers
+    /// if there is a change in status.
+    pub async fn set_service_status<S: AsRef<str>>(
+        &mut self,
+        service_name: S,
+        status: ServingStatus,
+    ) {
+        let service_name = service_name.as_ref();
+        debug!(service = service_name, %status, "set health status");
+        let mut statuses = self.statuses.lock().unwrap();
+        match statuses.get(service_name) {
+            // the original receiver is kept in the map, so sending never fails
+            Some((tx, _)) => tx.send(status).expect("channel should not be closed"),
+            None => {
+                statuses.insert(service_name.to_string(), watch::channel(status));
+            }
+        };
+    }
+
+    /// Clear the status of the given service.
+    pub async fn clear_service_status(&mut self, service_name: &str) {
+        self.statuses.lock().unwrap().remove(service_name);
+    }
+}
+
+/// A service providing implementations of gRPC health checking protocol.
+#[derive(Debug)]
+pub struct HealthService {
+    statuses: Arc<Mutex<HashMap<String, StatusPair>>>,
+}
+
+impl HealthService {
+    fn new(statuses: Arc<Mutex<HashMap<String, StatusPair>>>) -> Self {
+        HealthService { statuses }
+    }
+
+    fn service_health(&self, service_name: &str) -> Option<ServingStatus> {
+        let statuses = self.statuses.lock().unwrap();
+        statuses.get(service_name).map(|p| *p.1.borrow())
+    }
+}
+
+#[crate::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service_name = request.get_ref().service.as_str();
+        let Some(status) = self.service_health(service_name) else {
+            return Err(Status::not_found("service not registered"));
+        };
+        Ok(Response::new(response(status)))
+    }
+
+    type WatchStream = BoxStream<HealthCheckResponse>;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service_name = request.get_ref().service.as_str();
+        let mut status_rx = match self.statuses.lock().unwrap().get(service_name) {
+            None => return Err(Status::not_found("service not registered")),
+            Some(pair) => pair.1.clone(),
+        };
+
+        let output = async_stream::try_stream! {
+            // yield the current value
+            let status = *status_rx.borrow();
+            yield response(status);
+
+            while status_rx.changed().await.is_ok() {
+                let status = *status_rx.borrow();
+                yield response(status);
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::WatchStream))
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    let status = pb::health_check_response::ServingStatus::from(status) as i32;
+    HealthCheckResponse { status }
+}
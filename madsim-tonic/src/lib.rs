@@ -6,3 +6,5 @@ mod sim;
 pub use sim::*;
 #[cfg(not(madsim))]
 pub use tonic::*;
+#[cfg(all(not(madsim), feature = "health"))]
+pub use tonic_health as health;
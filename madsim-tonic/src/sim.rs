@@ -4,6 +4,17 @@ pub use tonic::{
     Request, Response, Status,
 };
 
+/// Include the generated code of the package.
+///
+/// The simulated code is included from the `sim` subdirectory of `OUT_DIR`. Code generated into
+/// another subdirectory with `tonic_build::Builder::sim_subdir` has to be included explicitly:
+///
+/// ```ignore
+/// #[cfg(madsim)]
+/// include!(concat!(env!("OUT_DIR"), "/sim_a/helloworld.rs"));
+/// #[cfg(not(madsim))]
+/// tonic::include_proto!("helloworld");
+/// ```
 #[macro_export]
 macro_rules! include_proto {
     ($package: tt) => {
@@ -13,6 +24,9 @@ macro_rules! include_proto {
 
 pub mod client;
 pub mod codec;
+pub mod fault;
+#[cfg(feature = "health")]
+pub mod health;
 pub(crate) mod tower;
 pub mod transport;
 
@@ -46,7 +60,9 @@ pub mod codegen {
     use std::any::Any;
     pub use std::net::SocketAddr;
     use std::time::Duration;
-    use tonic::{service::Interceptor, Request, Status};
+    use tonic::{service::Interceptor, Request, Response, Status};
+
+    use crate::codec::encoding_name;
 
     pub use futures_util as futures;
     pub use tonic::codegen::*;
@@ -60,28 +76,47 @@ pub mod codegen {
 
     pub trait RequestExt<T>: Sized {
         fn timeout(&self) -> Option<Duration>;
+        fn try_timeout(&self) -> Result<Option<Duration>, Status>;
         fn set_tcp_connect_info(&mut self, local_addr: SocketAddr, remote_addr: SocketAddr);
         fn intercept<F: Interceptor>(self, interceptor: &mut F) -> Result<Self, Status>;
+        fn check_encoding(&self, accepted: &[CompressionEncoding]) -> Result<(), Status>;
+        fn response_encoding(&self, enabled: &[CompressionEncoding])
+            -> Option<CompressionEncoding>;
         fn boxed(self) -> Request<BoxMessage>
         where
             T: Send + Sync + 'static;
     }
 
     impl<T> RequestExt<T> for Request<T> {
-        /// Get the timeout of Request.
+        /// Get the timeout of Request. An invalid `grpc-timeout` header is ignored.
         fn timeout(&self) -> Option<Duration> {
-            let s = self.metadata().get("grpc-timeout")?.to_str().unwrap();
-            let (value, unit) = s.split_at(s.len() - 1);
-            let value = value.parse::<u64>().expect("invalid grpc-timeout value");
-            Some(match unit {
-                "H" => Duration::from_secs(value * 60 * 60),
-                "M" => Duration::from_secs(value * 60),
-                "S" => Duration::from_secs(value),
-                "m" => Duration::from_millis(value),
-                "u" => Duration::from_micros(value),
-                "n" => Duration::from_nanos(value),
-                _ => panic!("invalid grpc-timeout unit: {unit}"),
-            })
+            self.try_timeout().ok().flatten()
+        }
+
+        /// Get the timeout of Request, or an `InvalidArgument` status if the `grpc-timeout`
+        /// header is invalid.
+        fn try_timeout(&self) -> Result<Option<Duration>, Status> {
+            let Some(header) = self.metadata().get("grpc-timeout") else {
+                return Ok(None);
+            };
+            let invalid = || Status::invalid_argument(format!("invalid grpc-timeout: {header:?}"));
+            let s = header.to_str().map_err(|_| invalid())?;
+            let unit = s.chars().last().ok_or_else(invalid)?;
+            let value = &s[..s.len() - unit.len_utf8()];
+            // the value has at most 8 digits
+            if value.is_empty() || value.len() > 8 {
+                return Err(invalid());
+            }
+            let value = value.parse::<u64>().map_err(|_| invalid())?;
+            Ok(Some(match unit {
+                'H' => Duration::from_secs(value * 60 * 60),
+                'M' => Duration::from_secs(value * 60),
+                'S' => Duration::from_secs(value),
+                'm' => Duration::from_millis(value),
+                'u' => Duration::from_micros(value),
+                'n' => Duration::from_nanos(value),
+                _ => return Err(invalid()),
+            }))
         }
 
         /// Set the remote address of Request.
@@ -100,6 +135,33 @@ pub mod codegen {
             Ok(Self::from_parts(metadata, extensions, inner))
         }
 
+        /// Check that the request is not compressed or compressed with an accepted encoding.
+        fn check_encoding(&self, accepted: &[CompressionEncoding]) -> Result<(), Status> {
+            let Some(value) = self.metadata().get("grpc-encoding") else {
+                return Ok(());
+            };
+            let name = value.to_str().unwrap_or_default();
+            if name == "identity" || accepted.iter().any(|e| encoding_name(*e) == Some(name)) {
+                return Ok(());
+            }
+            Err(Status::unimplemented(format!(
+                "Content is compressed with `{name}` which isn't supported"
+            )))
+        }
+
+        /// Select the first enabled encoding accepted by the client to compress responses.
+        fn response_encoding(
+            &self,
+            enabled: &[CompressionEncoding],
+        ) -> Option<CompressionEncoding> {
+            let value = self.metadata().get("grpc-accept-encoding")?;
+            let accepted: Vec<_> = value.to_str().ok()?.split(',').map(str::trim).collect();
+            enabled
+                .iter()
+                .copied()
+                .find(|e| encoding_name(*e).is_some_and(|name| accepted.contains(&name)))
+        }
+
         fn boxed(self) -> Request<BoxMessage>
         where
             T: Send + Sync + 'static,
@@ -107,4 +169,114 @@ pub mod codegen {
             self.map(|inner| Box::new(inner) as BoxMessage)
         }
     }
+
+    /// A response message with its encoded length, which the server uses for flow control.
+    pub struct EncodedMessage {
+        pub(crate) len: usize,
+        pub(crate) msg: BoxMessage,
+    }
+
+    /// Box a response message with its encoded length.
+    pub fn encoded<M: Send + Sync + 'static>(len: usize, msg: M) -> BoxMessage {
+        Box::new(EncodedMessage {
+            len,
+            msg: Box::new(msg),
+        })
+    }
+
+    /// Unwrap a response message boxed by [`encoded`], returning the length.
+    ///
+    /// Messages from hand-written services are not wrapped and have zero length.
+    pub(crate) fn unwrap_encoded(msg: BoxMessage) -> (BoxMessage, usize) {
+        match msg.downcast::<EncodedMessage>() {
+            Ok(encoded) => (encoded.msg, encoded.len),
+            Err(msg) => (msg, 0),
+        }
+    }
+
+    /// Measures the encoded length of messages for the message size limits.
+    ///
+    /// Generated clients pass it to [`Grpc`](crate::client::Grpc) in place of a codec.
+    pub trait MessageLen<M1, M2> {
+        /// Returns the encoded length of a request, or `None` if unknown.
+        fn request_len(&self, msg: &M1) -> Option<usize>;
+        /// Returns the encoded length of a response, or `None` if unknown.
+        fn response_len(&self, msg: &M2) -> Option<usize>;
+    }
+
+    /// Messages of unknown length are never limited.
+    impl<M1, M2> MessageLen<M1, M2> for () {
+        fn request_len(&self, _msg: &M1) -> Option<usize> {
+            None
+        }
+        fn response_len(&self, _msg: &M2) -> Option<usize> {
+            None
+        }
+    }
+
+    /// The encoded length of messages, e.g. `prost::Message::encoded_len`.
+    pub struct EncodedLen<M1, M2> {
+        /// The encoded length of a request.
+        pub request: fn(&M1) -> usize,
+        /// The encoded length of a response.
+        pub response: fn(&M2) -> usize,
+    }
+
+    impl<M1, M2> Clone for EncodedLen<M1, M2> {
+        fn clone(&self) -> Self {
+            EncodedLen {
+                request: self.request,
+                response: self.response,
+            }
+        }
+    }
+
+    impl<M1, M2> MessageLen<M1, M2> for EncodedLen<M1, M2> {
+        fn request_len(&self, msg: &M1) -> Option<usize> {
+            Some((self.request)(msg))
+        }
+        fn response_len(&self, msg: &M2) -> Option<usize> {
+            Some((self.response)(msg))
+        }
+    }
+
+    /// The default limit of the size of a decoded message, the same as tonic.
+    pub const DEFAULT_MAX_DECODING_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Fail with `OutOfRange` if a received message of `len` bytes exceeds the decoding limit.
+    pub fn check_decoded_len(len: usize, limit: Option<usize>) -> Result<(), Status> {
+        match limit {
+            Some(limit) if len > limit => Err(Status::out_of_range(format!(
+                "Error, decoded message length too large: found {len} bytes, the limit is: {limit} bytes"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail with `OutOfRange` if a message of `len` bytes to send exceeds the encoding limit.
+    pub fn check_encoded_len(len: usize, limit: Option<usize>) -> Result<(), Status> {
+        match limit {
+            Some(limit) if len > limit => Err(Status::out_of_range(format!(
+                "Error, encoded message length too large: found {len} bytes, the limit is: {limit} bytes"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Set the `grpc-encoding` header of the response returned by `future`.
+    pub fn with_response_encoding<T: Send + 'static>(
+        future: BoxFuture<Response<T>, Status>,
+        encoding: Option<CompressionEncoding>,
+    ) -> BoxFuture<Response<T>, Status> {
+        let Some(name) = encoding.and_then(encoding_name) else {
+            return future;
+        };
+        Box::pin(async move {
+            let mut response = future.await?;
+            response
+                .metadata_mut()
+                .insert("grpc-encoding", name.parse().unwrap());
+            Ok(response)
+        })
+    }
 }
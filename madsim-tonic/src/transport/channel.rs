@@ -1,15 +1,14 @@
 //! Client implementation and builder.
 
-use super::Error;
-use madsim::rand::Rng;
+use super::{circuit_breaker::CircuitBreaker, flow_control::WindowSize, Error};
+use madsim::time::Instant;
 use std::{
-    collections::HashMap,
     fmt,
     hash::Hash,
     io,
     net::SocketAddr,
     str::FromStr,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, Weak},
     time::Duration,
 };
 use tokio::sync::mpsc::{channel, Receiver, Sender};
@@ -18,6 +17,7 @@ use tonic::{
     transport::Uri,
 };
 use tower::discover::Change;
+use tracing::debug;
 
 /// Channel builder.
 #[derive(Debug, Clone)]
@@ -25,6 +25,14 @@ pub struct Endpoint {
     uri: Uri,
     timeout: Option<Duration>,
     connect_timeout: Option<Duration>,
+    circuit_breaker: Option<(usize, Duration)>,
+    prewarm: usize,
+    idle_timeout: Option<Duration>,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_while_idle: bool,
+    window: WindowSize,
+    /// Connections established in advance.
+    warm: Arc<WarmPool>,
 }
 
 impl Endpoint {
@@ -69,6 +77,56 @@ impl Endpoint {
         }
     }
 
+    /// Enable a circuit breaker on the channel.
+    ///
+    /// The circuit opens after `threshold` consecutive failed calls, and then all calls fail fast
+    /// with `Unavailable` until `cooldown` has elapsed on the simulated clock.
+    /// After the cooldown, a single trial call decides whether to close the circuit again.
+    ///
+    /// A call is considered failed if it returns `Unavailable`, `DeadlineExceeded`, `Unknown` or
+    /// `Internal`.
+    pub fn circuit_breaker(self, threshold: usize, cooldown: Duration) -> Self {
+        Endpoint {
+            circuit_breaker: Some((threshold, cooldown)),
+            ..self
+        }
+    }
+
+    /// Establish `n` connections in advance when connecting.
+    ///
+    /// The handshakes are paid up front in [`connect`](Self::connect), so the first calls find
+    /// warm connections. A connection is returned to the pool after each call. Connections
+    /// made while the pool is empty are closed after the call if the pool is full by then.
+    pub fn prewarm_connections(self, n: usize) -> Self {
+        Endpoint { prewarm: n, ..self }
+    }
+
+    /// Close pooled connections that have been idle for `dur`.
+    ///
+    /// A connection is idle since a call was last started on it. The next call after an idle
+    /// connection is closed transparently establishes a new one. Keep-alive pings sent while idle
+    /// (see [`keep_alive_while_idle`](Self::keep_alive_while_idle)) count as activity, so the
+    /// connection is never closed if they are sent more often than `dur`.
+    ///
+    /// This only applies to connections pooled by
+    /// [`prewarm_connections`](Self::prewarm_connections), as otherwise each call uses a new
+    /// connection.
+    pub fn idle_timeout(self, dur: Duration) -> Self {
+        Endpoint {
+            idle_timeout: Some(dur),
+            ..self
+        }
+    }
+
+    /// Returns how long a pooled connection can be idle before it is closed.
+    fn idle_close_after(&self) -> Option<Duration> {
+        let timeout = self.idle_timeout?;
+        match self.keep_alive_interval {
+            Some(interval) if self.keep_alive_while_idle && interval < timeout => None,
+            _ => Some(timeout),
+        }
+    }
+
     /// Create a channel from this config.
     pub async fn connect(&self) -> Result<Channel, Error> {
         if let Some(dur) = self.connect_timeout {
@@ -84,10 +142,26 @@ impl Endpoint {
     async fn connect_inner(&self) -> Result<Channel, Error> {
         // check if the endpoint is available
         let _ep = self.connect_ep().await?;
-        Ok(Channel {
+        while self.warm.len() < self.prewarm {
+            let ep = self.connect_ep().await?;
+            self.warm.put(WarmConnection::new(ep));
+        }
+        Ok(self.connect_lazy())
+    }
+
+    /// Create a channel without connecting.
+    ///
+    /// The connection is established by the first call, which fails with `Unavailable` if the
+    /// server can't be connected at that time. So the channel can be created before the server
+    /// is up. Connections are not established in advance even if
+    /// [`prewarm_connections`](Self::prewarm_connections) is set.
+    pub fn connect_lazy(&self) -> Channel {
+        Channel {
             ep: MultiEndpoint::new_one(self.clone()),
             timeout: self.timeout,
-        })
+            circuit_breaker: (self.circuit_breaker)
+                .map(|(threshold, cooldown)| Arc::new(CircuitBreaker::new(threshold, cooldown))),
+        }
     }
 
     /// Connect to a madsim Endpoint.
@@ -145,15 +219,22 @@ impl Endpoint {
 
     /// Sets the `SETTINGS_INITIAL_WINDOW_SIZE` option for HTTP2
     /// stream-level flow control.
-    pub fn initial_stream_window_size(self, _sz: impl Into<Option<u32>>) -> Self {
-        // ignore this setting
-        self
+    ///
+    /// In simulation, a server that runs out of the window pauses sending responses for a
+    /// round trip, as if waiting for a `WINDOW_UPDATE` frame. Requests are not flow controlled.
+    pub fn initial_stream_window_size(self, sz: impl Into<Option<u32>>) -> Self {
+        let mut window = self.window;
+        window.stream = sz.into().unwrap_or(WindowSize::default().stream);
+        Endpoint { window, ..self }
     }
 
     /// Sets the max connection-level flow control for HTTP2
-    pub fn initial_connection_window_size(self, _sz: impl Into<Option<u32>>) -> Self {
-        // ignore this setting
-        self
+    ///
+    /// See [`initial_stream_window_size`](Self::initial_stream_window_size) for the simulation.
+    pub fn initial_connection_window_size(self, sz: impl Into<Option<u32>>) -> Self {
+        let mut window = self.window;
+        window.connection = sz.into().unwrap_or(WindowSize::default().connection);
+        Endpoint { window, ..self }
     }
 
     /// Set the value of `TCP_NODELAY` option for accepted connections. Enabled by default.
@@ -163,9 +244,13 @@ impl Endpoint {
     }
 
     /// Set http2 KEEP_ALIVE_INTERVAL. Uses `hyper`'s default otherwise.
-    pub fn http2_keep_alive_interval(self, _interval: Duration) -> Self {
-        // ignore this setting
-        self
+    ///
+    /// In simulation, pings only matter to [`idle_timeout`](Self::idle_timeout).
+    pub fn http2_keep_alive_interval(self, interval: Duration) -> Self {
+        Endpoint {
+            keep_alive_interval: Some(interval),
+            ..self
+        }
     }
 
     /// Set http2 KEEP_ALIVE_TIMEOUT. Uses `hyper`'s default otherwise.
@@ -175,9 +260,11 @@ impl Endpoint {
     }
 
     /// Set http2 KEEP_ALIVE_WHILE_IDLE. Uses `hyper`'s default otherwise.
-    pub fn keep_alive_while_idle(self, _enabled: bool) -> Self {
-        // ignore this setting
-        self
+    pub fn keep_alive_while_idle(self, enabled: bool) -> Self {
+        Endpoint {
+            keep_alive_while_idle: enabled,
+            ..self
+        }
     }
 
     /// Sets whether to use an adaptive flow control. Uses `hyper`'s default otherwise.
@@ -193,6 +280,13 @@ impl From<Uri> for Endpoint {
             uri,
             timeout: None,
             connect_timeout: None,
+            circuit_breaker: None,
+            prewarm: 0,
+            idle_timeout: None,
+            keep_alive_interval: None,
+            keep_alive_while_idle: false,
+            window: WindowSize::default(),
+            warm: Default::default(),
         }
     }
 }
@@ -229,6 +323,7 @@ const DEFAULT_BUFFER_SIZE: usize = 1024;
 pub struct Channel {
     pub(crate) ep: MultiEndpoint,
     pub(crate) timeout: Option<Duration>,
+    pub(crate) circuit_breaker: Option<Arc<CircuitBreaker>>,
 }
 
 impl Channel {
@@ -236,6 +331,9 @@ impl Channel {
     ///
     /// This creates a [`Channel`] that will load balance across all the
     /// provided endpoints.
+    ///
+    /// In simulation, calls are sent to the endpoints in round-robin. An endpoint that can't be
+    /// connected is skipped for a second, and the call is sent to the next one instead.
     pub fn balance_list(list: impl Iterator<Item = Endpoint>) -> Self {
         let (channel, tx) = Self::balance_channel(DEFAULT_BUFFER_SIZE);
         list.for_each(|endpoint| {
@@ -257,6 +355,7 @@ impl Channel {
         let channel = Self {
             ep: MultiEndpoint::new_multi(rx),
             timeout: None,
+            circuit_breaker: None,
         };
         (channel, tx)
     }
@@ -280,80 +379,238 @@ impl MultiEndpoint {
         K: Hash + Eq + Send + Clone + 'static,
     {
         Self {
-            balance: Arc::new(DynamicEp::new(HashMap::new(), Some(rx))),
+            balance: Arc::new(DynamicEp::new(vec![], Some(rx))),
         }
     }
 
     /// Creates with one endpoint
     pub(crate) fn new_one(ep: Endpoint) -> Self {
         Self {
-            balance: Arc::new(DynamicEp::new([((), ep)].into_iter().collect(), None)),
+            balance: Arc::new(DynamicEp::new(vec![((), ep)], None)),
         }
     }
 
+    /// Connect to the next endpoint in rotation.
+    ///
+    /// If the endpoint is unreachable, it is taken out of rotation and the next one is tried.
+    /// Returns the connection, the flow control windows of the endpoint, and the lease of the
+    /// pooled connection if any.
     pub(crate) async fn connect1(
         &self,
-    ) -> io::Result<(madsim::net::Sender, madsim::net::Receiver)> {
-        let ep = self.balance.get_one().ok_or(io::Error::new(
-            io::ErrorKind::AddrNotAvailable,
-            "no endpoints available",
-        ))?;
-        let madsim_ep = ep
-            .connect_ep()
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
-        let addr = madsim_ep.peer_addr().unwrap();
-        madsim_ep.connect1(addr).await
+    ) -> io::Result<(
+        madsim::net::Sender,
+        madsim::net::Receiver,
+        WindowSize,
+        Option<ConnectionLease>,
+    )> {
+        let mut last_err = None;
+        for ep in self.balance.candidates() {
+            match Self::connect_to(&ep).await {
+                Ok((tx, rx, lease)) => {
+                    self.balance.mark_up(&ep.uri);
+                    return Ok((tx, rx, ep.window, lease));
+                }
+                Err(e) => {
+                    debug!(uri = %ep.uri, error = %e, "endpoint unreachable");
+                    self.balance.mark_down(&ep.uri);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::AddrNotAvailable, "no endpoints available")
+        }))
+    }
+
+    async fn connect_to(
+        ep: &Endpoint,
+    ) -> io::Result<(
+        madsim::net::Sender,
+        madsim::net::Receiver,
+        Option<ConnectionLease>,
+    )> {
+        let conn =
+            match ep.warm.take(ep.idle_close_after()) {
+                Some(conn) => conn,
+                None => WarmConnection::new(ep.connect_ep().await.map_err(|e| {
+                    io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string())
+                })?),
+            };
+        let addr = conn.ep.peer_addr().unwrap();
+        let (tx, rx) = conn.ep.connect1(addr).await?;
+        if ep.warm.len() < ep.prewarm {
+            let lease = ConnectionLease(Arc::downgrade(&conn.alive));
+            ep.warm.put(conn);
+            return Ok((tx, rx, Some(lease)));
+        }
+        // the pool is full, close the extra connection after the call
+        Ok((tx, rx, None))
+    }
+}
+
+/// Tells the server whether a pooled connection is still open.
+///
+/// It is sent in the extensions of each call on a pooled connection, so that the server can
+/// release the state of the connection once the client has closed it.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionLease(Weak<()>);
+
+impl ConnectionLease {
+    /// Returns whether the connection is still open.
+    pub(crate) fn is_alive(&self) -> bool {
+        self.0.strong_count() > 0
     }
 }
 
+/// A pooled connection.
+struct WarmConnection {
+    ep: madsim::net::Endpoint,
+    /// Dropped with the connection to expire its [`ConnectionLease`]s.
+    alive: Arc<()>,
+}
+
+impl WarmConnection {
+    fn new(ep: madsim::net::Endpoint) -> Self {
+        WarmConnection {
+            ep,
+            alive: Arc::new(()),
+        }
+    }
+}
+
+/// A pool of connected endpoints.
+#[derive(Default)]
+struct WarmPool {
+    /// Connections and the time they were last used.
+    eps: Mutex<Vec<(WarmConnection, Instant)>>,
+}
+
+impl WarmPool {
+    fn len(&self) -> usize {
+        self.eps.lock().unwrap().len()
+    }
+
+    /// Take a connection, closing those idle for `idle_timeout`.
+    fn take(&self, idle_timeout: Option<Duration>) -> Option<WarmConnection> {
+        let mut eps = self.eps.lock().unwrap();
+        if let Some(timeout) = idle_timeout {
+            let now = Instant::now();
+            eps.retain(|(conn, last_used)| {
+                let alive = now - *last_used < timeout;
+                if !alive {
+                    debug!(addr = ?conn.ep.local_addr(), "close idle connection");
+                }
+                alive
+            });
+        }
+        eps.pop().map(|(conn, _)| conn)
+    }
+
+    fn put(&self, conn: WarmConnection) {
+        self.eps.lock().unwrap().push((conn, Instant::now()));
+    }
+}
+
+impl fmt::Debug for WarmPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarmPool")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// How long an unreachable endpoint is out of rotation before it is tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Dynamically monitor changes of endpoints
 pub(crate) struct DynamicEp<K> {
-    eps: Mutex<HashMap<K, Endpoint>>,
+    state: Mutex<State<K>>,
     rx: Option<Mutex<Receiver<Change<K, Endpoint>>>>,
 }
 
+struct State<K> {
+    /// Endpoints in the order they are inserted, and the time until which they are down.
+    eps: Vec<(K, Endpoint, Option<Instant>)>,
+    /// The index of the next endpoint in rotation.
+    next: usize,
+}
+
 impl<K> DynamicEp<K>
 where
     K: Hash + Eq + Send + Clone + 'static,
 {
-    pub(crate) fn new(
-        eps: HashMap<K, Endpoint>,
-        rx: Option<Receiver<Change<K, Endpoint>>>,
-    ) -> Self {
+    pub(crate) fn new(eps: Vec<(K, Endpoint)>, rx: Option<Receiver<Change<K, Endpoint>>>) -> Self {
+        let eps = eps.into_iter().map(|(k, ep)| (k, ep, None)).collect();
         Self {
-            eps: Mutex::new(eps),
+            state: Mutex::new(State { eps, next: 0 }),
             rx: rx.map(Mutex::new),
         }
     }
+
+    fn set_down_until(&self, uri: &Uri, until: Option<Instant>) {
+        let mut state = self.state.lock().unwrap();
+        for (_, ep, down_until) in &mut state.eps {
+            if ep.uri == *uri {
+                *down_until = until;
+            }
+        }
+    }
 }
 
 impl<K> Balance for DynamicEp<K>
 where
     K: Hash + Eq + Send + Clone + 'static,
 {
-    fn get_one(&self) -> Option<Endpoint> {
-        let mut eps = self.eps.lock().unwrap();
+    fn candidates(&self) -> Vec<Endpoint> {
+        let mut state = self.state.lock().unwrap();
         if let Some(rx) = self.rx.as_ref() {
             let mut rx_l = rx.lock().unwrap();
             while let Ok(change) = rx_l.try_recv() {
                 match change {
-                    Change::Insert(k, ep) => eps.insert(k, ep),
-                    Change::Remove(k) => eps.remove(&k),
+                    Change::Insert(k, ep) => match state.eps.iter_mut().find(|(k0, ..)| *k0 == k) {
+                        Some(entry) => *entry = (k, ep, None),
+                        None => state.eps.push((k, ep, None)),
+                    },
+                    Change::Remove(k) => state.eps.retain(|(k0, ..)| *k0 != k),
                 };
             }
         }
-        let len = eps.len();
+        let len = state.eps.len();
         if len == 0 {
-            return None;
+            return vec![];
         }
-        let n = madsim::rand::thread_rng().gen_range(0..len);
-        eps.values().nth(n).cloned()
+        let now = Instant::now();
+        let (up, down): (Vec<_>, Vec<_>) = (0..len)
+            .map(|i| (state.next + i) % len)
+            .partition(|&i| state.eps[i].2.map_or(true, |until| until <= now));
+        if let Some(&first) = up.first() {
+            state.next = (first + 1) % len;
+        }
+        // try unreachable endpoints as a last resort
+        (up.into_iter().chain(down))
+            .map(|i| state.eps[i].1.clone())
+            .collect()
+    }
+
+    fn mark_down(&self, uri: &Uri) {
+        self.set_down_until(uri, Some(Instant::now() + RETRY_INTERVAL));
+    }
+
+    fn mark_up(&self, uri: &Uri) {
+        self.set_down_until(uri, None);
     }
 }
 
 /// Balance among endpoints
 pub(crate) trait Balance: Send + Sync {
-    /// Get a random endpoint.
-    fn get_one(&self) -> Option<Endpoint>;
+    /// Returns endpoints in the order to try.
+    ///
+    /// Reachable endpoints are picked in round-robin, followed by unreachable ones.
+    fn candidates(&self) -> Vec<Endpoint>;
+
+    /// Take an unreachable endpoint out of rotation for a while.
+    fn mark_down(&self, uri: &Uri);
+
+    /// Put an endpoint back into rotation.
+    fn mark_up(&self, uri: &Uri);
 }
@@ -0,0 +1,127 @@
+//! Client side circuit breaker.
+
+use crate::{Code, Status};
+use madsim::time::Instant;
+use std::{sync::Mutex, time::Duration};
+use tracing::debug;
+
+/// A circuit breaker shared by all clones of a [`Channel`](super::Channel).
+///
+/// The circuit opens after `threshold` consecutive failures. While it is open, calls are
+/// rejected with `Unavailable` without touching the network. Once `cooldown` has elapsed,
+/// one trial call is let through: success closes the circuit, failure opens it again.
+///
+/// A call that is cancelled before it completes, e.g. because the client future is dropped,
+/// counts as a failure.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    threshold: usize,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { failures: usize },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: usize, cooldown: Duration) -> Self {
+        assert!(threshold > 0, "failure threshold must be positive");
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Check whether a call is allowed.
+    ///
+    /// The outcome of the call must be recorded by [`Call::finish`].
+    pub(crate) fn check(&self) -> Result<Call<'_>, Status> {
+        let mut state = self.state.lock().unwrap();
+        let call = Call {
+            breaker: self,
+            finished: false,
+        };
+        match *state {
+            State::Closed { .. } => Ok(call),
+            State::Open { until } if Instant::now() >= until => {
+                debug!("circuit half-open");
+                *state = State::HalfOpen;
+                Ok(call)
+            }
+            // only one trial call is allowed in half-open state
+            State::Open { .. } | State::HalfOpen => {
+                Err(Status::unavailable("circuit breaker is open"))
+            }
+        }
+    }
+
+    /// Record whether a call failed.
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        match (&mut *state, failed) {
+            (State::Closed { failures }, true) => {
+                *failures += 1;
+                if *failures >= self.threshold {
+                    debug!(failures = *failures, "circuit open");
+                    *state = self.open();
+                }
+            }
+            (State::Closed { failures }, false) => *failures = 0,
+            (State::HalfOpen, true) => {
+                debug!("circuit open");
+                *state = self.open();
+            }
+            (State::HalfOpen, false) => {
+                debug!("circuit closed");
+                *state = State::Closed { failures: 0 };
+            }
+            (State::Open { .. }, _) => {}
+        }
+    }
+
+    fn open(&self) -> State {
+        State::Open {
+            until: Instant::now() + self.cooldown,
+        }
+    }
+}
+
+/// A call allowed by the [`CircuitBreaker`].
+///
+/// Dropping it without [`finish`](Call::finish) records a failure.
+pub(crate) struct Call<'a> {
+    breaker: &'a CircuitBreaker,
+    finished: bool,
+}
+
+impl Call<'_> {
+    /// Record the result of the call.
+    pub(crate) fn finish<T>(mut self, result: &Result<T, Status>) {
+        self.finished = true;
+        let failed = matches!(result, Err(status) if is_failure(status.code()));
+        self.breaker.record(failed);
+    }
+}
+
+impl Drop for Call<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            debug!("call cancelled");
+            self.breaker.record(true);
+        }
+    }
+}
+
+/// Returns whether the status code indicates a failure of the server or the network,
+/// rather than an error returned by the application.
+fn is_failure(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::Unknown | Code::Internal
+    )
+}
@@ -0,0 +1,67 @@
+//! HTTP/2 flow control of responses.
+
+use madsim::{net::NetSim, plugin, time::sleep};
+use std::{net::IpAddr, time::Duration};
+use tracing::debug;
+
+/// The initial flow control windows advertised by a client.
+///
+/// It is carried to the server in the extensions of requests, like a `SETTINGS` frame
+/// sent when the connection is established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WindowSize {
+    pub(crate) stream: u32,
+    pub(crate) connection: u32,
+}
+
+impl Default for WindowSize {
+    /// The defaults of `hyper` clients.
+    fn default() -> Self {
+        WindowSize {
+            stream: 2 * 1024 * 1024,
+            connection: 5 * 1024 * 1024,
+        }
+    }
+}
+
+/// The send window of a response stream.
+///
+/// Each call has its own connection in simulation, so the stream and connection windows are
+/// consumed together, and the smaller one takes effect. The client releases the window as soon
+/// as data arrives, so a sender that runs out of the window pauses for a round trip until the
+/// `WINDOW_UPDATE` frame comes back.
+#[derive(Debug)]
+pub(crate) struct FlowControl {
+    window: usize,
+    available: usize,
+    /// The address of the client.
+    peer: IpAddr,
+}
+
+impl FlowControl {
+    pub(crate) fn new(size: WindowSize, peer: IpAddr) -> Self {
+        let window = size.stream.min(size.connection).max(1) as usize;
+        FlowControl {
+            window,
+            available: window,
+            peer,
+        }
+    }
+
+    /// Wait until `len` bytes can be sent.
+    pub(crate) async fn reserve(&mut self, mut len: usize) {
+        while len > self.available {
+            len -= self.available;
+            debug!(window = self.window, "flow control pause");
+            sleep(self.round_trip()).await;
+            self.available = self.window;
+        }
+        self.available -= len;
+    }
+
+    /// Samples the time for data to reach the client and the window update to come back.
+    fn round_trip(&self) -> Duration {
+        // zero if the client is gone, as nobody waits for the data
+        (NetSim::current().sample_round_trip(plugin::node(), self.peer)).unwrap_or_default()
+    }
+}
@@ -6,7 +6,9 @@ pub use self::server::Server;
 pub use tonic::codegen::http::Uri;
 
 pub mod channel;
+pub(crate) mod circuit_breaker;
 mod error;
+pub(crate) mod flow_control;
 pub mod server;
 
 /// A trait to provide a static reference to the service's
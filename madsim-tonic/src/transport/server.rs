@@ -1,20 +1,29 @@
 //! Server implementation and builder.
 
+use super::channel::ConnectionLease;
+use super::flow_control::{FlowControl, WindowSize};
 use super::{Error, NamedService};
-use crate::codegen::{BoxMessage, BoxMessageStream, RequestExt};
+use crate::codegen::{unwrap_encoded, BoxMessage, BoxMessageStream, RequestExt};
 use crate::sim::AppendMetadata;
 use crate::tower::layer::util::{Identity, Stack};
-use crate::{Request, Response, Status};
+use crate::{metadata::MetadataMap, Extensions, Request, Response, Status};
 use async_stream::try_stream;
 use futures_util::{future::poll_fn, select_biased, FutureExt, StreamExt};
 use madsim::net::Endpoint;
 use std::{
+    any::Any,
     collections::HashMap,
+    fmt,
     future::{pending, Future},
     marker::PhantomData,
     net::SocketAddr,
+    panic::AssertUnwindSafe,
+    pin::pin,
+    sync::Arc,
+    task::Poll,
     time::Duration,
 };
+use tokio::sync::Semaphore;
 use tonic::codegen::{http::uri::PathAndQuery, BoxFuture, Service};
 #[cfg(feature = "tls")]
 use tonic::transport::ServerTlsConfig;
@@ -23,13 +32,46 @@ use tracing::*;
 /// A default batteries included `transport` server.
 #[derive(Clone, Debug)]
 pub struct Server<L = Identity> {
+    config: Config,
     _mark: PhantomData<L>,
 }
 
+/// Simulated server configurations.
+#[derive(Clone, Debug, Default)]
+struct Config {
+    /// Rate limits of methods: path -> (number of calls, period).
+    method_rate_limits: HashMap<String, (u64, Duration)>,
+    /// Calls fail with `Unavailable` until this long after the server starts.
+    readiness_delay: Duration,
+    /// Establishes the state of each connection.
+    connection_init: Option<ConnectionInit>,
+    /// The maximum number of calls handled at the same time.
+    concurrency_limit: Option<usize>,
+    /// Whether calls beyond the concurrency limit wait instead of failing.
+    queue_overloaded: bool,
+}
+
+/// Returns the state of a new connection, which inserts itself into the extensions of requests.
+#[derive(Clone)]
+struct ConnectionInit(
+    Arc<dyn Fn(SocketAddr, &MetadataMap) -> Result<ConnectionState, Status> + Send + Sync>,
+);
+
+type ConnectionState = Arc<dyn Fn(&mut Extensions) + Send + Sync>;
+
+impl fmt::Debug for ConnectionInit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionInit").finish_non_exhaustive()
+    }
+}
+
 #[allow(clippy::derivable_impls)]
 impl Default for Server {
     fn default() -> Self {
-        Self { _mark: PhantomData }
+        Self {
+            config: Config::default(),
+            _mark: PhantomData,
+        }
     }
 }
 
@@ -64,7 +106,77 @@ impl<L> Server<L> {
     /// Set the Tower Layer all services will be wrapped in.
     pub fn layer<NewLayer>(self, _new_layer: NewLayer) -> Server<Stack<NewLayer, L>> {
         tracing::warn!("layer is unimplemented and ignored");
-        Server { _mark: PhantomData }
+        Server {
+            config: self.config,
+            _mark: PhantomData,
+        }
+    }
+
+    /// Limit the rate of calls to a method.
+    ///
+    /// At most `num` calls to the method at `path` (e.g. `/helloworld.Greeter/SayHello`) are
+    /// accepted per `per` on the simulated clock, implemented as a token bucket allowing bursts
+    /// of up to `num` calls. Excess calls fail with `ResourceExhausted`.
+    #[must_use]
+    pub fn method_rate_limit(mut self, path: impl Into<String>, num: u64, per: Duration) -> Self {
+        assert!(num > 0, "rate limit must be positive");
+        (self.config.method_rate_limits).insert(path.into(), (num, per));
+        self
+    }
+
+    /// Reject calls with `Unavailable` for `delay` after the server starts.
+    ///
+    /// The server accepts connections immediately, like a real server that is still initializing.
+    #[must_use]
+    pub fn readiness_delay(mut self, delay: Duration) -> Self {
+        self.config.readiness_delay = delay;
+        self
+    }
+
+    /// Limit the number of calls handled by the server at the same time.
+    ///
+    /// A call is counted from its arrival until its response, including a response stream, has
+    /// been sent. Calls beyond the limit fail with `ResourceExhausted`, unless
+    /// [`queue_overloaded`](Server::queue_overloaded) is set.
+    #[must_use]
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "concurrency limit must be positive");
+        self.config.concurrency_limit = Some(limit);
+        self
+    }
+
+    /// Let calls beyond the [concurrency limit](Server::concurrency_limit) wait for earlier calls
+    /// to complete, in the order they arrive, instead of failing.
+    #[must_use]
+    pub fn queue_overloaded(mut self, enabled: bool) -> Self {
+        self.config.queue_overloaded = enabled;
+        self
+    }
+
+    /// Establish per-connection state, e.g. the identity of an authenticated client.
+    ///
+    /// `init` is called with the remote address and the metadata of the first call on each
+    /// connection. The returned value is inserted into the [extensions](Request::extensions) of
+    /// this call and all later calls on the connection, without calling `init` again.
+    /// If `init` returns an error, the call fails with it and the next call tries again.
+    ///
+    /// In simulation, a connection is a client socket. A [`Channel`](super::Channel) reuses
+    /// connections only if [`prewarm_connections`](super::Endpoint::prewarm_connections) is set.
+    #[must_use]
+    pub fn connection_init<T, F>(mut self, init: F) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(SocketAddr, &MetadataMap) -> Result<T, Status> + Send + Sync + 'static,
+    {
+        let init = move |addr: SocketAddr, metadata: &MetadataMap| {
+            let state = init(addr, metadata)?;
+            let state: ConnectionState = Arc::new(move |extensions: &mut Extensions| {
+                extensions.insert(state.clone());
+            });
+            Ok(state)
+        };
+        self.config.connection_init = Some(ConnectionInit(Arc::new(init)));
+        self
     }
 
     /// Configure TLS for this server.
@@ -92,14 +204,14 @@ impl<L> Server<L> {
     /// Sets the `SETTINGS_INITIAL_WINDOW_SIZE` option for HTTP2 stream-level flow control.
     #[must_use]
     pub fn initial_stream_window_size(self, _sz: impl Into<Option<u32>>) -> Self {
-        // ignore this setting
+        // ignore this setting, requests are not flow controlled in simulation
         self
     }
 
     /// Sets the max connection-level flow control for HTTP2
     #[must_use]
     pub fn initial_connection_window_size(self, _sz: impl Into<Option<u32>>) -> Self {
-        // ignore this setting
+        // ignore this setting, requests are not flow controlled in simulation
         self
     }
 
@@ -222,6 +334,17 @@ impl<L> Router<L> {
         let ep = Endpoint::bind(addr).await.map_err(Error::from_source)?;
         let local_addr = ep.local_addr().unwrap();
         let mut signal = Box::pin(signal).fuse();
+        let mut rate_limiters: HashMap<String, TokenBucket> = (self.server.config)
+            .method_rate_limits
+            .iter()
+            .map(|(path, &(num, per))| (path.clone(), TokenBucket::new(num, per)))
+            .collect();
+        let ready_at = madsim::time::Instant::now() + self.server.config.readiness_delay;
+        // the state of pooled connections, dropped when the client closes the connection
+        let mut connections: HashMap<SocketAddr, (ConnectionLease, ConnectionState)> =
+            HashMap::new();
+        let concurrency =
+            (self.server.config.concurrency_limit).map(|n| Arc::new(Semaphore::new(n)));
         loop {
             // receive a request
             let (tx, mut rx, addr) = select_biased! {
@@ -230,23 +353,42 @@ impl<L> Router<L> {
             };
             let msg = match rx.recv().await {
                 Ok(msg) => msg,
-                Err(_) => continue, // maybe handshake or error
+                Err(_) => {
+                    // maybe handshake or error
+                    // a handshake starts a new connection from the address
+                    connections.remove(&addr);
+                    continue;
+                }
             };
-            let (path, server_streaming, mut request) = *msg
+            let (path, server_streaming, request) = *msg
                 .downcast::<(PathAndQuery, bool, Request<BoxMessage>)>()
                 .expect("invalid type");
             let span = debug_span!("request", ?addr, ?path);
             debug!(parent: &span, "received");
 
+            let (mut metadata, extensions, mut msg) = request.into_parts();
+            let single = msg.downcast_ref::<()>().is_none();
+            let message = if single { Some(&mut msg) } else { None };
+            if let Err(err) =
+                crate::fault::intercept_request(path.path(), Some(&mut metadata), message)
+            {
+                reply_error(tx, server_streaming, err);
+                continue;
+            }
+            let mut request = Request::from_parts(metadata, extensions, msg);
+
             request.set_tcp_connect_info(local_addr, addr);
+            let window = (request.extensions_mut().remove::<WindowSize>()).unwrap_or_default();
+            let stream_path = path.clone();
             let request: Request<BoxMessageStream> = request.map(move |msg| {
-                if msg.downcast_ref::<()>().is_none() {
+                if single {
                     // single request
                     try_stream! { yield msg; }.boxed()
                 } else {
                     // request stream
                     try_stream! {
-                        while let Ok(msg) = rx.recv().await {
+                        while let Ok(mut msg) = rx.recv().await {
+                            crate::fault::intercept_request(stream_path.path(), None, Some(&mut msg))?;
                             yield msg;
                         }
                     }
@@ -254,27 +396,99 @@ impl<L> Router<L> {
                 }
             });
 
+            if madsim::time::Instant::now() < ready_at {
+                debug!(parent: &span, "not ready");
+                let err = Status::unavailable("server is not ready");
+                reply_error(tx, server_streaming, err);
+                continue;
+            }
+            if let Err(err) = crate::fault::check(path.path(), request.metadata()) {
+                reply_error(tx, server_streaming, err);
+                continue;
+            }
+            // a call without a lease is the only call on its connection
+            let lease = request.extensions_mut().remove::<ConnectionLease>();
+            if let Some(ConnectionInit(init)) = &self.server.config.connection_init {
+                let state = match connections.get(&addr) {
+                    Some((lease, state)) if lease.is_alive() => state.clone(),
+                    _ => match init(addr, request.metadata()) {
+                        Ok(state) => {
+                            debug!(parent: &span, "connection initialized");
+                            if let Some(lease) = lease {
+                                connections.retain(|_, (lease, _)| lease.is_alive());
+                                connections.insert(addr, (lease, state.clone()));
+                            }
+                            state
+                        }
+                        Err(err) => {
+                            reply_error(tx, server_streaming, err);
+                            continue;
+                        }
+                    },
+                };
+                state(request.extensions_mut());
+            }
+
             // call the service in a new spawned task
             let svc_name = path.path().split('/').nth(1).unwrap();
             let Some(svc) = &mut self.services.get_mut(svc_name) else {
-                // return error Unimplemented
-                madsim::task::spawn(async move {
-                    let mut err = Status::unimplemented(format!("service not found: {path}"));
-                    err.metadata_mut().append_metadata();
-                    let msg: BoxMessage = if server_streaming {
-                        Box::new(Err(err) as Result<Response<()>, Status>)
-                    } else {
-                        Box::new(Err(err) as Result<Response<BoxMessage>, Status>)
-                    };
-                    _ = tx.send(msg).await;
-                });
+                let err = Status::unimplemented(format!("service not found: {path}"));
+                reply_error(tx, server_streaming, err);
                 continue;
             };
+            if let Some(limiter) = rate_limiters.get_mut(path.path()) {
+                if !limiter.try_acquire() {
+                    debug!(parent: &span, "rate limited");
+                    let err = Status::resource_exhausted(format!("rate limit exceeded: {path}"));
+                    reply_error(tx, server_streaming, err);
+                    continue;
+                }
+            }
+            // a permit is taken now, or after waiting in the queue
+            let (mut permit, mut queue) = (None, None);
+            if let Some(semaphore) = &concurrency {
+                if self.server.config.queue_overloaded {
+                    // poll once to enter the queue in the order of arrival
+                    let mut acquire = Box::pin(semaphore.clone().acquire_owned());
+                    match futures_util::poll!(acquire.as_mut()) {
+                        Poll::Ready(p) => permit = Some(p.unwrap()),
+                        Poll::Pending => queue = Some(acquire),
+                    }
+                } else if let Ok(p) = semaphore.clone().try_acquire_owned() {
+                    permit = Some(p);
+                } else {
+                    debug!(parent: &span, "overloaded");
+                    let err = Status::resource_exhausted("too many concurrent requests");
+                    reply_error(tx, server_streaming, err);
+                    continue;
+                }
+            }
+            // the call is cancelled after the timeout of the client
+            let deadline = match request.try_timeout() {
+                Ok(timeout) => timeout.map(|t| madsim::time::Instant::now() + t),
+                Err(err) => {
+                    debug!(parent: &span, "invalid timeout");
+                    reply_error(tx, server_streaming, err);
+                    continue;
+                }
+            };
             poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
-            let rsp_future = svc.call((path, request));
+            let rsp_future = svc.call((path.clone(), request));
             madsim::task::spawn(async move {
-                let mut result: Result<Response<BoxMessageStream>, Status> =
-                    rsp_future.instrument(span.clone()).await;
+                let _permit = match queue {
+                    Some(queue) => Some(queue.await.unwrap()),
+                    None => permit,
+                };
+                let mut flow = FlowControl::new(window, addr.ip());
+                // a panicking handler fails the call instead of the whole simulation
+                let handler = AssertUnwindSafe(rsp_future.instrument(span.clone())).catch_unwind();
+                let mut result: Result<Response<BoxMessageStream>, Status> = select_biased! {
+                    result = handler.fuse() => match result {
+                        Ok(result) => result,
+                        Err(payload) => Err(handler_panicked(&span, payload)),
+                    },
+                    _ = expired(deadline).fuse() => Err(deadline_exceeded(&span)),
+                };
                 result.append_metadata();
                 if server_streaming {
                     let (header, stream) = match result {
@@ -285,17 +499,30 @@ impl<L> Router<L> {
                         }
                         Err(e) => (Err(e), None),
                     };
+                    let header = crate::fault::intercept_response(path.path(), header);
+                    let stream = if header.is_ok() { stream } else { None };
                     // send the header
                     tx.send(Box::new(header)).await?;
                     // send the stream
-                    let Some(mut stream) = stream else {
+                    let Some(stream) = stream else {
                         return Ok(());
                     };
+                    // the stream ends after the handler panics
+                    let mut stream = AssertUnwindSafe(stream).catch_unwind().map(|msg| {
+                        msg.unwrap_or_else(|payload| Err(handler_panicked(&span, payload)))
+                    });
                     let mut count = 0;
+                    let mut expired = pin!(expired(deadline).fuse());
                     loop {
                         let msg = select_biased! {
+                            // dropping the stream lets the handler observe the cancellation
                             _ = tx.closed().fuse() => {
-                                debug!(parent: &span, "client closed");
+                                debug!(parent: &span, "client closed after {count}");
+                                return Ok(());
+                            }
+                            _ = expired => {
+                                let msg: Result<BoxMessage, Status> = Err(deadline_exceeded(&span));
+                                tx.send(Box::new(msg)).await?;
                                 return Ok(());
                             }
                             msg = stream.next().fuse() => match msg {
@@ -303,6 +530,15 @@ impl<L> Router<L> {
                                 None => break,
                             }
                         };
+                        let msg = match msg {
+                            Ok(msg) => {
+                                let (msg, len) = unwrap_encoded(msg);
+                                flow.reserve(len).instrument(span.clone()).await;
+                                Ok(msg)
+                            }
+                            Err(e) => Err(e),
+                        };
+                        let msg = crate::fault::intercept_response(path.path(), msg);
                         // rsp: Result<BoxMessage, Status>
                         tx.send(Box::new(msg)).await?;
                         count += 1;
@@ -321,10 +557,13 @@ impl<L> Router<L> {
                                 }
                                 msg = stream.next().fuse() => msg.unwrap().unwrap(),
                             };
+                            let (inner, len) = unwrap_encoded(inner);
+                            flow.reserve(len).instrument(span.clone()).await;
                             Ok(Response::from_parts(metadata, inner, extensions))
                         }
                         Err(e) => Err(e),
                     };
+                    let rsp = crate::fault::intercept_response(path.path(), rsp);
                     // send the response
                     tx.send(Box::new(rsp)).await?;
                     debug!(parent: &span, "completed");
@@ -334,3 +573,77 @@ impl<L> Router<L> {
         }
     }
 }
+
+/// Completes when the deadline of a call passes, or never if there is no deadline.
+async fn expired(deadline: Option<madsim::time::Instant>) {
+    match deadline {
+        Some(deadline) => madsim::time::sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
+
+/// Log the cancellation of a call after its deadline and return the status of the call.
+fn deadline_exceeded(span: &Span) -> Status {
+    debug!(parent: span, "deadline exceeded");
+    Status::deadline_exceeded("Timeout expired")
+}
+
+/// Log the panic of a handler and return the status of the call.
+fn handler_panicked(span: &Span, payload: Box<dyn Any + Send>) -> Status {
+    let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "Box<dyn Any>"
+    };
+    error!(parent: span, "handler panicked: {msg}");
+    Status::internal(format!("handler panicked: {msg}"))
+}
+
+/// Return an error to the client without calling the service.
+fn reply_error(tx: madsim::net::Sender, server_streaming: bool, mut err: Status) {
+    madsim::task::spawn(async move {
+        err.metadata_mut().append_metadata();
+        let msg: BoxMessage = if server_streaming {
+            Box::new(Err(err) as Result<Response<()>, Status>)
+        } else {
+            Box::new(Err(err) as Result<Response<BoxMessage>, Status>)
+        };
+        _ = tx.send(msg).await;
+    });
+}
+
+/// A token bucket on the simulated clock.
+struct TokenBucket {
+    capacity: f64,
+    /// Tokens refilled per second.
+    rate: f64,
+    tokens: f64,
+    last: madsim::time::Instant,
+}
+
+impl TokenBucket {
+    fn new(num: u64, per: Duration) -> Self {
+        TokenBucket {
+            capacity: num as f64,
+            rate: num as f64 / per.as_secs_f64(),
+            tokens: num as f64,
+            last: madsim::time::Instant::now(),
+        }
+    }
+
+    /// Take a token if available.
+    fn try_acquire(&mut self) -> bool {
+        let now = madsim::time::Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -26,4 +26,6 @@ pub use _std::*;
 #[doc(hidden)]
 pub mod export {
     pub use futures_util as futures;
+    #[cfg(not(madsim))]
+    pub use tokio;
 }
@@ -63,9 +63,12 @@ mod tests {
             Config {
                 net: net::Config {
                     packet_loss_rate: 0.1,
-                    send_latency: Duration::from_millis(1)..Duration::from_millis(10)
+                    send_latency: Duration::from_millis(1)..Duration::from_millis(10),
+                    latency_correlation: 0.0,
+                    mtu: None,
+                    failure_detection_delay: Duration::ZERO..Duration::ZERO,
                 },
-                tcp: tcp::TcpConfig {}
+                tcp: tcp::TcpConfig::default(),
             }
         );
     }
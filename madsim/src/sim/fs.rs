@@ -1,18 +1,23 @@
 //! Asynchronous file system.
 
+use futures_util::future::{select, Either};
 use spin::{Mutex, RwLock};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt,
+    future::Future,
     io::{Error, ErrorKind, Result},
     path::{Path, PathBuf},
+    pin::pin,
     sync::Arc,
+    time::Duration,
 };
 use tracing::*;
 
 use crate::{
     plugin::{node, simulator, Simulator},
-    rand::GlobalRng,
+    rand::{GlobalRng, Rng},
+    sync::watch,
     task::NodeId,
     time::TimeHandle,
     Config,
@@ -20,19 +25,24 @@ use crate::{
 
 /// File system simulator.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
-#[derive(Default)]
 pub struct FsSim {
+    rand: GlobalRng,
+    time: TimeHandle,
     handles: Mutex<HashMap<NodeId, FsNodeHandle>>,
 }
 
 impl Simulator for FsSim {
-    fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &Config) -> Self {
-        Default::default()
+    fn new(rand: &GlobalRng, time: &TimeHandle, _config: &Config) -> Self {
+        FsSim {
+            rand: rand.clone(),
+            time: time.clone(),
+            handles: Mutex::new(HashMap::new()),
+        }
     }
 
     fn create_node(&self, id: NodeId) {
         let mut handles = self.handles.lock();
-        handles.insert(id, FsNodeHandle::new());
+        handles.insert(id, FsNodeHandle::new(self.rand.clone(), self.time.clone()));
     }
 
     fn reset_node(&self, id: NodeId) {
@@ -41,6 +51,11 @@ impl Simulator for FsSim {
 }
 
 impl FsSim {
+    /// Get [`FsSim`] of the current simulator.
+    pub fn current() -> Arc<Self> {
+        simulator()
+    }
+
     /// Return a handle of the specified node.
     fn get_node(&self, id: NodeId) -> FsNodeHandle {
         let handles = self.handles.lock();
@@ -48,8 +63,143 @@ impl FsSim {
     }
 
     /// Simulate a power failure. All data that does not reach the disk will be lost.
-    pub fn power_fail(&self, _id: NodeId) {
-        // TODO
+    ///
+    /// For each file with unsynced changes, whether its data and metadata (size) reach the disk
+    /// is decided randomly according to the node's [`JournalMode`].
+    pub fn power_fail(&self, id: NodeId) {
+        let handle = self.get_node(id);
+        let mode = *handle.journal_mode.lock();
+        let mut inodes: Vec<_> = (handle.fs.lock().iter())
+            .map(|(path, inode)| (path.clone(), inode.clone()))
+            .collect();
+        inodes.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, inode) in inodes {
+            let mut data = inode.data.write();
+            let mut durable = inode.durable.lock();
+            if *data == *durable {
+                continue;
+            }
+            let (data_flushed, meta_committed) = match mode {
+                JournalMode::Ordered => {
+                    let committed = self.rand.with(|rng| rng.gen_bool(0.5));
+                    (committed, committed)
+                }
+                JournalMode::Writeback => {
+                    self.rand.with(|rng| (rng.gen_bool(0.5), rng.gen_bool(0.5)))
+                }
+            };
+            let len = if meta_committed {
+                data.len()
+            } else {
+                durable.len()
+            };
+            if data_flushed {
+                durable.clone_from(&data);
+            }
+            durable.resize(len, 0);
+            trace!(?path, data_flushed, meta_committed, "power fail");
+            data.clone_from(&durable);
+        }
+    }
+
+    /// Set the journaling mode of the file system on the node.
+    pub fn set_journal_mode(&self, id: NodeId, mode: JournalMode) {
+        *self.get_node(id).journal_mode.lock() = mode;
+    }
+
+    /// Let fs operations on the node periodically stall.
+    ///
+    /// Starting from now, every `every` the disk stalls for `duration`.
+    /// Any operation issued during a stall will not complete until the stall ends.
+    pub fn inject_disk_stall(&self, id: NodeId, every: Duration, duration: Duration) {
+        assert!(!every.is_zero(), "stall period must be positive");
+        assert!(
+            duration <= every,
+            "stall duration must not exceed the period"
+        );
+        let handle = self.get_node(id);
+        *handle.stall.lock() = Some(DiskStall {
+            since: self.time.elapsed(),
+            every,
+            duration,
+        });
+    }
+
+    /// Set the block size of direct I/O on the node. The default is 512 bytes.
+    ///
+    /// Reads and writes on files opened for direct I/O fail with `EINVAL`
+    /// unless their offsets and lengths are multiples of the block size.
+    pub fn set_block_size(&self, id: NodeId, block_size: u64) {
+        assert!(block_size > 0, "block size must be positive");
+        *self.get_node(id).block_size.lock() = block_size;
+    }
+
+    /// Let write and sync operations on the node fail with an OS error, e.g. `libc::ENOSPC`.
+    ///
+    /// A failed operation has no effect on the file. `None` clears the error.
+    pub fn set_error(&self, id: NodeId, errno: Option<i32>) {
+        *self.get_node(id).error.lock() = errno.map(|errno| (errno, 1.0));
+    }
+
+    /// Let write and sync operations on the node fail with an OS error with the probability.
+    ///
+    /// Whether an operation fails is decided by the random number generator of the simulation,
+    /// so the same seed fails the same operations.
+    pub fn set_error_rate(&self, id: NodeId, errno: i32, probability: f64) {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "probability must be in [0, 1]"
+        );
+        *self.get_node(id).error.lock() = Some((errno, probability));
+    }
+
+    /// Set the latency of write and sync operations on the node.
+    pub fn set_latency(&self, id: NodeId, latency: Duration) {
+        *self.get_node(id).latency.lock() = latency;
+    }
+
+    /// Get the latency of write and sync operations on the node.
+    pub fn latency(&self, id: NodeId) -> Duration {
+        *self.get_node(id).latency.lock()
+    }
+
+    /// Stall or unstall write and sync operations on the node.
+    ///
+    /// While stalled, these operations will not complete until the node is unstalled.
+    pub fn set_stall(&self, id: NodeId, stalled: bool) {
+        self.get_node(id).stalled.send_replace(stalled);
+    }
+
+    /// Simulate bit-rot of data on the disk: flip the bits of `mask` in the durable byte at
+    /// `offset` of a file on the node.
+    ///
+    /// Following reads return the corrupted byte, unless it has been overwritten since the last
+    /// sync, in which case the corruption is exposed after a power failure.
+    pub fn corrupt_durable(
+        &self,
+        node: NodeId,
+        path: impl AsRef<Path>,
+        offset: u64,
+        mask: u8,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let inode = (self.get_node(node).fs.lock().get(path).cloned())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("file not found: {path:?}")))?;
+        let mut data = inode.data.write();
+        let mut durable = inode.durable.lock();
+        let Some(byte) = durable.get_mut(offset as usize) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("offset {offset} is beyond the durable data of {path:?}"),
+            ));
+        };
+        debug!(?path, offset, mask, "corrupt durable data");
+        let clean = data.get(offset as usize) == Some(byte);
+        *byte ^= mask;
+        if clean {
+            data[offset as usize] = *byte;
+        }
+        Ok(())
     }
 
     /// Get the size of given file.
@@ -67,13 +217,84 @@ impl FsSim {
 /// File system simulator for a node.
 #[derive(Clone)]
 struct FsNodeHandle {
+    rand: GlobalRng,
+    time: TimeHandle,
     fs: Arc<Mutex<HashMap<PathBuf, Arc<INode>>>>,
+    stall: Arc<Mutex<Option<DiskStall>>>,
+    latency: Arc<Mutex<Duration>>,
+    stalled: Arc<watch::Sender<bool>>,
+    /// The error code of failed write operations, and the probability to fail.
+    error: Arc<Mutex<Option<(i32, f64)>>>,
+    journal_mode: Arc<Mutex<JournalMode>>,
+    /// Number of mutating operations issued.
+    ops: Arc<Mutex<usize>>,
+    /// The number of mutating operations after which the node crashes.
+    crash_at: Arc<Mutex<Option<usize>>>,
+    /// Set when the crash point is reached.
+    crashed: Arc<watch::Sender<bool>>,
+    /// The alignment required by direct I/O.
+    block_size: Arc<Mutex<u64>>,
+}
+
+/// Journaling mode of the simulated file system.
+///
+/// It determines what may be observed after a power failure for files
+/// whose changes have not been synced.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    /// Data is written to disk before its metadata is committed.
+    ///
+    /// After a crash, an unsynced file either keeps all its changes or none of them.
+    #[default]
+    Ordered,
+    /// Data and metadata reach the disk independently.
+    ///
+    /// After a crash, the size of a file may be updated while its data is not,
+    /// exposing zeroed content that has never been written.
+    Writeback,
+}
+
+/// Periodic disk stalls of a node.
+#[derive(Debug, Clone, Copy)]
+struct DiskStall {
+    /// The elapsed time when the stall is injected.
+    since: Duration,
+    every: Duration,
+    duration: Duration,
+}
+
+impl DiskStall {
+    /// Returns the remaining time of the stall window at `now`.
+    fn remaining(&self, now: Duration) -> Option<Duration> {
+        let t = now.checked_sub(self.since)?;
+        if t < self.every {
+            return None;
+        }
+        let period = self.every.as_nanos();
+        let pos = Duration::from_nanos((t.as_nanos() % period) as u64);
+        if pos < self.duration {
+            Some(self.duration - pos)
+        } else {
+            None
+        }
+    }
 }
 
 impl FsNodeHandle {
-    fn new() -> Self {
+    fn new(rand: GlobalRng, time: TimeHandle) -> Self {
         FsNodeHandle {
+            rand,
+            time,
             fs: Arc::new(Mutex::new(HashMap::new())),
+            stall: Arc::new(Mutex::new(None)),
+            latency: Arc::new(Mutex::new(Duration::ZERO)),
+            stalled: Arc::new(watch::channel(false).0),
+            error: Arc::new(Mutex::new(None)),
+            journal_mode: Arc::new(Mutex::new(JournalMode::default())),
+            ops: Arc::new(Mutex::new(0)),
+            crash_at: Arc::new(Mutex::new(None)),
+            crashed: Arc::new(watch::channel(false).0),
+            block_size: Arc::new(Mutex::new(512)),
         }
     }
 
@@ -81,23 +302,77 @@ impl FsNodeHandle {
         simulator::<FsSim>().get_node(node())
     }
 
+    /// Wait until the disk is available.
+    async fn io_wait(&self) {
+        let stall = *self.stall.lock();
+        let remaining = stall.and_then(|stall| stall.remaining(self.time.elapsed()));
+        if let Some(remaining) = remaining {
+            trace!(?remaining, "disk stalled");
+            self.time.sleep(remaining).await;
+        }
+    }
+
+    /// Wait for the configured latency of write operations.
+    async fn write_wait(&self) {
+        self.io_wait().await;
+        let mut stalled = self.stalled.subscribe();
+        while *stalled.borrow_and_update() {
+            trace!("disk hung");
+            stalled.changed().await.unwrap();
+        }
+        let latency = *self.latency.lock();
+        if !latency.is_zero() {
+            self.time.sleep(latency).await;
+        }
+    }
+
+    /// Returns the injected error if the write operation should fail.
+    fn check_error(&self) -> Result<()> {
+        let Some((errno, probability)) = *self.error.lock() else {
+            return Ok(());
+        };
+        if probability < 1.0 && !self.rand.with(|rng| rng.gen_bool(probability)) {
+            return Ok(());
+        }
+        let error = Error::from_raw_os_error(errno);
+        trace!(%error, "inject fs error");
+        Err(error)
+    }
+
+    /// Count a mutating operation. Never returns if the operation hits the crash point.
+    async fn crash_point(&self) {
+        let mut ops = self.ops.lock();
+        if *self.crash_at.lock() == Some(*ops) {
+            drop(ops);
+            trace!("crash point reached");
+            self.crashed.send_replace(true);
+            std::future::pending::<()>().await;
+        }
+        *ops += 1;
+    }
+
     async fn open(&self, path: impl AsRef<Path>) -> Result<File> {
         let path = path.as_ref();
         trace!(?path, "open file");
+        self.io_wait().await;
         let fs = self.fs.lock();
         let inode = fs
             .get(path)
             .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("file not found: {path:?}")))?
             .clone();
         Ok(File {
+            handle: self.clone(),
             inode,
             can_write: false,
+            direct: false,
         })
     }
 
     async fn create(&self, path: impl AsRef<Path>) -> Result<File> {
         let path = path.as_ref();
         trace!(?path, "create file");
+        self.crash_point().await;
+        self.io_wait().await;
         let mut fs = self.fs.lock();
         let inode = fs
             .entry(path.into())
@@ -105,34 +380,72 @@ impl FsNodeHandle {
             .or_insert_with(|| Arc::new(INode::new(path)))
             .clone();
         Ok(File {
+            handle: self.clone(),
             inode,
             can_write: true,
+            direct: false,
         })
     }
 
     async fn metadata(&self, path: impl AsRef<Path>) -> Result<Metadata> {
         let path = path.as_ref();
+        self.io_wait().await;
         let fs = self.fs.lock();
         let inode = fs
             .get(path)
             .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("file not found: {path:?}")))?;
         Ok(inode.metadata())
     }
+
+    async fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        trace!(?from, ?to, "rename");
+        self.crash_point().await;
+        self.write_wait().await;
+        self.check_error()?;
+        // directories are prefixes of paths, so moving the entry under one lock updates the
+        // source and destination directories together, and a crash sees either both or none
+        let mut fs = self.fs.lock();
+        let inode = fs
+            .remove(from)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("file not found: {from:?}")))?;
+        *inode.path.lock() = to.into();
+        fs.insert(to.into(), inode);
+        Ok(())
+    }
+
+    /// Returns a snapshot of all files.
+    fn view(&self) -> FsView {
+        let fs = self.fs.lock();
+        let files = (fs.iter())
+            .map(|(path, inode)| (path.clone(), inode.data.read().clone()))
+            .collect();
+        FsView { files }
+    }
 }
 
 struct INode {
-    path: PathBuf,
+    /// The current location of the file.
+    path: Mutex<PathBuf>,
     data: RwLock<Vec<u8>>,
+    /// The content that has been persisted to disk.
+    durable: Mutex<Vec<u8>>,
 }
 
 impl INode {
     fn new(path: &Path) -> Self {
         INode {
-            path: path.into(),
+            path: Mutex::new(path.into()),
             data: RwLock::new(Vec::new()),
+            durable: Mutex::new(Vec::new()),
         }
     }
 
+    fn sync(&self) {
+        let data = self.data.read();
+        self.durable.lock().clone_from(&data);
+    }
+
     fn truncate(&self) {
         self.data.write().clear();
     }
@@ -146,14 +459,17 @@ impl INode {
 
 /// A reference to an open file on the filesystem.
 pub struct File {
+    handle: FsNodeHandle,
     inode: Arc<INode>,
     can_write: bool,
+    /// Whether the file is opened for direct I/O.
+    direct: bool,
 }
 
 impl fmt::Debug for File {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("File")
-            .field("path", &self.inode.path)
+            .field("path", &*self.inode.path.lock())
             .finish()
     }
 }
@@ -173,9 +489,46 @@ impl File {
         handle.create(path).await
     }
 
+    /// Attempts to open a file in read-only mode for direct I/O, like `O_DIRECT`.
+    ///
+    /// Offsets and lengths of reads must be aligned to the block size of the node.
+    /// See [`FsSim::set_block_size`].
+    #[cfg_attr(docsrs, doc(cfg(madsim)))]
+    pub async fn open_direct(path: impl AsRef<Path>) -> Result<File> {
+        let mut file = Self::open(path).await?;
+        file.direct = true;
+        Ok(file)
+    }
+
+    /// Opens a file in write-only mode for direct I/O, like `O_DIRECT`.
+    ///
+    /// Offsets and lengths of writes must be aligned to the block size of the node.
+    /// See [`FsSim::set_block_size`].
+    #[cfg_attr(docsrs, doc(cfg(madsim)))]
+    pub async fn create_direct(path: impl AsRef<Path>) -> Result<File> {
+        let mut file = Self::create(path).await?;
+        file.direct = true;
+        Ok(file)
+    }
+
+    /// Returns `EINVAL` if the range is misaligned for direct I/O.
+    fn check_alignment(&self, offset: u64, len: usize) -> Result<()> {
+        if !self.direct {
+            return Ok(());
+        }
+        let block_size = *self.handle.block_size.lock();
+        if offset % block_size != 0 || len as u64 % block_size != 0 {
+            trace!(offset, len, block_size, "misaligned direct I/O");
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        Ok(())
+    }
+
     /// Reads a number of bytes starting from a given offset.
     #[instrument(skip(buf), fields(len = buf.len()))]
     pub async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.check_alignment(offset, buf.len())?;
+        self.handle.io_wait().await;
         let data = self.inode.data.read();
         let end = data.len().min(offset as usize + buf.len());
         let len = end - offset as usize;
@@ -193,6 +546,10 @@ impl File {
                 "the file is read only",
             ));
         }
+        self.check_alignment(offset, buf.len())?;
+        self.handle.crash_point().await;
+        self.handle.write_wait().await;
+        self.handle.check_error()?;
         let mut data = self.inode.data.write();
         let end = data.len().min(offset as usize + buf.len());
         let len = end - offset as usize;
@@ -208,6 +565,9 @@ impl File {
     /// Truncates or extends the underlying file, updating the size of this file to become `size`.
     #[instrument]
     pub async fn set_len(&self, size: u64) -> Result<()> {
+        self.handle.crash_point().await;
+        self.handle.write_wait().await;
+        self.handle.check_error()?;
         let mut data = self.inode.data.write();
         data.resize(size as usize, 0);
         // TODO: random delay
@@ -217,13 +577,17 @@ impl File {
     /// Attempts to sync all OS-internal metadata to disk.
     #[instrument]
     pub async fn sync_all(&self) -> Result<()> {
-        // TODO: random delay
+        self.handle.crash_point().await;
+        self.handle.write_wait().await;
+        self.handle.check_error()?;
+        self.inode.sync();
         Ok(())
     }
 
     /// Queries metadata about the underlying file.
     #[instrument]
     pub async fn metadata(&self) -> Result<Metadata> {
+        self.handle.io_wait().await;
         Ok(self.inode.metadata())
     }
 }
@@ -243,6 +607,16 @@ pub async fn metadata(path: impl AsRef<Path>) -> Result<Metadata> {
     handle.metadata(path).await
 }
 
+/// Rename a file, replacing the original file if `to` already exists.
+///
+/// The rename is atomic, and it is persisted to disk once this function returns.
+/// This also holds when `from` and `to` are in different directories: after a crash, the file
+/// is found at exactly one of the two locations.
+pub async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    let handle = FsNodeHandle::current();
+    handle.rename(from, to).await
+}
+
 /// Metadata information about a file.
 pub struct Metadata {
     len: u64,
@@ -256,10 +630,125 @@ impl Metadata {
     }
 }
 
+/// A read-only snapshot of the files on a node.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsView {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl FsView {
+    /// Returns the contents of a file, or `None` if it does not exist.
+    pub fn read(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        self.files.get(path.as_ref()).map(|data| data.as_slice())
+    }
+
+    /// Returns whether the file exists.
+    pub fn exists(&self, path: impl AsRef<Path>) -> bool {
+        self.files.contains_key(path.as_ref())
+    }
+
+    /// Returns an iterator over the paths of all files, in sorted order.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(|path| path.as_path())
+    }
+}
+
+/// An invalid state recovered after a crash, found by [`check_crash_consistency`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone)]
+pub struct CrashInconsistency {
+    /// The number of mutating operations completed before the crash.
+    pub crash_point: usize,
+    /// The seed of the run.
+    pub seed: u64,
+    /// The files recovered after the crash.
+    pub view: FsView,
+}
+
+impl fmt::Display for CrashInconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid state after crashing at operation {} (seed {}): {:?}",
+            self.crash_point, self.seed, self.view
+        )
+    }
+}
+
+impl std::error::Error for CrashInconsistency {}
+
+/// The number of seeds to explore at each crash point.
+const SEEDS_PER_CRASH_POINT: u64 = 8;
+
+/// Check that a workload leaves the file system in a valid state wherever it crashes.
+///
+/// The workload is first run to completion on a fresh node to count its mutating operations
+/// (create, write, set_len, sync and rename). Then for every crash point from before the first
+/// operation to after the last one, it is run again and the node suffers a power failure right
+/// before that operation. The files recovered after the crash are passed to `valid`.
+/// Each crash point is explored with multiple seeds, since a power failure randomly decides
+/// which unsynced changes reach the disk.
+///
+/// Returns the first invalid recovery found. All runs are deterministic.
+pub fn check_crash_consistency<F>(
+    workload: fn() -> F,
+    valid: impl Fn(&FsView) -> bool,
+) -> std::result::Result<(), CrashInconsistency>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (_, num_ops) = run_until_crash(0, workload, None);
+    for crash_point in 0..=num_ops {
+        for seed in 0..SEEDS_PER_CRASH_POINT {
+            let (view, _) = run_until_crash(seed, workload, Some(crash_point));
+            if !valid(&view) {
+                return Err(CrashInconsistency {
+                    crash_point,
+                    seed,
+                    view,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the workload on a new node until it finishes or reaches the crash point.
+///
+/// Returns the files after a power failure and the number of mutating operations.
+fn run_until_crash<F>(seed: u64, workload: fn() -> F, crash_at: Option<usize>) -> (FsView, usize)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    // run in a new thread in case the caller is inside a runtime
+    std::thread::spawn(move || {
+        let runtime = crate::runtime::Runtime::with_seed_and_config(seed, Config::default());
+        let node = runtime.create_node().build();
+        let id = node.id();
+        runtime.block_on(async move {
+            let handle = FsSim::current().get_node(id);
+            *handle.crash_at.lock() = crash_at;
+            let mut crashed = handle.crashed.subscribe();
+            let crashed = pin!(async move { _ = crashed.wait_for(|crashed| *crashed).await });
+            let task = pin!(node.spawn(workload()));
+            if let Either::Left((ret, _)) = select(task, crashed).await {
+                ret.unwrap();
+            }
+            // losing power kills all tasks on the node
+            crate::runtime::Handle::current().kill(id);
+            let ops = *handle.ops.lock();
+            (handle.view(), ops)
+        })
+    })
+    .join()
+    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::runtime::Runtime;
+    use crate::{runtime::Runtime, time::Instant};
 
     #[test]
     fn create_open_read_write() {
@@ -293,4 +782,313 @@ mod tests {
         });
         runtime.block_on(f).unwrap();
     }
+
+    #[test]
+    fn direct_io_alignment() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let f = node.spawn(async move {
+            FsSim::current().set_block_size(crate::plugin::node(), 4096);
+            let file = File::create_direct("file").await.unwrap();
+            file.write_all_at(&[1; 4096], 0).await.unwrap();
+            file.write_all_at(&[2; 4096], 4096).await.unwrap();
+
+            // misaligned offset or length
+            for (len, offset) in [(4096, 512), (100, 0)] {
+                let err = file.write_all_at(&vec![3; len], offset).await.unwrap_err();
+                assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+            }
+            assert_eq!(file.metadata().await.unwrap().len(), 8192);
+
+            let file = File::open_direct("file").await.unwrap();
+            let mut buf = vec![0; 4096];
+            file.read_at(&mut buf, 4096).await.unwrap();
+            assert_eq!(buf, [2; 4096]);
+            let err = file.read_at(&mut buf[..10], 0).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+
+            // buffered I/O is not restricted
+            let file = File::open("file").await.unwrap();
+            file.read_at(&mut buf[..10], 1).await.unwrap();
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn disk_stall() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        node.inject_disk_stall(Duration::from_secs(10), Duration::from_secs(2));
+        let f = node.spawn(async move {
+            let file = File::create("file").await.unwrap();
+
+            // normal operations are fast
+            let t0 = Instant::now();
+            file.write_all_at(b"hello", 0).await.unwrap();
+            assert!(t0.elapsed() < Duration::from_millis(1));
+
+            // operations during a stall window wait until it ends
+            crate::time::sleep_until(t0 + Duration::from_secs(10)).await;
+            let t1 = Instant::now();
+            file.sync_all().await.unwrap();
+            assert!(t1.elapsed() >= Duration::from_millis(1990));
+
+            // fast again after the stall
+            crate::time::sleep_until(t0 + Duration::from_secs(15)).await;
+            let t2 = Instant::now();
+            file.sync_all().await.unwrap();
+            assert!(t2.elapsed() < Duration::from_millis(1));
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn fs_latency() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        runtime
+            .handle()
+            .set_fs_latency(node.id(), Duration::from_millis(50));
+        let f = node.spawn(async move {
+            let file = File::create("file").await.unwrap();
+            let t0 = Instant::now();
+            file.write_all_at(b"hello", 0).await.unwrap();
+            let elapsed = t0.elapsed();
+            assert!(elapsed >= Duration::from_millis(50));
+            assert!(elapsed < Duration::from_millis(51));
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn fs_stall() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let node_id = node.id();
+        runtime.block_on(async move {
+            let t0 = Instant::now();
+            let f = node.spawn(async move {
+                let file = File::create("file").await.unwrap();
+                file.write_all_at(b"hello", 0).await.unwrap();
+                file.sync_all().await.unwrap();
+                Instant::now()
+            });
+            crate::runtime::Handle::current().set_fs_stall(node_id, true);
+            crate::time::sleep(Duration::from_secs(10)).await;
+            assert!(!f.is_finished());
+
+            crate::runtime::Handle::current().set_fs_stall(node_id, false);
+            let t1 = f.await.unwrap();
+            assert!(t1 - t0 >= Duration::from_secs(10));
+        });
+    }
+
+    #[test]
+    fn fs_error() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let node_id = node.id();
+        runtime.block_on(async move {
+            let handle = crate::runtime::Handle::current();
+            handle.set_fs_error(node_id, Some(libc::ENOSPC));
+            node.spawn(async move {
+                // the file can still be created and read
+                let file = File::create("file").await.unwrap();
+                let err = file.write_all_at(b"hello", 0).await.unwrap_err();
+                assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+                assert!(read("file").await.unwrap().is_empty());
+            })
+            .await
+            .unwrap();
+
+            handle.set_fs_error(node_id, None);
+            node.spawn(async move {
+                let file = File::create("file").await.unwrap();
+                file.write_all_at(b"hello", 0).await.unwrap();
+                file.sync_all().await.unwrap();
+            })
+            .await
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn fs_error_rate() {
+        /// Returns whether each fsync failed.
+        fn run(seed: u64) -> Vec<bool> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let node = runtime.create_node().build();
+            let node_id = node.id();
+            runtime.block_on(async move {
+                crate::runtime::Handle::current().set_fs_error_rate(node_id, libc::EIO, 0.5);
+                node.spawn(async move {
+                    let file = File::create("file").await.unwrap();
+                    let mut failed = vec![];
+                    for i in 0..20 {
+                        // failed writes may be retried
+                        while file.write_all_at(&[i], i as u64).await.is_err() {}
+                        match file.sync_all().await {
+                            Ok(()) => failed.push(false),
+                            Err(err) => {
+                                assert_eq!(err.raw_os_error(), Some(libc::EIO));
+                                failed.push(true);
+                            }
+                        }
+                    }
+                    failed
+                })
+                .await
+                .unwrap()
+            })
+        }
+        let failed = run(1);
+        assert!(
+            failed.contains(&true) && failed.contains(&false),
+            "{failed:?}"
+        );
+        // the same seed fails the same operations
+        assert_eq!(run(1), failed);
+    }
+
+    #[test]
+    fn journal_mode() {
+        /// Returns the contents of all files after a power failure.
+        fn crash(mode: JournalMode) -> Vec<Vec<u8>> {
+            let runtime = Runtime::new();
+            let node = runtime.create_node().build();
+            let id = node.id();
+            runtime.block_on(async move {
+                let fs = FsSim::current();
+                fs.set_journal_mode(id, mode);
+                node.spawn(async move {
+                    for i in 0..20 {
+                        let file = File::create(format!("file{i}")).await.unwrap();
+                        file.write_all_at(b"hello", 0).await.unwrap();
+                    }
+                })
+                .await
+                .unwrap();
+                fs.power_fail(id);
+                let handle = fs.get_node(id);
+                let files = handle.fs.lock();
+                files
+                    .values()
+                    .map(|inode| inode.data.read().clone())
+                    .collect()
+            })
+        }
+        // metadata never points at unwritten data in ordered mode
+        let files = crash(JournalMode::Ordered);
+        assert!(files.iter().all(|f| f.is_empty() || f == b"hello"));
+
+        // but it can in writeback mode
+        let files = crash(JournalMode::Writeback);
+        assert!(files.iter().any(|f| f == &[0; 5]));
+    }
+
+    #[test]
+    fn crash_consistency() {
+        /// Atomically replace the contents of a file.
+        async fn replace(path: &str, data: &[u8], sync: bool) {
+            let tmp = format!("{path}.tmp");
+            let file = File::create(&tmp).await.unwrap();
+            file.write_all_at(data, 0).await.unwrap();
+            if sync {
+                file.sync_all().await.unwrap();
+            }
+            rename(&tmp, path).await.unwrap();
+        }
+        let valid = |view: &FsView| matches!(view.read("data"), None | Some(b"v1") | Some(b"v2"));
+
+        async fn workload() {
+            replace("data", b"v1", true).await;
+            replace("data", b"v2", true).await;
+        }
+        check_crash_consistency(workload, valid).unwrap();
+
+        // without sync, the renamed file may be empty after a crash
+        async fn buggy_workload() {
+            replace("data", b"v1", false).await;
+            replace("data", b"v2", false).await;
+        }
+        let error = check_crash_consistency(buggy_workload, valid).unwrap_err();
+        assert_eq!(error.view.read("data"), Some(&b""[..]));
+    }
+
+    #[test]
+    fn corrupt_durable() {
+        fn checksum(data: &[u8]) -> u32 {
+            data.iter()
+                .fold(0x811c9dc5, |h, b| (h ^ *b as u32).wrapping_mul(0x01000193))
+        }
+
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let id = node.id();
+        let f = node.spawn(async move {
+            let data = b"hello world";
+            let file = File::create("file").await.unwrap();
+            file.write_all_at(data, 0).await.unwrap();
+            file.write_all_at(&checksum(data).to_le_bytes(), 11)
+                .await
+                .unwrap();
+            file.sync_all().await.unwrap();
+            // a dirty byte masks the corruption until a power failure
+            file.write_all_at(b"W", 6).await.unwrap();
+
+            let fs = FsSim::current();
+            fs.corrupt_durable(id, "file", 1, 0x01).unwrap();
+            fs.corrupt_durable(id, "file", 6, 0xff).unwrap();
+            let err = fs.corrupt_durable(id, "file", 15, 0x01).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+            let mut buf = [0; 15];
+            file.read_at(&mut buf, 0).await.unwrap();
+            assert_eq!(&buf[..11], b"hdllo World");
+            fs.power_fail(id);
+            file.read_at(&mut buf, 0).await.unwrap();
+            assert_eq!(buf[1], b'e' ^ 0x01);
+            // unless the dirty byte reaches the disk
+            assert!(buf[6] == b'w' ^ 0xff || buf[6] == b'W', "{}", buf[6]);
+            // detected by the checksum
+            let stored = u32::from_le_bytes(buf[11..].try_into().unwrap());
+            assert_ne!(checksum(&buf[..11]), stored);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn cross_directory_rename() {
+        async fn workload() {
+            let file = File::create("tmp/data").await.unwrap();
+            file.write_all_at(b"hello", 0).await.unwrap();
+            file.sync_all().await.unwrap();
+            rename("tmp/data", "published/data").await.unwrap();
+            // and back to the old directory under a new name
+            rename("published/data", "tmp/data.old").await.unwrap();
+        }
+        let valid = |view: &FsView| {
+            let paths: Vec<_> = view.paths().collect();
+            // crashed before the file is created
+            if paths.is_empty() {
+                return true;
+            }
+            paths.len() == 1 && matches!(view.read(paths[0]), Some(b"") | Some(b"hello"))
+        };
+        check_crash_consistency(workload, valid).unwrap();
+
+        // open files follow the rename
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let f = node.spawn(async move {
+            let file = File::create("tmp/data").await.unwrap();
+            rename("tmp/data", "published/data").await.unwrap();
+            assert_eq!(format!("{file:?}"), r#"File { path: "published/data" }"#);
+            assert_eq!(
+                metadata("tmp/data").await.err().unwrap().kind(),
+                ErrorKind::NotFound
+            );
+        });
+        runtime.block_on(f).unwrap();
+    }
 }
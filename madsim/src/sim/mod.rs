@@ -13,10 +13,15 @@ pub mod fs;
 pub mod net;
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
 pub mod plugin;
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub mod process;
 pub mod rand;
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
 pub mod runtime;
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub mod scenario;
 pub mod signal;
+pub mod sync;
 pub mod task;
 pub mod time;
 mod utils;
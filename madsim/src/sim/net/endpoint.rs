@@ -14,6 +14,7 @@ pub struct Endpoint {
     guard: Arc<BindGuard>,
     socket: Arc<EndpointSocket>,
     pub(super) peer: Arc<Mutex<Option<SocketAddr>>>,
+    qos: Arc<Mutex<QosClass>>,
     /// Incoming connections.
     conn_rx: async_channel::Receiver<(PayloadSender, PayloadReceiver, SocketAddr)>,
 }
@@ -31,6 +32,7 @@ impl Endpoint {
             guard,
             socket,
             peer: Arc::new(Mutex::new(None)),
+            qos: Arc::new(Mutex::new(QosClass::default())),
             conn_rx,
         })
     }
@@ -55,6 +57,12 @@ impl Endpoint {
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "not connected"))
     }
 
+    /// Sets the QoS class of messages sent from this endpoint.
+    #[cfg_attr(docsrs, doc(cfg(madsim)))]
+    pub fn set_qos(&self, qos: QosClass) {
+        *self.qos.lock() = qos;
+    }
+
     /// Sends data with tag on the socket to the given address.
     ///
     /// # Example
@@ -126,6 +134,7 @@ impl Endpoint {
                 self.guard.addr.port(),
                 dst,
                 Udp,
+                *self.qos.lock(),
                 Box::new((tag, data)),
             )
             .await?;
@@ -258,6 +267,7 @@ impl Receiver {
     pub async fn recv(&mut self) -> io::Result<Payload> {
         (self.rx.next().await)
             .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))
+            .and_then(ConnectionReset::check)
     }
 }
 
@@ -266,7 +276,7 @@ impl Stream for Receiver {
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         match self.rx.poll_next_unpin(cx) {
-            Poll::Ready(Some(value)) => Poll::Ready(Some(Ok(value))),
+            Poll::Ready(Some(value)) => Poll::Ready(Some(ConnectionReset::check(value))),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
@@ -407,6 +417,53 @@ mod tests {
         runtime.block_on(f).unwrap();
     }
 
+    #[test]
+    fn qos_priority() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        // 100ms for each bulk message
+        simulator::<NetSim>().limit_link_bandwidth(node1.id(), node2.id(), 1_000_000);
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let bulk = Endpoint::bind(addr1).await.unwrap();
+            bulk.set_qos(QosClass::Low);
+            let urgent = Endpoint::bind("10.0.0.1:2").await.unwrap();
+            urgent.set_qos(QosClass::High);
+            barrier_.wait().await;
+
+            for _ in 0..10 {
+                bulk.send_to(addr2, 1, &[0; 100_000]).await.unwrap();
+            }
+            urgent.send_to(addr2, 2, &[1; 100]).await.unwrap();
+        });
+
+        let f = node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            barrier.wait().await;
+            let start = Instant::now();
+
+            // the urgent message is only behind the bulk message being transmitted
+            let mut buf = vec![0; 100_000];
+            net.recv_from(2, &mut buf).await.unwrap();
+            let urgent = start.elapsed();
+            let mut bulk = vec![];
+            for _ in 0..10 {
+                net.recv_from(1, &mut buf).await.unwrap();
+                bulk.push(start.elapsed());
+            }
+            assert!(urgent < bulk[1], "{urgent:?} {bulk:?}");
+            assert!(urgent < Duration::from_millis(200), "{urgent:?}");
+            assert!(bulk[9] >= Duration::from_secs(1), "{bulk:?}");
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+
     #[test]
     fn receiver_drop() {
         let runtime = Runtime::new();
@@ -512,6 +569,354 @@ mod tests {
         runtime.block_on(f).unwrap();
     }
 
+    #[test]
+    fn deterministic_ephemeral_port() {
+        fn run(seed: u64) -> Vec<u16> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let ip = "10.0.0.1".parse::<IpAddr>().unwrap();
+            let node = runtime.create_node().ip(ip).build();
+            let f = node.spawn(async move {
+                let mut eps = vec![];
+                for _ in 0..5 {
+                    eps.push(Endpoint::bind("10.0.0.1:0").await.unwrap());
+                }
+                let ports: Vec<u16> = eps
+                    .iter()
+                    .map(|ep| ep.local_addr().unwrap().port())
+                    .collect();
+                // ports are not reused after drop
+                drop(eps);
+                let ep = Endpoint::bind("10.0.0.1:0").await.unwrap();
+                assert!(!ports.contains(&ep.local_addr().unwrap().port()));
+                ports
+            });
+            runtime.block_on(f).unwrap()
+        }
+        let ports = run(1);
+        let mut dedup = ports.clone();
+        dedup.sort_unstable();
+        dedup.dedup();
+        assert_eq!(dedup.len(), ports.len());
+        assert_eq!(run(1), ports);
+    }
+
+    #[test]
+    fn net_trace() {
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let run = |trace: bool| {
+            let runtime = Runtime::with_seed_and_config(1, crate::Config::default());
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let id1 = node1.id();
+            let barrier = Arc::new(Barrier::new(2));
+
+            let barrier_ = barrier.clone();
+            let f = node1.spawn(async move {
+                NetSim::current().enable_trace(trace);
+                let ep = Endpoint::bind(addr1).await.unwrap();
+                barrier_.wait().await;
+
+                let mut buf = vec![0; 0x10];
+                let (len, from) = ep.recv_from(1, &mut buf).await.unwrap();
+                assert_eq!((&buf[..len], from), (&b"ping"[..], addr2));
+                crate::runtime::Handle::current().elapsed()
+            });
+            node2.spawn(async move {
+                let ep = Endpoint::bind(addr2).await.unwrap();
+                barrier.wait().await;
+
+                ep.send_to(addr1, 1, b"ping").await.unwrap();
+                let net = NetSim::current();
+                net.clog_node(id1);
+                ep.send_to(addr1, 1, b"lost").await.unwrap();
+                net.unclog_node(id1);
+            });
+            let recv_time = runtime.block_on(f).unwrap();
+            (
+                runtime.handle().take_net_trace(),
+                recv_time,
+                node1.id(),
+                node2.id(),
+            )
+        };
+
+        let (events, recv_time, id1, id2) = run(true);
+        let nodes: Vec<_> = events.iter().map(|e| e.node).collect();
+        assert_eq!(nodes, [id2, id1, id2, id1, id1]);
+        let kinds: Vec<_> = events.iter().map(|e| e.kind.clone()).collect();
+        let (src, dst, len) = (addr2, addr1, Some(4));
+        let (inbound, outbound) = (true, true);
+        assert_eq!(
+            kinds,
+            [
+                NetEventKind::Send { src, dst, len },
+                NetEventKind::ClogNode { inbound, outbound },
+                NetEventKind::Drop { src, dst, len },
+                NetEventKind::UnclogNode { inbound, outbound },
+                NetEventKind::Recv { src, dst, len },
+            ]
+        );
+        // timestamps are logical time
+        assert!(events.windows(2).all(|w| w[0].time <= w[1].time));
+        assert_eq!(events[4].time, recv_time);
+        let latency = events[4].time - events[0].time;
+        assert!((Duration::from_millis(1)..Duration::from_millis(10)).contains(&latency));
+
+        // recording doesn't affect the simulation
+        let (events, recv_time_untraced, ..) = run(false);
+        assert!(events.is_empty());
+        assert_eq!(recv_time_untraced, recv_time);
+    }
+
+    #[test]
+    fn latency_correlation() {
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let latencies = |correlation: f64| {
+            let runtime = Runtime::with_seed_and_config(1, crate::Config::default());
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let barrier = Arc::new(Barrier::new(2));
+
+            let barrier_ = barrier.clone();
+            node2.spawn(async move {
+                let _ep = Endpoint::bind(addr2).await.unwrap();
+                barrier_.wait().await;
+                std::future::pending::<()>().await;
+            });
+            let f = node1.spawn(async move {
+                let net = NetSim::current();
+                net.update_config(|cfg| cfg.latency_correlation = correlation);
+                net.enable_trace(true);
+                let ep = Endpoint::bind(addr1).await.unwrap();
+                barrier.wait().await;
+                for _ in 0..50 {
+                    ep.send_to(addr2, 1, b"ping").await.unwrap();
+                    sleep(Duration::from_millis(100)).await;
+                }
+            });
+            runtime.block_on(f).unwrap();
+
+            let mut send_time = Duration::ZERO;
+            let mut latencies = vec![];
+            for event in runtime.handle().take_net_trace() {
+                match event.kind {
+                    NetEventKind::Send { .. } => send_time = event.time,
+                    NetEventKind::Recv { .. } => latencies.push(event.time - send_time),
+                    _ => {}
+                }
+            }
+            assert_eq!(latencies.len(), 50);
+            // differences between successive latencies
+            (latencies.windows(2))
+                .map(|w| w[0].max(w[1]) - w[0].min(w[1]))
+                .collect::<Vec<_>>()
+        };
+
+        let diffs = latencies(0.99);
+        assert!(diffs.iter().all(|d| *d < Duration::from_micros(200)));
+
+        // the expected difference of two independent samples is 1/3 of the range (3ms)
+        let diffs = latencies(0.0);
+        let mean = diffs.iter().sum::<Duration>() / diffs.len() as u32;
+        assert!(mean > Duration::from_millis(1), "{mean:?}");
+    }
+
+    #[test]
+    fn asymmetric_latency() {
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        // returns the round-trip time of a ping
+        let rtt = |seed: u64| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let handle = runtime.handle();
+            handle.set_latency(node1.id(), node2.id(), Duration::from_millis(10));
+            handle.set_latency(node2.id(), node1.id(), Duration::from_millis(200));
+            let barrier = Arc::new(Barrier::new(2));
+
+            let barrier_ = barrier.clone();
+            node2.spawn(async move {
+                let ep = Endpoint::bind(addr2).await.unwrap();
+                barrier_.wait().await;
+                let mut buf = vec![0; 0x10];
+                let (len, from) = ep.recv_from(1, &mut buf).await.unwrap();
+                ep.send_to(from, 1, &buf[..len]).await.unwrap();
+            });
+            let f = node1.spawn(async move {
+                let ep = Endpoint::bind(addr1).await.unwrap();
+                barrier.wait().await;
+                let t0 = Instant::now();
+                ep.send_to(addr2, 1, b"ping").await.unwrap();
+                let mut buf = vec![0; 0x10];
+                let (len, _) = ep.recv_from(1, &mut buf).await.unwrap();
+                assert_eq!(&buf[..len], b"ping");
+                t0.elapsed()
+            });
+            runtime.block_on(f).unwrap()
+        };
+        let elapsed = rtt(1);
+        assert!(
+            (Duration::from_millis(210)..Duration::from_millis(211)).contains(&elapsed),
+            "{elapsed:?}"
+        );
+        assert_eq!(rtt(1), elapsed);
+    }
+
+    #[test]
+    fn latency_schedule() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let handle = runtime.handle();
+        let t0 = handle.now();
+        let ms = Duration::from_millis;
+        // a spike between 10s and 20s
+        handle.set_latency_schedule(
+            node1.id(),
+            node2.id(),
+            vec![
+                (t0, ms(1)),
+                (t0 + Duration::from_secs(10), ms(500)),
+                (t0 + Duration::from_secs(20), ms(1)),
+            ],
+        );
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            // one message every second, in the middle of each second
+            for i in 0..30u64 {
+                sleep_until(t0 + ms(1000 * i + 500)).await;
+                ep.send_to(addr2, 1, &i.to_be_bytes()).await.unwrap();
+            }
+        });
+        let f = node2.spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            barrier.wait().await;
+            let mut delays = vec![];
+            for _ in 0..30 {
+                let mut buf = [0; 8];
+                ep.recv_from(1, &mut buf).await.unwrap();
+                let i = u64::from_be_bytes(buf);
+                delays.push((i, Instant::now() - (t0 + ms(1000 * i + 500))));
+            }
+            delays
+        });
+        let delays = runtime.block_on(f).unwrap();
+        for (i, delay) in delays {
+            if (10..20).contains(&i) {
+                assert!(delay >= ms(500) && delay < ms(510), "{i}: {delay:?}");
+            } else {
+                assert!(delay < ms(10), "{i}: {delay:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn fragmentation() {
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        // returns the datagram received and the number of packets sent
+        let run = |seed: u64, len: usize| {
+            let mut config = crate::Config::default();
+            config.net.mtu = Some(1000);
+            config.net.packet_loss_rate = 0.2;
+            let runtime = Runtime::with_seed_and_config(seed, config);
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let barrier = Arc::new(Barrier::new(2));
+
+            let barrier_ = barrier.clone();
+            let f = node2.spawn(async move {
+                let ep = Endpoint::bind(addr2).await.unwrap();
+                barrier_.wait().await;
+                let mut buf = vec![0; 10000];
+                let ret = timeout(Duration::from_secs(1), ep.recv_from(1, &mut buf)).await;
+                ret.ok().map(|ret| buf[..ret.unwrap().0].to_vec())
+            });
+            node1.spawn(async move {
+                let ep = Endpoint::bind(addr1).await.unwrap();
+                barrier.wait().await;
+                let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                ep.send_to(addr2, 1, &data).await.unwrap();
+            });
+            let received = runtime.block_on(f).unwrap();
+            let sent = runtime.block_on(async { NetSim::current().stat().msg_count });
+            (received, sent)
+        };
+
+        let data: Vec<u8> = (0..3500).map(|i| i as u8).collect();
+        let (mut lost, mut delivered) = (None, None);
+        for seed in 0..100 {
+            let (received, sent) = run(seed, 3500);
+            assert_eq!(run(seed, 3500), (received.clone(), sent));
+            match received {
+                None if sent > 0 => lost = Some(seed),
+                Some(received) => {
+                    // reassembled from 4 fragments
+                    assert_eq!(received, data);
+                    assert_eq!(sent, 4);
+                    delivered = Some(seed);
+                }
+                None => {}
+            }
+        }
+        // some fragments arrive but the datagram is lost
+        assert!(lost.is_some());
+        assert!(delivered.is_some());
+
+        // a datagram within the MTU is a single packet
+        let seed = (0..100).find(|&seed| run(seed, 1000).0.is_some()).unwrap();
+        assert_eq!(run(seed, 1000).1, 1);
+    }
+
+    #[test]
+    fn duplicate() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let node1_id = node1.id();
+
+        let barrier_ = barrier.clone();
+        let f = node2.spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            barrier_.wait().await;
+            let mut received = vec![];
+            loop {
+                let mut buf = vec![0; 0x10];
+                let ret = timeout(Duration::from_secs(1), ep.recv_from(1, &mut buf)).await;
+                let Ok(ret) = ret else {
+                    return received;
+                };
+                let (len, from) = ret.unwrap();
+                assert_eq!(from, addr1);
+                received.push((Instant::now(), buf[..len].to_vec()));
+            }
+        });
+        node1.spawn(async move {
+            NetSim::current().set_duplicate(node1_id, 1.0);
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            ep.send_to(addr2, 1, b"ping").await.unwrap();
+        });
+
+        let received = runtime.block_on(f).unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].1, b"ping");
+        assert_eq!(received[1].1, b"ping");
+        assert_eq!(received[1].0 - received[0].0, DUPLICATE_DELAY);
+    }
+
     #[test]
     fn localhost() {
         let runtime = Runtime::new();
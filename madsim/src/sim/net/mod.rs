@@ -36,14 +36,22 @@
 //! ```
 
 use bytes::Bytes;
-use futures_util::{stream::BoxStream, StreamExt};
+use futures_util::{
+    future::{select, Either},
+    stream::BoxStream,
+    StreamExt,
+};
 use spin::Mutex;
 use std::{
     any::Any,
     collections::HashMap,
     io,
-    net::{IpAddr, SocketAddr},
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
     time::Instant,
 };
 use tokio::sync::{mpsc, oneshot};
@@ -53,6 +61,7 @@ use crate::{
     buggify::buggify_with_prob,
     plugin,
     rand::{GlobalRng, Rng},
+    sync::watch,
     task::{NodeId, NodeInfo, Spawner},
     time::{sleep, sleep_until, Duration, TimeHandle},
 };
@@ -62,10 +71,12 @@ mod dns;
 mod endpoint;
 pub mod ipvs;
 mod network;
+mod qos;
 #[cfg(feature = "rpc")]
 #[cfg_attr(docsrs, doc(cfg(feature = "rpc")))]
 pub mod rpc;
 pub mod tcp;
+mod trace;
 mod udp;
 pub mod unix;
 
@@ -75,7 +86,11 @@ pub use self::endpoint::{Endpoint, Receiver, Sender};
 use self::ipvs::{IpVirtualServer, ServiceAddr};
 pub use self::network::{Config, Stat};
 use self::network::{Direction, IpProtocol, Network, Socket};
+use self::qos::Links;
+pub use self::qos::QosClass;
 pub use self::tcp::{TcpListener, TcpStream};
+use self::trace::{payload_len, Tracer};
+pub use self::trace::{NetEvent, NetEventKind};
 pub use self::udp::UdpSocket;
 pub use self::unix::{UnixDatagram, UnixListener, UnixStream};
 
@@ -89,11 +104,39 @@ pub struct NetSim {
     time: TimeHandle,
     hooks_req: Mutex<HashMap<NodeId, MsgHookFn>>,
     hooks_rsp: Mutex<HashMap<NodeId, MsgHookFn>>,
+    tracer: Tracer,
+    links: Links,
+    /// Reset flags of established connections and the nodes at both ends.
+    connections: Mutex<Vec<(NodeId, NodeId, Weak<ResetFlag>)>>,
+    /// The number of messages sent on connections and not yet received.
+    in_flight: Arc<AtomicUsize>,
+    tcp: Mutex<tcp::TcpConfig>,
+    #[cfg(unix)]
+    unix: unix::UnixSockets,
 }
 
 /// Message sent to a network socket.
 pub type Payload = Box<dyn Any + Send + Sync>;
 
+/// The delay between a datagram and its copy, see [`NetSim::set_duplicate`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub const DUPLICATE_DELAY: Duration = Duration::from_millis(1);
+
+/// Returns a copy of the datagram, or `None` if it is not data and can not be copied.
+fn clone_datagram(msg: &Payload) -> Option<Payload> {
+    // messages sent from `Endpoint` are tagged
+    if let Some((tag, data)) = msg.downcast_ref::<(u64, Payload)>() {
+        let data = clone_datagram(data)?;
+        return Some(Box::new((*tag, data)));
+    }
+    if let Some(data) = msg.downcast_ref::<Vec<u8>>() {
+        Some(Box::new(data.clone()))
+    } else {
+        msg.downcast_ref::<Bytes>()
+            .map(|data| Box::new(data.clone()) as Payload)
+    }
+}
+
 type MsgHookFn = Arc<dyn Fn(&Payload) -> bool + Send + Sync>;
 
 impl plugin::Simulator for NetSim {
@@ -110,6 +153,13 @@ impl plugin::Simulator for NetSim {
             time: time.clone(),
             hooks_req: Default::default(),
             hooks_rsp: Default::default(),
+            tracer: Tracer::new(time.clone()),
+            links: Links::default(),
+            connections: Default::default(),
+            in_flight: Default::default(),
+            tcp: Mutex::new(config.tcp.clone()),
+            #[cfg(unix)]
+            unix: Default::default(),
         }
     }
 
@@ -134,18 +184,101 @@ impl NetSim {
         self.network.lock().stat().clone()
     }
 
+    /// Get network configurations.
+    pub fn config(&self) -> Config {
+        self.network.lock().config().clone()
+    }
+
     /// Update network configurations.
     pub fn update_config(&self, f: impl FnOnce(&mut Config)) {
         let mut network = self.network.lock();
         network.update_config(f);
     }
 
+    /// Get TCP configurations.
+    pub fn tcp_config(&self) -> tcp::TcpConfig {
+        self.tcp.lock().clone()
+    }
+
+    /// Update TCP configurations.
+    ///
+    /// The new configurations apply to listeners and streams created afterwards.
+    pub fn update_tcp_config(&self, f: impl FnOnce(&mut tcp::TcpConfig)) {
+        f(&mut self.tcp.lock());
+    }
+
     /// Reset a node.
     ///
     /// All connections will be closed.
+    /// Peers of the node will get `ConnectionReset` on their connections.
     pub fn reset_node(&self, id: NodeId) {
         let mut network = self.network.lock();
         network.reset_node(id);
+        drop(network);
+        self.links.reset_node(id);
+        #[cfg(unix)]
+        self.unix.reset_node(id);
+        self.detect_failure(id);
+    }
+
+    /// Reset the connections of a killed node after each peer detects the failure.
+    fn detect_failure(&self, id: NodeId) {
+        let mut network = self.network.lock();
+        let mut delays = HashMap::new();
+        self.connections.lock().retain(|(node1, node2, flag)| {
+            let Some(flag) = flag.upgrade() else {
+                return false;
+            };
+            let peer = match (*node1 == id, *node2 == id) {
+                (true, false) => *node2,
+                (false, true) => *node1,
+                (true, true) => {
+                    flag.reset();
+                    return false;
+                }
+                (false, false) => return true,
+            };
+            let delay = *(delays.entry(peer)).or_insert_with(|| network.failure_detection_delay());
+            if delay.is_zero() {
+                flag.reset();
+            } else {
+                flag.detecting.store(true, Ordering::Relaxed);
+                self.time.add_timer(delay, move || flag.reset());
+            }
+            false
+        });
+    }
+
+    /// Abruptly reset all connections from or to the node.
+    ///
+    /// Pending and future reads and writes on both ends fail with `ConnectionReset`,
+    /// and messages in flight are discarded.
+    pub fn reset_connections(&self, id: NodeId) {
+        let mut connections = self.connections.lock();
+        connections.retain(|(node1, node2, flag)| {
+            let Some(flag) = flag.upgrade() else {
+                return false;
+            };
+            if *node1 == id || *node2 == id {
+                flag.reset();
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Returns the number of established connections from `src` to `dst`.
+    ///
+    /// It counts the connections that `src` has opened to `dst`, e.g. by
+    /// [`TcpStream::connect`]. A connection is established until either end is dropped or it is
+    /// reset. This can be used to check that a connection pool reuses its connections.
+    pub fn connection_count(&self, src: NodeId, dst: NodeId) -> usize {
+        let connections = self.connections.lock();
+        (connections.iter())
+            .filter(|(node1, node2, _)| *node1 == src && *node2 == dst)
+            .filter_map(|(_, _, flag)| flag.upgrade())
+            .filter(|flag| !flag.is_reset() && flag.open_ends.load(Ordering::Relaxed) == 2)
+            .count()
     }
 
     /// Set IP address of a node.
@@ -162,17 +295,22 @@ impl NetSim {
 
     /// Unclog the node.
     pub fn unclog_node(&self, id: NodeId) {
-        self.network.lock().unclog_node(id, Direction::Both);
+        self.unclog_node_inner(id, Direction::Both);
     }
 
     /// Unclog the node for receive.
     pub fn unclog_node_in(&self, id: NodeId) {
-        self.network.lock().unclog_node(id, Direction::In);
+        self.unclog_node_inner(id, Direction::In);
     }
 
     /// Unclog the node for send.
     pub fn unclog_node_out(&self, id: NodeId) {
-        self.network.lock().unclog_node(id, Direction::Out);
+        self.unclog_node_inner(id, Direction::Out);
+    }
+
+    fn unclog_node_inner(&self, id: NodeId, direction: Direction) {
+        self.network.lock().unclog_node(id, direction);
+        self.tracer.record(id, NetEventKind::unclog_node(direction));
     }
 
     /// Disconnect a node from the network.
@@ -183,43 +321,123 @@ impl NetSim {
 
     /// Clog the node.
     pub fn clog_node(&self, id: NodeId) {
-        self.network.lock().clog_node(id, Direction::Both);
+        self.clog_node_inner(id, Direction::Both);
     }
 
     /// Clog the node for receive.
     pub fn clog_node_in(&self, id: NodeId) {
-        self.network.lock().clog_node(id, Direction::In);
+        self.clog_node_inner(id, Direction::In);
     }
 
     /// Clog the node for send.
     pub fn clog_node_out(&self, id: NodeId) {
-        self.network.lock().clog_node(id, Direction::Out);
+        self.clog_node_inner(id, Direction::Out);
+    }
+
+    fn clog_node_inner(&self, id: NodeId, direction: Direction) {
+        self.network.lock().clog_node(id, direction);
+        self.tracer.record(id, NetEventKind::clog_node(direction));
     }
 
     /// Connect a pair of nodes.
     #[deprecated(since = "0.3.0", note = "call `unclog_link` twice instead")]
     pub fn connect2(&self, node1: NodeId, node2: NodeId) {
-        let mut network = self.network.lock();
-        network.unclog_link(node1, node2);
-        network.unclog_link(node2, node1);
+        self.unclog_link(node1, node2);
+        self.unclog_link(node2, node1);
     }
 
     /// Unclog the link from `src` to `dst`.
     pub fn unclog_link(&self, src: NodeId, dst: NodeId) {
         self.network.lock().unclog_link(src, dst);
+        self.tracer.record(src, NetEventKind::UnclogLink { dst });
     }
 
     /// Disconnect a pair of nodes.
     #[deprecated(since = "0.3.0", note = "call `clog_link` twice instead")]
     pub fn disconnect2(&self, node1: NodeId, node2: NodeId) {
-        let mut network = self.network.lock();
-        network.clog_link(node1, node2);
-        network.clog_link(node2, node1);
+        self.clog_link(node1, node2);
+        self.clog_link(node2, node1);
     }
 
     /// Clog the link from `src` to `dst`.
     pub fn clog_link(&self, src: NodeId, dst: NodeId) {
         self.network.lock().clog_link(src, dst);
+        self.tracer.record(src, NetEventKind::ClogLink { dst });
+    }
+
+    /// Set a fixed latency of the link from `src` to `dst`.
+    ///
+    /// It replaces [`send_latency`](Config::send_latency) for packets in this direction only,
+    /// so the two directions of a link can have different latencies.
+    pub fn set_link_latency(&self, src: NodeId, dst: NodeId, latency: Duration) {
+        self.network
+            .lock()
+            .set_link_latency(src, dst, Some(latency));
+    }
+
+    /// Samples the time for a packet to go from `node` to the node of `peer` and back, with the
+    /// latencies of the links in both directions.
+    ///
+    /// Packet loss and clogging are not taken into account. Returns `None` if no node has the
+    /// address `peer`.
+    pub fn sample_round_trip(&self, node: NodeId, peer: IpAddr) -> Option<Duration> {
+        let mut network = self.network.lock();
+        let peer = network.resolve_dest_node(node, SocketAddr::new(peer, 0), IpProtocol::Tcp)?;
+        Some(network.sample_latency(node, peer) + network.sample_latency(peer, node))
+    }
+
+    /// Restore the configured latency of the link from `src` to `dst`.
+    pub fn unset_link_latency(&self, src: NodeId, dst: NodeId) {
+        self.network.lock().set_link_latency(src, dst, None);
+    }
+
+    /// Limit the bandwidth of the link from `src` to `dst` in bytes per second.
+    ///
+    /// Messages sent by [`Endpoint`]s and [`UdpSocket`]s on the link are transmitted one at a
+    /// time, each taking `len / bandwidth`, before the usual latency. Queued messages are
+    /// transmitted in the order of their [`QosClass`]. Data over connections, such as
+    /// [`TcpStream`]s, is not limited.
+    pub fn limit_link_bandwidth(&self, src: NodeId, dst: NodeId, bytes_per_sec: u64) {
+        assert!(bytes_per_sec > 0, "bandwidth must be positive");
+        self.links.set_bandwidth(src, dst, bytes_per_sec);
+    }
+
+    /// Remove the bandwidth limit of the link from `src` to `dst`.
+    pub fn unlimit_link_bandwidth(&self, src: NodeId, dst: NodeId) {
+        self.links.set_bandwidth(src, dst, 0);
+    }
+
+    /// Set the probability that a datagram sent by the node is delivered twice.
+    ///
+    /// The copy arrives [`DUPLICATE_DELAY`] after the original, and is dropped by packet loss
+    /// independently of it. Messages over connections, such as [`TcpStream`]s, are never
+    /// duplicated. A probability of 0 disables duplication, which is the default.
+    pub fn set_duplicate(&self, node: NodeId, probability: f64) {
+        self.network.lock().set_duplicate(node, probability);
+    }
+
+    /// Enable or disable recording of network events.
+    ///
+    /// When enabled, every message sent, delivered or dropped, every new connection and every
+    /// clog or unclog is recorded with its logical timestamp. This includes messages sent and
+    /// received over established connections, such as [`TcpStream`]s, but not over local channels
+    /// that don't go through the network. Recording doesn't affect the simulation, so a seed still
+    /// reproduces the same execution.
+    ///
+    /// Recorded events can be retrieved by [`take_trace`](Self::take_trace) or
+    /// [`Handle::take_net_trace`](crate::runtime::Handle::take_net_trace).
+    pub fn enable_trace(&self, enabled: bool) {
+        self.tracer.set_enabled(enabled);
+    }
+
+    /// Take all network events recorded so far.
+    pub fn take_trace(&self) -> Vec<NetEvent> {
+        self.tracer.take()
+    }
+
+    /// Returns the number of messages sent on connections and not yet received.
+    pub(crate) fn num_in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
     }
 
     /// Add a DNS record for the cluster.
@@ -301,11 +519,20 @@ impl NetSim {
         port: u16,
         mut dst: SocketAddr,
         protocol: IpProtocol,
+        qos: QosClass,
         msg: Payload,
     ) -> io::Result<()> {
         self.rand_delay().await?;
+        let len = payload_len(&msg);
+        let drop_msg = |dst| {
+            let ip = self.network.lock().node_ip(node);
+            let src = (ip.unwrap_or(Ipv4Addr::UNSPECIFIED.into()), port).into();
+            self.tracer
+                .record(node, NetEventKind::Drop { src, dst, len });
+        };
         if let Some(hook) = self.hooks_req.lock().get(&node).cloned() {
             if !hook(&msg) {
+                drop_msg(dst);
                 return Ok(());
             }
         }
@@ -315,19 +542,54 @@ impl NetSim {
         {
             dst = addr.parse().expect("invalid socket address");
         }
-        if let Some((ip, dst_node, socket, latency)) =
-            self.network.lock().try_send(node, dst, protocol)
-        {
+        let ret = {
+            let mut network = self.network.lock();
+            let ret = network.try_send(node, dst, protocol);
+            ret.and_then(|(ip, dst_node, socket, latency)| {
+                let latency = network.test_fragments(node, dst_node, len.unwrap_or(0), latency)?;
+                let duplicate = network.test_duplicate(node, dst_node);
+                Some((ip, dst_node, socket, latency, duplicate))
+            })
+        };
+        if let Some((ip, dst_node, socket, latency, duplicate)) = ret {
             trace!(?latency, "delay");
+            let src = (ip, port).into();
+            self.tracer
+                .record(node, NetEventKind::Send { src, dst, len });
+            let copy = if duplicate {
+                clone_datagram(&msg)
+            } else {
+                None
+            };
             let hook = self.hooks_rsp.lock().get(&dst_node).cloned();
-            self.time.add_timer(latency, move || {
-                if let Some(hook) = hook {
-                    if !hook(&msg) {
-                        return;
-                    }
+            let tracer = self.tracer.clone();
+            let time = self.time.clone();
+            let deliver = move || {
+                let deliver_after = |latency, msg: Payload| {
+                    let hook = hook.clone();
+                    let tracer = tracer.clone();
+                    let socket = socket.clone();
+                    time.add_timer(latency, move || {
+                        if let Some(hook) = hook {
+                            if !hook(&msg) {
+                                tracer.record(node, NetEventKind::Drop { src, dst, len });
+                                return;
+                            }
+                        }
+                        tracer.record(dst_node, NetEventKind::Recv { src, dst, len });
+                        socket.deliver(src, dst, msg);
+                    });
+                };
+                deliver_after(latency, msg);
+                if let Some(copy) = copy {
+                    trace!("duplicate");
+                    deliver_after(latency + DUPLICATE_DELAY, copy);
                 }
-                socket.deliver((ip, port).into(), dst, msg);
-            });
+            };
+            let link = (node, dst_node);
+            (self.links).transmit(&self.time, link, qos, len.unwrap_or(0), deliver);
+        } else {
+            drop_msg(dst);
         }
         Ok(())
     }
@@ -353,24 +615,44 @@ impl NetSim {
             io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused")
         })?;
         let src = (ip, port).into();
-        let (tx1, rx1) = self.channel(node, dst, protocol);
-        let (tx2, rx2) = self.channel(dst_node, src, protocol);
+        let refused = || {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "connection refused: accept queue is full",
+            )
+        };
+        if socket.refuses_connection() {
+            return Err(refused());
+        }
         trace!(?latency, "delay");
-        // FIXME: delay
-        // self.time.add_timer(latency, move || {
+        let handshake_rtts = self.tcp.lock().handshake_rtts;
+        if protocol == IpProtocol::Tcp && handshake_rtts > 0 {
+            // both ends are connected once the handshake completes
+            let handshake = latency * 2 * handshake_rtts;
+            trace!(?handshake, "tcp handshake");
+            sleep(handshake).await;
+            // the accept queue may have filled up during the handshake
+            if socket.refuses_connection() {
+                return Err(refused());
+            }
+        }
+        self.tracer.record(node, NetEventKind::Connect { src, dst });
+        let reset = Arc::new(ResetFlag::new());
+        (self.connections.lock()).push((node, dst_node, Arc::downgrade(&reset)));
+        let (tx1, rx1) = self.channel((node, src), (dst_node, dst), protocol, reset.clone());
+        let (tx2, rx2) = self.channel((dst_node, dst), (node, src), protocol, reset);
         socket.new_connection(src, dst, tx2, rx1);
-        // });
         Ok((tx1, rx2, src))
     }
 
     /// Create a reliable, ordered channel between two endpoints.
     fn channel(
         self: &Arc<Self>,
-        node: NodeId,
-        dst: SocketAddr,
+        (node, src): (NodeId, SocketAddr),
+        (dst_node, dst): (NodeId, SocketAddr),
         protocol: IpProtocol,
+        reset: Arc<ResetFlag>,
     ) -> (PayloadSender, PayloadReceiver) {
-        let (tx, mut rx) = mpsc::unbounded_channel();
         let net = self.clone();
         let test_link = Arc::new(move || {
             net.network
@@ -378,26 +660,91 @@ impl NetSim {
                 .try_send(node, dst, protocol)
                 .map(|(_, _, _, latency)| net.time.now_instant() + latency)
         });
+        let trace = ChannelTrace {
+            tracer: self.tracer.clone(),
+            src: (node, src),
+            dst: (dst_node, dst),
+        };
+        self.channel_with(test_link, Some(trace), reset)
+    }
+
+    /// Create a channel between two sockets on the same node.
+    ///
+    /// It doesn't go through the network, so partitions and the latency of links don't apply.
+    /// Messages arrive as soon as the timer allows.
+    fn local_channel(&self, reset: Arc<ResetFlag>) -> (PayloadSender, PayloadReceiver) {
+        let time = self.time.clone();
+        self.channel_with(Arc::new(move || Some(time.now_instant())), None, reset)
+    }
+
+    fn channel_with(
+        &self,
+        test_link: Arc<dyn Fn() -> State + Send + Sync>,
+        trace: Option<ChannelTrace>,
+        reset: Arc<ResetFlag>,
+    ) -> (PayloadSender, PayloadReceiver) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut reset_rx = reset.flag.subscribe();
+        let reset_ = reset.clone();
+        let open = OpenEnd::new(reset.clone());
+        let in_flight = InFlight {
+            channel: Default::default(),
+            total: self.in_flight.clone(),
+        };
+        // messages left in the channel are never received
+        let clear = ClearOnDrop(in_flight.clone());
         let sender = PayloadSender {
             test_link: test_link.clone(),
             tx,
+            reset,
+            in_flight: in_flight.clone(),
+            trace: trace.clone(),
         };
         let recver = async_stream::stream! {
-            while let Some((value, mut state)) = rx.recv().await {
-                // wait until the link is ready
-                let mut backoff = Duration::from_millis(1);
-                let arrive_time = loop {
-                    if let Some(arrive_time) = state {
-                        break arrive_time;
-                    }
-                    // backoff
-                    sleep(backoff).await;
-                    backoff = (backoff * 2).min(Duration::from_secs(10));
-                    // retry
-                    state = test_link();
+            let _clear = clear;
+            let _open = open;
+            loop {
+                let recv = async {
+                    let (value, mut state) = rx.recv().await?;
+                    // wait until the link is ready
+                    let mut backoff = Duration::from_millis(1);
+                    let arrive_time = loop {
+                        if let Some(arrive_time) = state {
+                            break arrive_time;
+                        }
+                        // backoff
+                        sleep(backoff).await;
+                        backoff = (backoff * 2).min(Duration::from_secs(10));
+                        // retry
+                        state = test_link();
+                    };
+                    sleep_until(arrive_time).await;
+                    Some(value)
+                };
+                let msg = match select(pin!(recv), pin!(reset_rx.wait_for(|r| *r))).await {
+                    Either::Left((msg, _)) => msg,
+                    Either::Right(_) => None,
                 };
-                sleep_until(arrive_time).await;
-                yield value;
+                match msg {
+                    Some(value) => {
+                        in_flight.sub();
+                        if let Some(trace) = &trace {
+                            trace.recv(&value);
+                        }
+                        yield value;
+                    }
+                    None => {
+                        if reset_.is_detecting() {
+                            // the peer was killed, and it is silent until the failure is detected
+                            _ = reset_rx.wait_for(|r| *r).await;
+                        }
+                        // distinguish an abrupt reset from an orderly shutdown
+                        if *reset_rx.borrow() {
+                            yield Box::new(ConnectionReset) as Payload;
+                        }
+                        break;
+                    }
+                }
             }
         }
         .boxed();
@@ -409,23 +756,173 @@ impl NetSim {
 pub struct PayloadSender {
     test_link: Arc<dyn Fn() -> State + Send + Sync>,
     tx: mpsc::UnboundedSender<(Payload, State)>,
+    reset: Arc<ResetFlag>,
+    in_flight: InFlight,
+    /// `None` for channels that don't go through the network.
+    trace: Option<ChannelTrace>,
+}
+
+/// Records messages sent and received on a channel as [`NetEvent`]s.
+#[derive(Clone)]
+struct ChannelTrace {
+    tracer: Tracer,
+    /// The sender node and address.
+    src: (NodeId, SocketAddr),
+    /// The receiver node and address.
+    dst: (NodeId, SocketAddr),
+}
+
+impl ChannelTrace {
+    fn send(&self, msg: &Payload) {
+        let kind = NetEventKind::Send {
+            src: self.src.1,
+            dst: self.dst.1,
+            len: payload_len(msg),
+        };
+        self.tracer.record(self.src.0, kind);
+    }
+
+    fn recv(&self, msg: &Payload) {
+        let kind = NetEventKind::Recv {
+            src: self.src.1,
+            dst: self.dst.1,
+            len: payload_len(msg),
+        };
+        self.tracer.record(self.dst.0, kind);
+    }
+}
+
+/// Counts the messages sent on a channel and not yet received, and their total over the network.
+#[derive(Clone)]
+struct InFlight {
+    channel: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl InFlight {
+    fn add(&self) {
+        self.channel.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn sub(&self) {
+        self.channel.fetch_sub(1, Ordering::Relaxed);
+        self.total.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Removes the messages left in a channel from the total when the receiver is dropped.
+struct ClearOnDrop(InFlight);
+
+impl Drop for ClearOnDrop {
+    fn drop(&mut self) {
+        let left = self.0.channel.swap(0, Ordering::Relaxed);
+        self.0.total.fetch_sub(left, Ordering::Relaxed);
+    }
+}
+
+/// Counts an end of a connection as open until its receiver is dropped.
+struct OpenEnd(Arc<ResetFlag>);
+
+impl OpenEnd {
+    fn new(flag: Arc<ResetFlag>) -> Self {
+        flag.open_ends.fetch_add(1, Ordering::Relaxed);
+        OpenEnd(flag)
+    }
+}
+
+impl Drop for OpenEnd {
+    fn drop(&mut self) {
+        self.0.open_ends.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 /// The link state when sending a packet.
 type State = Option<Instant>;
 
+/// Whether a connection has been reset. Shared by both directions of the connection.
+pub(crate) struct ResetFlag {
+    flag: watch::Sender<bool>,
+    /// Whether an end has been killed, and the connection will be reset once its peer detects
+    /// the failure.
+    detecting: AtomicBool,
+    /// The number of ends whose receiver is alive.
+    open_ends: AtomicUsize,
+}
+
+impl ResetFlag {
+    pub(crate) fn new() -> Self {
+        ResetFlag {
+            flag: watch::channel(false).0,
+            detecting: AtomicBool::new(false),
+            open_ends: AtomicUsize::new(0),
+        }
+    }
+
+    fn is_reset(&self) -> bool {
+        *self.flag.borrow()
+    }
+
+    fn is_detecting(&self) -> bool {
+        self.detecting.load(Ordering::Relaxed)
+    }
+
+    fn reset(&self) {
+        self.flag.send_replace(true);
+    }
+}
+
+/// The last message yielded by a [`PayloadReceiver`] when the connection is reset.
+pub(crate) struct ConnectionReset;
+
+impl ConnectionReset {
+    /// Returns an error if the message marks a reset connection.
+    pub(crate) fn check(msg: Payload) -> io::Result<Payload> {
+        if msg.is::<ConnectionReset>() {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "connection reset by peer",
+            ));
+        }
+        Ok(msg)
+    }
+}
+
 impl PayloadSender {
     fn send(&self, value: Payload) -> Option<()> {
-        let state = (self.test_link)();
-        self.tx.send((value, state)).ok()
+        self.send_delayed(value, Duration::ZERO)
+    }
+
+    /// Send a message that arrives `extra` later than the latency of the link.
+    fn send_delayed(&self, value: Payload, extra: Duration) -> Option<()> {
+        if self.reset.is_reset() {
+            return None;
+        }
+        let state = (self.test_link)().map(|arrive_time| arrive_time + extra);
+        if let Some(trace) = &self.trace {
+            trace.send(&value);
+        }
+        if self.tx.send((value, state)).is_err() {
+            // messages to a killed peer are lost until the failure is detected
+            return self.reset.is_detecting().then_some(());
+        }
+        self.in_flight.add();
+        Some(())
     }
 
     fn is_closed(&self) -> bool {
-        self.tx.is_closed()
+        self.reset.is_reset() || (self.tx.is_closed() && !self.reset.is_detecting())
     }
 
     async fn closed(&self) {
-        self.tx.closed().await;
+        let mut reset_rx = self.reset.flag.subscribe();
+        let closed = async {
+            self.tx.closed().await;
+            if self.reset.is_detecting() {
+                std::future::pending::<()>().await;
+            }
+        };
+        select(pin!(closed), pin!(reset_rx.wait_for(|r| *r))).await;
     }
 }
 
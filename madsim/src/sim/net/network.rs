@@ -7,7 +7,7 @@ use std::{
     hash::{Hash, Hasher},
     io,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    ops::Range,
+    ops::{Range, RangeInclusive},
     sync::Arc,
     time::Duration,
 };
@@ -27,10 +27,15 @@ pub(crate) struct Network {
     clogged_node_in: HashSet<NodeId>,
     clogged_node_out: HashSet<NodeId>,
     clogged_link: HashSet<(NodeId, NodeId)>,
+    /// The latency of the last packet sent on each link.
+    last_latency: HashMap<(NodeId, NodeId), Duration>,
+    /// The fixed latency of links, overriding `Config::send_latency`.
+    link_latency: HashMap<(NodeId, NodeId), Duration>,
+    /// The probability that a datagram sent by the node is delivered twice.
+    duplicate_rate: HashMap<NodeId, f64>,
 }
 
 /// A node in the network.
-#[derive(Default)]
 struct Node {
     /// IP address of the node.
     ///
@@ -38,8 +43,13 @@ struct Node {
     ip: Option<IpAddr>,
     /// Sockets in the node.
     sockets: HashMap<(SocketAddr, IpProtocol), Arc<dyn Socket>>,
+    /// The next port to try when allocating an ephemeral port.
+    next_ephemeral_port: u16,
 }
 
+/// The range of ephemeral ports, same as the default on Linux.
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 32768..=60999;
+
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IpProtocol {
@@ -61,6 +71,11 @@ pub trait Socket: Any + Send + Sync {
         _rx: PayloadReceiver,
     ) {
     }
+
+    /// Returns whether new connections are refused, e.g. when the accept queue is full.
+    fn refuses_connection(&self) -> bool {
+        false
+    }
 }
 
 /// Network configurations.
@@ -73,6 +88,29 @@ pub struct Config {
     /// The latency range of sending packets.
     #[serde(default = "default_send_latency")]
     pub send_latency: Range<Duration>,
+    /// Correlation between the latencies of successive packets on a link, in `[0, 1]`.
+    ///
+    /// Each latency is a weighted mix of the previous latency on the link and a fresh sample:
+    /// `last * correlation + sample * (1 - correlation)`. `0` makes latencies independent.
+    #[serde(default)]
+    pub latency_correlation: f64,
+    /// The maximum size of a datagram sent in one packet. `None` for unlimited.
+    ///
+    /// Larger datagrams sent by [`Endpoint`](super::Endpoint)s and
+    /// [`UdpSocket`](super::UdpSocket)s are split into fragments of this size, like IP
+    /// fragmentation. Each fragment may be lost independently with `packet_loss_rate`. The
+    /// receiver gets the datagram when its last fragment arrives, or nothing if any fragment is
+    /// lost.
+    #[serde(default)]
+    pub mtu: Option<usize>,
+    /// The range of delays before a peer notices that a node has been killed.
+    ///
+    /// Each peer samples its own delay, so peers of a killed node see their connections to it
+    /// reset at staggered times. Until then, the connections are silent: nothing arrives from
+    /// the killed node, and what is sent to it is lost. An empty range, the default, resets the
+    /// connections at once.
+    #[serde(default)]
+    pub failure_detection_delay: Range<Duration>,
 }
 
 impl Default for Config {
@@ -80,6 +118,9 @@ impl Default for Config {
         Config {
             packet_loss_rate: 0.0,
             send_latency: default_send_latency(),
+            latency_correlation: 0.0,
+            mtu: None,
+            failure_detection_delay: Duration::ZERO..Duration::ZERO,
         }
     }
 }
@@ -93,6 +134,9 @@ impl Hash for Config {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.packet_loss_rate.to_bits().hash(state);
         self.send_latency.hash(state);
+        self.latency_correlation.to_bits().hash(state);
+        self.mtu.hash(state);
+        self.failure_detection_delay.hash(state);
     }
 }
 
@@ -112,6 +156,17 @@ pub enum Direction {
     Both,
 }
 
+impl Direction {
+    /// Returns whether the direction includes (in, out).
+    pub fn split(self) -> (bool, bool) {
+        match self {
+            Direction::In => (true, false),
+            Direction::Out => (false, true),
+            Direction::Both => (true, true),
+        }
+    }
+}
+
 impl Network {
     pub fn new(rand: GlobalRng, config: Config) -> Self {
         Self {
@@ -123,6 +178,9 @@ impl Network {
             clogged_node_in: HashSet::new(),
             clogged_node_out: HashSet::new(),
             clogged_link: HashSet::new(),
+            last_latency: HashMap::new(),
+            link_latency: HashMap::new(),
+            duplicate_rate: HashMap::new(),
         }
     }
 
@@ -130,13 +188,22 @@ impl Network {
         f(&mut self.config);
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn stat(&self) -> &Stat {
         &self.stat
     }
 
     pub fn insert_node(&mut self, id: NodeId) {
         debug!(%id, "insert_node");
-        self.nodes.insert(id, Default::default());
+        let node = Node {
+            ip: None,
+            sockets: HashMap::new(),
+            next_ephemeral_port: self.rand.gen_range(EPHEMERAL_PORTS),
+        };
+        self.nodes.insert(id, node);
     }
 
     pub fn reset_node(&mut self, id: NodeId) {
@@ -159,6 +226,11 @@ impl Network {
         // TODO: what if we change the IP when there are opening sockets?
     }
 
+    /// Returns the IP address of a node.
+    pub fn node_ip(&self, id: NodeId) -> Option<IpAddr> {
+        self.nodes.get(&id)?.ip
+    }
+
     pub fn clog_node(&mut self, id: NodeId, direction: Direction) {
         assert!(self.nodes.contains_key(&id), "node not found");
         debug!(%id, ?direction, "clog_node");
@@ -202,6 +274,49 @@ impl Network {
             || self.clogged_link.contains(&(src, dst))
     }
 
+    /// Sets the latency of the link from `src` to `dst`, or restores the configured range if
+    /// `None`.
+    pub fn set_link_latency(&mut self, src: NodeId, dst: NodeId, latency: Option<Duration>) {
+        assert!(self.nodes.contains_key(&src), "node not found");
+        assert!(self.nodes.contains_key(&dst), "node not found");
+        debug!(?src, ?dst, ?latency, "set_link_latency");
+        match latency {
+            Some(latency) => self.link_latency.insert((src, dst), latency),
+            None => self.link_latency.remove(&(src, dst)),
+        };
+    }
+
+    pub fn set_duplicate(&mut self, id: NodeId, probability: f64) {
+        assert!(self.nodes.contains_key(&id), "node not found");
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "duplicate probability must be in [0, 1]: {probability}"
+        );
+        debug!(%id, probability, "set_duplicate");
+        if probability == 0.0 {
+            self.duplicate_rate.remove(&id);
+        } else {
+            self.duplicate_rate.insert(id, probability);
+        }
+    }
+
+    /// Returns whether a copy of the datagram sent from `src` to `dst` should be delivered.
+    ///
+    /// The copy is subject to packet loss like the original.
+    pub fn test_duplicate(&mut self, src: NodeId, dst: NodeId) -> bool {
+        let Some(&rate) = self.duplicate_rate.get(&src) else {
+            return false;
+        };
+        if !self.rand.gen_bool(rate)
+            || self.link_clogged(src, dst)
+            || self.rand.gen_bool(self.config.packet_loss_rate)
+        {
+            return false;
+        }
+        self.stat.msg_count += 1;
+        true
+    }
+
     /// Bind a socket to the specified address.
     pub fn bind(
         &mut self,
@@ -222,8 +337,13 @@ impl Network {
             ));
         }
         // resolve port if unspecified
+        // ports are allocated sequentially from a random start point of each node,
+        // so they are deterministic for a given seed and not reused immediately.
         if addr.port() == 0 {
-            let port = (1..=u16::MAX)
+            let (start, end) = (*EPHEMERAL_PORTS.start(), *EPHEMERAL_PORTS.end());
+            let next = node.next_ephemeral_port;
+            let port = (next..=end)
+                .chain(start..next)
                 .find(|&port| {
                     !node
                         .sockets
@@ -232,6 +352,7 @@ impl Network {
                 .ok_or_else(|| {
                     io::Error::new(io::ErrorKind::AddrInUse, "no available ephemeral port")
                 })?;
+            node.next_ephemeral_port = if port == end { start } else { port + 1 };
             addr.set_port(port);
         }
         // insert socket
@@ -263,9 +384,67 @@ impl Network {
             None
         } else {
             self.stat.msg_count += 1;
-            // TODO: special value for loopback
-            Some(self.rand.gen_range(self.config.send_latency.clone()))
+            Some(self.sample_latency(src, dst))
+        }
+    }
+
+    /// Samples the latency of a packet from `src` to `dst`, regardless of packet loss.
+    pub fn sample_latency(&mut self, src: NodeId, dst: NodeId) -> Duration {
+        if let Some(&latency) = self.link_latency.get(&(src, dst)) {
+            return latency;
         }
+        // TODO: special value for loopback
+        let latency = &self.config.send_latency;
+        let sample = dist::uniform_duration_with(&mut self.rand, latency.start, latency.end);
+        self.correlate_latency(src, dst, sample)
+    }
+
+    /// Test the link for the fragments of a datagram of `len` bytes, after the first one was sent
+    /// with `latency`.
+    ///
+    /// Returns the latency of the last fragment to arrive, or `None` if any fragment is lost.
+    pub fn test_fragments(
+        &mut self,
+        src: NodeId,
+        dst: NodeId,
+        len: usize,
+        mut latency: Duration,
+    ) -> Option<Duration> {
+        let Some(mtu) = self.config.mtu else {
+            return Some(latency);
+        };
+        assert!(mtu > 0, "MTU must be positive");
+        for _ in 1..len.div_ceil(mtu) {
+            latency = latency.max(self.test_link(src, dst)?);
+        }
+        Some(latency)
+    }
+
+    /// Returns the delay before a peer notices that a node has been killed.
+    pub fn failure_detection_delay(&mut self) -> Duration {
+        let delay = &self.config.failure_detection_delay;
+        if delay.is_empty() {
+            return delay.start;
+        }
+        dist::uniform_duration_with(&mut self.rand, delay.start, delay.end)
+    }
+
+    /// Mixes a latency sample with the last latency on the link.
+    fn correlate_latency(&mut self, src: NodeId, dst: NodeId, sample: Duration) -> Duration {
+        let correlation = self.config.latency_correlation;
+        if correlation == 0.0 {
+            return sample;
+        }
+        assert!(
+            (0.0..=1.0).contains(&correlation),
+            "latency correlation must be in [0, 1]: {correlation}"
+        );
+        let latency = match self.last_latency.get(&(src, dst)) {
+            Some(last) => last.mul_f64(correlation) + sample.mul_f64(1.0 - correlation),
+            None => sample,
+        };
+        self.last_latency.insert((src, dst), latency);
+        latency
     }
 
     /// Resolve destination node from IP address.
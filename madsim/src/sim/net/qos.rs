@@ -0,0 +1,145 @@
+//! Bandwidth limits and QoS classes of links.
+
+use super::*;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use tokio::sync::Notify;
+
+/// The QoS class of messages.
+///
+/// On a link with limited bandwidth, messages are transmitted one at a time.
+/// Queued messages of a higher class are transmitted before those of lower classes,
+/// and messages of the same class are transmitted in the order they are sent.
+/// A message being transmitted is never preempted.
+///
+/// See [`NetSim::limit_link_bandwidth`] and [`Endpoint::set_qos`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QosClass {
+    /// Bulk traffic.
+    Low,
+    /// The default class.
+    #[default]
+    Normal,
+    /// Latency-sensitive traffic.
+    High,
+}
+
+/// Transmission queues of links with limited bandwidth.
+#[derive(Default)]
+pub(super) struct Links {
+    links: Mutex<HashMap<(NodeId, NodeId), Arc<Link>>>,
+}
+
+struct Link {
+    /// Bandwidth in bytes per second. 0 for unlimited.
+    bandwidth: AtomicU64,
+    queue: Mutex<Queue>,
+    notify: Notify,
+    /// Whether the task transmitting messages is running on the source node.
+    ///
+    /// The task exits once the limit is lifted and the queue is drained.
+    started: AtomicBool,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_seq: u64,
+    /// Messages ordered by class from high to low, then by sending order.
+    messages: BTreeMap<(Reverse<QosClass>, u64), (usize, Deliver)>,
+}
+
+/// Called when a message has been transmitted.
+type Deliver = Box<dyn FnOnce() + Send>;
+
+impl Links {
+    /// Set the bandwidth of the link from `src` to `dst`. 0 for unlimited.
+    pub fn set_bandwidth(&self, src: NodeId, dst: NodeId, bandwidth: u64) {
+        let mut links = self.links.lock();
+        if let Some(link) = links.get(&(src, dst)) {
+            link.bandwidth.store(bandwidth, Ordering::Relaxed);
+            // wake up the idle task to exit
+            link.notify.notify_one();
+            if bandwidth == 0 && link.queue.lock().messages.is_empty() {
+                links.remove(&(src, dst));
+            }
+        } else if bandwidth != 0 {
+            let link = Link {
+                bandwidth: AtomicU64::new(bandwidth),
+                queue: Mutex::new(Queue::default()),
+                notify: Notify::new(),
+                started: AtomicBool::new(false),
+            };
+            links.insert((src, dst), Arc::new(link));
+        }
+    }
+
+    /// Transmit a message of `len` bytes on the link, then call `deliver`.
+    ///
+    /// This must be called on the source node.
+    pub fn transmit(
+        &self,
+        time: &TimeHandle,
+        (src, dst): (NodeId, NodeId),
+        qos: QosClass,
+        len: usize,
+        deliver: impl FnOnce() + Send + 'static,
+    ) {
+        let Some(link) = self.links.lock().get(&(src, dst)).cloned() else {
+            return deliver();
+        };
+        let mut queue = link.queue.lock();
+        // keep the order with queued messages after the limit is lifted
+        if link.bandwidth.load(Ordering::Relaxed) == 0 && queue.messages.is_empty() {
+            drop(queue);
+            return deliver();
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        (queue.messages).insert((Reverse(qos), seq), (len, Box::new(deliver)));
+        drop(queue);
+        if !link.started.swap(true, Ordering::Relaxed) {
+            crate::task::spawn(link.clone().run(time.clone()));
+        }
+        link.notify.notify_one();
+    }
+
+    /// Discard messages queued on the node, as the transmitting tasks have been killed.
+    pub fn reset_node(&self, id: NodeId) {
+        for ((src, _), link) in self.links.lock().iter() {
+            if *src == id {
+                link.queue.lock().messages.clear();
+                link.started.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Link {
+    /// Transmit queued messages one by one, until the limit is lifted and the queue is drained.
+    async fn run(self: Arc<Self>, time: TimeHandle) {
+        loop {
+            let next = self.queue.lock().messages.pop_first();
+            let Some((_, (len, deliver))) = next else {
+                if self.bandwidth.load(Ordering::Relaxed) == 0 {
+                    self.started.store(false, Ordering::Relaxed);
+                    return;
+                }
+                self.notify.notified().await;
+                continue;
+            };
+            let bandwidth = self.bandwidth.load(Ordering::Relaxed);
+            if bandwidth != 0 {
+                // `sleep` waits for at least 1ms, which is too long for small messages
+                let (tx, rx) = oneshot::channel();
+                let duration = Duration::from_secs_f64(len as f64 / bandwidth as f64);
+                time.add_timer(duration, move || _ = tx.send(()));
+                _ = rx.await;
+            }
+            deliver();
+        }
+    }
+}
@@ -1,13 +1,103 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    hash::{Hash, Hasher},
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 
 /// tcp configurations.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
-pub struct TcpConfig {}
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TcpConfig {
+    /// The warmup model of new connections. `None` to disable.
+    #[serde(default)]
+    pub slow_start: Option<SlowStart>,
+    /// The number of round trips for [`TcpStream::connect`](super::TcpStream::connect) to
+    /// establish a connection, e.g. 1 for the 3-way handshake.
+    ///
+    /// Each round trip takes twice the latency of the link sampled for the connection.
+    /// By default, connections are established without delay.
+    #[serde(default)]
+    pub handshake_rtts: u32,
+    /// The number of bytes a [`TcpStream`](super::TcpStream) buffers for the peer to read.
+    ///
+    /// Written data takes space in the buffer until the peer reads it, whether it is unflushed,
+    /// in flight, or received and not yet read. Writing to a full buffer waits for the peer.
+    /// Defaults to [`DEFAULT_SEND_BUFFER_SIZE`].
+    #[serde(default = "default_send_buffer_size")]
+    pub send_buffer_size: usize,
+    /// The maximum number of bytes a read on a [`TcpStream`](super::TcpStream) returns.
+    /// `None` for unlimited.
+    ///
+    /// When set, each read returns a random number of bytes between 1 and this limit, or what
+    /// is available if less, so readers have to loop to get a whole message. The sizes are drawn
+    /// from the seeded random generator. It can be overridden for each stream by
+    /// [`TcpStream::set_max_read_size`](super::TcpStream::set_max_read_size).
+    #[serde(default)]
+    pub max_read_size: Option<usize>,
+    /// The maximum number of connections waiting to be accepted by a
+    /// [`TcpListener`](super::TcpListener). `None` for unlimited.
+    ///
+    /// Once the queue is full, [`TcpStream::connect`](super::TcpStream::connect) fails with
+    /// `ConnectionRefused`, like Linux with `tcp_abort_on_overflow` set, until the listener
+    /// accepts a connection.
+    #[serde(default)]
+    pub backlog: Option<usize>,
+}
+
+/// The default send buffer size of TCP streams, like `net.core.wmem_default` of Linux.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub const DEFAULT_SEND_BUFFER_SIZE: usize = 212992;
+
+fn default_send_buffer_size() -> usize {
+    DEFAULT_SEND_BUFFER_SIZE
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            slow_start: None,
+            handshake_rtts: 0,
+            send_buffer_size: DEFAULT_SEND_BUFFER_SIZE,
+            max_read_size: None,
+            backlog: None,
+        }
+    }
+}
 
 #[allow(clippy::derived_hash_with_manual_eq)]
 impl Hash for TcpConfig {
-    fn hash<H: Hasher>(&self, _state: &mut H) {}
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.slow_start.hash(state);
+        self.handshake_rtts.hash(state);
+        self.send_buffer_size.hash(state);
+        self.max_read_size.hash(state);
+        self.backlog.hash(state);
+    }
+}
+
+/// Extra latency of data sent on a freshly established connection, like TCP slow start.
+///
+/// Data flushed to a [`TcpStream`](super::TcpStream) arrives `extra_latency` later than usual
+/// at first. The extra latency decays linearly with the number of bytes sent on the stream,
+/// and vanishes once `warmup_bytes` have been sent. Each direction of a connection warms up
+/// independently. The model draws no random numbers, so it is the same under every seed.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct SlowStart {
+    /// Extra latency of the first data sent on a connection.
+    pub extra_latency: Duration,
+    /// Number of bytes to send before the connection reaches its steady state.
+    pub warmup_bytes: u64,
+}
+
+impl SlowStart {
+    /// Returns the extra latency of data sent after `sent` bytes.
+    pub(crate) fn extra_latency_after(&self, sent: u64) -> Duration {
+        if sent >= self.warmup_bytes {
+            return Duration::ZERO;
+        }
+        let remaining = (self.warmup_bytes - sent) as f64 / self.warmup_bytes as f64;
+        self.extra_latency.mul_f64(remaining)
+    }
 }
@@ -1,6 +1,7 @@
 use std::{fmt, io::Result};
 use tracing::instrument;
 
+use super::{stream::ReadState, SendBuffer, ShortReads, Warmup};
 use crate::net::{IpProtocol::Tcp, *};
 
 /// A TCP socket server, listening for connections.
@@ -30,11 +31,18 @@ impl TcpListener {
     /// the addresses succeed in creating a listener, the error returned from
     /// the last attempt (the last address) is returned.
     ///
+    /// Binding an address that is already bound on this node fails with `AddrInUse`.
+    /// The address can be bound again as soon as the listener is dropped, even if accepted
+    /// streams are still alive, like a socket with `SO_REUSEADDR` set.
+    ///
     /// [`ToSocketAddrs`]: trait@crate::net::ToSocketAddrs
     #[instrument]
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<TcpListener> {
-        // TODO: simulate backlog
-        let (tx, rx) = async_channel::unbounded();
+        let net = plugin::simulator::<NetSim>();
+        let (tx, rx) = match net.tcp_config().backlog {
+            Some(backlog) => async_channel::bounded(backlog.max(1)),
+            None => async_channel::unbounded(),
+        };
         let guard = BindGuard::bind(addr, Tcp, Arc::new(TcpListenerSocket { tx })).await?;
 
         Ok(TcpListener {
@@ -54,12 +62,12 @@ impl TcpListener {
     pub async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
         self.guard.net.rand_delay().await?;
 
-        let mut stream = (self.rx.recv().await)
+        let stream = (self.rx.recv().await)
             .map_err(|e| io::Error::new(io::ErrorKind::ConnectionReset, e))?;
         let peer_addr = stream.peer;
         trace!(?peer_addr, "accept tcp connection");
 
-        stream.guard = Some(self.guard.clone());
+        // accepted streams don't keep the port bound
         Ok((stream, peer_addr))
     }
 
@@ -82,15 +90,22 @@ impl Socket for TcpListenerSocket {
         tx: PayloadSender,
         rx: PayloadReceiver,
     ) {
+        let net = plugin::simulator::<NetSim>();
         let stream = TcpStream {
             guard: None,
             addr,
             peer,
             write_buf: Default::default(),
-            read_buf: Default::default(),
-            tx,
-            rx,
+            send_buf: SendBuffer::new(&net),
+            tx: Some(tx),
+            read: ReadState::new(rx),
+            warmup: Warmup::new(&net),
+            short_reads: ShortReads::new(&net),
         };
         let _ = self.tx.try_send(stream);
     }
+
+    fn refuses_connection(&self) -> bool {
+        self.tx.is_full()
+    }
 }
@@ -58,12 +58,17 @@ pub use self::stream::*;
 mod tests {
     use super::*;
     use crate::{
-        net::{ipvs::*, NetSim},
+        net::{ipvs::*, NetEventKind, NetSim},
         plugin,
-        runtime::Runtime,
-        time::timeout,
+        runtime::{ExitReason, Handle, Runtime},
+        time::{sleep, timeout},
+    };
+    use std::{
+        io::ErrorKind,
+        net::{IpAddr, SocketAddr},
+        sync::Arc,
+        time::Duration,
     };
-    use std::{io::ErrorKind, net::SocketAddr, sync::Arc, time::Duration};
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
         sync::Barrier,
@@ -208,6 +213,836 @@ mod tests {
         runtime.block_on(f2).unwrap();
     }
 
+    #[test]
+    fn reset_on_kill() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id1 = node1.id();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let (_stream, _) = listener.accept().await.unwrap();
+            barrier.wait().await;
+            std::future::pending::<()>().await;
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            barrier_.wait().await;
+
+            Handle::current().kill(id1);
+            let mut buf = [0; 20];
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+            stream.write_all(b"hello").await.unwrap();
+            let err = stream.flush().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        });
+
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn staggered_failure_detection() {
+        // returns the time each peer notices the server is killed, since the kill
+        let run = |seed| {
+            let mut config = crate::Config::default();
+            config.net.failure_detection_delay = Duration::from_secs(1)..Duration::from_secs(2);
+            let runtime = Runtime::with_seed_and_config(seed, config);
+            let addr0 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let server = runtime.create_node().ip(addr0.ip()).build();
+            server.spawn(async move {
+                let listener = TcpListener::bind(addr0).await.unwrap();
+                let mut streams = vec![];
+                loop {
+                    streams.push(listener.accept().await.unwrap());
+                }
+            });
+            let peers: Vec<_> = (1..=4)
+                .map(|i| {
+                    let ip = format!("10.0.0.{}", i + 1).parse().unwrap();
+                    runtime.create_node().ip(ip).build()
+                })
+                .collect();
+            runtime.block_on(async move {
+                sleep(Duration::from_secs(1)).await;
+                let mut streams = vec![];
+                for peer in &peers {
+                    let stream = peer.spawn(TcpStream::connect(addr0)).await.unwrap();
+                    streams.push((peer, stream.unwrap()));
+                }
+                sleep(Duration::from_secs(1)).await;
+                Handle::current().kill(server.id());
+                let t0 = crate::time::Instant::now();
+                let tasks: Vec<_> = (streams.into_iter())
+                    .map(|(peer, mut stream)| {
+                        peer.spawn(async move {
+                            // writes are lost silently until the failure is detected
+                            stream.write_all(b"ping").await.unwrap();
+                            stream.flush().await.unwrap();
+                            let mut buf = [0; 4];
+                            let err = stream.read(&mut buf).await.unwrap_err();
+                            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+                            t0.elapsed()
+                        })
+                    })
+                    .collect();
+                let mut elapsed = vec![];
+                for task in tasks {
+                    elapsed.push(task.await.unwrap());
+                }
+                elapsed
+            })
+        };
+        let elapsed = run(1);
+        for t in &elapsed {
+            assert!(
+                (Duration::from_secs(1)..Duration::from_secs(2)).contains(t),
+                "{t:?}"
+            );
+        }
+        let mut distinct = elapsed.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), elapsed.len(), "{elapsed:?}");
+        assert_eq!(run(1), elapsed);
+    }
+
+    #[test]
+    fn half_close() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // the request ends with EOF
+            let mut request = vec![];
+            stream.read_to_end(&mut request).await.unwrap();
+            assert_eq!(request, b"request");
+            stream.write_all(b"response").await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(b"request").await.unwrap();
+            stream.shutdown().await.unwrap();
+
+            let err = stream.write_all(b"more").await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+
+            // the read half is still open
+            let mut response = vec![];
+            stream.read_to_end(&mut response).await.unwrap();
+            assert_eq!(response, b"response");
+        });
+
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn slow_start() {
+        let mut config = crate::Config::default();
+        config.tcp.slow_start = Some(SlowStart {
+            extra_latency: Duration::from_millis(100),
+            warmup_bytes: 1000,
+        });
+        let runtime = Runtime::with_seed_and_config(1, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 500];
+            loop {
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            let mut rtts = vec![];
+            let mut buf = [0; 500];
+            for _ in 0..4 {
+                let t0 = crate::time::Instant::now();
+                stream.write_all(&buf).await.unwrap();
+                stream.flush().await.unwrap();
+                stream.read_exact(&mut buf).await.unwrap();
+                rtts.push(t0.elapsed());
+            }
+            rtts
+        });
+
+        let rtts = runtime.block_on(f2).unwrap();
+        let ms = Duration::from_millis;
+        // both directions warm up: 100ms, then 50ms after 500 bytes, then none after 1000 bytes
+        assert!(rtts[0] >= ms(200) && rtts[0] < ms(220), "{rtts:?}");
+        assert!(rtts[1] >= ms(100) && rtts[1] < ms(120), "{rtts:?}");
+        assert!(rtts[2] < ms(20), "{rtts:?}");
+        assert!(rtts[3] < ms(20), "{rtts:?}");
+    }
+
+    #[test]
+    fn send_buffer_backpressure() {
+        let mut config = crate::Config::default();
+        config.tcp.send_buffer_size = 1000;
+        let runtime = Runtime::with_seed_and_config(1, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            // a paused reader
+            let (mut stream, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_secs(5)).await;
+            let resumed = crate::time::Instant::now();
+            let mut data = vec![];
+            stream.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, [7; 10_000]);
+
+            // a reader that goes away
+            let (stream, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+            drop(stream);
+            resumed
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(&[7; 10_000]).await.unwrap();
+            let written = crate::time::Instant::now();
+            stream.shutdown().await.unwrap();
+
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            let err = stream.write_all(&[7; 10_000]).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+            written
+        });
+
+        let written = runtime.block_on(f2).unwrap();
+        let resumed = runtime.block_on(f1).unwrap();
+        // the writer waits for the reader to drain the buffer
+        assert!(written > resumed, "{written:?} {resumed:?}");
+    }
+
+    #[test]
+    fn peek() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // wait for the first bytes
+            let t0 = crate::time::Instant::now();
+            let mut buf = [0; 4];
+            assert_eq!(stream.peek(&mut buf).await.unwrap(), 4);
+            assert!(t0.elapsed() >= Duration::from_secs(1));
+            assert_eq!(&buf, b"HTTP");
+            // peek again without consuming the data
+            let mut buf = [0; 4];
+            assert_eq!(stream.peek(&mut buf).await.unwrap(), 4);
+            assert_eq!(&buf, b"HTTP");
+            // the data is read after peeking
+            let mut buf = [0; 8];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"HTTP/1.1");
+            // EOF
+            assert_eq!(stream.peek(&mut buf).await.unwrap(), 0);
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+            stream.write_all(b"HTTP/1.1").await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        runtime.block_on(f2).unwrap();
+        runtime.block_on(f1).unwrap();
+    }
+
+    #[test]
+    fn net_trace() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let (id1, id2) = (node1.id(), node2.id());
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let (mut stream, peer) = listener.accept().await.unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            peer
+        });
+
+        let f2 = node2.spawn(async move {
+            NetSim::current().enable_trace(true);
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            stream.flush().await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+        });
+
+        runtime.block_on(f2).unwrap();
+        let src = runtime.block_on(f1).unwrap();
+        // stream data is recorded like datagrams
+        let events: Vec<_> = (runtime.handle().take_net_trace().into_iter())
+            .filter(|e| !matches!(e.kind, NetEventKind::Connect { .. }))
+            .map(|e| (e.node, e.kind))
+            .collect();
+        let (dst, len) = (addr1, Some(5));
+        assert_eq!(
+            events,
+            [
+                (id2, NetEventKind::Send { src, dst, len }),
+                (id1, NetEventKind::Recv { src, dst, len }),
+            ]
+        );
+    }
+
+    #[test]
+    fn backlog() {
+        let mut config = crate::Config::default();
+        config.tcp.backlog = Some(2);
+        let runtime = Runtime::with_seed_and_config(1, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            // stop accepting for a while
+            sleep(Duration::from_secs(10)).await;
+            let _stream = listener.accept().await.unwrap();
+            sleep(Duration::from_secs(10)).await;
+        });
+
+        let f2 = node2.spawn(async move {
+            NetSim::current().enable_trace(true);
+            barrier_.wait().await;
+            let _s1 = TcpStream::connect(addr1).await.unwrap();
+            let _s2 = TcpStream::connect(addr1).await.unwrap();
+            // the accept queue is full
+            let err = TcpStream::connect(addr1).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+            let err = TcpStream::connect(addr1).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+            // the server drains one connection
+            sleep(Duration::from_secs(15)).await;
+            let _s3 = TcpStream::connect(addr1).await.unwrap();
+            let err = TcpStream::connect(addr1).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+            // refused connections are not traced
+            let connects = (NetSim::current().take_trace().iter())
+                .filter(|event| matches!(event.kind, NetEventKind::Connect { .. }))
+                .count();
+            assert_eq!(connects, 3);
+        });
+
+        runtime.block_on(f2).unwrap();
+        runtime.block_on(f1).unwrap();
+    }
+
+    #[test]
+    fn update_tcp_config() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        NetSim::current().update_tcp_config(|config| config.backlog = Some(1));
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        node1.spawn(async move {
+            let _listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            sleep(Duration::from_secs(10)).await;
+        });
+
+        let f = node2.spawn(async move {
+            barrier_.wait().await;
+            let _s1 = TcpStream::connect(addr1).await.unwrap();
+            let err = TcpStream::connect(addr1).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn short_reads() {
+        fn read_sizes(seed: u64) -> Vec<usize> {
+            let mut config = crate::Config::default();
+            config.tcp.max_read_size = Some(4096);
+            let runtime = Runtime::with_seed_and_config(seed, config);
+            let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_ = barrier.clone();
+
+            let payload: Vec<u8> = (0..65536).map(|i| i as u8).collect();
+            let payload_ = payload.clone();
+            let f1 = node1.spawn(async move {
+                let listener = TcpListener::bind(addr1).await.unwrap();
+                barrier.wait().await;
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut data = vec![];
+                let mut sizes = vec![];
+                let mut buf = vec![0; 65536];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&buf[..n]);
+                    sizes.push(n);
+                }
+                assert_eq!(data, payload_);
+                sizes
+            });
+            node2.spawn(async move {
+                barrier_.wait().await;
+                let mut stream = TcpStream::connect(addr1).await.unwrap();
+                stream.write_all(&payload).await.unwrap();
+                stream.shutdown().await.unwrap();
+            });
+            runtime.block_on(f1).unwrap()
+        }
+
+        let sizes = read_sizes(1);
+        assert!(sizes.len() >= 16, "{sizes:?}");
+        assert!(sizes.iter().all(|&n| n <= 4096), "{sizes:?}");
+        assert_eq!(sizes.iter().sum::<usize>(), 65536);
+        assert_eq!(read_sizes(1), sizes);
+        assert_ne!(read_sizes(2), sizes);
+    }
+
+    #[test]
+    fn handshake_latency() {
+        /// Returns the time to connect with the handshake round trips.
+        fn connect_time(seed: u64, handshake_rtts: u32) -> Duration {
+            let mut config = crate::Config::default();
+            config.net.send_latency = Duration::from_millis(10)..Duration::from_millis(20);
+            config.tcp.handshake_rtts = handshake_rtts;
+            let runtime = Runtime::with_seed_and_config(seed, config);
+            let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_ = barrier.clone();
+
+            node1.spawn(async move {
+                let listener = TcpListener::bind(addr1).await.unwrap();
+                barrier.wait().await;
+                let (_stream, _) = listener.accept().await.unwrap();
+                sleep(Duration::from_secs(10)).await;
+            });
+
+            let f = node2.spawn(async move {
+                barrier_.wait().await;
+                let t0 = crate::time::Instant::now();
+                let _stream = TcpStream::connect(addr1).await.unwrap();
+                t0.elapsed()
+            });
+            runtime.block_on(f).unwrap()
+        }
+
+        let ms = Duration::from_millis;
+        let base = connect_time(1, 0);
+        assert!(base < ms(5), "{base:?}");
+        // one round trip of the latency sampled for the connection
+        let elapsed = connect_time(1, 1);
+        let rtt = elapsed - base;
+        assert!(rtt >= ms(20) && rtt < ms(40), "{rtt:?}");
+        assert_eq!(connect_time(1, 1), elapsed);
+        // the same latency is sampled, task polls add a few nanoseconds
+        let elapsed = connect_time(1, 2);
+        assert!(elapsed > base + rtt * 2 - ms(1) && elapsed < base + rtt * 2 + ms(1));
+    }
+
+    #[test]
+    fn reset_connections() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id2 = node2.id();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 20];
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+            // new connections are not affected
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let len = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"hello");
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier_.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            // the reset is not delayed by messages in flight
+            stream.write_all(b"lost").await.unwrap();
+            stream.flush().await.unwrap();
+            Handle::current().reset_connections(id2);
+            let mut buf = [0; 20];
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn connection_count() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let (id1, id2) = (node1.id(), node2.id());
+
+        // an echo server
+        node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                crate::task::spawn(async move {
+                    let mut buf = [0; 4];
+                    while stream.read_exact(&mut buf).await.is_ok() {
+                        stream.write_all(&buf).await.unwrap();
+                        stream.flush().await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let f = node2.spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let net = plugin::simulator::<NetSim>();
+            async fn request(stream: &mut TcpStream, i: u32) {
+                stream.write_all(&i.to_be_bytes()).await.unwrap();
+                stream.flush().await.unwrap();
+                let mut buf = [0; 4];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(u32::from_be_bytes(buf), i);
+            }
+
+            // a pool of 2 connections
+            let mut pool = vec![];
+            for _ in 0..2 {
+                pool.push(TcpStream::connect(addr1).await.unwrap());
+            }
+            for i in 0..10 {
+                request(&mut pool[i as usize % 2], i).await;
+                assert_eq!(net.connection_count(id2, id1), 2);
+            }
+            // in one direction only
+            assert_eq!(net.connection_count(id1, id2), 0);
+
+            // a connection is closed when either end is dropped
+            pool.pop();
+            assert_eq!(net.connection_count(id2, id1), 1);
+
+            // a new connection per request
+            for i in 0..3 {
+                let mut stream = TcpStream::connect(addr1).await.unwrap();
+                request(&mut stream, i).await;
+                assert_eq!(net.connection_count(id2, id1), 2);
+            }
+            assert_eq!(net.connection_count(id2, id1), 1);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn oom_kill_in_flight() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let reasons = Arc::new(spin::Mutex::new(vec![]));
+        let reasons1 = reasons.clone();
+        // an echo server that takes 1s to reply
+        let node1 = (runtime.create_node())
+            .ip(addr1.ip())
+            .init(move || {
+                let reasons = reasons1.clone();
+                async move {
+                    // why the previous incarnation died
+                    let reason = Handle::current().exit_reason(plugin::node());
+                    reasons.lock().push(reason);
+                    let listener = TcpListener::bind(addr1).await.unwrap();
+                    loop {
+                        let (mut stream, _) = listener.accept().await.unwrap();
+                        crate::task::spawn(async move {
+                            let mut buf = [0; 5];
+                            stream.read_exact(&mut buf).await.unwrap();
+                            sleep(Duration::from_secs(1)).await;
+                            stream.write_all(&buf).await.unwrap();
+                            stream.flush().await.unwrap();
+                        });
+                    }
+                }
+            })
+            .build();
+        let id1 = node1.id();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+
+        let f = node2.spawn(async move {
+            let call = || async move {
+                let mut stream = TcpStream::connect(addr1).await?;
+                stream.write_all(b"hello").await?;
+                stream.flush().await?;
+                let mut buf = [0; 5];
+                stream.read_exact(&mut buf).await?;
+                Ok::<_, std::io::Error>(buf)
+            };
+            sleep(Duration::from_secs(1)).await;
+            let rpc = crate::task::spawn(call());
+            sleep(Duration::from_millis(500)).await;
+            Handle::current().oom_kill(id1);
+            let err = rpc.await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+            assert!(Handle::current().is_exit(id1));
+            assert_eq!(
+                Handle::current().exit_reason(id1),
+                Some(ExitReason::OutOfMemory)
+            );
+
+            Handle::current().restart(id1);
+            sleep(Duration::from_secs(1)).await;
+            assert_eq!(&call().await.unwrap(), b"hello");
+        });
+        runtime.block_on(f).unwrap();
+        assert_eq!(*reasons.lock(), [None, Some(ExitReason::OutOfMemory)]);
+    }
+
+    #[test]
+    fn quiescent_after_workload() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime
+            .create_node()
+            .ip("10.0.0.2".parse().unwrap())
+            .build();
+
+        runtime.block_on(async move {
+            let server = node1.spawn(async move {
+                let listener = TcpListener::bind(addr1).await.unwrap();
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+                stream.flush().await.unwrap();
+            });
+            let client = node2.spawn(async move {
+                sleep(Duration::from_secs(1)).await;
+                let mut stream = TcpStream::connect(addr1).await.unwrap();
+                stream.write_all(b"hello").await.unwrap();
+                stream.flush().await.unwrap();
+                let mut buf = [0; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"hello");
+            });
+            client.await.unwrap();
+            server.await.unwrap();
+            // let the timers of the workload expire
+            sleep(Duration::from_secs(1)).await;
+            Handle::current().assert_quiescent();
+        });
+    }
+
+    #[test]
+    fn quiescent_after_unread_stream() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime
+            .create_node()
+            .ip("10.0.0.2".parse().unwrap())
+            .build();
+
+        runtime.block_on(async move {
+            let server = node1.spawn(async move {
+                let listener = TcpListener::bind(addr1).await.unwrap();
+                let (stream, _) = listener.accept().await.unwrap();
+                // drop the stream without ever reading from it
+                sleep(Duration::from_secs(2)).await;
+                drop(stream);
+            });
+            let client = node2.spawn(async move {
+                sleep(Duration::from_secs(1)).await;
+                let mut stream = TcpStream::connect(addr1).await.unwrap();
+                stream.write_all(b"hello").await.unwrap();
+                stream.flush().await.unwrap();
+                stream
+            });
+            let _stream = client.await.unwrap();
+            server.await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+            Handle::current().assert_quiescent();
+        });
+    }
+
+    #[test]
+    fn addr_consistency() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let ip2 = "10.0.0.2".parse::<IpAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(ip2).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        // the listener is bound to all interfaces
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind("0.0.0.0:1").await.unwrap();
+            barrier_.wait().await;
+            let mut tuples = vec![];
+            for _ in 0..2 {
+                let (stream, peer) = listener.accept().await.unwrap();
+                assert_eq!(stream.peer_addr().unwrap(), peer);
+                tuples.push((stream.local_addr().unwrap(), peer));
+            }
+            // a loopback connection on the same node
+            let stream = TcpStream::connect("127.0.0.1:1").await.unwrap();
+            let (accepted, _) = listener.accept().await.unwrap();
+            assert_eq!(stream.local_addr().unwrap(), accepted.peer_addr().unwrap());
+            assert_eq!(stream.peer_addr().unwrap(), accepted.local_addr().unwrap());
+            assert_eq!(
+                accepted.local_addr().unwrap(),
+                "127.0.0.1:1".parse().unwrap()
+            );
+            assert!(stream.local_addr().unwrap().ip().is_loopback());
+            tuples
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier.wait().await;
+            let mut tuples = vec![];
+            for _ in 0..2 {
+                let stream = TcpStream::connect(addr1).await.unwrap();
+                tuples.push((stream.local_addr().unwrap(), stream.peer_addr().unwrap()));
+            }
+            tuples
+        });
+
+        let connected = runtime.block_on(f2).unwrap();
+        let accepted = runtime.block_on(f1).unwrap();
+        for ((local, peer), accepted) in connected.iter().zip(&accepted) {
+            // the four-tuple seen by the acceptor is swapped
+            assert_eq!(*accepted, (*peer, *local));
+            assert_eq!(*peer, addr1);
+            assert_eq!(local.ip(), ip2);
+            assert_ne!(local.port(), 0);
+        }
+        // each connection has its own ephemeral port
+        assert_ne!(connected[0].0.port(), connected[1].0.port());
+    }
+
+    #[test]
+    fn bind_conflict() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            let err = TcpListener::bind(addr1).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::AddrInUse);
+            // other IPs and ports are free
+            let _listener = TcpListener::bind("127.0.0.1:1").await.unwrap();
+            let _listener = TcpListener::bind("10.0.0.1:2").await.unwrap();
+            barrier_.wait().await;
+
+            // rebind after drop succeeds immediately, even if accepted streams are still alive
+            let (_stream, _) = listener.accept().await.unwrap();
+            drop(listener);
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            listener.accept().await.unwrap();
+        });
+
+        node2.spawn(async move {
+            barrier.wait().await;
+            let _stream = TcpStream::connect(addr1).await.unwrap();
+            barrier.wait().await;
+            let _stream = TcpStream::connect(addr1).await.unwrap();
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+
     #[test]
     fn ip_resolve() {
         let runtime = Runtime::new();
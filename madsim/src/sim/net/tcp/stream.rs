@@ -1,12 +1,14 @@
+use super::SlowStart;
 use crate::net::{IpProtocol::Tcp, *};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{BufMut, BytesMut};
+use spin::Mutex;
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::{
     fmt,
     io::Result,
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll, Waker},
 };
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tracing::*;
@@ -18,9 +20,46 @@ pub struct TcpStream {
     pub(super) peer: SocketAddr,
     /// Buffer write data to be flushed.
     pub(super) write_buf: BytesMut,
-    pub(super) read_buf: Bytes,
-    pub(super) tx: PayloadSender,
-    pub(super) rx: PayloadReceiver,
+    /// The space taken by data written to the peer and not yet read.
+    pub(super) send_buf: Arc<SendBuffer>,
+    /// `None` if the write half has been shut down.
+    pub(super) tx: Option<PayloadSender>,
+    /// Behind a lock so that [`peek`](TcpStream::peek) can take `&self`.
+    pub(super) read: Mutex<ReadState>,
+    pub(super) warmup: Warmup,
+    pub(super) short_reads: ShortReads,
+}
+
+/// The receiving side of a TCP stream.
+pub(super) struct ReadState {
+    /// Received data not yet read.
+    buf: Segment,
+    rx: PayloadReceiver,
+}
+
+impl ReadState {
+    pub(super) fn new(rx: PayloadReceiver) -> Mutex<Self> {
+        Mutex::new(ReadState {
+            buf: Default::default(),
+            rx,
+        })
+    }
+
+    /// Receives segments until the buffer is not empty.
+    ///
+    /// Returns `false` if the peer has shut down its write half and all data has been read.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool>> {
+        while self.buf.is_empty() {
+            match ready!(self.rx.poll_next_unpin(cx)) {
+                Some(data) => {
+                    let data = ConnectionReset::check(data)?;
+                    self.buf = *data.downcast::<Segment>().unwrap();
+                }
+                None => return Poll::Ready(Ok(false)),
+            }
+        }
+        Poll::Ready(Ok(true))
+    }
 }
 
 impl fmt::Debug for TcpStream {
@@ -78,9 +117,11 @@ impl TcpStream {
             addr: local_addr,
             peer: addr,
             write_buf: Default::default(),
-            read_buf: Default::default(),
-            tx,
-            rx,
+            send_buf: SendBuffer::new(&net),
+            tx: Some(tx),
+            read: ReadState::new(rx),
+            warmup: Warmup::new(&net),
+            short_reads: ShortReads::new(&net),
         };
         Ok(stream)
     }
@@ -91,6 +132,15 @@ impl TcpStream {
         Ok(())
     }
 
+    /// Sets the maximum number of bytes a read returns. `None` for unlimited.
+    ///
+    /// Each read returns a random number of bytes up to the limit, so short reads can be
+    /// tested. See [`TcpConfig::max_read_size`](super::TcpConfig::max_read_size).
+    pub fn set_max_read_size(&mut self, max: Option<usize>) {
+        assert_ne!(max, Some(0), "max read size must be positive");
+        self.short_reads.max = max;
+    }
+
     /// Returns the socket address of the local half of this TCP connection.
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.addr)
@@ -101,6 +151,25 @@ impl TcpStream {
         Ok(self.peer)
     }
 
+    /// Receives data on the socket from the remote address to which it is connected, without
+    /// removing that data from the queue. On success, returns the number of bytes peeked.
+    ///
+    /// Successive calls return the same data, until it is consumed by a read. This waits
+    /// until some data is available, and returns 0 once the peer has shut down its write half
+    /// and all data has been read.
+    pub async fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        std::future::poll_fn(|cx| {
+            let mut read = self.read.lock();
+            if !ready!(read.poll_fill(cx))? {
+                return Poll::Ready(Ok(0));
+            }
+            let data = read.buf.peek(buf.len());
+            buf[..data.len()].copy_from_slice(data);
+            Poll::Ready(Ok(data.len()))
+        })
+        .await
+    }
+
     /// Tries to read data from the stream into the provided buffer, advancing
     /// the buffer's internal cursor, returning how many bytes were read.
     ///
@@ -110,11 +179,12 @@ impl TcpStream {
     /// by the async task and can exist entirely on the stack.
     pub fn try_read_buf<B: BufMut>(&mut self, buf: &mut B) -> io::Result<usize> {
         // read the buffer if not empty
-        if !self.read_buf.is_empty() {
-            let len = self.read_buf.len().min(buf.remaining_mut());
-            buf.put_slice(&self.read_buf[..len]);
-            self.read_buf.advance(len);
-            return Ok(len);
+        let read = self.read.get_mut();
+        if !read.buf.is_empty() {
+            let len = self.short_reads.limit(buf.remaining_mut());
+            let data = read.buf.take(len);
+            buf.put_slice(&data);
+            return Ok(data.len());
         }
         Err(io::Error::new(
             io::ErrorKind::WouldBlock,
@@ -136,55 +206,226 @@ impl AsyncRead for TcpStream {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        // read the buffer if not empty
-        if !self.read_buf.is_empty() {
-            let len = self.read_buf.len().min(buf.remaining());
-            buf.put_slice(&self.read_buf[..len]);
-            self.read_buf.advance(len);
-            return Poll::Ready(Ok(()));
-        }
-        // otherwise wait on channel
-        let poll_res = { self.rx.poll_next_unpin(cx) };
-        match poll_res {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Some(data)) => {
-                self.read_buf = *data.downcast::<Bytes>().unwrap();
-                self.poll_read(cx, buf)
-            }
-            // ref: https://man7.org/linux/man-pages/man2/recv.2.html
-            // > When a stream socket peer has performed an orderly shutdown, the
-            // > return value will be 0 (the traditional "end-of-file" return).
-            Poll::Ready(None) => Poll::Ready(Ok(())),
+        let this = &mut *self;
+        let read = this.read.get_mut();
+        // read the buffer, or wait on the channel if it is empty
+        // ref: https://man7.org/linux/man-pages/man2/recv.2.html
+        // > When a stream socket peer has performed an orderly shutdown, the
+        // > return value will be 0 (the traditional "end-of-file" return).
+        if ready!(read.poll_fill(cx))? {
+            let len = this.short_reads.limit(buf.remaining());
+            let data = read.buf.take(len);
+            buf.put_slice(&data);
         }
+        Poll::Ready(Ok(()))
     }
 }
 
 impl AsyncWrite for TcpStream {
+    /// Buffers the data to be sent on flush.
+    ///
+    /// Only the free space of the send buffer is written. If the buffer is full, the buffered
+    /// data is sent and the write waits for the peer to read.
     fn poll_write(
         mut self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize>> {
-        self.write_buf.extend_from_slice(buf);
-        // TODO: simulate buffer full, partial write
-        Poll::Ready(Ok(buf.len()))
+        let Some(tx) = &self.tx else {
+            return Poll::Ready(Err(write_shutdown()));
+        };
+        let len = self.send_buf.reserve(buf.len(), cx);
+        if len == 0 {
+            if tx.is_closed() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "connection reset",
+                )));
+            }
+            if !self.write_buf.is_empty() {
+                ready!(self.as_mut().poll_flush(cx))?;
+            }
+            return Poll::Pending;
+        }
+        self.write_buf.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let Some(tx) = &this.tx else {
+            return Poll::Ready(Err(write_shutdown()));
+        };
         // send data
-        let data = self.write_buf.split().freeze();
-        self.tx
-            .send(Box::new(data))
+        let data = this.write_buf.split().freeze();
+        let extra = this.warmup.extra_latency(data.len());
+        let segment = Segment {
+            data,
+            send_buf: Some(this.send_buf.clone()),
+        };
+        tx.send_delayed(Box::new(segment), extra)
             .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))?;
         Poll::Ready(Ok(()))
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
-        // TODO: simulate shutdown
+    /// Shuts down the write half of the connection.
+    ///
+    /// Buffered data is sent first, then the peer reads EOF after receiving it. The read half
+    /// stays open to receive data from the peer.
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.tx.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.as_mut().poll_flush(cx))?;
+        // dropping the sender closes the channel after the data in flight
+        self.tx = None;
         Poll::Ready(Ok(()))
     }
 }
 
+/// The send buffer of a stream, shared with the data in flight to the peer.
+pub(super) struct SendBuffer {
+    size: usize,
+    state: Mutex<SendBufferState>,
+}
+
+struct SendBufferState {
+    /// Number of bytes written and not yet read by the peer.
+    used: usize,
+    /// The writer waiting for free space.
+    waker: Option<Waker>,
+}
+
+impl SendBuffer {
+    pub(super) fn new(net: &NetSim) -> Arc<Self> {
+        Arc::new(SendBuffer {
+            size: net.tcp_config().send_buffer_size,
+            state: Mutex::new(SendBufferState {
+                used: 0,
+                waker: None,
+            }),
+        })
+    }
+
+    /// Takes up to `len` bytes of free space, returning the number of bytes taken.
+    ///
+    /// If there is no free space, the writer is woken when the peer reads.
+    fn reserve(&self, len: usize, cx: &mut Context<'_>) -> usize {
+        let mut state = self.state.lock();
+        let len = len.min(self.size.saturating_sub(state.used));
+        if len == 0 {
+            state.waker = Some(cx.waker().clone());
+        }
+        state.used += len;
+        len
+    }
+
+    /// Frees the space of `len` bytes read by the peer.
+    fn release(&self, len: usize) {
+        let mut state = self.state.lock();
+        state.used -= len;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Data sent to the peer.
+///
+/// The data takes space in the send buffer of the writer until it is read. Dropping it, e.g.
+/// when the reader is dropped before reading, also frees the space.
+#[derive(Default)]
+pub(crate) struct Segment {
+    data: Bytes,
+    send_buf: Option<Arc<SendBuffer>>,
+}
+
+impl Segment {
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns up to `len` bytes without consuming them.
+    fn peek(&self, len: usize) -> &[u8] {
+        &self.data[..len.min(self.data.len())]
+    }
+
+    /// Reads up to `len` bytes.
+    fn take(&mut self, len: usize) -> Bytes {
+        let data = self.data.split_to(len.min(self.data.len()));
+        if let Some(send_buf) = &self.send_buf {
+            send_buf.release(data.len());
+        }
+        data
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        self.take(usize::MAX);
+    }
+}
+
+/// The progress of slow start of the write half.
+pub(super) struct Warmup {
+    slow_start: Option<SlowStart>,
+    /// Number of bytes sent so far.
+    sent: u64,
+}
+
+impl Warmup {
+    pub(super) fn new(net: &NetSim) -> Self {
+        Warmup {
+            slow_start: net.tcp_config().slow_start,
+            sent: 0,
+        }
+    }
+
+    /// Returns the extra latency of sending `len` more bytes.
+    fn extra_latency(&mut self, len: usize) -> Duration {
+        let Some(slow_start) = &self.slow_start else {
+            return Duration::ZERO;
+        };
+        let extra = slow_start.extra_latency_after(self.sent);
+        self.sent += len as u64;
+        extra
+    }
+}
+
+/// Limits the size of reads.
+pub(super) struct ShortReads {
+    max: Option<usize>,
+    rand: GlobalRng,
+}
+
+impl ShortReads {
+    pub(super) fn new(net: &NetSim) -> Self {
+        ShortReads {
+            max: net.tcp_config().max_read_size,
+            rand: net.rand.clone(),
+        }
+    }
+
+    /// Returns the number of bytes to read into a buffer of `len` bytes.
+    fn limit(&self, len: usize) -> usize {
+        match self.max {
+            Some(max) if len > 1 => {
+                let max = max.clamp(1, len);
+                self.rand.with(|rng| rng.gen_range(1..=max))
+            }
+            _ => len,
+        }
+    }
+}
+
+fn write_shutdown() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "write half has been shut down")
+}
+
 /// Socket registered in the [`Network`].
 struct TcpStreamSocket;
 
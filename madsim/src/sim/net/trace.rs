@@ -0,0 +1,167 @@
+//! Recording of network events.
+
+use super::{network::Direction, tcp::Segment, Payload};
+use crate::{task::NodeId, time::TimeHandle};
+use bytes::Bytes;
+use spin::Mutex;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+/// An event happened in the simulated network.
+///
+/// Use [`NetSim::enable_trace`](super::NetSim::enable_trace) to start recording events and
+/// [`Handle::take_net_trace`](crate::runtime::Handle::take_net_trace) to retrieve them.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetEvent {
+    /// The logical time elapsed since the runtime was created.
+    pub time: Duration,
+    /// The node on which the event happened.
+    ///
+    /// For messages this is the sender, except for [`NetEventKind::Recv`] which happens on the
+    /// receiver.
+    pub node: NodeId,
+    /// What happened.
+    pub kind: NetEventKind,
+}
+
+/// The kind of a [`NetEvent`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NetEventKind {
+    /// A message is sent to the network.
+    Send {
+        /// Source address.
+        src: SocketAddr,
+        /// Destination address.
+        dst: SocketAddr,
+        /// Length of the payload in bytes, if known.
+        len: Option<usize>,
+    },
+    /// A message is delivered to the destination socket.
+    Recv {
+        /// Source address.
+        src: SocketAddr,
+        /// Destination address.
+        dst: SocketAddr,
+        /// Length of the payload in bytes, if known.
+        len: Option<usize>,
+    },
+    /// A message is dropped by a clogged link, packet loss, a hook,
+    /// or because no socket is bound to the destination.
+    Drop {
+        /// Source address.
+        src: SocketAddr,
+        /// Destination address.
+        dst: SocketAddr,
+        /// Length of the payload in bytes, if known.
+        len: Option<usize>,
+    },
+    /// A connection is established.
+    Connect {
+        /// Source address.
+        src: SocketAddr,
+        /// Destination address.
+        dst: SocketAddr,
+    },
+    /// The node is clogged.
+    ClogNode {
+        /// Whether incoming messages are clogged.
+        inbound: bool,
+        /// Whether outgoing messages are clogged.
+        outbound: bool,
+    },
+    /// The node is unclogged.
+    UnclogNode {
+        /// Whether incoming messages are unclogged.
+        inbound: bool,
+        /// Whether outgoing messages are unclogged.
+        outbound: bool,
+    },
+    /// The link from the node to `dst` is clogged.
+    ClogLink {
+        /// Destination node.
+        dst: NodeId,
+    },
+    /// The link from the node to `dst` is unclogged.
+    UnclogLink {
+        /// Destination node.
+        dst: NodeId,
+    },
+}
+
+impl NetEventKind {
+    pub(super) fn clog_node(direction: Direction) -> Self {
+        let (inbound, outbound) = direction.split();
+        NetEventKind::ClogNode { inbound, outbound }
+    }
+
+    pub(super) fn unclog_node(direction: Direction) -> Self {
+        let (inbound, outbound) = direction.split();
+        NetEventKind::UnclogNode { inbound, outbound }
+    }
+}
+
+/// Network event recorder.
+///
+/// Recording only reads the clock and never draws random numbers,
+/// so it doesn't change the behavior of the simulation.
+#[derive(Clone)]
+pub(super) struct Tracer {
+    time: TimeHandle,
+    /// Recorded events. `None` if recording is disabled.
+    events: Arc<Mutex<Option<Vec<NetEvent>>>>,
+}
+
+impl Tracer {
+    pub fn new(time: TimeHandle) -> Self {
+        Tracer {
+            time,
+            events: Default::default(),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut events = self.events.lock();
+        match (enabled, events.is_some()) {
+            (true, false) => *events = Some(vec![]),
+            (false, true) => *events = None,
+            _ => {}
+        }
+    }
+
+    pub fn record(&self, node: NodeId, kind: NetEventKind) {
+        if let Some(events) = &mut *self.events.lock() {
+            events.push(NetEvent {
+                time: self.time.elapsed(),
+                node,
+                kind,
+            });
+        }
+    }
+
+    /// Takes all events recorded so far.
+    pub fn take(&self) -> Vec<NetEvent> {
+        self.events
+            .lock()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the length of a message if it is made of bytes.
+pub(super) fn payload_len(msg: &Payload) -> Option<usize> {
+    // messages sent from `Endpoint` are tagged
+    let msg = match msg.downcast_ref::<(u64, Payload)>() {
+        Some((_, msg)) => msg,
+        None => msg,
+    };
+    if let Some(data) = msg.downcast_ref::<Vec<u8>>() {
+        Some(data.len())
+    } else if let Some(segment) = msg.downcast_ref::<Segment>() {
+        Some(segment.len())
+    } else {
+        msg.downcast_ref::<Bytes>().map(|data| data.len())
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(unused)]
+
 use std::{io::Result, path::Path};
 
 /// An I/O object representing a Unix datagram socket.
@@ -1,11 +1,187 @@
-//! Unix domain socket utility types.
+//! Unix domain sockets between tasks on the same node.
+//!
+//! A socket is bound to a virtual path on the current node, which doesn't touch the file system
+//! and is not visible to other nodes. Streams are delivered by the simulator like TCP streams,
+//! but they don't go through the network, so partitions and link latencies don't affect them.
+//!
+//! # Example
+//!
+//! ```
+//! use madsim::{net::{UnixListener, UnixStream}, runtime::Runtime};
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! let runtime = Runtime::new();
+//! let node = runtime.create_node().build();
+//! let f = node.spawn(async {
+//!     let listener = UnixListener::bind("/run/app.sock").unwrap();
+//!     madsim::task::spawn(async move {
+//!         let (mut stream, _) = listener.accept().await.unwrap();
+//!         stream.write_all(b"hello").await.unwrap();
+//!     });
+//!     let mut stream = UnixStream::connect("/run/app.sock").await.unwrap();
+//!     let mut buf = [0; 5];
+//!     stream.read_exact(&mut buf).await.unwrap();
+//!     assert_eq!(&buf, b"hello");
+//! });
+//! runtime.block_on(f).unwrap();
+//! ```
 
 #![cfg(unix)]
-#![allow(unused)]
-#![doc(hidden)]
 
 mod datagram;
 mod stream;
 
 pub use self::datagram::UnixDatagram;
 pub use self::stream::{UnixListener, UnixStream};
+
+use crate::task::NodeId;
+use spin::Mutex;
+use std::{collections::HashMap, io, path::PathBuf};
+
+/// Listeners of Unix sockets, keyed by the node and the path they are bound to.
+#[derive(Default)]
+pub(super) struct UnixSockets {
+    listeners: Mutex<HashMap<(NodeId, PathBuf), async_channel::Sender<UnixStream>>>,
+}
+
+impl UnixSockets {
+    fn bind(
+        &self,
+        node: NodeId,
+        path: PathBuf,
+        tx: async_channel::Sender<UnixStream>,
+    ) -> io::Result<()> {
+        let mut listeners = self.listeners.lock();
+        if listeners.contains_key(&(node, path.clone())) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("address in use: {}", path.display()),
+            ));
+        }
+        listeners.insert((node, path), tx);
+        Ok(())
+    }
+
+    fn unbind(&self, node: NodeId, path: PathBuf) {
+        self.listeners.lock().remove(&(node, path));
+    }
+
+    fn get(&self, node: NodeId, path: PathBuf) -> Option<async_channel::Sender<UnixStream>> {
+        self.listeners.lock().get(&(node, path)).cloned()
+    }
+
+    /// Close all listeners on the node.
+    pub(super) fn reset_node(&self, id: NodeId) {
+        self.listeners.lock().retain(|(node, _), _| *node != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::NetSim,
+        plugin,
+        runtime::{Handle, Runtime},
+        time::{sleep, Duration, Instant},
+    };
+    use std::path::Path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const PATH: &str = "/run/echo.sock";
+
+    /// Round-trips messages through an echo server and returns the time of each round trip.
+    fn echo(seed: u64) -> Vec<Duration> {
+        let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+        let node = runtime
+            .create_node()
+            .ip("10.0.0.1".parse().unwrap())
+            .build();
+        let other = runtime
+            .create_node()
+            .ip("10.0.0.2".parse().unwrap())
+            .build();
+        let f = node.spawn(async move {
+            let listener = UnixListener::bind(PATH).unwrap();
+            let err = UnixListener::bind(PATH).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+            assert_eq!(
+                listener.local_addr().unwrap().as_pathname(),
+                Some(Path::new(PATH))
+            );
+            crate::task::spawn(async move {
+                loop {
+                    let (mut stream, peer) = listener.accept().await.unwrap();
+                    assert!(peer.is_unnamed());
+                    crate::task::spawn(async move {
+                        let mut buf = [0; 64];
+                        loop {
+                            let n = stream.read(&mut buf).await.unwrap();
+                            if n == 0 {
+                                break;
+                            }
+                            stream.write_all(&buf[..n]).await.unwrap();
+                        }
+                    });
+                }
+            });
+
+            // same-node sockets are not affected by partitions
+            NetSim::current().clog_node(plugin::node());
+            let mut stream = UnixStream::connect(PATH).await.unwrap();
+            assert_eq!(
+                stream.peer_addr().unwrap().as_pathname(),
+                Some(Path::new(PATH))
+            );
+            let mut times = vec![];
+            for msg in [&b"hello"[..], b"unix", b"sockets"] {
+                let t0 = Instant::now();
+                stream.write_all(msg).await.unwrap();
+                let mut buf = vec![0; msg.len()];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(buf, msg);
+                times.push(t0.elapsed());
+            }
+            stream.shutdown().await.unwrap();
+            times
+        });
+        let times = runtime.block_on(f).unwrap();
+
+        // the path is not visible from other nodes
+        let f = other.spawn(async {
+            let err = UnixStream::connect(PATH).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        });
+        runtime.block_on(f).unwrap();
+        times
+    }
+
+    #[test]
+    fn round_trip() {
+        let times = echo(1);
+        assert!(
+            times.iter().all(|t| *t < Duration::from_millis(10)),
+            "{times:?}"
+        );
+        assert_eq!(echo(1), times);
+    }
+
+    #[test]
+    fn rebind_after_restart() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        runtime.block_on(async move {
+            node.spawn(async {
+                let _listener = UnixListener::bind(PATH).unwrap();
+                std::future::pending::<()>().await;
+            });
+            sleep(Duration::from_secs(1)).await;
+            // the listener is closed along with the node
+            Handle::current().restart(node.id());
+            let f = node.spawn(async {
+                UnixListener::bind(PATH).unwrap();
+            });
+            f.await.unwrap();
+        });
+    }
+}
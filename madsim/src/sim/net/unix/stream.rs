@@ -1,58 +1,192 @@
-use bytes::BufMut;
+use crate::{
+    net::{ConnectionReset, NetSim, PayloadReceiver, PayloadSender, ResetFlag},
+    plugin,
+    task::NodeInfo,
+};
+use bytes::{Buf, BufMut, Bytes};
+use futures_util::StreamExt;
 use std::{
+    fmt,
     io::{self, Result},
     os::unix::{
         io::{AsRawFd, RawFd},
         net::SocketAddr,
     },
-    path::Path,
+    path::{Path, PathBuf},
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::*;
+
+/// A Unix socket which can accept connections from other Unix sockets on the same node.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub struct UnixListener {
+    net: Arc<NetSim>,
+    node: Arc<NodeInfo>,
+    path: PathBuf,
+    /// Incoming connections.
+    rx: async_channel::Receiver<UnixStream>,
+}
 
-/// A Unix socket which can accept connections from other Unix sockets.
-#[doc(hidden)]
-#[derive(Debug)]
-pub struct UnixListener {}
+impl fmt::Debug for UnixListener {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("UnixListener")
+            .field("path", &self.path)
+            .finish()
+    }
+}
 
 impl UnixListener {
-    /// Creates a new [`UnixListener`] bound to the specified path.
+    /// Creates a new [`UnixListener`] bound to the specified path on the current node.
+    ///
+    /// Binding a path that is already bound on this node fails with `AddrInUse`.
+    /// The path is released when the listener is dropped, so there is no socket file to remove
+    /// before binding it again.
     pub fn bind<P>(path: P) -> Result<UnixListener>
     where
         P: AsRef<Path>,
     {
-        todo!()
+        let net = plugin::simulator::<NetSim>();
+        let node = crate::context::current_task().node.clone();
+        let path = path.as_ref().to_path_buf();
+        let (tx, rx) = async_channel::unbounded();
+        net.unix.bind(node.id, path.clone(), tx)?;
+        Ok(UnixListener {
+            net,
+            node,
+            path,
+            rx,
+        })
     }
 
     /// Accepts a new incoming connection to this listener.
+    ///
+    /// The address of the peer is unnamed.
+    #[instrument]
     pub async fn accept(&self) -> Result<(UnixStream, SocketAddr)> {
-        todo!()
+        self.net.rand_delay().await?;
+        let stream = (self.rx.recv().await)
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionReset, e))?;
+        trace!(path = ?self.path, "accept unix connection");
+        let peer = stream.peer.clone();
+        Ok((stream, peer))
+    }
+
+    /// Returns the local socket address of this listener.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        SocketAddr::from_pathname(&self.path)
+    }
+}
+
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        // avoid interfering with restarted node
+        if self.node.is_killed() {
+            return;
+        }
+        self.net.unix.unbind(self.node.id, self.path.clone());
     }
 }
 
 /// A structure representing a connected Unix socket.
-#[doc(hidden)]
-#[derive(Debug)]
-pub struct UnixStream {}
+pub struct UnixStream {
+    addr: SocketAddr,
+    peer: SocketAddr,
+    read_buf: Bytes,
+    /// `None` if the write half has been shut down.
+    tx: Option<PayloadSender>,
+    rx: PayloadReceiver,
+}
+
+impl fmt::Debug for UnixStream {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("UnixStream")
+            .field("addr", &self.addr)
+            .field("peer", &self.peer)
+            .finish()
+    }
+}
 
 impl UnixStream {
-    /// Connects to the socket named by `path`.
+    /// Connects to the socket named by `path` on the current node.
+    ///
+    /// Fails with `NotFound` if no listener is bound to the path.
     pub async fn connect<P>(path: P) -> Result<UnixStream>
     where
         P: AsRef<Path>,
     {
-        todo!();
+        let net = plugin::simulator::<NetSim>();
+        net.rand_delay().await?;
+        let node = plugin::node();
+        let path = path.as_ref();
+        let not_found = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such socket: {}", path.display()),
+            )
+        };
+        let listener = net
+            .unix
+            .get(node, path.to_path_buf())
+            .ok_or_else(not_found)?;
+
+        let reset = Arc::new(ResetFlag::new());
+        (net.connections.lock()).push((node, node, Arc::downgrade(&reset)));
+        let (tx1, rx1) = net.local_channel(reset.clone());
+        let (tx2, rx2) = net.local_channel(reset);
+        let named = SocketAddr::from_pathname(path)?;
+        // an empty path makes an unnamed address
+        let unnamed = SocketAddr::from_pathname("")?;
+        let server = UnixStream::new(named.clone(), unnamed.clone(), tx2, rx1);
+        listener.try_send(server).map_err(|_| not_found())?;
+        trace!(?path, "connect unix socket");
+        Ok(UnixStream::new(unnamed, named, tx1, rx2))
+    }
+
+    fn new(addr: SocketAddr, peer: SocketAddr, tx: PayloadSender, rx: PayloadReceiver) -> Self {
+        UnixStream {
+            addr,
+            peer,
+            read_buf: Bytes::new(),
+            tx: Some(tx),
+            rx,
+        }
     }
 
+    /// Returns the socket address of the local half of this connection.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.addr.clone())
+    }
+
+    /// Returns the socket address of the remote half of this connection.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.peer.clone())
+    }
+
+    /// Tries to read data from the stream into the provided buffer, advancing
+    /// the buffer's internal cursor, returning how many bytes were read.
+    ///
+    /// Receives any pending data from the socket but does not wait for new data
+    /// to arrive.
     pub fn try_read_buf<B: BufMut>(&mut self, buf: &mut B) -> io::Result<usize> {
-        unimplemented!();
+        if !self.read_buf.is_empty() {
+            let len = self.read_buf.len().min(buf.remaining_mut());
+            buf.put_slice(&self.read_buf[..len]);
+            self.read_buf.advance(len);
+            return Ok(len);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "read buffer is empty",
+        ))
     }
 }
 
 impl AsRawFd for UnixStream {
     fn as_raw_fd(&self) -> RawFd {
-        todo!();
+        todo!("UnixStream::as_raw_fd");
     }
 }
 
@@ -62,24 +196,50 @@ impl AsyncRead for UnixStream {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        todo!();
+        if !self.read_buf.is_empty() {
+            let len = self.read_buf.len().min(buf.remaining());
+            buf.put_slice(&self.read_buf[..len]);
+            self.read_buf.advance(len);
+            return Poll::Ready(Ok(()));
+        }
+        match self.rx.poll_next_unpin(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(data)) => {
+                let data = ConnectionReset::check(data)?;
+                self.read_buf = *data.downcast::<Bytes>().unwrap();
+                self.poll_read(cx, buf)
+            }
+            // EOF after the peer has shut down the write half
+            Poll::Ready(None) => Poll::Ready(Ok(())),
+        }
     }
 }
 
 impl AsyncWrite for UnixStream {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<Result<usize>> {
-        todo!()
+    /// Sends the data to the peer.
+    ///
+    /// Unlike [`TcpStream`](crate::net::TcpStream), writes are not buffered until flush.
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let Some(tx) = &self.tx else {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "write half has been shut down",
+            )));
+        };
+        tx.send(Box::new(Bytes::copy_from_slice(buf)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))?;
+        Poll::Ready(Ok(buf.len()))
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
-        todo!()
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
-        todo!()
+    /// Shuts down the write half of the connection.
+    ///
+    /// The peer reads EOF after the data in flight.
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.tx = None;
+        Poll::Ready(Ok(()))
     }
 }
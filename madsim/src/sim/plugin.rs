@@ -42,14 +42,25 @@ pub trait Simulator: Any + Send + Sync + DowncastSync {
 impl_downcast!(sync Simulator);
 
 /// Get the simulator.
+///
+/// A simulator that has not been added to the runtime is created on the first access,
+/// and [`Simulator::create_node`] is called for all existing nodes.
 pub fn simulator<S: Simulator>() -> Arc<S> {
     crate::context::current(|h| {
-        let sims = h.sims.lock();
-        sims[&TypeId::of::<S>()]
-            .clone()
-            .downcast_arc()
-            .ok()
-            .unwrap()
+        if let Some(sim) = h.sims.lock().get(&TypeId::of::<S>()) {
+            return sim.clone().downcast_arc().ok().unwrap();
+        }
+        // create the simulator without holding the lock, as it may access other simulators
+        let supervisor = h.task.get_node(NodeId::zero()).unwrap();
+        let sim = Arc::new(S::new1(&h.rand, &h.time, &supervisor, &h.config));
+        sim.create_node(NodeId::zero());
+        for id in h.task.node_ids() {
+            sim.create_node(id);
+        }
+        let mut sims = h.sims.lock();
+        // keep the one created first if the constructor has accessed this simulator
+        let sim = sims.entry(TypeId::of::<S>()).or_insert(sim);
+        sim.clone().downcast_arc().ok().unwrap()
     })
 }
 
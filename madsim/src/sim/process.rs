@@ -0,0 +1,367 @@
+//! Simulated processes.
+//!
+//! Real processes can not be spawned in simulation, as they break determinism. Instead, a test
+//! registers a handler for each program with [`register`]. A [`Command`] spawns the handler as a
+//! task on the current node, and the output returned by the handler becomes the output of the
+//! process. Handlers can sleep to simulate programs that take time to exit.
+//!
+//! # Example
+//!
+//! ```
+//! use madsim::{process::{self, Command, Exit}, runtime::Runtime, time::{sleep, Duration}};
+//!
+//! Runtime::new().block_on(async {
+//!     process::register("git", |args| async move {
+//!         sleep(Duration::from_secs(1)).await;
+//!         Exit {
+//!             code: 0,
+//!             stdout: format!("{args:?}").into_bytes(),
+//!             ..Default::default()
+//!         }
+//!     });
+//!     let output = Command::new("git").arg("status").output().await.unwrap();
+//!     assert!(output.status.success());
+//!     assert_eq!(output.stdout, br#"["status"]"#);
+//! });
+//! ```
+
+use crate::{
+    plugin::{self, Simulator},
+    rand::GlobalRng,
+    task::JoinHandle,
+    time::TimeHandle,
+    Config,
+};
+use futures_util::future::BoxFuture;
+use spin::Mutex;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    future::Future,
+    io,
+    process::{ExitStatus, Output},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+use tracing::debug;
+
+/// The result of a simulated process.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exit {
+    /// The exit code.
+    pub code: i32,
+    /// The data written to stdout.
+    pub stdout: Vec<u8>,
+    /// The data written to stderr.
+    pub stderr: Vec<u8>,
+}
+
+type Handler = Arc<dyn Fn(Vec<String>) -> BoxFuture<'static, Exit> + Send + Sync>;
+
+/// Register the handler of a program.
+///
+/// When a [`Command`] of the program is spawned, the handler is called with the arguments and
+/// runs as a task on the node. The program must be named exactly as in [`Command::new`].
+/// Registering a program again replaces the previous handler.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub fn register<F, Fut>(program: impl Into<String>, handler: F)
+where
+    F: Fn(Vec<String>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Exit> + Send + 'static,
+{
+    let handler: Handler = Arc::new(move |args| Box::pin(handler(args)));
+    let sim = plugin::simulator::<ProcessSim>();
+    sim.handlers.lock().insert(program.into(), handler);
+}
+
+/// A builder of simulated processes, like [`tokio::process::Command`].
+///
+/// [`tokio::process::Command`]: https://docs.rs/tokio/latest/tokio/process/struct.Command.html
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+    kill_on_drop: bool,
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Command")
+            .field("program", &self.program)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl Command {
+    /// Constructs a new `Command` for launching the program.
+    pub fn new(program: impl AsRef<OsStr>) -> Command {
+        Command {
+            program: program.as_ref().to_string_lossy().into(),
+            args: vec![],
+            kill_on_drop: false,
+        }
+    }
+
+    /// Adds an argument to pass to the program.
+    pub fn arg(&mut self, arg: impl AsRef<OsStr>) -> &mut Command {
+        self.args.push(arg.as_ref().to_string_lossy().into());
+        self
+    }
+
+    /// Adds multiple arguments to pass to the program.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Controls whether the process is killed when its [`Child`] is dropped.
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Command {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    /// Spawns the process on the current node.
+    ///
+    /// Returns `NotFound` if no handler is registered for the program.
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        let sim = plugin::simulator::<ProcessSim>();
+        let handler = sim.handlers.lock().get(&self.program).cloned();
+        let Some(handler) = handler else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("program not found: {}", self.program),
+            ));
+        };
+        let pid = sim.next_pid.fetch_add(1, Ordering::Relaxed);
+        debug!(pid, program = %self.program, args = ?self.args, "spawn process");
+        let task = crate::task::spawn(handler(self.args.clone()));
+        Ok(Child {
+            pid,
+            state: State::Running(task),
+            kill_on_drop: self.kill_on_drop,
+        })
+    }
+
+    /// Spawns the process and waits for it to exit.
+    pub async fn status(&mut self) -> io::Result<ExitStatus> {
+        self.spawn()?.wait().await
+    }
+
+    /// Spawns the process, waits for it to exit and collects its output.
+    pub async fn output(&mut self) -> io::Result<Output> {
+        self.spawn()?.wait_with_output().await
+    }
+}
+
+/// A simulated process spawned by a [`Command`].
+#[derive(Debug)]
+pub struct Child {
+    pid: u32,
+    state: State,
+    kill_on_drop: bool,
+}
+
+#[derive(Debug)]
+enum State {
+    Running(JoinHandle<Exit>),
+    Exited(Output),
+}
+
+impl Child {
+    /// Returns the process ID, or `None` if the process has been waited.
+    ///
+    /// Process IDs are allocated sequentially, so they are deterministic.
+    pub fn id(&self) -> Option<u32> {
+        match self.state {
+            State::Running(_) => Some(self.pid),
+            State::Exited(_) => None,
+        }
+    }
+
+    /// Waits for the process to exit and returns its status.
+    ///
+    /// The output of the process is kept for [`wait_with_output`](Child::wait_with_output).
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        Ok(self.wait_output().await.status)
+    }
+
+    /// Waits for the process to exit and collects its output.
+    pub async fn wait_with_output(mut self) -> io::Result<Output> {
+        Ok(self.wait_output().await.clone())
+    }
+
+    async fn wait_output(&mut self) -> &Output {
+        if let State::Running(task) = &mut self.state {
+            let output = match task.await {
+                Ok(exit) => Output {
+                    status: exit_status(exit.code),
+                    stdout: exit.stdout,
+                    stderr: exit.stderr,
+                },
+                Err(_) => Output {
+                    status: killed(),
+                    stdout: vec![],
+                    stderr: vec![],
+                },
+            };
+            debug!(pid = self.pid, status = ?output.status, "process exited");
+            self.state = State::Exited(output);
+        }
+        match &self.state {
+            State::Exited(output) => output,
+            State::Running(_) => unreachable!(),
+        }
+    }
+
+    /// Kills the process without waiting for it to exit.
+    ///
+    /// Returns `InvalidInput` if the process has been waited.
+    pub fn start_kill(&mut self) -> io::Result<()> {
+        match &self.state {
+            State::Running(task) => {
+                task.abort();
+                Ok(())
+            }
+            State::Exited(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid argument: can't kill an exited process",
+            )),
+        }
+    }
+
+    /// Kills the process and waits for it to exit.
+    pub async fn kill(&mut self) -> io::Result<()> {
+        self.start_kill()?;
+        self.wait().await?;
+        Ok(())
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if let (true, State::Running(task)) = (self.kill_on_drop, &self.state) {
+            task.abort();
+        }
+    }
+}
+
+/// Returns the status of a process exited with `code`.
+fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw((code & 0xff) << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// Returns the status of a killed process.
+fn killed() -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(libc::SIGKILL)
+    }
+    #[cfg(windows)]
+    {
+        exit_status(1)
+    }
+}
+
+/// Registered programs of a runtime.
+struct ProcessSim {
+    handlers: Mutex<HashMap<String, Handler>>,
+    next_pid: AtomicU32,
+}
+
+impl Simulator for ProcessSim {
+    fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &Config) -> Self {
+        ProcessSim {
+            handlers: Default::default(),
+            next_pid: AtomicU32::new(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Handle, Runtime},
+        time::{sleep, Duration, Instant},
+    };
+
+    #[test]
+    fn exit_code_after_delay() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            register("helper", |args| async move {
+                sleep(Duration::from_secs(3)).await;
+                Exit {
+                    code: 2,
+                    stdout: args.join(" ").into_bytes(),
+                    stderr: b"failed".to_vec(),
+                }
+            });
+            let node = Handle::current().create_node().build();
+            let output = node
+                .spawn(async {
+                    let t0 = Instant::now();
+                    let mut child = Command::new("helper").args(["-v", "run"]).spawn().unwrap();
+                    assert_eq!(child.id(), Some(1));
+                    let status = child.wait().await.unwrap();
+                    assert_eq!(status.code(), Some(2));
+                    assert_eq!(child.id(), None);
+                    let elapsed = t0.elapsed();
+                    (child.wait_with_output().await.unwrap(), elapsed)
+                })
+                .await
+                .unwrap();
+            let (output, elapsed) = output;
+            assert!(elapsed >= Duration::from_secs(3) && elapsed < Duration::from_millis(3010));
+            assert!(!output.status.success());
+            assert_eq!(output.stdout, b"-v run");
+            assert_eq!(output.stderr, b"failed");
+        });
+    }
+
+    #[test]
+    fn not_found() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let err = Command::new("helper").status().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        });
+    }
+
+    #[test]
+    fn kill() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            register("server", |_| async {
+                sleep(Duration::from_secs(3600)).await;
+                Exit::default()
+            });
+            let mut child = Command::new("server").spawn().unwrap();
+            sleep(Duration::from_secs(1)).await;
+            child.kill().await.unwrap();
+            let status = child.wait().await.unwrap();
+            assert_eq!(status.code(), None);
+            assert!(child.start_kill().is_err());
+        });
+    }
+}
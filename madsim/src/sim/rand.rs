@@ -22,6 +22,58 @@ pub mod prelude {
     };
 }
 
+/// Deterministic samplers of durations.
+///
+/// All functions draw from the global random number generator, so the samples are reproducible
+/// by seed. The simulated network uses [`uniform_duration`](dist::uniform_duration) to sample
+/// the latency of each packet from [`Config::send_latency`](crate::net::Config::send_latency).
+pub mod dist {
+    use super::{thread_rng, Rng};
+    use std::time::Duration;
+
+    /// Samples a duration uniformly from the range `[lo, hi)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lo >= hi`.
+    pub fn uniform_duration(lo: Duration, hi: Duration) -> Duration {
+        uniform_duration_with(&mut thread_rng(), lo, hi)
+    }
+
+    /// Samples a duration from the exponential distribution with the given mean.
+    pub fn exp(mean: Duration) -> Duration {
+        exp_with(&mut thread_rng(), mean)
+    }
+
+    /// Samples a duration from the normal distribution, clamped to zero if negative.
+    pub fn normal(mean: Duration, std_dev: Duration) -> Duration {
+        normal_with(&mut thread_rng(), mean, std_dev)
+    }
+
+    pub(crate) fn uniform_duration_with(
+        rng: &mut impl Rng,
+        lo: Duration,
+        hi: Duration,
+    ) -> Duration {
+        rng.gen_range(lo..hi)
+    }
+
+    pub(crate) fn exp_with(rng: &mut impl Rng, mean: Duration) -> Duration {
+        // inverse transform sampling, `1 - u` is in (0, 1]
+        let u = 1.0 - rng.gen::<f64>();
+        mean.mul_f64(-u.ln())
+    }
+
+    pub(crate) fn normal_with(rng: &mut impl Rng, mean: Duration, std_dev: Duration) -> Duration {
+        // Box-Muller transform
+        let u1 = 1.0 - rng.gen::<f64>();
+        let u2 = rng.gen::<f64>();
+        let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+        let secs = mean.as_secs_f64() + z * std_dev.as_secs_f64();
+        Duration::from_secs_f64(secs.max(0.0))
+    }
+}
+
 /// Global deterministic random number generator.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
 #[derive(Clone)]
@@ -32,6 +84,8 @@ pub struct GlobalRng {
 struct Inner {
     seed: u64,
     rng: Xoshiro256PlusPlus,
+    /// The number of calls on the inner RNG.
+    draws: u64,
     log: Option<Vec<u8>>,
     check: Option<(Vec<u8>, usize)>,
     buggify: bool,
@@ -51,6 +105,7 @@ impl GlobalRng {
         let inner = Inner {
             seed,
             rng: SeedableRng::seed_from_u64(seed),
+            draws: 0,
             log: None,
             check: None,
             buggify: false,
@@ -64,6 +119,7 @@ impl GlobalRng {
     pub(crate) fn with<T>(&self, f: impl FnOnce(&mut Xoshiro256PlusPlus) -> T) -> T {
         let mut lock = self.inner.lock();
         let ret = f(&mut lock.rng);
+        lock.draws += 1;
         // log or check
         if lock.log.is_some() || lock.check.is_some() {
             let t = crate::time::TimeHandle::try_current().map(|t| t.elapsed());
@@ -92,6 +148,23 @@ impl GlobalRng {
         lock.seed
     }
 
+    /// Returns the number of calls on the inner RNG.
+    pub(crate) fn draws(&self) -> u64 {
+        self.inner.lock().draws
+    }
+
+    /// Returns the state of the inner RNG.
+    pub(crate) fn state(&self) -> Xoshiro256PlusPlus {
+        self.inner.lock().rng.clone()
+    }
+
+    /// Restore the state of the inner RNG and the number of calls on it.
+    pub(crate) fn set_state(&self, rng: Xoshiro256PlusPlus, draws: u64) {
+        let mut lock = self.inner.lock();
+        lock.rng = rng;
+        lock.draws = draws;
+    }
+
     pub(crate) fn enable_check(&self, log: Log) {
         let mut lock = self.inner.lock();
         lock.check = Some((log.0, 0));
@@ -166,6 +239,40 @@ where
     thread_rng().gen()
 }
 
+/// Generates the bytes of a random (version 4) UUID using the global random number generator.
+///
+/// Unlike UUIDs generated from the entropy source of the OS, the generated ids are the same
+/// in every run with the same seed. Convert the bytes with `uuid::Uuid::from_bytes`.
+pub fn gen_uuid_v4() -> [u8; 16] {
+    let mut bytes: [u8; 16] = random();
+    // version 4 and RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    bytes
+}
+
+/// Shuffles the slice in place using the global random number generator.
+///
+/// The permutation is the same in every run with the same seed, given that the same random
+/// numbers have been drawn before.
+pub fn shuffle<T>(slice: &mut [T]) {
+    thread_rng().with(|rng| fisher_yates(rng, slice));
+}
+
+/// Returns the elements of the vector in a random order, see [`shuffle`].
+pub fn shuffled<T>(mut vec: Vec<T>) -> Vec<T> {
+    shuffle(&mut vec);
+    vec
+}
+
+/// The algorithm is kept here, so the permutations don't change with the version of `rand`.
+fn fisher_yates<T>(rng: &mut impl Rng, slice: &mut [T]) {
+    for i in (1..slice.len()).rev() {
+        let j = rng.gen_range(0..=i);
+        slice.swap(i, j);
+    }
+}
+
 /// Random log for determinism check.
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct Log(Vec<u8>);
@@ -326,6 +433,74 @@ mod tests {
         assert_eq!(seqs.len(), 3, "hashmap is not deterministic");
     }
 
+    #[test]
+    fn deterministic_dist() {
+        use super::dist;
+        use std::time::Duration;
+
+        let samples = |seed| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                let ms = Duration::from_millis;
+                (0..10)
+                    .map(|_| {
+                        (
+                            dist::uniform_duration(ms(1), ms(10)),
+                            dist::exp(ms(5)),
+                            dist::normal(ms(5), ms(2)),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let seq = samples(1);
+        assert_eq!(seq, samples(1));
+        assert_ne!(seq, samples(2));
+        for (uniform, _, _) in seq {
+            assert!(Duration::from_millis(1) <= uniform && uniform < Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn deterministic_uuid() {
+        let ids = |seed| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async { (0..10).map(|_| super::gen_uuid_v4()).collect::<Vec<_>>() })
+        };
+        let seq = ids(1);
+        assert_eq!(seq, ids(1));
+        assert_ne!(seq, ids(2));
+        for id in seq {
+            assert_eq!(id[6] >> 4, 4);
+            assert_eq!(id[8] >> 6, 0b10);
+        }
+    }
+
+    #[test]
+    fn deterministic_shuffle() {
+        let shuffles = |seed| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                // draws from the current position of the global RNG
+                let mut rng = super::thread_rng().state();
+                let mut expected: Vec<u32> = (0..20).collect();
+                super::fisher_yates(&mut rng, &mut expected);
+
+                let mut v: Vec<u32> = (0..20).collect();
+                super::shuffle(&mut v);
+                assert_eq!(v, expected);
+                (v, super::shuffled((0..20).collect::<Vec<u32>>()))
+            })
+        };
+        let (first, second) = shuffles(1);
+        assert_eq!(shuffles(1), (first.clone(), second.clone()));
+        assert_ne!(first, second);
+        assert_ne!(shuffles(2).0, first);
+        let mut sorted = first;
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+    }
+
     // https://github.com/madsim-rs/madsim/issues/201
     #[test]
     fn getrandom_should_be_deterministic() {
@@ -344,6 +519,7 @@ mod tests {
                     jobs: 1,
                     config: crate::Config::default(),
                     time_limit: None,
+                    step_limit: None,
                     check: false,
                     allow_system_thread: false,
                 }
@@ -1,4 +1,4 @@
-use super::{Config, Runtime};
+use super::{Config, Runtime, Scheduling};
 use futures_util::{stream, StreamExt};
 use std::future::Future;
 use std::time::{Duration, SystemTime};
@@ -15,10 +15,16 @@ pub struct Builder {
     pub config: Config,
     /// The time limit for the test.
     pub time_limit: Option<Duration>,
+    /// The limit of task polls for the test.
+    pub step_limit: Option<u64>,
     /// Enable determinism check.
     pub check: bool,
     /// Allow spawning system thread.
     pub allow_system_thread: bool,
+    /// Panic on std APIs that break determinism, see [`Runtime::set_strict`].
+    pub strict: bool,
+    /// The order of running ready tasks.
+    pub scheduling: Scheduling,
 }
 
 #[allow(clippy::doc_overindented_list_items)]
@@ -49,6 +55,12 @@ impl Builder {
     ///
     ///     By default, there is no time limit.
     ///
+    /// - `MADSIM_TEST_STEP_LIMIT`: Set the limit of task polls for the test.
+    ///
+    ///     The test will panic if tasks are polled more times than the limit, e.g. by busy loops.
+    ///
+    ///     By default, there is no step limit.
+    ///
     /// - `MADSIM_TEST_CHECK_DETERMINISM`: Enable determinism check.
     ///
     ///     The test will be run at least twice with the same seed.
@@ -61,6 +73,18 @@ impl Builder {
     ///     Spawning system thread may cause the test to be non-deterministic.
     ///
     ///     By default, it is disallowed.
+    ///
+    /// - `MADSIM_STRICT`: Enable strict mode.
+    ///
+    ///     Tasks calling std APIs that break determinism, like `std::thread::sleep`, will panic.
+    ///
+    ///     By default, it is disabled.
+    ///
+    /// - `MADSIM_TEST_SCHEDULING`: Set the order of running ready tasks, `random` or `fifo`.
+    ///
+    ///     See [`Scheduling`] for details.
+    ///
+    ///     By default, it is `random`.
     pub fn from_env() -> Self {
         let seed: u64 = if let Ok(seed_str) = std::env::var("MADSIM_TEST_SEED") {
             seed_str
@@ -101,19 +125,33 @@ impl Builder {
                     .expect("MADSIM_TEST_TIME_LIMIT should be an number"),
             )
         });
+        let step_limit = std::env::var("MADSIM_TEST_STEP_LIMIT").ok().map(|num_str| {
+            num_str
+                .parse()
+                .expect("MADSIM_TEST_STEP_LIMIT should be an integer")
+        });
         let check = std::env::var("MADSIM_TEST_CHECK_DETERMINISM").is_ok();
         if check {
             count = count.max(2);
         }
         let allow_system_thread = std::env::var("MADSIM_ALLOW_SYSTEM_THREAD").is_ok();
+        let strict = std::env::var("MADSIM_STRICT").is_ok();
+        let scheduling = match std::env::var("MADSIM_TEST_SCHEDULING").as_deref() {
+            Ok("random") | Err(_) => Scheduling::Randomized,
+            Ok("fifo") => Scheduling::Fifo,
+            Ok(s) => panic!("MADSIM_TEST_SCHEDULING should be `random` or `fifo`, not {s:?}"),
+        };
         Builder {
             seed,
             count,
             jobs,
             config,
             time_limit,
+            step_limit,
             check,
             allow_system_thread,
+            strict,
+            scheduling,
         }
     }
 
@@ -136,9 +174,16 @@ impl Builder {
                         if let Some(limit) = self.time_limit {
                             rt.set_time_limit(limit);
                         }
+                        if let Some(limit) = self.step_limit {
+                            rt.set_step_limit(limit);
+                        }
                         if self.allow_system_thread {
                             rt.set_allow_system_thread(true);
                         }
+                        if self.strict {
+                            rt.set_strict(true);
+                        }
+                        rt.set_scheduling(self.scheduling);
                         let ret = rt.block_on(f());
                         tx.send(()).unwrap();
                         ret
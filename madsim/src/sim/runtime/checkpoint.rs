@@ -0,0 +1,236 @@
+use super::*;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+/// A point of a simulation to return to.
+///
+/// Tasks are arbitrary futures which can not be copied, so a checkpoint does not hold the state
+/// of the simulation. Instead, it records the seed and config, the simulated time and the state
+/// of the random number generator. Since the simulation is deterministic, the state at the
+/// checkpoint is rebuilt by replaying the same steps from the beginning, see
+/// [`Runtime::restore`]. Alternatively, [`Runtime::fork`] continues from the random number
+/// generator and the clock of the checkpoint, leaving the rest to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub(super) seed: u64,
+    pub(super) config: Config,
+    pub(super) time: Duration,
+    pub(super) draws: u64,
+    pub(super) rng: Xoshiro256PlusPlus,
+}
+
+impl Checkpoint {
+    /// Returns the random seed of the simulation.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the simulated time elapsed since the start of the simulation.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+}
+
+impl Runtime {
+    /// Returns a checkpoint of the current simulation state.
+    ///
+    /// This should be called between calls to [`block_on`](Runtime::block_on).
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.handle.checkpoint()
+    }
+
+    /// Create a runtime to return to the checkpoint.
+    ///
+    /// The returned runtime starts from the beginning with the seed and config of the checkpoint.
+    /// The caller should run the same steps that led to the checkpoint, after which the state is
+    /// identical to the checkpoint and the simulation can be continued with variations.
+    ///
+    /// # Panics
+    ///
+    /// [`block_on`](Runtime::block_on) panics when it returns at the time of the checkpoint, but
+    /// the replay has diverged from the run that created the checkpoint. If it returns after the
+    /// time of the checkpoint, the replay has passed the checkpoint and is not checked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{rand, runtime::Runtime, time::{sleep, Duration}};
+    ///
+    /// fn setup(rt: &Runtime) {
+    ///     rt.create_node().build().spawn(async {
+    ///         loop {
+    ///             sleep(Duration::from_millis(rand::random::<u64>() % 100)).await;
+    ///         }
+    ///     });
+    /// }
+    ///
+    /// let rt = Runtime::new();
+    /// setup(&rt);
+    /// rt.block_on(sleep(Duration::from_secs(10)));
+    /// let checkpoint = rt.checkpoint();
+    ///
+    /// // replay to the checkpoint
+    /// let rt = Runtime::restore(checkpoint.clone());
+    /// setup(&rt);
+    /// rt.block_on(sleep(Duration::from_secs(10)));
+    /// assert_eq!(rt.checkpoint(), checkpoint);
+    /// ```
+    pub fn restore(checkpoint: Checkpoint) -> Self {
+        let rt = Runtime::with_seed_and_config(checkpoint.seed, checkpoint.config.clone());
+        *rt.replay.lock() = Some(checkpoint);
+        rt
+    }
+
+    /// Create a runtime that continues from the random number generator and the clock of the
+    /// checkpoint.
+    ///
+    /// The returned runtime starts at the time of the checkpoint, and draws the same random
+    /// numbers as the original runtime after the checkpoint. Nodes, tasks, timers and the state
+    /// of simulators are not carried over, so the caller should rebuild them from the state of
+    /// the application. This is cheaper than [`restore`](Runtime::restore) since nothing is
+    /// replayed, but the continuation is identical to the original run only if the rebuilt state
+    /// is.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{rand, runtime::Runtime, time::{sleep, Duration}};
+    ///
+    /// let rt = Runtime::new();
+    /// rt.block_on(sleep(Duration::from_secs(10)));
+    /// let checkpoint = rt.checkpoint();
+    /// let x = rt.block_on(async { rand::random::<u64>() });
+    ///
+    /// let rt = Runtime::fork(&checkpoint);
+    /// assert_eq!(rt.handle().elapsed(), checkpoint.time());
+    /// assert_eq!(rt.block_on(async { rand::random::<u64>() }), x);
+    /// ```
+    pub fn fork(checkpoint: &Checkpoint) -> Self {
+        let rt = Runtime::with_seed_and_config(checkpoint.seed, checkpoint.config.clone());
+        (rt.rand).set_state(checkpoint.rng.clone(), checkpoint.draws);
+        rt.handle.time.advance(checkpoint.time);
+        rt
+    }
+
+    /// Check that the replay reaches the checkpoint when it returns at the time of the checkpoint.
+    pub(super) fn check_replay(&self) {
+        let mut replay = self.replay.lock();
+        let Some(checkpoint) = replay.as_ref() else {
+            return;
+        };
+        let elapsed = self.handle.elapsed();
+        if elapsed < checkpoint.time {
+            return;
+        }
+        if elapsed > checkpoint.time {
+            // the state at the checkpoint is gone
+            tracing::debug!(?elapsed, checkpoint = ?checkpoint.time, "replay passed the checkpoint");
+            *replay = None;
+            return;
+        }
+        let reached = self.checkpoint();
+        if reached != *checkpoint {
+            panic!("replay diverged from the checkpoint:\nexpected: {checkpoint:?}\nreached: {reached:?}");
+        }
+        *replay = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{sleep, Instant};
+
+    /// Spawn a node that records random numbers at random times.
+    fn setup(rt: &Runtime) -> Arc<Mutex<Vec<(Duration, u64)>>> {
+        let events = Arc::new(Mutex::new(vec![]));
+        let events0 = events.clone();
+        rt.create_node().build().spawn(async move {
+            let start = Instant::now();
+            loop {
+                sleep(Duration::from_millis(crate::rand::random::<u64>() % 100)).await;
+                events0
+                    .lock()
+                    .push((start.elapsed(), crate::rand::random()));
+            }
+        });
+        events
+    }
+
+    /// Run from the checkpoint at 5s to 10s and return the events in between.
+    fn run_to_end(rt: &Runtime, events: &Mutex<Vec<(Duration, u64)>>) -> Vec<(Duration, u64)> {
+        let skip = events.lock().len();
+        rt.block_on(sleep(Duration::from_secs(5)));
+        events.lock()[skip..].to_vec()
+    }
+
+    #[test]
+    fn restore() {
+        let rt = Runtime::with_seed_and_config(1, Config::default());
+        let events = setup(&rt);
+        rt.block_on(sleep(Duration::from_secs(5)));
+        let checkpoint = rt.checkpoint();
+        assert_eq!(checkpoint.seed(), 1);
+        assert!(checkpoint.time() >= Duration::from_secs(5));
+        let continuation = run_to_end(&rt, &events);
+        assert!(!continuation.is_empty());
+
+        let rt = Runtime::restore(checkpoint.clone());
+        let events = setup(&rt);
+        rt.block_on(sleep(Duration::from_secs(5)));
+        assert_eq!(rt.checkpoint(), checkpoint);
+        assert_eq!(run_to_end(&rt, &events), continuation);
+    }
+
+    #[test]
+    fn fork() {
+        let draw = || async {
+            sleep(Duration::from_secs(1)).await;
+            let time = Handle::current().elapsed();
+            let numbers: Vec<u64> = (0..10).map(|_| crate::rand::random()).collect();
+            (time, numbers)
+        };
+        let rt = Runtime::with_seed_and_config(1, Config::default());
+        rt.block_on(draw());
+        // the supervisor can take a checkpoint in the middle of a simulation
+        let checkpoint = rt.block_on(async { Handle::current().checkpoint() });
+        assert_eq!(rt.checkpoint(), checkpoint);
+        let expected = rt.block_on(draw());
+        // advance the original runtime
+        rt.block_on(draw());
+        assert!(rt.checkpoint().time() > expected.0);
+
+        let rt = Runtime::fork(&checkpoint);
+        assert_eq!(rt.checkpoint(), checkpoint);
+        assert_eq!(rt.block_on(draw()), expected);
+        // and again
+        let rt = Runtime::fork(&checkpoint);
+        assert_eq!(rt.block_on(draw()), expected);
+    }
+
+    #[test]
+    fn replay_past_checkpoint() {
+        let rt = Runtime::with_seed_and_config(1, Config::default());
+        setup(&rt);
+        rt.block_on(sleep(Duration::from_secs(5)));
+        let checkpoint = rt.checkpoint();
+
+        // a replay overshooting the checkpoint is not checked
+        let rt = Runtime::restore(checkpoint);
+        setup(&rt);
+        rt.block_on(sleep(Duration::from_secs(7)));
+        assert!(rt.replay.lock().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "replay diverged from the checkpoint")]
+    fn diverged_replay() {
+        let rt = Runtime::new();
+        setup(&rt);
+        rt.block_on(sleep(Duration::from_secs(5)));
+        let checkpoint = rt.checkpoint();
+
+        // the node is not spawned in the replay
+        let rt = Runtime::restore(checkpoint);
+        rt.block_on(sleep(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,134 @@
+//! Integration with [`tracing_subscriber`].
+//!
+//! Tasks run inside the span of their node, `node{id=.. name=..}`, so a formatter that prints
+//! the span context tags each event with the node it came from. [`SimTime`] prints the logical
+//! time of the simulation instead of the wall clock, and [`layer`] combines the two into a
+//! formatting layer that can be added to a custom subscriber:
+//!
+//! ```
+//! use tracing_subscriber::prelude::*;
+//!
+//! let subscriber = tracing_subscriber::registry().with(madsim::runtime::log::layer());
+//! tracing::subscriber::with_default(subscriber, || {
+//!     madsim::runtime::Runtime::new().block_on(async {
+//!         tracing::info!("hello");
+//!     });
+//! });
+//! ```
+
+use super::context;
+use std::fmt;
+use tracing_subscriber::{
+    fmt::{format, time::FormatTime},
+    registry::LookupSpan,
+};
+
+/// A timer that formats the logical time elapsed since the start of the simulation.
+///
+/// Nothing is written for events outside of a simulation.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SimTime;
+
+impl FormatTime for SimTime {
+    fn format_time(&self, w: &mut format::Writer<'_>) -> fmt::Result {
+        let Some(elapsed) = context::try_current(|h| h.elapsed()) else {
+            return Ok(());
+        };
+        write!(w, "{}.{:09}s", elapsed.as_secs(), elapsed.subsec_nanos())
+    }
+}
+
+/// The formatting layer returned by [`layer`].
+pub type Layer<S> =
+    tracing_subscriber::fmt::Layer<S, format::DefaultFields, format::Format<format::Full, SimTime>>;
+
+/// Returns the formatting layer of [`init_logger`](super::init_logger).
+///
+/// Events are timestamped with [`SimTime`] and prefixed with their node and task spans.
+pub fn layer<S>() -> Layer<S>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_subscriber::fmt::layer().with_timer(SimTime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::Runtime,
+        time::{sleep, Duration},
+    };
+    use spin::Mutex;
+    use std::{io, sync::Arc};
+    use tracing_subscriber::prelude::*;
+
+    /// Collects the output of the subscriber.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events_carry_node_and_time() {
+        let output = Output::default();
+        let output_ = output.clone();
+        let layer = layer()
+            .with_ansi(false)
+            .with_writer(move || output_.clone());
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let runtime = Runtime::new();
+            runtime.block_on(async {
+                let tasks: Vec<_> = (1..=2)
+                    .map(|i| {
+                        let node = crate::runtime::Handle::current().create_node().build();
+                        assert_eq!(node.id().to_string(), i.to_string());
+                        node.spawn(async move {
+                            for round in 0..3 {
+                                sleep(Duration::from_millis(300 * i)).await;
+                                tracing::info!(round, "tick");
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            });
+        });
+
+        let output = String::from_utf8(output.0.lock().clone()).unwrap();
+        let mut last = Duration::ZERO;
+        let mut count = [0; 2];
+        for line in output.lines().filter(|line| line.contains(" tick round=")) {
+            let (time, rest) = line.split_once(' ').unwrap();
+            let (secs, nanos) = time.strip_suffix('s').unwrap().split_once('.').unwrap();
+            let time = Duration::new(secs.parse().unwrap(), nanos.parse().unwrap());
+            assert!(time >= last, "{output}");
+            last = time;
+
+            let id: usize = (rest.split_once("node{id=").unwrap().1)
+                .split(|c: char| !c.is_ascii_digit())
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap();
+            let round: u64 = rest.rsplit_once("round=").unwrap().1.parse().unwrap();
+            // node i logs round r after (r + 1) * 300ms * i
+            let expected = Duration::from_millis(300 * id as u64 * (round + 1));
+            assert!(time >= expected && time < expected + Duration::from_millis(50));
+            count[id - 1] += 1;
+        }
+        assert_eq!(count, [3, 3], "{output}");
+    }
+}
@@ -14,12 +14,32 @@ use std::{
 };
 
 mod builder;
+mod checkpoint;
 pub(crate) mod context;
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub mod log;
 mod metrics;
 
 pub use self::builder::Builder;
+pub use self::checkpoint::Checkpoint;
 pub use self::metrics::RuntimeMetrics;
 
+/// The order in which the executor runs ready tasks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Run a ready task chosen by the random generator.
+    ///
+    /// Different seeds run concurrent tasks in different interleavings, so a sweep of seeds
+    /// explores many of them. The same seed always runs the same interleaving.
+    #[default]
+    Randomized,
+    /// Run ready tasks in the order they were woken.
+    ///
+    /// The interleaving doesn't depend on the seed, except through random latencies or
+    /// faults. This gives stable runs while changing other parts of the code.
+    Fifo,
+}
+
 /// The madsim runtime.
 ///
 /// The runtime provides basic components for deterministic simulation,
@@ -35,6 +55,8 @@ pub struct Runtime {
     rand: rand::GlobalRng,
     task: task::Executor,
     handle: Handle,
+    /// The checkpoint to reach if the runtime is restored from it.
+    replay: Mutex<Option<Checkpoint>>,
 }
 
 impl Default for Runtime {
@@ -61,16 +83,22 @@ impl Runtime {
             sims,
             config,
             allow_system_thread: false,
+            strict: false,
+        };
+        let rt = Runtime {
+            rand,
+            task,
+            handle,
+            replay: Mutex::new(None),
         };
-        let rt = Runtime { rand, task, handle };
         rt.add_simulator::<fs::FsSim>();
         rt.add_simulator::<net::NetSim>();
+        rt.add_simulator::<sync::barrier::BarrierSim>();
         rt
     }
 
     /// Register a simulator.
     pub fn add_simulator<S: plugin::Simulator>(&self) {
-        let mut sims = self.handle.sims.lock();
         let sim = Arc::new(S::new1(
             &self.handle.rand,
             &self.handle.time,
@@ -79,7 +107,7 @@ impl Runtime {
         ));
         // create node for supervisor
         sim.create_node(NodeId::zero());
-        sims.insert(TypeId::of::<S>(), sim);
+        self.handle.sims.lock().insert(TypeId::of::<S>(), sim);
     }
 
     /// Return a handle to the runtime.
@@ -126,12 +154,16 @@ impl Runtime {
     /// ```
     pub fn block_on<F: Future>(&self, future: F) -> F::Output {
         let _guard = crate::context::enter(self.handle.clone());
-        self.task.block_on(future)
+        let output = self.task.block_on(future);
+        self.check_replay();
+        output
     }
 
     /// Set a time limit of the execution.
     ///
-    /// The runtime will panic when time limit exceeded.
+    /// The runtime will panic when time limit exceeded. The panic message shows the next timer
+    /// and the number of pending tasks on each node by spawn location, to help find the task
+    /// that keeps the simulation running.
     ///
     /// # Example
     ///
@@ -149,6 +181,36 @@ impl Runtime {
         self.task.set_time_limit(limit);
     }
 
+    /// Set a limit of the number of task polls in the execution.
+    ///
+    /// Tasks that yield in a loop keep the simulation busy without waiting for timers, so a
+    /// [time limit](Runtime::set_time_limit) may never be reached. The runtime will panic when
+    /// step limit exceeded, showing the last polled task and the number of pending tasks on each
+    /// node by spawn location. The count is deterministic for a given seed.
+    ///
+    /// # Example
+    ///
+    /// ```should_panic
+    /// use madsim::{runtime::Runtime, task::yield_now};
+    ///
+    /// let mut rt = Runtime::new();
+    /// rt.set_step_limit(1000);
+    ///
+    /// rt.block_on(async {
+    ///     loop {
+    ///         yield_now().await;
+    ///     }
+    /// });
+    /// ```
+    pub fn set_step_limit(&mut self, limit: u64) {
+        self.task.set_step_limit(limit);
+    }
+
+    /// Set the order of running ready tasks. See [`Scheduling`].
+    pub fn set_scheduling(&mut self, scheduling: Scheduling) {
+        self.task.set_scheduling(scheduling);
+    }
+
     /// Set whether to allow spawning system thread.
     ///
     /// Spawning system thread is not allowed by default because it may cause non-determinism.
@@ -157,6 +219,16 @@ impl Runtime {
         self.handle.allow_system_thread = allowed;
     }
 
+    /// Set whether to panic when tasks call std APIs that break determinism.
+    ///
+    /// In strict mode, a task calling `std::thread::sleep` panics once it yields, pointing to
+    /// `madsim::time::sleep` instead. The real sleep would block all nodes without advancing
+    /// the simulated time. This is disabled by default, and can be enabled by the environment
+    /// variable `MADSIM_STRICT` in tests.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.handle.strict = strict;
+    }
+
     /// Check determinism of the future.
     ///
     /// # Example
@@ -219,6 +291,7 @@ pub struct Handle {
 
     pub(crate) config: Config,
     pub(crate) allow_system_thread: bool,
+    pub(crate) strict: bool,
 }
 
 impl fmt::Debug for Handle {
@@ -269,6 +342,33 @@ impl Handle {
         self.rand.seed()
     }
 
+    /// Returns the current logical time of the simulation.
+    ///
+    /// Unlike [`Instant::now`](std::time::Instant::now), this can be called from any thread
+    /// holding the handle, e.g. the supervisor code orchestrating faults.
+    pub fn now(&self) -> time::Instant {
+        self.time.now_instant()
+    }
+
+    /// Returns the amount of logical time elapsed since the runtime was created.
+    pub fn elapsed(&self) -> Duration {
+        self.time.elapsed()
+    }
+
+    /// Returns a checkpoint of the current simulation state.
+    ///
+    /// Unlike [`Runtime::checkpoint`], this can be called by the supervisor in the middle of a
+    /// simulation.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            seed: self.rand.seed(),
+            config: self.config.clone(),
+            time: self.elapsed(),
+            draws: self.rand.draws(),
+            rng: self.rand.state(),
+        }
+    }
+
     /// Kill a node.
     ///
     /// - All tasks spawned on this node will be killed immediately.
@@ -277,11 +377,40 @@ impl Handle {
         self.task.kill(&id);
     }
 
+    /// Kill a node as the OOM killer would.
+    ///
+    /// The node is killed abruptly like [`kill`](Handle::kill), but its [exit
+    /// reason](Handle::exit_reason) is [`ExitReason::OutOfMemory`], so that a supervisor or the
+    /// restarted node can tell it apart from other kills.
+    pub fn oom_kill(&self, id: impl ToNodeId) {
+        self.task.oom_kill(&id);
+    }
+
     /// Restart a node。
     pub fn restart(&self, id: impl ToNodeId) {
         self.task.restart(&id);
     }
 
+    /// Reboot nodes one at a time, waiting `settle` after each reboot, like a rolling upgrade.
+    ///
+    /// Each node is killed and restarted with its disk preserved, see [`NodeHandle::reboot`].
+    /// Only one node is rebooted at a time, so a cluster keeps its quorum as long as a node
+    /// recovers within `settle`. On return, the last node has also been given `settle` to
+    /// recover.
+    pub async fn rolling_restart(
+        &self,
+        nodes: impl IntoIterator<Item = impl ToNodeId>,
+        settle: Duration,
+    ) {
+        for id in nodes {
+            let id = id.to_node_id(&self.task);
+            tracing::debug!(%id, "rolling restart");
+            self.task.kill(id);
+            self.task.restart(id);
+            time::sleep(settle).await;
+        }
+    }
+
     /// Pause the execution of a node.
     pub fn pause(&self, id: impl ToNodeId) {
         self.task.pause(id);
@@ -297,11 +426,30 @@ impl Handle {
         self.task.send_ctrl_c(id);
     }
 
+    /// Send a shutdown signal (`SIGTERM`) to the node.
+    ///
+    /// It is received by listeners of [`SignalKind::terminate`] on the node at the current
+    /// logical time, which can run their graceful shutdown logic. A node without such a listener
+    /// is killed, as a process is by default.
+    ///
+    /// [`SignalKind::terminate`]: crate::signal::unix::SignalKind::terminate
+    pub fn send_shutdown(&self, id: impl ToNodeId) {
+        self.task.send_signal(id, libc::SIGTERM);
+    }
+
     /// Returns whether the node is killed or exited.
     pub fn is_exit(&self, id: impl ToNodeId) -> bool {
         self.task.is_exit(id)
     }
 
+    /// Returns why the node exited last time, or `None` if it has never exited.
+    ///
+    /// The reason is kept after the node is restarted, so the init task can check why the
+    /// previous incarnation died.
+    pub fn exit_reason(&self, id: impl ToNodeId) -> Option<ExitReason> {
+        self.task.exit_reason(id)
+    }
+
     /// Create a node which will be bound to the specified address.
     pub fn create_node(&self) -> NodeBuilder<'_> {
         NodeBuilder::new(self)
@@ -309,7 +457,16 @@ impl Handle {
 
     /// Return a handle of the specified node.
     pub fn get_node(&self, id: impl ToNodeId) -> Option<NodeHandle> {
-        self.task.get_node(id).map(|task| NodeHandle { task })
+        self.task.get_node(id).map(|task| NodeHandle {
+            task,
+            handle: self.task.clone(),
+            sims: self.sims.clone(),
+        })
+    }
+
+    /// Returns the IDs of all nodes, including killed ones, in the order they were created.
+    pub fn nodes(&self) -> Vec<NodeId> {
+        self.task.node_ids()
     }
 
     /// Returns a view that lets you get information about how the runtime is
@@ -319,6 +476,228 @@ impl Handle {
             task: self.task.clone(),
         }
     }
+
+    /// Waits until `count` participants have arrived at the barrier named `name`.
+    ///
+    /// Tasks on any node, and the supervisor, can wait on the same barrier. Once the last
+    /// participant arrives, all of them proceed at the same logical time, in an order decided
+    /// by the seed, and the barrier is ready for the next `count` participants. A participant
+    /// leaves the barrier if its future is dropped, e.g. when its node is killed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the barrier was created with a different `count`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::runtime::{Handle, Runtime};
+    ///
+    /// Runtime::new().block_on(async {
+    ///     let handle = Handle::current();
+    ///     let node = handle.create_node().build();
+    ///     let task = node.spawn(async { Handle::current().barrier("ready", 2).await });
+    ///     // inject faults once all nodes are ready
+    ///     assert!(handle.barrier("ready", 2).await.is_leader());
+    ///     assert!(!task.await.unwrap().is_leader());
+    /// });
+    /// ```
+    pub async fn barrier(&self, name: &str, count: usize) -> sync::BarrierWaitResult {
+        let sim = get_sim::<sync::barrier::BarrierSim>(&self.sims);
+        sim.wait(name, count).await
+    }
+
+    /// Returns true if the simulation has no pending work.
+    ///
+    /// The simulation is quiescent when no task other than the current one is ready to run, no
+    /// timer is pending and no message is in flight on connections. Nothing happens from then on
+    /// unless the current task does something, so this can be asserted after a workload to check
+    /// that no background task is left running.
+    ///
+    /// Timers of dropped [`sleep`](crate::time::sleep)s and [`timeout`](crate::time::timeout)s
+    /// are pending until their deadlines, as timers can not be cancelled.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{runtime::{Handle, Runtime}, time::{interval, Duration}};
+    ///
+    /// Runtime::new().block_on(async {
+    ///     let handle = Handle::current();
+    ///     assert!(handle.is_quiescent());
+    ///
+    ///     madsim::task::spawn(async {
+    ///         let mut interval = interval(Duration::from_secs(1));
+    ///         loop {
+    ///             interval.tick().await;
+    ///         }
+    ///     });
+    ///     madsim::task::yield_now().await;
+    ///     assert!(!handle.is_quiescent());
+    /// });
+    /// ```
+    pub fn is_quiescent(&self) -> bool {
+        self.pending_work().is_none()
+    }
+
+    /// Panics if the simulation is not quiescent, showing the pending work.
+    ///
+    /// See [`is_quiescent`](Handle::is_quiescent).
+    #[track_caller]
+    pub fn assert_quiescent(&self) {
+        if let Some(work) = self.pending_work() {
+            panic!("simulation is not quiescent: {work}");
+        }
+    }
+
+    /// Describes the pending work, or returns `None` if there is none.
+    fn pending_work(&self) -> Option<String> {
+        let runnable = self.task.num_runnable();
+        let next_timer = self.time.next_timer();
+        let in_flight = get_sim::<net::NetSim>(&self.sims).num_in_flight();
+        if runnable == 0 && next_timer.is_none() && in_flight == 0 {
+            return None;
+        }
+        Some(format!(
+            "runnable tasks: {runnable}, next timer at: {next_timer:?}, messages in flight: {in_flight}\n\
+             pending tasks by node by spawn: {}",
+            self.task.num_tasks_by_node_by_spawn()
+        ))
+    }
+
+    /// Set the latency of fs write and sync operations on a node.
+    pub fn set_fs_latency(&self, id: impl ToNodeId, latency: Duration) {
+        let id = id.to_node_id(&self.task);
+        get_sim::<fs::FsSim>(&self.sims).set_latency(id, latency);
+    }
+
+    /// Stall or unstall fs write and sync operations on a node.
+    ///
+    /// This simulates a hung disk: stalled operations won't complete until the node is unstalled.
+    pub fn set_fs_stall(&self, id: impl ToNodeId, stalled: bool) {
+        let id = id.to_node_id(&self.task);
+        get_sim::<fs::FsSim>(&self.sims).set_stall(id, stalled);
+    }
+
+    /// Let fs write and sync operations on a node fail with an OS error, e.g. `libc::ENOSPC`.
+    ///
+    /// `None` clears the error. See [`FsSim::set_error`](crate::fs::FsSim::set_error).
+    pub fn set_fs_error(&self, id: impl ToNodeId, errno: Option<i32>) {
+        let id = id.to_node_id(&self.task);
+        get_sim::<fs::FsSim>(&self.sims).set_error(id, errno);
+    }
+
+    /// Let fs write and sync operations on a node fail with an OS error with the probability.
+    ///
+    /// See [`FsSim::set_error_rate`](crate::fs::FsSim::set_error_rate).
+    pub fn set_fs_error_rate(&self, id: impl ToNodeId, errno: i32, probability: f64) {
+        let id = id.to_node_id(&self.task);
+        get_sim::<fs::FsSim>(&self.sims).set_error_rate(id, errno, probability);
+    }
+
+    /// Set the latency of packets sent from `src` to `dst`.
+    ///
+    /// The opposite direction is not affected. See
+    /// [`NetSim::set_link_latency`](net::NetSim::set_link_latency).
+    pub fn set_latency(&self, src: impl ToNodeId, dst: impl ToNodeId, latency: Duration) {
+        let (src, dst) = (src.to_node_id(&self.task), dst.to_node_id(&self.task));
+        get_sim::<net::NetSim>(&self.sims).set_link_latency(src, dst, latency);
+    }
+
+    /// Set the latency of packets sent between two nodes in both directions.
+    pub fn set_latency_between(
+        &self,
+        node1: impl ToNodeId,
+        node2: impl ToNodeId,
+        latency: Duration,
+    ) {
+        let (node1, node2) = (node1.to_node_id(&self.task), node2.to_node_id(&self.task));
+        let net = get_sim::<net::NetSim>(&self.sims);
+        net.set_link_latency(node1, node2, latency);
+        net.set_link_latency(node2, node1, latency);
+    }
+
+    /// Schedule changes of the latency of packets sent from `src` to `dst`.
+    ///
+    /// Each entry sets the latency like [`set_latency`](Self::set_latency) at its time, until
+    /// the next entry. Packets are delayed by the latency in effect when they are sent, so
+    /// packets in flight are not affected by a change. Entries in the past apply immediately.
+    ///
+    /// For example, a spike of 500ms between 10s and 20s over a normal latency of 1ms:
+    ///
+    /// ```
+    /// # use madsim::{runtime::Runtime, time::Duration};
+    /// # let runtime = Runtime::new();
+    /// # let (node1, node2) = (runtime.create_node().build(), runtime.create_node().build());
+    /// let handle = runtime.handle();
+    /// let t0 = handle.now();
+    /// handle.set_latency_schedule(
+    ///     node1.id(),
+    ///     node2.id(),
+    ///     vec![
+    ///         (t0, Duration::from_millis(1)),
+    ///         (t0 + Duration::from_secs(10), Duration::from_millis(500)),
+    ///         (t0 + Duration::from_secs(20), Duration::from_millis(1)),
+    ///     ],
+    /// );
+    /// ```
+    pub fn set_latency_schedule(
+        &self,
+        src: impl ToNodeId,
+        dst: impl ToNodeId,
+        schedule: Vec<(time::Instant, Duration)>,
+    ) {
+        let (src, dst) = (src.to_node_id(&self.task), dst.to_node_id(&self.task));
+        let net = get_sim::<net::NetSim>(&self.sims);
+        for (at, latency) in schedule {
+            let net = net.clone();
+            // timers of the same time fire in order, so the last entry of a time wins
+            self.time.add_timer_at(at, move || {
+                net.set_link_latency(src, dst, latency);
+            });
+        }
+    }
+
+    /// Set the wall clock skew of a node.
+    ///
+    /// `SystemTime::now()` on the node is shifted by the skew. Use [`ClockSkew::ZERO`] to reset.
+    ///
+    /// [`ClockSkew::ZERO`]: time::ClockSkew::ZERO
+    pub fn set_clock_skew(&self, id: impl ToNodeId, skew: time::ClockSkew) {
+        let id = id.to_node_id(&self.task);
+        self.time.set_clock_skew(id, skew);
+    }
+
+    /// Take all network events recorded so far.
+    ///
+    /// Recording must be enabled by [`NetSim::enable_trace`](net::NetSim::enable_trace) first.
+    /// This is useful for dumping a timeline of what happened on the wire when a test fails.
+    pub fn take_net_trace(&self) -> Vec<net::NetEvent> {
+        get_sim::<net::NetSim>(&self.sims).take_trace()
+    }
+
+    /// Abruptly reset all connections from or to a node, like a TCP RST.
+    ///
+    /// Reads and writes on these connections fail with `ConnectionReset` on both ends.
+    /// Killing a node does the same to its connections.
+    pub fn reset_connections(&self, id: impl ToNodeId) {
+        let id = id.to_node_id(&self.task);
+        get_sim::<net::NetSim>(&self.sims).reset_connections(id);
+    }
+}
+
+/// The reason why a node exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ExitReason {
+    /// The init task of the node completed.
+    Exited,
+    /// Killed by [`Handle::kill`], a restart, or a "ctrl-c" signal without a handler.
+    Killed,
+    /// Killed by [`Handle::oom_kill`].
+    OutOfMemory,
+    /// A task panicked and the node is [restarted on panic](NodeBuilder::restart_on_panic).
+    Panicked,
 }
 
 /// Builds a node with custom configurations.
@@ -414,7 +793,11 @@ impl<'a> NodeBuilder<'a> {
                 }
             }
         }
-        NodeHandle { task }
+        NodeHandle {
+            task,
+            handle: self.handle.task.clone(),
+            sims: self.handle.sims.clone(),
+        }
     }
 }
 
@@ -422,6 +805,8 @@ impl<'a> NodeBuilder<'a> {
 #[derive(Clone)]
 pub struct NodeHandle {
     task: task::Spawner,
+    handle: task::TaskHandle,
+    sims: Arc<Simulators>,
 }
 
 impl NodeHandle {
@@ -430,20 +815,79 @@ impl NodeHandle {
         self.task.node_id()
     }
 
-    /// Spawn a future onto the runtime.
+    /// Spawn a future onto the node.
+    ///
+    /// This can be called at any time, e.g. by the supervisor in the middle of a simulation.
+    /// The task runs in the context of the node, so it uses the node's IP address and file
+    /// system, and is killed along with the node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{runtime::Handle, time::{sleep, Duration}};
+    ///
+    /// # madsim::runtime::Runtime::new().block_on(async {
+    /// let node = Handle::current().create_node().build();
+    /// sleep(Duration::from_secs(5)).await;
+    /// // start a client on the node at T+5s
+    /// node.spawn(async { /* ... */ }).await.unwrap();
+    /// # });
+    /// ```
+    ///
+    /// After the node is restarted, tasks are spawned on the new incarnation of the node.
     #[track_caller]
     pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send + 'static,
     {
-        self.task.spawn(future)
+        match self.handle.get_node(self.id()) {
+            Some(task) => task.spawn(future),
+            None => self.task.spawn(future),
+        }
+    }
+
+    /// Reboot the node: kill it, then restart it with its disk preserved.
+    ///
+    /// Like a power cycle, all tasks on the node are killed and data not synced to the disk is
+    /// lost. Then the init closure of the node runs again and can recover from the disk.
+    pub fn reboot(&self) {
+        let id = self.id();
+        self.handle.kill(id);
+        self.handle.restart(id);
+    }
+
+    /// Let the node's fs operations periodically stall.
+    ///
+    /// Starting from now, the disk stalls for `duration` every `every`.
+    /// Operations issued during a stall are blocked until the stall ends.
+    pub fn inject_disk_stall(&self, every: Duration, duration: Duration) {
+        get_sim::<fs::FsSim>(&self.sims).inject_disk_stall(self.id(), every, duration);
     }
 }
 
+/// Get the simulator from the collection.
+fn get_sim<S: plugin::Simulator>(sims: &Simulators) -> Arc<S> {
+    let sims = sims.lock();
+    sims[&TypeId::of::<S>()]
+        .clone()
+        .downcast_arc()
+        .ok()
+        .unwrap()
+}
+
 /// Initialize logger.
+///
+/// Log lines are tagged with the node and the logical time of the simulation, see [`log`].
+/// Like `tracing_subscriber::fmt::init`, logs are filtered by the `RUST_LOG` environment variable.
 pub fn init_logger() {
     use std::sync::Once;
+    use tracing_subscriber::EnvFilter;
     static LOGGER_INIT: Once = Once::new();
-    LOGGER_INIT.call_once(tracing_subscriber::fmt::init);
+    LOGGER_INIT.call_once(|| {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_timer(log::SimTime)
+            .init();
+    });
 }
@@ -0,0 +1,315 @@
+//! Composing faults into a deterministic plan.
+//!
+//! Real incidents rarely involve a single fault. A [`Scenario`] combines network partitions,
+//! slow disks and clock skews on a shared timeline, so that a known incident can be encoded
+//! once and replayed under any seed.
+//!
+//! # Example
+//!
+//! ```
+//! use madsim::{
+//!     runtime::{Handle, Runtime},
+//!     scenario::Scenario,
+//!     time::{ClockSkew, Duration},
+//! };
+//!
+//! Runtime::new().block_on(async {
+//!     let handle = Handle::current();
+//!     let n1 = handle.create_node().build().id();
+//!     let n2 = handle.create_node().build().id();
+//!     let secs = Duration::from_secs;
+//!     Scenario::builder()
+//!         .partition(secs(1)..secs(3), [n1], [n2])
+//!         .slow_disk(secs(2)..secs(4), n2, Duration::from_millis(100))
+//!         .skew(secs(1)..secs(5), n1, ClockSkew::Ahead(secs(60)))
+//!         .run_for(secs(6))
+//!         .await;
+//! });
+//! ```
+
+use crate::{
+    fs::FsSim,
+    net::NetSim,
+    runtime::Handle,
+    task::NodeId,
+    time::{self, ClockSkew, Duration, Instant},
+};
+use std::ops::Range;
+use tracing::debug;
+
+/// A plan of faults injected at fixed times.
+///
+/// Fault windows are relative to the start of [`run_for`](Scenario::run_for).
+/// The plan never draws random numbers, so it is applied identically under every seed.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    faults: Vec<(Range<Duration>, Fault)>,
+}
+
+/// Builds a [`Scenario`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Default)]
+#[must_use]
+pub struct ScenarioBuilder {
+    scenario: Scenario,
+}
+
+#[derive(Debug, Clone)]
+enum Fault {
+    Partition { a: Vec<NodeId>, b: Vec<NodeId> },
+    SlowDisk { node: NodeId, latency: Duration },
+    Skew { node: NodeId, skew: ClockSkew },
+}
+
+impl Scenario {
+    /// Returns a builder of an empty scenario.
+    pub fn builder() -> ScenarioBuilder {
+        ScenarioBuilder::default()
+    }
+
+    /// Run the scenario for `duration` from now.
+    ///
+    /// Faults whose windows start after `duration` are never applied.
+    /// Faults still active when the run ends are healed before returning.
+    pub async fn run_for(&self, duration: Duration) {
+        let handle = Handle::current();
+        let start = Instant::now();
+
+        // (time, is_start, index): at the same time, faults end before others start
+        let mut events = vec![];
+        for (i, (window, _)) in self.faults.iter().enumerate() {
+            events.push((window.start, true, i));
+            events.push((window.end, false, i));
+        }
+        events.sort();
+
+        let mut saved_latency = vec![None; self.faults.len()];
+        let mut active = vec![false; self.faults.len()];
+        for (at, is_start, i) in events {
+            if at > duration {
+                break;
+            }
+            time::sleep_until(start + at).await;
+            let fault = &self.faults[i].1;
+            if is_start {
+                debug!(?at, ?fault, "scenario: begin");
+                saved_latency[i] = fault.begin(&handle);
+                active[i] = true;
+            } else {
+                debug!(?at, ?fault, "scenario: end");
+                fault.end(&handle, saved_latency[i]);
+                active[i] = false;
+            }
+        }
+        time::sleep_until(start + duration).await;
+
+        for (i, (_, fault)) in self.faults.iter().enumerate() {
+            if active[i] {
+                debug!(?fault, "scenario: heal");
+                fault.end(&handle, saved_latency[i]);
+            }
+        }
+    }
+}
+
+impl Fault {
+    /// Apply the fault. Returns the disk latency before a slowdown.
+    fn begin(&self, handle: &Handle) -> Option<Duration> {
+        match self {
+            Fault::Partition { a, b } => {
+                let net = NetSim::current();
+                for &a in a {
+                    for &b in b {
+                        net.clog_link(a, b);
+                        net.clog_link(b, a);
+                    }
+                }
+                None
+            }
+            Fault::SlowDisk { node, latency } => {
+                let fs = FsSim::current();
+                let saved = fs.latency(*node);
+                fs.set_latency(*node, *latency);
+                Some(saved)
+            }
+            Fault::Skew { node, skew } => {
+                handle.set_clock_skew(*node, *skew);
+                None
+            }
+        }
+    }
+
+    /// Heal the fault.
+    fn end(&self, handle: &Handle, saved_latency: Option<Duration>) {
+        match self {
+            Fault::Partition { a, b } => {
+                let net = NetSim::current();
+                for &a in a {
+                    for &b in b {
+                        net.unclog_link(a, b);
+                        net.unclog_link(b, a);
+                    }
+                }
+            }
+            Fault::SlowDisk { node, .. } => {
+                let fs = FsSim::current();
+                fs.set_latency(*node, saved_latency.unwrap_or_default());
+            }
+            Fault::Skew { node, .. } => handle.set_clock_skew(*node, ClockSkew::ZERO),
+        }
+    }
+}
+
+impl ScenarioBuilder {
+    /// Partition nodes in `a` from nodes in `b` during `window`.
+    ///
+    /// Links between the two groups are clogged in both directions.
+    pub fn partition(
+        self,
+        window: Range<Duration>,
+        a: impl IntoIterator<Item = NodeId>,
+        b: impl IntoIterator<Item = NodeId>,
+    ) -> Self {
+        let a = a.into_iter().collect();
+        let b = b.into_iter().collect();
+        self.fault(window, Fault::Partition { a, b })
+    }
+
+    /// Set the latency of disk writes and syncs on `node` during `window`.
+    pub fn slow_disk(self, window: Range<Duration>, node: NodeId, latency: Duration) -> Self {
+        self.fault(window, Fault::SlowDisk { node, latency })
+    }
+
+    /// Skew the wall clock of `node` during `window`.
+    pub fn skew(self, window: Range<Duration>, node: NodeId, skew: ClockSkew) -> Self {
+        self.fault(window, Fault::Skew { node, skew })
+    }
+
+    fn fault(mut self, window: Range<Duration>, fault: Fault) -> Self {
+        assert!(!window.is_empty(), "empty fault window: {window:?}");
+        self.scenario.faults.push((window, fault));
+        self
+    }
+
+    /// Build the scenario.
+    pub fn build(self) -> Scenario {
+        self.scenario
+    }
+
+    /// Build the scenario and run it for `duration` from now.
+    ///
+    /// See [`Scenario::run_for`].
+    pub async fn run_for(self, duration: Duration) {
+        self.build().run_for(duration).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fs::File, runtime::Runtime};
+    use std::time::SystemTime;
+
+    /// Observations of each fault type, relative to the start of the scenario.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Timeline {
+        /// Partition changes recorded by the network tracer.
+        partition: Vec<(Duration, bool)>,
+        /// Duration of a disk sync issued at each time.
+        sync: Vec<(Duration, Duration)>,
+        /// Wall clock drift observed at each time.
+        skew: Vec<(Duration, Duration)>,
+    }
+
+    fn run(seed: u64) -> Timeline {
+        let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+        runtime.block_on(async {
+            let handle = Handle::current();
+            let n1 = handle.create_node().build();
+            let n2 = handle.create_node().build();
+            let n3 = handle.create_node().build();
+            let (start, t0) = (Instant::now(), handle.elapsed());
+            let secs = Duration::from_secs;
+            // observe between fault boundaries to avoid racing with them
+            let ticks = move || (0..14).map(move |i| secs(i) / 2 + Duration::from_millis(250));
+
+            let disk = n2.spawn(async move {
+                let file = File::create("file").await.unwrap();
+                let mut sync = vec![];
+                for tick in ticks() {
+                    time::sleep_until(start + tick).await;
+                    let t0 = Instant::now();
+                    file.sync_all().await.unwrap();
+                    sync.push((tick, t0.elapsed()));
+                }
+                sync
+            });
+            let clock = n3.spawn(async move {
+                let (i0, s0) = (Instant::now(), SystemTime::now());
+                let mut skew = vec![];
+                for tick in ticks() {
+                    time::sleep_until(start + tick).await;
+                    let wall = SystemTime::now().duration_since(s0).unwrap();
+                    skew.push((tick, wall - i0.elapsed()));
+                }
+                skew
+            });
+
+            NetSim::current().enable_trace(true);
+            Scenario::builder()
+                .partition(secs(1)..secs(3), [n1.id()], [n2.id(), n3.id()])
+                .slow_disk(secs(2)..secs(4), n2.id(), Duration::from_millis(100))
+                .skew(secs(1)..secs(7), n3.id(), ClockSkew::Ahead(secs(60)))
+                .run_for(secs(6))
+                .await;
+
+            // events are recorded on the source node, in order
+            let partition = (handle.take_net_trace().into_iter())
+                .filter(|e| e.node == n1.id())
+                .filter_map(|e| match e.kind {
+                    crate::net::NetEventKind::ClogLink { .. } => Some((e.time, true)),
+                    crate::net::NetEventKind::UnclogLink { .. } => Some((e.time, false)),
+                    _ => None,
+                })
+                .map(|(t, clogged)| (t - t0, clogged))
+                .collect();
+            Timeline {
+                partition,
+                sync: disk.await.unwrap(),
+                skew: clock.await.unwrap(),
+            }
+        })
+    }
+
+    #[test]
+    fn combined_faults() {
+        let secs = Duration::from_secs;
+        let ms = Duration::from_millis;
+        let timeline = run(0);
+
+        // clogged at 1s, unclogged at 3s
+        let clogged: Vec<_> = timeline.partition.iter().map(|(_, c)| *c).collect();
+        assert_eq!(clogged, [true, true, false, false]);
+        let (clog, unclog) = (timeline.partition[0].0, timeline.partition[2].0);
+        assert!(clog >= secs(1) && clog < secs(1) + ms(1), "{clog:?}");
+        assert!(unclog >= secs(3) && unclog < secs(3) + ms(1), "{unclog:?}");
+
+        // the disk is slow from 2s to 4s
+        for (tick, latency) in &timeline.sync {
+            let slow = (secs(2)..secs(4)).contains(tick);
+            assert_eq!(*latency >= ms(100), slow, "{tick:?} {latency:?}");
+        }
+
+        // the clock is skewed from 1s, and healed when the run ends at 6s before the window ends
+        for (tick, skew) in &timeline.skew {
+            let skewed = (secs(1)..secs(6)).contains(tick);
+            assert_eq!(skew.as_secs(), if skewed { 60 } else { 0 }, "{tick:?}");
+        }
+
+        // the same plan is applied under every seed
+        for seed in 1..4 {
+            assert_eq!(run(seed), timeline);
+        }
+    }
+}
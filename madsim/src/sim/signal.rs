@@ -1,12 +1,97 @@
 //! Asynchronous signal handling.
+//!
+//! Signals are sent to a node by [`Handle::send_ctrl_c`] and [`Handle::send_shutdown`], and are
+//! received at that logical time. A node that has no handler of the signal is killed.
+//!
+//! [`Handle::send_ctrl_c`]: crate::runtime::Handle::send_ctrl_c
+//! [`Handle::send_shutdown`]: crate::runtime::Handle::send_shutdown
 
 /// Completes when a "ctrl-c" notification is sent to the process.
 pub async fn ctrl_c() -> std::io::Result<()> {
-    let mut rx = crate::context::current_task().node.ctrl_c();
+    let mut rx = crate::context::current_task().node.signal(libc::SIGINT);
     _ = rx.changed().await;
     Ok(())
 }
 
+/// Unix specific signals.
+#[cfg(unix)]
+pub mod unix {
+    use crate::sync::watch;
+    use std::io;
+
+    /// Represents the specific kind of signal to listen for.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct SignalKind(i32);
+
+    impl SignalKind {
+        /// Allows for listening to any valid OS signal.
+        pub const fn from_raw(signum: i32) -> Self {
+            SignalKind(signum)
+        }
+
+        /// Get the signal's numeric value.
+        pub const fn as_raw_value(&self) -> i32 {
+            self.0
+        }
+
+        /// Represents the `SIGHUP` signal.
+        pub const fn hangup() -> Self {
+            SignalKind(libc::SIGHUP)
+        }
+
+        /// Represents the `SIGINT` signal.
+        pub const fn interrupt() -> Self {
+            SignalKind(libc::SIGINT)
+        }
+
+        /// Represents the `SIGQUIT` signal.
+        pub const fn quit() -> Self {
+            SignalKind(libc::SIGQUIT)
+        }
+
+        /// Represents the `SIGTERM` signal, sent by [`Handle::send_shutdown`].
+        ///
+        /// [`Handle::send_shutdown`]: crate::runtime::Handle::send_shutdown
+        pub const fn terminate() -> Self {
+            SignalKind(libc::SIGTERM)
+        }
+
+        /// Represents the `SIGUSR1` signal.
+        pub const fn user_defined1() -> Self {
+            SignalKind(libc::SIGUSR1)
+        }
+
+        /// Represents the `SIGUSR2` signal.
+        pub const fn user_defined2() -> Self {
+            SignalKind(libc::SIGUSR2)
+        }
+    }
+
+    /// An listener for receiving a particular type of OS signal.
+    #[derive(Debug)]
+    pub struct Signal {
+        rx: watch::Receiver<()>,
+    }
+
+    impl Signal {
+        /// Receives the next signal notification event.
+        ///
+        /// Signals sent since the last call are coalesced into one. `None` is never returned.
+        pub async fn recv(&mut self) -> Option<()> {
+            self.rx.changed().await.ok()
+        }
+    }
+
+    /// Creates a new listener which will receive notifications when the current node receives
+    /// the specified signal.
+    ///
+    /// This installs a handler of the signal, so the node is no longer killed by it.
+    pub fn signal(kind: SignalKind) -> io::Result<Signal> {
+        let rx = crate::context::current_task().node.signal(kind.0);
+        Ok(Signal { rx })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -67,4 +152,49 @@ mod tests {
             }
         });
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn graceful_shutdown() {
+        use super::unix::{signal, SignalKind};
+        use crate::runtime::ExitReason;
+
+        let runtime = Runtime::new();
+        let cleaned_up = Arc::new(spin::Mutex::new(None));
+        let cleaned_up1 = cleaned_up.clone();
+        let node = runtime
+            .create_node()
+            .init(move || {
+                let cleaned_up = cleaned_up1.clone();
+                async move {
+                    let mut shutdown = signal(SignalKind::terminate()).unwrap();
+                    shutdown.recv().await;
+                    // the cleanup yields before the node stops
+                    time::sleep(Duration::from_millis(100)).await;
+                    *cleaned_up.lock() = Some(time::Instant::now());
+                }
+            })
+            .build();
+
+        runtime.block_on(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            let handle = Handle::current();
+            assert!(!handle.is_exit(node.id()));
+            let t0 = time::Instant::now();
+            handle.send_shutdown(node.id());
+
+            time::sleep(Duration::from_secs(1)).await;
+            assert_eq!(*cleaned_up.lock(), Some(t0 + Duration::from_millis(100)));
+            assert!(handle.is_exit(node.id()));
+            assert_eq!(handle.exit_reason(node.id()), Some(ExitReason::Exited));
+
+            // without a handler, the signal kills the node
+            let node = handle.create_node().build();
+            let h = node.spawn(pending::<()>());
+            handle.send_shutdown(node.id());
+            time::sleep(Duration::from_secs(1)).await;
+            assert!(h.is_finished());
+            assert_eq!(handle.exit_reason(node.id()), Some(ExitReason::Killed));
+        });
+    }
 }
@@ -0,0 +1,179 @@
+use super::watch;
+use crate::{plugin::Simulator, rand::GlobalRng, time::TimeHandle, Config};
+use spin::Mutex;
+use std::{collections::HashMap, sync::Arc};
+use tracing::debug;
+
+/// The result of waiting on a barrier of [`Handle::barrier`](crate::runtime::Handle::barrier).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns true if this participant arrived last and released the barrier.
+    ///
+    /// Exactly one participant of each release is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+/// Named barriers of a runtime.
+pub(crate) struct BarrierSim {
+    barriers: Mutex<HashMap<String, Arc<Barrier>>>,
+}
+
+/// A reusable barrier, released each time `count` participants have arrived.
+struct Barrier {
+    count: usize,
+    state: Mutex<State>,
+    /// The number of releases.
+    generation: watch::Sender<u64>,
+}
+
+struct State {
+    arrived: usize,
+    generation: u64,
+}
+
+impl Simulator for BarrierSim {
+    fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &Config) -> Self {
+        BarrierSim {
+            barriers: Default::default(),
+        }
+    }
+}
+
+impl BarrierSim {
+    pub(crate) async fn wait(&self, name: &str, count: usize) -> BarrierWaitResult {
+        assert!(count > 0, "a barrier must have at least one participant");
+        let barrier = (self.barriers.lock().entry(name.into()))
+            .or_insert_with(|| {
+                Arc::new(Barrier {
+                    count,
+                    state: Mutex::new(State {
+                        arrived: 0,
+                        generation: 0,
+                    }),
+                    generation: watch::channel(0).0,
+                })
+            })
+            .clone();
+        assert_eq!(
+            barrier.count, count,
+            "barrier {name:?} has {} participants, not {count}",
+            barrier.count
+        );
+        let (mut released, generation) = {
+            let mut state = barrier.state.lock();
+            state.arrived += 1;
+            debug!(name, arrived = state.arrived, count, "barrier arrive");
+            if state.arrived == count {
+                debug!(name, "barrier release");
+                state.arrived = 0;
+                state.generation += 1;
+                barrier.generation.send_replace(state.generation);
+                return BarrierWaitResult(true);
+            }
+            (barrier.generation.subscribe(), state.generation)
+        };
+        let mut guard = Departure {
+            barrier: &barrier,
+            generation,
+            armed: true,
+        };
+        released.wait_for(|&g| g > generation).await.unwrap();
+        guard.armed = false;
+        BarrierWaitResult(false)
+    }
+}
+
+/// Removes a waiting participant from the barrier if its `wait` future is dropped, e.g. when
+/// its node is killed.
+struct Departure<'a> {
+    barrier: &'a Barrier,
+    generation: u64,
+    armed: bool,
+}
+
+impl Drop for Departure<'_> {
+    fn drop(&mut self) {
+        let mut state = self.barrier.state.lock();
+        if self.armed && state.generation == self.generation {
+            state.arrived -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Handle, Runtime},
+        time::{sleep, Duration, Instant},
+    };
+
+    /// Returns the arrival order of 3 nodes at a barrier and the order they proceed.
+    fn choreography(seed: u64) -> (Vec<usize>, Vec<(usize, bool)>) {
+        let runtime = Runtime::with_seed_and_config(seed, Config::default());
+        runtime.block_on(async {
+            let handle = Handle::current();
+            let arrived = Arc::new(Mutex::new(vec![]));
+            let proceeded = Arc::new(Mutex::new(vec![]));
+            let mut tasks = vec![];
+            for i in 0..3 {
+                let node = handle.create_node().build();
+                let (arrived, proceeded) = (arrived.clone(), proceeded.clone());
+                tasks.push(node.spawn(async move {
+                    let delay = crate::rand::random::<u64>() % 1000;
+                    sleep(Duration::from_millis(delay)).await;
+                    arrived.lock().push(i);
+                    let result = Handle::current().barrier("ready", 3).await;
+                    proceeded.lock().push((i, result.is_leader()));
+                    Instant::now()
+                }));
+            }
+            let mut times = vec![];
+            for task in tasks {
+                times.push(task.await.unwrap());
+            }
+            // nobody proceeds before the last arrival
+            assert!(times.iter().all(|t| *t == times[0]));
+            let arrived = arrived.lock().clone();
+            let proceeded = proceeded.lock().clone();
+            (arrived, proceeded)
+        })
+    }
+
+    #[test]
+    fn release_after_all_arrive() {
+        let (arrived, proceeded) = choreography(1);
+        assert_eq!(proceeded.len(), 3);
+        // the last to arrive is the leader
+        let leaders: Vec<_> = proceeded.iter().filter(|(_, leader)| *leader).collect();
+        assert_eq!(leaders, [&(arrived[2], true)]);
+        // and the release is reproducible
+        assert_eq!(choreography(1), (arrived, proceeded));
+    }
+
+    #[test]
+    fn reuse_and_departure() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let handle = Handle::current();
+            let node = handle.create_node().build();
+            // a participant on a killed node leaves the barrier
+            node.spawn(async { Handle::current().barrier("b", 2).await });
+            sleep(Duration::from_secs(1)).await;
+            handle.kill(node.id());
+            sleep(Duration::from_secs(1)).await;
+
+            for _ in 0..2 {
+                let waiter = crate::task::spawn(async { Handle::current().barrier("b", 2).await });
+                sleep(Duration::from_secs(1)).await;
+                assert!(!waiter.is_finished());
+                assert!(handle.barrier("b", 2).await.is_leader());
+                assert!(!waiter.await.unwrap().is_leader());
+            }
+        });
+    }
+}
@@ -0,0 +1,9 @@
+//! Synchronization primitives for coordinating simulated nodes.
+
+pub(crate) mod barrier;
+pub mod mpsc;
+mod quorum;
+pub mod watch;
+
+pub use self::barrier::BarrierWaitResult;
+pub use self::quorum::QuorumBarrier;
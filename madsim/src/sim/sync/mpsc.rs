@@ -0,0 +1,450 @@
+//! A multi-producer, single-consumer queue with an optional growth guard.
+//!
+//! The channel is a thin wrapper over [`tokio::sync::mpsc`]. It tracks the number of buffered
+//! messages, so a test can assert that an unbounded buffer between a fast producer and a slow
+//! consumer doesn't grow without limit. See [`UnboundedReceiver::with_growth_guard`].
+//!
+//! A [bounded](channel) channel applies backpressure: once the buffer is full, [`Sender::send`]
+//! waits for a slot. Waiting senders are queued in FIFO order and each freed slot goes to the
+//! sender that has waited the longest, so the order in which senders unblock only depends on the
+//! order they started waiting, which is decided by the seeded executor.
+
+use spin::Mutex;
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+#[doc(no_inline)]
+pub use tokio::sync::mpsc::error;
+
+use self::error::{SendError, TryRecvError, TrySendError};
+
+/// Creates a bounded mpsc channel with a buffer of `buffer` messages.
+///
+/// # Panics
+///
+/// Panics if the buffer capacity is 0.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::channel(buffer);
+    let guard = Arc::new(Mutex::new(Guard::default()));
+    let tx = Sender {
+        inner: tx,
+        guard: guard.clone(),
+    };
+    let rx = Receiver { inner: rx, guard };
+    (tx, rx)
+}
+
+/// Creates an unbounded mpsc channel for communicating between asynchronous tasks.
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let guard = Arc::new(Mutex::new(Guard::default()));
+    let tx = UnboundedSender {
+        inner: tx,
+        guard: guard.clone(),
+    };
+    let rx = UnboundedReceiver { inner: rx, guard };
+    (tx, rx)
+}
+
+/// Buffer size tracking shared by both ends of a channel.
+#[derive(Debug, Default)]
+struct Guard {
+    /// The number of buffered messages.
+    len: usize,
+    /// The maximum number of buffered messages ever seen.
+    peak: usize,
+    /// Panic if the number of buffered messages exceeds this limit.
+    limit: Option<usize>,
+}
+
+/// Send values to the associated [`Receiver`].
+pub struct Sender<T> {
+    inner: mpsc::Sender<T>,
+    guard: Arc<Mutex<Guard>>,
+}
+
+/// Receive values from the associated [`Sender`].
+pub struct Receiver<T> {
+    inner: mpsc::Receiver<T>,
+    guard: Arc<Mutex<Guard>>,
+}
+
+/// Send values to the associated [`UnboundedReceiver`].
+pub struct UnboundedSender<T> {
+    inner: mpsc::UnboundedSender<T>,
+    guard: Arc<Mutex<Guard>>,
+}
+
+/// Receive values from the associated [`UnboundedSender`].
+pub struct UnboundedReceiver<T> {
+    inner: mpsc::UnboundedReceiver<T>,
+    guard: Arc<Mutex<Guard>>,
+}
+
+impl Guard {
+    fn push(&mut self) {
+        self.len += 1;
+        self.peak = self.peak.max(self.len);
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting until there is capacity.
+    ///
+    /// Fails if the receiver has been dropped or closed.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let permit = match self.inner.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => return Err(SendError(value)),
+        };
+        permit.send(value);
+        self.guard.lock().push();
+        Ok(())
+    }
+
+    /// Attempts to immediately send a message on this channel.
+    ///
+    /// Fails with [`TrySendError::Full`] if there is no capacity, even if some senders are
+    /// waiting in [`send`](Sender::send).
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_send(message)?;
+        self.guard.lock().push();
+        Ok(())
+    }
+
+    /// Returns the current capacity of the channel.
+    ///
+    /// The capacity is the buffer size minus the number of buffered messages, minus the slots
+    /// reserved by senders that have been woken but not sent yet.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Returns the buffer size of the channel.
+    pub fn max_capacity(&self) -> usize {
+        self.inner.max_capacity()
+    }
+
+    /// Completes when the receiver has dropped.
+    pub async fn closed(&self) {
+        self.inner.closed().await;
+    }
+
+    /// Checks if the channel has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Returns `true` if senders belong to the same channel.
+    pub fn same_channel(&self, other: &Self) -> bool {
+        self.inner.same_channel(&other.inner)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value for this receiver.
+    ///
+    /// Receiving a value frees a slot for the sender that has waited the longest.
+    pub async fn recv(&mut self) -> Option<T> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Tries to receive the next value for this receiver.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let message = self.inner.try_recv()?;
+        self.guard.lock().len -= 1;
+        Ok(message)
+    }
+
+    /// Closes the receiving half of a channel, without dropping it.
+    ///
+    /// Buffered messages can still be received.
+    pub fn close(&mut self) {
+        self.inner.close();
+    }
+
+    /// Polls to receive the next message on this channel.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let poll = self.inner.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = &poll {
+            self.guard.lock().len -= 1;
+        }
+        poll
+    }
+
+    /// Returns the number of messages in the channel.
+    pub fn len(&self) -> usize {
+        self.guard.lock().len
+    }
+
+    /// Returns whether the channel is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of messages ever buffered in the channel.
+    pub fn peak_len(&self) -> usize {
+        self.guard.lock().peak
+    }
+}
+
+impl<T> UnboundedSender<T> {
+    /// Attempts to send a message on this channel without blocking.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the message makes the buffer exceed the growth guard of the receiver.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.inner.send(message)?;
+        let mut guard = self.guard.lock();
+        guard.push();
+        if let Some(limit) = guard.limit.filter(|&limit| guard.len > limit) {
+            let peak = guard.peak;
+            drop(guard);
+            panic!("mpsc buffer reached {peak} messages, exceeding the growth guard of {limit}");
+        }
+        Ok(())
+    }
+
+    /// Completes when the receiver has dropped.
+    pub async fn closed(&self) {
+        self.inner.closed().await;
+    }
+
+    /// Checks if the channel has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Returns `true` if senders belong to the same channel.
+    pub fn same_channel(&self, other: &Self) -> bool {
+        self.inner.same_channel(&other.inner)
+    }
+}
+
+impl<T> UnboundedReceiver<T> {
+    /// Panics when a send makes the number of buffered messages exceed `limit`.
+    ///
+    /// The panic message contains the peak buffer size.
+    #[must_use]
+    pub fn with_growth_guard(self, limit: usize) -> Self {
+        self.guard.lock().limit = Some(limit);
+        self
+    }
+
+    /// Receives the next value for this receiver.
+    pub async fn recv(&mut self) -> Option<T> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Tries to receive the next value for this receiver.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let message = self.inner.try_recv()?;
+        self.guard.lock().len -= 1;
+        Ok(message)
+    }
+
+    /// Closes the receiving half of a channel, without dropping it.
+    pub fn close(&mut self) {
+        self.inner.close();
+    }
+
+    /// Polls to receive the next message on this channel.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let poll = self.inner.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = &poll {
+            self.guard.lock().len -= 1;
+        }
+        poll
+    }
+
+    /// Returns the number of messages in the channel.
+    pub fn len(&self) -> usize {
+        self.guard.lock().len
+    }
+
+    /// Returns whether the channel is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of messages ever buffered in the channel.
+    pub fn peak_len(&self) -> usize {
+        self.guard.lock().peak
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+            guard: self.guard.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Sender")
+            .field("guard", &*self.guard.lock())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Receiver")
+            .field("guard", &*self.guard.lock())
+            .finish()
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        UnboundedSender {
+            inner: self.inner.clone(),
+            guard: self.guard.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for UnboundedSender<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("UnboundedSender")
+            .field("guard", &*self.guard.lock())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for UnboundedReceiver<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("UnboundedReceiver")
+            .field("guard", &*self.guard.lock())
+            .finish()
+    }
+}
+
+// the module is shared with std, but the tests need the simulator
+#[cfg(all(test, madsim))]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::Runtime,
+        time::{sleep, Duration},
+    };
+
+    /// Sends a message every millisecond and receives one every `consume_interval`.
+    fn produce_consume(limit: usize, consume_interval: Duration) -> usize {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let f = node.spawn(async move {
+            let (tx, rx) = unbounded_channel();
+            let mut rx = rx.with_growth_guard(limit);
+            let producer = crate::task::spawn(async move {
+                for i in 0..100 {
+                    tx.send(i).unwrap();
+                    sleep(Duration::from_millis(1)).await;
+                }
+            });
+            while rx.recv().await.is_some() {
+                sleep(consume_interval).await;
+            }
+            producer.await.unwrap();
+            rx.peak_len()
+        });
+        runtime.block_on(f).unwrap()
+    }
+
+    /// Returns the order in which senders start waiting on a full channel and unblock.
+    fn unblock_order(seed: u64) -> (Vec<usize>, Vec<usize>) {
+        let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+        runtime.block_on(async {
+            let (tx, mut rx) = channel(2);
+            tx.try_send(usize::MAX).unwrap();
+            tx.try_send(usize::MAX).unwrap();
+            let waiting = Arc::new(Mutex::new(vec![]));
+            let unblocked = Arc::new(Mutex::new(vec![]));
+            for i in 0..8 {
+                let tx = tx.clone();
+                let waiting = waiting.clone();
+                let unblocked = unblocked.clone();
+                crate::task::spawn(async move {
+                    let delay = crate::rand::random::<u64>() % 100;
+                    sleep(Duration::from_millis(delay)).await;
+                    waiting.lock().push(i);
+                    tx.send(i).await.unwrap();
+                    unblocked.lock().push(i);
+                });
+            }
+            sleep(Duration::from_secs(1)).await;
+            assert_eq!(tx.capacity(), 0);
+            assert!(unblocked.lock().is_empty());
+            // each freed slot unblocks exactly one sender
+            for n in 1..=8 {
+                rx.recv().await.unwrap();
+                sleep(Duration::from_millis(10)).await;
+                assert_eq!(unblocked.lock().len(), n);
+                assert_eq!(rx.len(), 2);
+                assert_eq!(tx.capacity(), 0);
+            }
+            let waiting = waiting.lock().clone();
+            let unblocked = unblocked.lock().clone();
+            (waiting, unblocked)
+        })
+    }
+
+    #[test]
+    fn deterministic_unblock_order() {
+        let (waiting, unblocked) = unblock_order(1);
+        // first come, first served
+        assert_eq!(unblocked, waiting);
+        assert_eq!(unblock_order(1), (waiting.clone(), unblocked));
+        // the order is decided by the seed
+        assert!((2..10).any(|seed| unblock_order(seed).0 != waiting));
+    }
+
+    #[test]
+    fn capacity() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let (tx, mut rx) = channel(3);
+            assert_eq!(tx.max_capacity(), 3);
+            for i in 0..3 {
+                assert_eq!(tx.capacity(), 3 - i);
+                tx.try_send(i).unwrap();
+            }
+            assert!(matches!(tx.try_send(3), Err(TrySendError::Full(3))));
+            assert_eq!((tx.capacity(), rx.len()), (0, 3));
+            assert_eq!(rx.recv().await, Some(0));
+            assert_eq!((tx.capacity(), rx.len()), (1, 2));
+            tx.send(3).await.unwrap();
+            assert_eq!(rx.peak_len(), 3);
+
+            // buffered messages are received after the receiver is closed
+            rx.close();
+            assert!(matches!(tx.try_send(4), Err(TrySendError::Closed(4))));
+            assert!(tx.send(4).await.is_err());
+            for i in 1..=3 {
+                assert_eq!(rx.try_recv().unwrap(), i);
+            }
+            assert!(rx.try_recv().is_err());
+            assert_eq!(rx.len(), 0);
+        });
+    }
+
+    #[test]
+    fn growth_within_guard() {
+        let peak = produce_consume(10, Duration::from_micros(500));
+        assert!(peak <= 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "mpsc buffer reached 11 messages, exceeding the growth guard of 10")]
+    fn growth_guard_trips() {
+        produce_consume(10, Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,356 @@
+use super::watch;
+#[cfg(madsim)]
+use crate::{
+    plugin::{self, Simulator},
+    rand::GlobalRng,
+    task::NodeId,
+    time::TimeHandle,
+    Config,
+};
+use spin::Mutex;
+use std::sync::Arc;
+#[cfg(madsim)]
+use std::{collections::HashMap, sync::Weak};
+use tracing::debug;
+
+/// A barrier that releases once a majority of live members have arrived.
+///
+/// The barrier is created with the total number of members. A member that is waiting on the
+/// barrier leaves the membership if its [`wait`] future is dropped before release, which is
+/// what happens when its node is killed. The quorum is then recomputed over the remaining
+/// members, so the barrier can still be released after some members have failed.
+///
+/// In simulation, a barrier created by `with_members` also knows the node of each member, so a
+/// member whose node is killed departs even if it never arrived. Such a barrier counts arrivals
+/// per node.
+///
+/// The barrier is released only once. After that, [`wait`] returns immediately.
+///
+/// [`wait`]: QuorumBarrier::wait
+#[derive(Debug, Clone)]
+pub struct QuorumBarrier {
+    inner: Arc<Quorum>,
+}
+
+#[derive(Debug)]
+struct Quorum {
+    state: Mutex<State>,
+    released: watch::Sender<bool>,
+}
+
+#[derive(Debug)]
+struct State {
+    total: usize,
+    arrived: usize,
+    departed: usize,
+    /// Members of a barrier created by `with_members`.
+    #[cfg(madsim)]
+    members: HashMap<NodeId, Member>,
+}
+
+#[cfg(madsim)]
+#[derive(Debug, Default)]
+struct Member {
+    arrived: bool,
+    killed: bool,
+}
+
+impl State {
+    /// Returns the number of arrivals required for release.
+    fn quorum(&self) -> usize {
+        self.total.saturating_sub(self.departed) / 2 + 1
+    }
+}
+
+/// The barriers created by [`QuorumBarrier::with_members`], notified when a node is killed.
+#[cfg(madsim)]
+struct QuorumSim {
+    barriers: Mutex<Vec<Weak<Quorum>>>,
+}
+
+#[cfg(madsim)]
+impl Simulator for QuorumSim {
+    fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &Config) -> Self {
+        QuorumSim {
+            barriers: Default::default(),
+        }
+    }
+
+    fn reset_node(&self, id: NodeId) {
+        self.barriers.lock().retain(|barrier| {
+            let Some(barrier) = barrier.upgrade() else {
+                return false;
+            };
+            barrier.kill(id);
+            true
+        });
+    }
+}
+
+impl QuorumBarrier {
+    /// Creates a new barrier for `total` members.
+    pub fn new(total: usize) -> Self {
+        assert!(total > 0, "a barrier must have at least one member");
+        Self::with_state(State {
+            total,
+            arrived: 0,
+            departed: 0,
+            #[cfg(madsim)]
+            members: HashMap::new(),
+        })
+    }
+
+    /// Creates a new barrier whose members are the nodes.
+    ///
+    /// Only tasks on the nodes count as arrivals. A member whose node is killed departs, and
+    /// joins again once a task of the restarted node arrives.
+    #[cfg(madsim)]
+    pub fn with_members(nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let members: HashMap<_, _> = (nodes.into_iter())
+            .map(|id| (id, Member::default()))
+            .collect();
+        assert!(
+            !members.is_empty(),
+            "a barrier must have at least one member"
+        );
+        let barrier = Self::with_state(State {
+            total: members.len(),
+            arrived: 0,
+            departed: 0,
+            members,
+        });
+        let sim = plugin::simulator::<QuorumSim>();
+        sim.barriers.lock().push(Arc::downgrade(&barrier.inner));
+        barrier
+    }
+
+    fn with_state(state: State) -> Self {
+        QuorumBarrier {
+            inner: Arc::new(Quorum {
+                state: Mutex::new(state),
+                released: watch::channel(false).0,
+            }),
+        }
+    }
+
+    /// Waits until a majority of live members have arrived.
+    pub async fn wait(&self) {
+        let mut released = self.inner.released.subscribe();
+        if *released.borrow_and_update() {
+            return;
+        }
+        let mut guard = Departure {
+            barrier: &self.inner,
+            #[cfg(madsim)]
+            node: plugin::node(),
+            armed: false,
+        };
+        guard.armed = guard.arrive();
+        self.inner.try_release();
+        while !*released.borrow_and_update() {
+            released.changed().await.unwrap();
+        }
+        guard.armed = false;
+    }
+
+    /// Returns whether the barrier has been released.
+    pub fn is_released(&self) -> bool {
+        self.inner.is_released()
+    }
+}
+
+impl Quorum {
+    fn is_released(&self) -> bool {
+        *self.released.borrow()
+    }
+
+    /// Release the barrier if the quorum is reached.
+    fn try_release(&self) {
+        let state = self.state.lock();
+        if state.arrived >= state.quorum() && !self.is_released() {
+            debug!(arrived = state.arrived, "barrier release");
+            self.released.send_replace(true);
+        }
+    }
+
+    /// A member leaves the barrier because its node is killed.
+    #[cfg(madsim)]
+    fn kill(&self, id: NodeId) {
+        if self.is_released() {
+            return;
+        }
+        {
+            let mut state = self.state.lock();
+            let Some(member) = state.members.get_mut(&id) else {
+                return;
+            };
+            let arrived = std::mem::take(&mut member.arrived);
+            let departed = !std::mem::replace(&mut member.killed, true);
+            state.arrived -= arrived as usize;
+            state.departed += departed as usize;
+            debug!(node = %id, quorum = state.quorum(), "barrier member killed");
+        }
+        self.try_release();
+    }
+}
+
+/// A waiting member, removed from the barrier if its `wait` future is dropped.
+struct Departure<'a> {
+    barrier: &'a Quorum,
+    /// The node of the waiting task.
+    #[cfg(madsim)]
+    node: NodeId,
+    armed: bool,
+}
+
+impl Departure<'_> {
+    /// Counts the arrival of the member. Returns whether it has newly arrived.
+    fn arrive(&self) -> bool {
+        let mut state = self.barrier.state.lock();
+        #[cfg(madsim)]
+        if !state.members.is_empty() {
+            let Some(member) = state.members.get_mut(&self.node) else {
+                // tasks of other nodes only wait for the release
+                return false;
+            };
+            let rejoined = std::mem::take(&mut member.killed);
+            let arrived = !std::mem::replace(&mut member.arrived, true);
+            state.departed -= rejoined as usize;
+            state.arrived += arrived as usize;
+            debug!(
+                arrived = state.arrived,
+                quorum = state.quorum(),
+                "barrier arrive"
+            );
+            return arrived;
+        }
+        state.arrived += 1;
+        debug!(
+            arrived = state.arrived,
+            quorum = state.quorum(),
+            "barrier arrive"
+        );
+        true
+    }
+
+    /// Removes the member from the barrier.
+    fn depart(&self) {
+        let mut state = self.barrier.state.lock();
+        #[cfg(madsim)]
+        if !state.members.is_empty() {
+            // the member is still alive unless its node is killed
+            if let Some(member) = state.members.get_mut(&self.node) {
+                if std::mem::take(&mut member.arrived) {
+                    state.arrived -= 1;
+                }
+            }
+            debug!(quorum = state.quorum(), "barrier member departed");
+            return;
+        }
+        state.arrived -= 1;
+        state.departed += 1;
+        debug!(quorum = state.quorum(), "barrier member departed");
+    }
+}
+
+impl Drop for Departure<'_> {
+    fn drop(&mut self) {
+        if !self.armed || self.barrier.is_released() {
+            return;
+        }
+        self.depart();
+        self.barrier.try_release();
+    }
+}
+
+// the module is shared with std, but the tests need the simulator
+#[cfg(all(test, madsim))]
+mod tests {
+    use super::*;
+    use crate::{
+        net::NetSim,
+        runtime::{Handle, Runtime},
+        time::{sleep, Duration},
+    };
+
+    #[test]
+    fn majority_release() {
+        let runtime = Runtime::new();
+        let nodes: Vec<_> = (0..5).map(|_| runtime.create_node().build()).collect();
+        runtime.block_on(async move {
+            let barrier = QuorumBarrier::new(5);
+            // 2 nodes are partitioned and never arrive
+            for node in &nodes[3..] {
+                NetSim::current().clog_node(node.id());
+            }
+            let mut tasks = vec![];
+            for node in &nodes[..3] {
+                let barrier = barrier.clone();
+                tasks.push(node.spawn(async move { barrier.wait().await }));
+                sleep(Duration::from_secs(1)).await;
+                assert_eq!(barrier.is_released(), tasks.len() == 3);
+            }
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn killed_members_leave() {
+        let runtime = Runtime::new();
+        let nodes: Vec<_> = (0..5).map(|_| runtime.create_node().build()).collect();
+        runtime.block_on(async move {
+            let barrier = QuorumBarrier::new(5);
+            for node in &nodes[..2] {
+                let barrier = barrier.clone();
+                node.spawn(async move { barrier.wait().await });
+            }
+            sleep(Duration::from_secs(1)).await;
+            assert!(!barrier.is_released());
+
+            // the quorum becomes 2 of the 3 live members
+            Handle::current().kill(nodes[0].id());
+            Handle::current().kill(nodes[1].id());
+            sleep(Duration::from_secs(1)).await;
+
+            let barrier1 = barrier.clone();
+            nodes[2].spawn(async move { barrier1.wait().await });
+            sleep(Duration::from_secs(1)).await;
+            assert!(!barrier.is_released());
+
+            let barrier1 = barrier.clone();
+            nodes[3]
+                .spawn(async move { barrier1.wait().await })
+                .await
+                .unwrap();
+            assert!(barrier.is_released());
+        });
+    }
+
+    #[test]
+    fn killed_members_depart_before_arriving() {
+        let runtime = Runtime::new();
+        let nodes: Vec<_> = (0..5).map(|_| runtime.create_node().build()).collect();
+        runtime.block_on(async move {
+            let barrier = QuorumBarrier::with_members(nodes.iter().map(|node| node.id()));
+            for node in &nodes[..2] {
+                let barrier = barrier.clone();
+                node.spawn(async move { barrier.wait().await });
+                // a second task of the same node doesn't count
+                let barrier = barrier.clone();
+                node.spawn(async move { barrier.wait().await });
+            }
+            sleep(Duration::from_secs(1)).await;
+            assert!(!barrier.is_released());
+
+            // the quorum becomes 2 of the 3 live members, which have arrived
+            Handle::current().kill(nodes[3].id());
+            sleep(Duration::from_secs(1)).await;
+            assert!(!barrier.is_released());
+            Handle::current().kill(nodes[4].id());
+            sleep(Duration::from_secs(1)).await;
+            assert!(barrier.is_released());
+        });
+    }
+}
@@ -0,0 +1,280 @@
+//! A single-producer, multi-consumer channel that only retains the last sent value.
+//!
+//! The channel has the same API and semantics as [`tokio::sync::watch`], which it wraps. The only
+//! difference is how receivers are woken. Tokio spreads waiting receivers over several wait lists
+//! picked by a thread-local random number generator that is not controlled by the simulation, so
+//! the order in which receivers wake up may differ between runs of the same seed. Here receivers
+//! wait in a single FIFO list and are woken in the order they started waiting, then scheduled by
+//! the seeded executor.
+//!
+//! A burst of sends without yielding wakes each receiver once, and it observes the latest value.
+
+use futures_util::FutureExt;
+use std::{fmt, pin::pin, sync::Arc};
+use tokio::sync::{watch, Notify};
+
+#[doc(no_inline)]
+pub use tokio::sync::watch::{error, Ref};
+
+use self::error::{RecvError, SendError};
+
+/// Creates a new watch channel, returning the "send" and "receive" handles.
+pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = watch::channel(init);
+    let notify = Arc::new(Notify::new());
+    let tx = Sender {
+        inner: tx,
+        notify: notify.clone(),
+    };
+    let rx = Receiver { inner: rx, notify };
+    (tx, rx)
+}
+
+/// Sends values to the associated [`Receiver`]s.
+pub struct Sender<T> {
+    inner: watch::Sender<T>,
+    notify: Arc<Notify>,
+}
+
+/// Receives values from the associated [`Sender`].
+pub struct Receiver<T> {
+    inner: watch::Receiver<T>,
+    notify: Arc<Notify>,
+}
+
+impl<T> Sender<T> {
+    /// Creates the sending half of a channel without receivers.
+    pub fn new(init: T) -> Self {
+        channel(init).0
+    }
+
+    /// Sends a new value, notifying all receivers.
+    ///
+    /// Fails if there are no receivers.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.inner.send(value)?;
+        self.notify.notify_waiters();
+        Ok(())
+    }
+
+    /// Modifies the value in place, notifying all receivers.
+    pub fn send_modify<F: FnOnce(&mut T)>(&self, modify: F) {
+        self.inner.send_modify(modify);
+        self.notify.notify_waiters();
+    }
+
+    /// Modifies the value in place, notifying all receivers if `modify` returns true.
+    pub fn send_if_modified<F: FnOnce(&mut T) -> bool>(&self, modify: F) -> bool {
+        let modified = self.inner.send_if_modified(modify);
+        if modified {
+            self.notify.notify_waiters();
+        }
+        modified
+    }
+
+    /// Sends a new value even if there are no receivers, returning the previous value.
+    pub fn send_replace(&self, value: T) -> T {
+        let old = self.inner.send_replace(value);
+        self.notify.notify_waiters();
+        old
+    }
+
+    /// Returns a reference to the most recently sent value.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.inner.borrow()
+    }
+
+    /// Checks if all receivers have been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Completes when all receivers have been dropped.
+    pub async fn closed(&self) {
+        self.inner.closed().await;
+    }
+
+    /// Creates a new receiver, which sees the current value as seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver {
+            inner: self.inner.subscribe(),
+            notify: self.notify.clone(),
+        }
+    }
+
+    /// Returns the number of receivers.
+    pub fn receiver_count(&self) -> usize {
+        self.inner.receiver_count()
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // waiters are polled after the inner sender is dropped, and find the channel closed
+        self.notify.notify_waiters();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Returns a reference to the most recently sent value, without marking it as seen.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.inner.borrow()
+    }
+
+    /// Returns a reference to the most recently sent value and marks it as seen.
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        self.inner.borrow_and_update()
+    }
+
+    /// Checks if the channel has a value that has not been seen.
+    ///
+    /// Fails if the sender has been dropped.
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        self.inner.has_changed()
+    }
+
+    /// Marks the current value as not seen.
+    pub fn mark_changed(&mut self) {
+        self.inner.mark_changed();
+    }
+
+    /// Marks the current value as seen.
+    pub fn mark_unchanged(&mut self) {
+        self.inner.mark_unchanged();
+    }
+
+    /// Waits for a value that has not been seen, and marks it as seen.
+    ///
+    /// Fails if the sender has been dropped and the current value has been seen.
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        loop {
+            // register before checking to not miss a send in between
+            let mut notified = pin!(self.notify.notified());
+            notified.as_mut().enable();
+            if let Some(res) = self.inner.changed().now_or_never() {
+                return res;
+            }
+            notified.await;
+        }
+    }
+
+    /// Waits for a value that satisfies `f`, starting from the current value, and marks it as
+    /// seen.
+    ///
+    /// Fails if the sender has been dropped before such a value is sent.
+    pub async fn wait_for(
+        &mut self,
+        mut f: impl FnMut(&T) -> bool,
+    ) -> Result<Ref<'_, T>, RecvError> {
+        loop {
+            if f(&self.inner.borrow_and_update()) {
+                return Ok(self.inner.borrow());
+            }
+            self.changed().await?;
+        }
+    }
+
+    /// Returns true if the receivers belong to the same channel.
+    pub fn same_channel(&self, other: &Self) -> bool {
+        self.inner.same_channel(&other.inner)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver {
+            inner: self.inner.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Sender<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, fmt)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Receiver<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::Runtime,
+        time::{sleep, Duration},
+    };
+    use spin::Mutex;
+
+    /// Returns the values observed by each wakeup of several subscribers, in wake order.
+    fn observe(seed: u64) -> Vec<(usize, u32)> {
+        let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+        runtime.block_on(async {
+            let (tx, rx) = channel(0);
+            let observed = Arc::new(Mutex::new(vec![]));
+            for i in 0..8 {
+                let mut rx = rx.clone();
+                let observed = observed.clone();
+                crate::task::spawn(async move {
+                    while rx.changed().await.is_ok() {
+                        let value = *rx.borrow_and_update();
+                        observed.lock().push((i, value));
+                    }
+                });
+            }
+            sleep(Duration::from_secs(1)).await;
+            for value in 1..=3 {
+                tx.send(value).unwrap();
+                sleep(Duration::from_secs(1)).await;
+            }
+            // a burst is observed as its latest value
+            for value in 4..=6 {
+                tx.send(value).unwrap();
+            }
+            sleep(Duration::from_secs(1)).await;
+            drop(tx);
+            sleep(Duration::from_secs(1)).await;
+            let observed = observed.lock().clone();
+            observed
+        })
+    }
+
+    #[test]
+    fn deterministic_wake_order() {
+        let observed = observe(1);
+        assert_eq!(observed.len(), 8 * 4);
+        for (round, wakeups) in observed.chunks(8).enumerate() {
+            let value = [1, 2, 3, 6][round];
+            assert!(wakeups.iter().all(|&(_, v)| v == value), "{wakeups:?}");
+        }
+        // the same seed produces the same order
+        assert_eq!(observe(1), observed);
+    }
+
+    #[test]
+    fn changed_semantics() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let (tx, mut rx) = channel(0);
+            assert!(!rx.has_changed().unwrap());
+            tx.send(1).unwrap();
+            assert!(rx.has_changed().unwrap());
+            // `borrow` doesn't mark the value as seen
+            assert_eq!(*rx.borrow(), 1);
+            rx.changed().await.unwrap();
+            assert!(!rx.has_changed().unwrap());
+            assert_eq!(*rx.wait_for(|v| *v == 1).await.unwrap(), 1);
+
+            // an unseen value is returned after the sender is dropped
+            tx.send(2).unwrap();
+            drop(tx);
+            rx.changed().await.unwrap();
+            assert_eq!(*rx.borrow_and_update(), 2);
+            assert!(rx.changed().await.is_err());
+        });
+    }
+}
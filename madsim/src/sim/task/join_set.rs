@@ -0,0 +1,274 @@
+use super::*;
+use std::collections::VecDeque;
+
+/// A collection of tasks spawned on the current node.
+///
+/// Unlike `tokio::task::JoinSet`, the order of [`join_next`](JoinSet::join_next) is
+/// deterministic: tasks are joined in the order they completed, and tasks completing at the
+/// same logical time are ordered by the seeded scheduling of the executor. Aborted tasks are
+/// joined in the order they were spawned.
+///
+/// All tasks are aborted when the set is dropped.
+pub struct JoinSet<T> {
+    /// The tasks by the order they were spawned.
+    tasks: BTreeMap<u64, JoinHandle<T>>,
+    /// The keys of completed tasks by the order they completed.
+    completed: Arc<Mutex<VecDeque<u64>>>,
+    next_key: u64,
+}
+
+impl<T> fmt::Debug for JoinSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinSet").field("len", &self.len()).finish()
+    }
+}
+
+impl<T> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> JoinSet<T> {
+    /// Create a new `JoinSet`.
+    pub fn new() -> Self {
+        JoinSet {
+            tasks: BTreeMap::new(),
+            completed: Default::default(),
+            next_key: 0,
+        }
+    }
+
+    /// Returns the number of tasks currently in the `JoinSet`.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns whether the `JoinSet` is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Wraps `future` to record its completion.
+    fn track<F: Future<Output = T>>(&mut self, future: F) -> (u64, impl Future<Output = T>) {
+        let key = self.next_key;
+        self.next_key += 1;
+        let completed = self.completed.clone();
+        let future = async move {
+            let output = future.await;
+            completed.lock().push_back(key);
+            output
+        };
+        (key, future)
+    }
+
+    fn insert(&mut self, key: u64, handle: JoinHandle<T>) -> AbortHandle {
+        let abort = handle.abort_handle();
+        self.tasks.insert(key, handle);
+        abort
+    }
+
+    /// Spawn the provided task on the `JoinSet`, returning an [`AbortHandle`] that can be used
+    /// to remotely cancel the task.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called outside of a madsim runtime.
+    #[track_caller]
+    pub fn spawn<F>(&mut self, task: F) -> AbortHandle
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (key, task) = self.track(task);
+        let handle = spawn(task);
+        self.insert(key, handle)
+    }
+
+    /// Spawn the provided `!Send` task on the `JoinSet`, returning an [`AbortHandle`] that can
+    /// be used to remotely cancel the task.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called outside of a madsim runtime.
+    #[track_caller]
+    pub fn spawn_local<F>(&mut self, task: F) -> AbortHandle
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let (key, task) = self.track(task);
+        let handle = spawn_local(task);
+        self.insert(key, handle)
+    }
+
+    /// Waits until one of the tasks in the set completes and returns its output.
+    ///
+    /// Returns `None` if the set is empty.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        std::future::poll_fn(|cx| self.poll_join_next(cx)).await
+    }
+
+    /// Tries to join one of the tasks in the set that has completed and return its output.
+    ///
+    /// Returns `None` if there are no completed tasks, or if the set is empty.
+    pub fn try_join_next(&mut self) -> Option<Result<T, JoinError>> {
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+        match self.poll_join_next(&mut cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => None,
+        }
+    }
+
+    /// Polls for one of the tasks in the set to complete.
+    pub fn poll_join_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, JoinError>>> {
+        if self.tasks.is_empty() {
+            return Poll::Ready(None);
+        }
+        // completed tasks first, in the order they completed
+        while let Some(key) = self.pop_completed() {
+            // skip detached tasks
+            if let Some(mut handle) = self.tasks.remove(&key) {
+                let res = handle.poll_unpin(cx);
+                assert!(res.is_ready(), "completed task is not ready");
+                return res.map(Some);
+            }
+        }
+        // then aborted tasks, and register the waker on the others
+        let mut joined = None;
+        for (key, handle) in self.tasks.iter_mut() {
+            if let Poll::Ready(res) = handle.poll_unpin(cx) {
+                joined = Some((*key, res));
+                break;
+            }
+        }
+        match joined {
+            Some((key, res)) => {
+                self.tasks.remove(&key);
+                Poll::Ready(Some(res))
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    fn pop_completed(&self) -> Option<u64> {
+        self.completed.lock().pop_front()
+    }
+
+    /// Aborts all tasks on this `JoinSet`.
+    ///
+    /// This does not remove the tasks from the `JoinSet`. To wait for the tasks to complete
+    /// cancellation, call [`join_next`](JoinSet::join_next) in a loop until the set is empty.
+    pub fn abort_all(&mut self) {
+        for handle in self.tasks.values() {
+            handle.abort();
+        }
+    }
+
+    /// Aborts all tasks and waits for them to finish shutting down.
+    pub async fn shutdown(&mut self) {
+        self.abort_all();
+        while self.join_next().await.is_some() {}
+    }
+
+    /// Removes all tasks from this `JoinSet` without aborting them.
+    ///
+    /// The tasks removed by this call will continue to run in the background even if the
+    /// `JoinSet` is dropped.
+    pub fn detach_all(&mut self) {
+        self.tasks.clear();
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        self.abort_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Handle, Runtime},
+        time::{sleep, Instant},
+    };
+
+    /// Returns the order of tasks joined, with some completing at the same time.
+    fn join_order(seed: u64) -> Vec<usize> {
+        let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+        runtime.block_on(async {
+            let node = Handle::current().create_node().build();
+            node.spawn(async {
+                let mut set = JoinSet::new();
+                for (i, ms) in [300, 100, 500, 200, 400, 200, 200].into_iter().enumerate() {
+                    set.spawn(async move {
+                        sleep(Duration::from_millis(ms)).await;
+                        i
+                    });
+                }
+                let t0 = Instant::now();
+                let mut order = vec![];
+                while let Some(res) = set.join_next().await {
+                    order.push(res.unwrap());
+                }
+                let elapsed = t0.elapsed();
+                assert!(elapsed >= Duration::from_millis(500), "{elapsed:?}");
+                order
+            })
+            .await
+            .unwrap()
+        })
+    }
+
+    #[test]
+    fn join_in_completion_order() {
+        let order = join_order(1);
+        assert_eq!(order.len(), 7);
+        assert_eq!(order[0], 1);
+        // the tasks of 200ms in any order
+        let mut ties = order[1..4].to_vec();
+        ties.sort();
+        assert_eq!(ties, [3, 5, 6]);
+        assert_eq!(order[4..], [0, 4, 2]);
+        // reproducible under the same seed
+        assert_eq!(join_order(1), order);
+    }
+
+    #[test]
+    fn abort_and_shutdown() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let mut set = JoinSet::new();
+            set.spawn(async { 0 });
+            for _ in 0..3 {
+                set.spawn(std::future::pending::<i32>());
+            }
+            sleep(Duration::from_secs(1)).await;
+            // the completed task is joined first
+            assert_eq!(set.try_join_next().unwrap().unwrap(), 0);
+            assert!(set.try_join_next().is_none());
+
+            set.abort_all();
+            assert_eq!(set.len(), 3);
+            for _ in 0..3 {
+                let err = set.join_next().await.unwrap().unwrap_err();
+                assert!(err.is_cancelled());
+            }
+            assert!(set.join_next().await.is_none());
+
+            // shutdown waits for aborted tasks
+            let flag = Arc::new(());
+            for _ in 0..3 {
+                let flag = flag.clone();
+                set.spawn(async move {
+                    let _flag = flag;
+                    std::future::pending::<i32>().await
+                });
+            }
+            set.shutdown().await;
+            assert!(set.is_empty());
+            assert_eq!(Arc::strong_count(&flag), 1);
+        });
+    }
+}
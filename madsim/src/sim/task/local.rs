@@ -0,0 +1,55 @@
+use super::*;
+use std::{cell::RefCell, marker::PhantomData, rc::Rc};
+
+/// A set of `!Send` tasks, like [`tokio::task::LocalSet`].
+///
+/// The simulation runs every task on a single thread, so [`spawn_local`] works anywhere and a
+/// `LocalSet` is not required. It is provided for code written against tokio. Tasks spawned on
+/// the set run on the current node, in the same deterministic order as other tasks, and are
+/// aborted when the set is dropped.
+///
+/// Unlike tokio, [`spawn_local`] called within [`run_until`](LocalSet::run_until) does not add
+/// the task to the set.
+///
+/// [`tokio::task::LocalSet`]: https://docs.rs/tokio/latest/tokio/task/struct.LocalSet.html
+#[derive(Debug, Default)]
+pub struct LocalSet {
+    tasks: RefCell<Vec<AbortHandle>>,
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl LocalSet {
+    /// Returns a new empty local task set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns a `!Send` future on the set, returning a [`JoinHandle`] for it.
+    #[track_caller]
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let handle = spawn_local(future);
+        let mut tasks = self.tasks.borrow_mut();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle.abort_handle());
+        handle
+    }
+
+    /// Runs a future to completion.
+    ///
+    /// Tasks on the set are scheduled whether or not this is called.
+    pub async fn run_until<F: Future>(&self, future: F) -> F::Output {
+        future.await
+    }
+}
+
+impl Drop for LocalSet {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().drain(..) {
+            task.abort();
+        }
+    }
+}
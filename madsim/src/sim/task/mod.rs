@@ -2,7 +2,8 @@
 
 use super::{
     rand::GlobalRng,
-    runtime::{NodeBuilder, Simulators},
+    runtime::{ExitReason, NodeBuilder, Scheduling, Simulators},
+    sync::watch,
     time::{TimeHandle, TimeRuntime},
     utils::mpsc,
 };
@@ -24,11 +25,8 @@ use std::{
     task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
-use tokio::sync::watch;
 use tracing::{debug, error, error_span, trace, Span};
 
-pub use tokio::task::yield_now;
-
 type StaticLocation = &'static Location<'static>;
 type Runnable = async_task::Runnable<Weak<TaskInfo>>;
 #[doc(hidden)]
@@ -36,9 +34,13 @@ pub type FallibleTask<T> = async_task::FallibleTask<T, Weak<TaskInfo>>;
 
 mod builder;
 mod join;
+mod join_set;
+mod local;
 
 pub use self::builder::*;
 pub use self::join::*;
+pub use self::join_set::*;
+pub use self::local::*;
 
 pub(crate) struct Executor {
     queue: mpsc::Receiver<Runnable>,
@@ -46,6 +48,10 @@ pub(crate) struct Executor {
     rand: GlobalRng,
     time: TimeRuntime,
     time_limit: Option<Duration>,
+    step_limit: Option<u64>,
+    scheduling: Scheduling,
+    /// The number of tasks run so far.
+    steps: AtomicU64,
 }
 
 /// A unique identifier for a node.
@@ -101,14 +107,16 @@ pub(crate) struct NodeInfo {
     paused: AtomicBool,
     /// A flag indicating that the node has been killed.
     killed: AtomicBool,
+    /// Why the node has been killed.
+    exit_reason: Mutex<Option<ExitReason>>,
     /// All tasks spawned in this node.
     tasks: Mutex<Vec<Weak<TaskInfo>>>,
-    /// Sender of the "ctrl-c" signal.
+    /// Senders of signals by signal number, such as "ctrl-c" (`SIGINT`).
     ///
-    /// This value is `None` at the beginning, meaning that `signal::ctrl_c` has never been called,
-    /// and sending "ctrl-c" will cause the node being killed. Once `signal::ctrl_c` is called,
-    /// this will be set to `Some`, and sending "ctrl-c" will no longer kill the node.
-    ctrl_c: Mutex<Option<watch::Sender<()>>>,
+    /// A signal is absent at the beginning, meaning that no handler has been installed, e.g. by
+    /// `signal::ctrl_c`, and sending the signal will cause the node being killed. Once a handler
+    /// is installed, sending the signal will no longer kill the node.
+    signals: Mutex<HashMap<i32, watch::Sender<()>>>,
 }
 
 impl NodeInfo {
@@ -130,7 +138,9 @@ impl NodeInfo {
         task
     }
 
-    fn kill(&self) {
+    fn kill(&self, reason: ExitReason) {
+        // the first reason wins if the node is killed again
+        self.exit_reason.lock().get_or_insert(reason);
         self.killed.store(true, Ordering::Relaxed);
         for task in self.tasks.lock().drain(..) {
             if let Some(task) = task.upgrade() {
@@ -163,12 +173,11 @@ impl NodeInfo {
         self.killed.load(Ordering::Relaxed)
     }
 
-    /// Get a receiver of "ctrl-c" signal.
-    pub(crate) fn ctrl_c(&self) -> watch::Receiver<()> {
-        self.ctrl_c
-            .lock()
-            .get_or_insert_with(|| {
-                debug!("ctrl-c signal handler installed");
+    /// Get a receiver of the signal, installing a handler for it.
+    pub(crate) fn signal(&self, signum: i32) -> watch::Receiver<()> {
+        (self.signals.lock().entry(signum))
+            .or_insert_with(|| {
+                debug!(signum, "signal handler installed");
                 watch::channel(()).0
             })
             .subscribe()
@@ -193,14 +202,18 @@ impl Executor {
                     span: error_span!("node", id = %NodeId::zero(), name = "main"),
                     paused: AtomicBool::new(false),
                     killed: AtomicBool::new(false),
+                    exit_reason: Mutex::new(None),
                     tasks: Mutex::new(vec![]),
-                    ctrl_c: Mutex::new(None),
+                    signals: Default::default(),
                 }),
                 sims,
             },
             time: TimeRuntime::new(&rand),
             rand,
             time_limit: None,
+            step_limit: None,
+            scheduling: Scheduling::default(),
+            steps: AtomicU64::new(0),
         }
     }
 
@@ -216,6 +229,14 @@ impl Executor {
         self.time_limit = Some(limit);
     }
 
+    pub fn set_step_limit(&mut self, limit: u64) {
+        self.step_limit = Some(limit);
+    }
+
+    pub fn set_scheduling(&mut self, scheduling: Scheduling) {
+        self.scheduling = scheduling;
+    }
+
     #[track_caller]
     pub fn block_on<F: Future>(&self, future: F) -> F::Output {
         // push the future into ready queue.
@@ -251,17 +272,28 @@ impl Executor {
                 }
             }
             if let Some(limit) = self.time_limit {
-                assert!(
-                    self.time.handle().elapsed() < limit,
-                    "time limit exceeded: {limit:?}"
-                )
+                if self.time.handle().elapsed() >= limit {
+                    panic!(
+                        "time limit exceeded: {limit:?}\nnext timer at: {:?}\npending tasks by node by spawn: {}",
+                        self.time.next_event(),
+                        self.handle.num_tasks_by_node_by_spawn(),
+                    );
+                }
             }
         }
     }
 
+    /// Pops the next task to run from the ready queue.
+    fn next_ready(&self) -> Option<Runnable> {
+        match self.scheduling {
+            Scheduling::Randomized => self.queue.try_recv_random(&self.rand).ok(),
+            Scheduling::Fifo => self.queue.try_recv().ok(),
+        }
+    }
+
     /// Drain all tasks from ready queue and run them.
     fn run_all_ready(&self) {
-        while let Ok(runnable) = self.queue.try_recv_random(&self.rand) {
+        while let Some(runnable) = self.next_ready() {
             let Some(info) = runnable.metadata().upgrade() else {
                 // future has been dropped
                 continue;
@@ -278,6 +310,9 @@ impl Executor {
             } else {
                 fn run(runnable: Runnable) {
                     runnable.run();
+                    if let Some(msg) = STRICT_VIOLATION.take() {
+                        panic!("{msg}");
+                    }
                 }
                 run
             };
@@ -306,7 +341,7 @@ impl Executor {
                         "task panicked, restarting node {} {:?} after {:?}",
                         node_id, info.node.name, delay
                     );
-                    self.kill(node_id);
+                    self.kill_id(node_id, ExitReason::Panicked);
                     let h = self.handle.clone();
                     self.time
                         .handle()
@@ -316,6 +351,21 @@ impl Executor {
                 }
             }
 
+            // tasks yielding in a loop never leave this loop, so the limit is checked here
+            let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(limit) = self.step_limit {
+                if steps > limit {
+                    panic!(
+                        "step limit exceeded: {limit}\nlast task: node={} {:?}, task={} (spawned at {})\npending tasks by node by spawn: {}",
+                        info.node.id,
+                        info.node.name.as_ref().map_or("<unnamed>", |s| s),
+                        info.id,
+                        info.location,
+                        self.handle.num_tasks_by_node_by_spawn(),
+                    );
+                }
+            }
+
             // advance time: 50-100ns
             let dur = Duration::from_nanos(self.rand.with(|rng| rng.gen_range(50..100)));
             self.time.handle().advance(dur);
@@ -347,6 +397,8 @@ struct Node {
     paused: Vec<Runnable>,
     /// A function to spawn the initial task.
     init: Option<InitFn>,
+    /// Why the previous incarnation of the node exited.
+    last_exit: Option<ExitReason>,
 }
 
 pub(crate) type InitFn = Arc<dyn Fn(&Spawner) + Send + Sync>;
@@ -356,14 +408,21 @@ impl TaskHandle {
     pub fn kill(&self, id: impl ToNodeId) {
         debug!(node = %id, "kill");
         let id = id.to_node_id(self);
-        self.kill_id(id);
+        self.kill_id(id, ExitReason::Killed);
     }
 
-    fn kill_id(&self, id: NodeId) {
+    /// Kill all tasks of the node as the OOM killer would.
+    pub fn oom_kill(&self, id: impl ToNodeId) {
+        debug!(node = %id, "oom kill");
+        let id = id.to_node_id(self);
+        self.kill_id(id, ExitReason::OutOfMemory);
+    }
+
+    fn kill_id(&self, id: NodeId, reason: ExitReason) {
         let mut nodes = self.nodes.lock();
         let node = nodes.get_mut(&id).expect("node not found");
         node.paused.clear();
-        node.info.kill();
+        node.info.kill(reason);
 
         for sim in self.sims.lock().values() {
             sim.reset_node(id);
@@ -384,13 +443,15 @@ impl TaskHandle {
             restart_on_panic_matching: node.info.restart_on_panic_matching.clone(),
             paused: AtomicBool::new(false),
             killed: AtomicBool::new(false),
+            exit_reason: Mutex::new(None),
             span: error_span!(parent: None, "node", %id, name = &node.info.name),
             tasks: Mutex::new(vec![]),
-            ctrl_c: Mutex::new(None),
+            signals: Default::default(),
         });
         let old_info = std::mem::replace(&mut node.info, new_info);
         node.paused.clear();
-        old_info.kill();
+        old_info.kill(ExitReason::Killed);
+        node.last_exit = *old_info.exit_reason.lock();
 
         if let Some(init) = &node.init {
             init(&Spawner {
@@ -425,19 +486,24 @@ impl TaskHandle {
 
     /// Send a "ctrl-c" signal to the node.
     pub fn send_ctrl_c(&self, id: impl ToNodeId) {
-        debug!(node = %id, "send ctrl-c");
+        self.send_signal(id, libc::SIGINT);
+    }
+
+    /// Send a signal to the node, which kills the node if it has no handler of the signal.
+    pub fn send_signal(&self, id: impl ToNodeId, signum: i32) {
+        debug!(node = %id, signum, "send signal");
         let id = id.to_node_id(self);
         let mut nodes = self.nodes.lock();
         let node = nodes.get_mut(&id).expect("node not found");
-        if let Some(tx) = &*node.info.ctrl_c.lock() {
+        if let Some(tx) = node.info.signals.lock().get(&signum) {
             // may return error if no receiver
             _ = tx.send(());
             return;
         }
         drop(nodes);
-        // "ctrl-c" has never been called. kill node
-        debug!(node = %id, "killed by ctrl-c");
-        self.kill_id(id);
+        // no handler has been installed. kill node
+        debug!(node = %id, signum, "killed by signal");
+        self.kill_id(id, ExitReason::Killed);
     }
 
     /// Returns whether the node is killed or exited.
@@ -448,6 +514,15 @@ impl TaskHandle {
         node.info.killed.load(Ordering::Relaxed)
     }
 
+    /// Returns why the node exited last time.
+    pub fn exit_reason(&self, id: impl ToNodeId) -> Option<ExitReason> {
+        let id = id.to_node_id(self);
+        let nodes = self.nodes.lock();
+        let node = nodes.get(&id).expect("node not found");
+        let reason = *node.info.exit_reason.lock();
+        reason.or(node.last_exit)
+    }
+
     /// Create a new node.
     pub fn create_node(&self, builder: &NodeBuilder<'_>) -> Spawner {
         let id = NodeId(self.next_node_id.fetch_add(1, Ordering::Relaxed));
@@ -462,8 +537,9 @@ impl TaskHandle {
             restart_on_panic_matching: builder.restart_on_panic_matching.clone(),
             paused: AtomicBool::new(false),
             killed: AtomicBool::new(false),
+            exit_reason: Mutex::new(None),
             tasks: Mutex::new(vec![]),
-            ctrl_c: Mutex::new(None),
+            signals: Default::default(),
         });
         let handle = Spawner {
             sender: self.sender.clone(),
@@ -476,6 +552,7 @@ impl TaskHandle {
             info,
             paused: vec![],
             init: builder.init.clone(),
+            last_exit: None,
         };
         self.nodes.lock().insert(id, node);
         handle
@@ -498,6 +575,13 @@ impl TaskHandle {
         self.nodes.lock().len()
     }
 
+    /// Returns the IDs of all nodes except the main node, in ascending order.
+    pub(crate) fn node_ids(&self) -> Vec<NodeId> {
+        let mut ids: Vec<_> = self.nodes.lock().keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
     pub fn num_tasks(&self) -> usize {
         self.nodes
             .lock()
@@ -522,6 +606,11 @@ impl TaskHandle {
             .collect()
     }
 
+    /// Returns the number of tasks that are ready to run.
+    pub(crate) fn num_runnable(&self) -> usize {
+        self.sender.len()
+    }
+
     pub fn num_tasks_by_node_by_spawn(&self) -> String {
         let map = self
             .nodes
@@ -554,12 +643,14 @@ impl ToNodeId for NodeId {
 }
 
 impl ToNodeId for &str {
+    /// Nodes may share a name, in which case the earliest created one is chosen.
     fn to_node_id(&self, task: &TaskHandle) -> NodeId {
-        match (task.nodes.lock().iter()).find(|(_, node)| match &node.info.name {
-            Some(name) => name == self,
-            None => false,
-        }) {
-            Some((id, _)) => *id,
+        match (task.nodes.lock().iter())
+            .filter(|(_, node)| node.info.name.as_deref() == Some(*self))
+            .map(|(id, _)| *id)
+            .min()
+        {
+            Some(id) => id,
             None => panic!("node not found: {self}"),
         }
     }
@@ -657,7 +748,7 @@ impl Spawner {
     pub(crate) fn exit(&self) {
         debug!(node = %self.info.id, "exit");
         // FIXME: clear paused tasks
-        self.info.kill();
+        self.info.kill(ExitReason::Exited);
     }
 }
 
@@ -671,7 +762,10 @@ where
     Spawner::current().spawn(future)
 }
 
-/// Spawns a `!Send` future on the local task set.
+/// Spawns a `!Send` future on the current node, returning a [`JoinHandle`] for it.
+///
+/// All tasks of a simulation run on one thread, so `!Send` tasks are scheduled in the same
+/// deterministic order as tasks spawned by [`spawn`].
 #[track_caller]
 pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
 where
@@ -681,6 +775,42 @@ where
     Spawner::current().spawn_local(future)
 }
 
+/// Spawns a new asynchronous task that starts running after `delay`, returning a
+/// [`JoinHandle`] for it.
+///
+/// The future is not polled until the delay has elapsed on the simulated clock, which is
+/// useful to reproduce a specific interleaving. Aborting the task before it starts drops the
+/// future without polling it.
+#[track_caller]
+pub fn spawn_delayed<F>(delay: Duration, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Spawner::current().spawn(async move {
+        crate::time::sleep(delay).await;
+        future.await
+    })
+}
+
+/// Yields execution back to the scheduler.
+///
+/// The current task is pushed back to the ready queue immediately. The executor always picks
+/// the next task to run from all ready tasks using the runtime's random number generator, so the
+/// interleaving of yielding tasks is a function of the seed alone.
+pub async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
 /// Runs the provided closure on a thread where blocking is acceptable.
 #[deprecated(
     since = "0.3.0",
@@ -765,6 +895,26 @@ unsafe extern "C" fn sysconf(name: libc::c_int) -> libc::c_long {
     SYSCONF(name)
 }
 
+thread_local! {
+    /// The nondeterministic std API called by the running task in strict mode.
+    static STRICT_VIOLATION: std::cell::Cell<Option<&'static str>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// Reports a call to a nondeterministic std API inside a task, if strict mode is enabled.
+///
+/// The task panics with `msg` once it yields. Returns whether the call is reported, in which
+/// case the API should return without doing anything.
+pub(crate) fn report_strict_violation(msg: &'static str) -> bool {
+    if crate::context::try_current_task().is_none()
+        || !crate::context::try_current(|h| h.strict).unwrap_or_default()
+    {
+        return false;
+    }
+    STRICT_VIOLATION.set(Some(msg));
+    true
+}
+
 /// Forbid creating system thread in simulation.
 #[no_mangle]
 #[inline(never)]
@@ -796,10 +946,14 @@ mod tests {
     use super::*;
     use crate::{
         context::{current, current_node},
+        net::Endpoint,
         runtime::{init_logger, Handle, Runtime},
         time,
     };
-    use std::{collections::HashSet, sync::atomic::AtomicUsize, time::Duration};
+    use std::{
+        cell::RefCell, collections::HashSet, net::IpAddr, rc::Rc, sync::atomic::AtomicUsize,
+        time::Duration,
+    };
 
     #[test]
     fn spawn_in_block_on() {
@@ -810,6 +964,152 @@ mod tests {
         });
     }
 
+    #[test]
+    fn spawn_local_not_send() {
+        let run = |seed| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                let order = Rc::new(RefCell::new(vec![]));
+                let local = LocalSet::new();
+                let tasks: Vec<_> = (0..10)
+                    .map(|i| {
+                        // the `Rc` is held across an await point
+                        let order = order.clone();
+                        let task = async move {
+                            time::sleep(Duration::from_millis(10)).await;
+                            order.borrow_mut().push(i);
+                            i * 2
+                        };
+                        match i % 2 {
+                            0 => spawn_local(task),
+                            _ => local.spawn_local(task),
+                        }
+                    })
+                    .collect();
+                let outputs = local
+                    .run_until(async {
+                        let mut outputs = vec![];
+                        for task in tasks {
+                            outputs.push(task.await.unwrap());
+                        }
+                        outputs
+                    })
+                    .await;
+                assert_eq!(outputs, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+
+                // tasks on the set are aborted when it is dropped
+                let flag = Rc::new(RefCell::new(false));
+                let flag1 = flag.clone();
+                local.spawn_local(async move {
+                    time::sleep(Duration::from_secs(1)).await;
+                    *flag1.borrow_mut() = true;
+                });
+                drop(local);
+                time::sleep(Duration::from_secs(2)).await;
+                assert!(!*flag.borrow());
+
+                let order = order.borrow().clone();
+                order
+            })
+        };
+        let order = run(1);
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+        // the same seed produces the same order
+        assert_eq!(run(1), order);
+    }
+
+    #[test]
+    fn spawn_delayed_start() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let started = Arc::new(AtomicBool::new(false));
+            let started_ = started.clone();
+            let t0 = time::Instant::now();
+            let handle = spawn_delayed(Duration::from_secs(2), async move {
+                started_.store(true, Ordering::Relaxed);
+                time::Instant::now()
+            });
+            time::sleep(Duration::from_secs(1)).await;
+            assert!(!started.load(Ordering::Relaxed));
+            let start = handle.await.unwrap();
+            assert!(start - t0 >= Duration::from_secs(2));
+
+            // aborted before start, never polled
+            let started_ = started.clone();
+            started.store(false, Ordering::Relaxed);
+            let handle = spawn_delayed(Duration::from_secs(2), async move {
+                started_.store(true, Ordering::Relaxed);
+            });
+            time::sleep(Duration::from_secs(1)).await;
+            handle.abort();
+            time::sleep(Duration::from_secs(2)).await;
+            assert!(!started.load(Ordering::Relaxed));
+        });
+    }
+
+    #[test]
+    fn spawn_on_node_later() {
+        let runtime = Runtime::new();
+        let ip1: IpAddr = "10.0.0.1".parse().unwrap();
+        let node1 = runtime.create_node().ip(ip1).build();
+        let node2 = runtime
+            .create_node()
+            .ip("10.0.0.2".parse().unwrap())
+            .build();
+        runtime.block_on(async move {
+            let server = node2.spawn(async {
+                let ep = Endpoint::bind("10.0.0.2:1").await.unwrap();
+                let (_, from) = ep.recv_from(1, &mut []).await.unwrap();
+                from
+            });
+            // node1 has no task until now
+            time::sleep(Duration::from_secs(5)).await;
+            let id = node1.id();
+            let node1 = Handle::current().get_node(id).unwrap();
+            node1
+                .spawn(async move {
+                    assert_eq!(current_node(), id);
+                    let ep = Endpoint::bind("0.0.0.0:0").await.unwrap();
+                    ep.send_to("10.0.0.2:1", 1, b"hello").await.unwrap();
+                })
+                .await
+                .unwrap();
+            assert_eq!(server.await.unwrap().ip(), ip1);
+        });
+    }
+
+    #[test]
+    fn yield_now_order() {
+        fn turns(seed: u64) -> Vec<usize> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                let turns = Arc::new(Mutex::new(vec![]));
+                let tasks: Vec<_> = (0..8)
+                    .map(|i| {
+                        let turns = turns.clone();
+                        spawn(async move {
+                            for _ in 0..4 {
+                                turns.lock().push(i);
+                                yield_now().await;
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+                Arc::try_unwrap(turns).unwrap().into_inner()
+            })
+        }
+        let order = turns(1);
+        assert_eq!(order.len(), 32);
+        assert_eq!(turns(1), order);
+        // different seeds explore different interleavings
+        assert!((2..10).any(|seed| turns(seed) != order));
+    }
+
     #[test]
     fn kill() {
         let runtime = Runtime::new();
@@ -890,6 +1190,127 @@ mod tests {
         });
     }
 
+    #[test]
+    fn reboot() {
+        use crate::fs::{self, File};
+
+        let runtime = Runtime::new();
+        // the state read from disk by each incarnation of the node
+        let recovered = Arc::new(Mutex::new(vec![]));
+        let recovered_ = recovered.clone();
+        let node = runtime
+            .create_node()
+            .init(move || {
+                let recovered = recovered_.clone();
+                async move {
+                    let state = fs::read("state").await.ok();
+                    recovered.lock().push(state);
+                }
+            })
+            .build();
+
+        runtime.block_on(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            node.spawn(async move {
+                let file = File::create("state").await.unwrap();
+                file.write_all_at(b"persisted", 0).await.unwrap();
+                file.sync_all().await.unwrap();
+                // never finishes before the reboot
+                time::sleep(Duration::from_secs(10)).await;
+                unreachable!();
+            });
+            time::sleep(Duration::from_secs(1)).await;
+            node.reboot();
+            assert!(!Handle::current().is_exit(node.id()));
+
+            time::sleep(Duration::from_secs(1)).await;
+            assert_eq!(*recovered.lock(), [None, Some(b"persisted".to_vec())]);
+            // the handle spawns on the restarted node
+            let state = node.spawn(fs::read("state")).await.unwrap().unwrap();
+            assert_eq!(state, b"persisted");
+        });
+    }
+
+    #[test]
+    fn rolling_restart() {
+        use crate::fs::{self, File};
+
+        let runtime = Runtime::new();
+        // (time, node, number of previous boots read from disk)
+        let boots = Arc::new(Mutex::new(vec![]));
+        let nodes: Vec<_> = (0..3)
+            .map(|_| {
+                let boots = boots.clone();
+                runtime
+                    .create_node()
+                    .init(move || {
+                        let boots = boots.clone();
+                        async move {
+                            let n = fs::read("boots").await.map_or(0, |b| b[0]);
+                            let file = File::create("boots").await.unwrap();
+                            file.write_all_at(&[n + 1], 0).await.unwrap();
+                            file.sync_all().await.unwrap();
+                            let id = crate::plugin::node();
+                            boots.lock().push((time::Instant::now(), id, n));
+                        }
+                    })
+                    .build()
+            })
+            .collect();
+
+        runtime.block_on(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            let t0 = time::Instant::now();
+            let settle = Duration::from_secs(5);
+            let handle = Handle::current();
+            handle
+                .rolling_restart(nodes.iter().map(|n| n.id()), settle)
+                .await;
+            assert!(t0.elapsed() >= settle * 3);
+
+            let boots = boots.lock();
+            assert_eq!(boots.len(), 6);
+            for (i, node) in nodes.iter().enumerate() {
+                assert!(!handle.is_exit(node.id()));
+                // each node recovers from its own disk
+                let (time, id, n) = boots[3 + i];
+                assert_eq!((id, n), (node.id(), 1));
+                // one node at a time
+                let since = settle * i as u32;
+                assert!(time - t0 >= since && time - t0 < since + Duration::from_secs(1));
+            }
+        });
+    }
+
+    #[test]
+    fn nodes_in_creation_order() {
+        fn run(seed: u64) -> Vec<(NodeId, String)> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                let handle = Handle::current();
+                let mut created = vec![];
+                for i in 0..10 {
+                    let name = format!("node-{}", crate::rand::random::<u8>() % 3);
+                    let node = handle.create_node().name(&name).build();
+                    // killed nodes are still listed
+                    if i % 4 == 0 {
+                        handle.kill(node.id());
+                    }
+                    created.push((node.id(), name));
+                }
+                let ids: Vec<_> = created.iter().map(|(id, _)| *id).collect();
+                assert_eq!(handle.nodes(), ids);
+
+                // a shared name refers to the earliest created node
+                let (first, name) = &created[0];
+                assert_eq!(handle.get_node(name.as_str()).unwrap().id(), *first);
+                created
+            })
+        }
+        let nodes = run(1);
+        assert_eq!(run(1), nodes);
+    }
+
     #[test]
     fn restart_on_panic() {
         let runtime = Runtime::new();
@@ -995,6 +1416,43 @@ mod tests {
         assert_eq!(seqs.len(), 10);
     }
 
+    #[test]
+    fn deterministic_semaphore_wake_order() {
+        fn run(seed: u64) -> (Vec<usize>, Vec<usize>) {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                let sem = Arc::new(tokio::sync::Semaphore::new(0));
+                let parked = Arc::new(Mutex::new(vec![]));
+                let acquired = Arc::new(Mutex::new(vec![]));
+                let mut tasks = vec![];
+                for i in 0..8 {
+                    let sem = sem.clone();
+                    let parked = parked.clone();
+                    let acquired = acquired.clone();
+                    tasks.push(spawn(async move {
+                        parked.lock().push(i);
+                        let _permit = sem.acquire().await.unwrap();
+                        acquired.lock().push(i);
+                        std::future::pending::<()>().await;
+                    }));
+                }
+                time::sleep(Duration::from_secs(1)).await;
+                for _ in 0..8 {
+                    sem.add_permits(1);
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+                let parked = parked.lock().clone();
+                let acquired = acquired.lock().clone();
+                (parked, acquired)
+            })
+        }
+        let (parked, acquired) = run(1);
+        // waiters are woken in FIFO order
+        assert_eq!(parked, acquired);
+        // the same seed produces the same order
+        assert_eq!(run(1), (parked, acquired));
+    }
+
     #[test]
     fn deterministic_std_thread_available_parallelism() {
         let runtime = Runtime::new();
@@ -1061,6 +1519,64 @@ mod tests {
         });
     }
 
+    #[test]
+    fn scheduling() {
+        // two tasks incrementing a counter with a yield between read and write
+        fn race(seed: u64, scheduling: Scheduling) -> (u32, Vec<&'static str>) {
+            let mut runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.set_scheduling(scheduling);
+            runtime.block_on(async {
+                let counter = Arc::new(Mutex::new(0));
+                let trace = Arc::new(Mutex::new(vec![]));
+                let tasks: Vec<_> = ["a", "b"]
+                    .into_iter()
+                    .map(|name| {
+                        let (counter, trace) = (counter.clone(), trace.clone());
+                        spawn(async move {
+                            let value = *counter.lock();
+                            trace.lock().push(name);
+                            yield_now().await;
+                            *counter.lock() = value + 1;
+                            trace.lock().push(name);
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+                let counter = *counter.lock();
+                let trace = trace.lock().clone();
+                (counter, trace)
+            })
+        }
+
+        // the lost update is found by some seeds but not the others
+        let results: HashSet<_> = (0..20)
+            .map(|seed| race(seed, Scheduling::Randomized))
+            .collect();
+        assert!(
+            results.iter().any(|(counter, _)| *counter == 1),
+            "{results:?}"
+        );
+        assert!(
+            results.iter().any(|(counter, _)| *counter == 2),
+            "{results:?}"
+        );
+        // each of them is reproducible
+        assert_eq!(
+            race(3, Scheduling::Randomized),
+            race(3, Scheduling::Randomized)
+        );
+
+        // a single interleaving in FIFO order
+        let results: HashSet<_> = (0..20).map(|seed| race(seed, Scheduling::Fifo)).collect();
+        assert_eq!(results.len(), 1, "{results:?}");
+        assert_eq!(
+            results.into_iter().next().unwrap(),
+            (1, vec!["a", "b", "a", "b"])
+        );
+    }
+
     #[test]
     fn join_cancelled() {
         let runtime = Runtime::new();
@@ -1178,4 +1694,77 @@ mod tests {
             time::sleep(Duration::from_secs(114514)).await;
         })
     }
+
+    #[test]
+    fn step_limit_catches_busy_loop() {
+        let mut runtime = Runtime::new();
+        runtime.set_step_limit(10_000);
+        let node = runtime.create_node().name("spinner").build();
+        node.spawn(async move {
+            // spin without advancing the simulated clock by timers
+            loop {
+                yield_now().await;
+            }
+        });
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            runtime.block_on(time::sleep(Duration::from_secs(1)))
+        }))
+        .unwrap_err();
+        let msg = panic_message::panic_message(&err);
+        assert!(msg.starts_with("step limit exceeded: 10000"), "{msg}");
+        assert!(msg.contains("\"spinner\""), "{msg}");
+    }
+
+    #[test]
+    fn lingering_interval_is_not_quiescent() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().name("ticker").build();
+        runtime.block_on(async move {
+            let handle = Handle::current();
+            // a finished task leaves no work behind
+            node.spawn(time::sleep(Duration::from_secs(1)))
+                .await
+                .unwrap();
+            handle.assert_quiescent();
+
+            node.spawn(async move {
+                let mut interval = time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                }
+            });
+            time::sleep(Duration::from_secs(10)).await;
+            assert!(!handle.is_quiescent());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                handle.assert_quiescent()
+            }))
+            .unwrap_err();
+            let msg = panic_message::panic_message(&err);
+            assert!(msg.starts_with("simulation is not quiescent"), "{msg}");
+            assert!(msg.contains("\"ticker\""), "{msg}");
+        });
+    }
+
+    #[test]
+    fn time_limit_dumps_pending_tasks() {
+        let mut runtime = Runtime::new();
+        runtime.set_time_limit(Duration::from_secs(60));
+        let node = runtime.create_node().name("retrier").build();
+        node.spawn(async move {
+            // retry forever
+            let mut backoff = Duration::from_millis(100);
+            loop {
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(10));
+            }
+        });
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            runtime.block_on(std::future::pending::<()>())
+        }))
+        .unwrap_err();
+        let msg = panic_message::panic_message(&err);
+        assert!(msg.starts_with("time limit exceeded: 60s"), "{msg}");
+        assert!(msg.contains("retrier"), "{msg}");
+        assert!(msg.contains("next timer at: Some("), "{msg}");
+    }
 }
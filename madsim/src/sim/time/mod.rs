@@ -1,22 +1,30 @@
 //! Utilities for tracking time.
 //!
-//!
-
-use crate::rand::{GlobalRng, Rng};
+//! Timers with the same deadline, such as two [`sleep`]s until the same instant, fire in the
+//! order they were registered, i.e. the order the futures were first polled. Firing a timer
+//! only wakes its task, so the tasks woken at the same instant are then run in an order chosen
+//! by the seeded executor. Both orders are reproducible with the same seed.
+
+use crate::{
+    rand::{GlobalRng, Rng},
+    task::NodeId,
+};
 use futures_util::{select_biased, FutureExt};
-use naive_timer::Timer;
 use spin::Mutex;
 #[doc(no_inline)]
 pub use std::time::{Duration, Instant};
-use std::{future::Future, sync::Arc, time::SystemTime};
+use std::{collections::HashMap, future::Future, pin::pin, sync::Arc, time::SystemTime};
+use tokio::sync::oneshot;
 
 pub mod error;
 mod interval;
 mod sleep;
 mod system_time;
+mod timer;
 
 pub use self::interval::{interval, interval_at, Interval, MissedTickBehavior};
 pub use self::sleep::{sleep, sleep_until, Sleep};
+use self::timer::Timer;
 
 pub(crate) struct TimeRuntime {
     handle: TimeHandle,
@@ -33,6 +41,7 @@ impl TimeRuntime {
         let handle = TimeHandle {
             timer: Arc::new(Mutex::new(Timer::default())),
             clock: Arc::new(Clock::new(base_time)),
+            skews: Default::default(),
         };
         TimeRuntime { handle }
     }
@@ -59,6 +68,11 @@ impl TimeRuntime {
         }
     }
 
+    /// Returns the elapsed time of the next timer event.
+    pub fn next_event(&self) -> Option<Duration> {
+        self.handle.timer.lock().next()
+    }
+
     #[allow(dead_code)]
     /// Get the current time.
     pub fn now_instant(&self) -> Instant {
@@ -71,6 +85,8 @@ impl TimeRuntime {
 pub struct TimeHandle {
     timer: Arc<Mutex<Timer>>,
     clock: Arc<Clock>,
+    /// Wall clock skew of each node.
+    skews: Arc<Mutex<HashMap<NodeId, ClockSkew>>>,
 }
 
 impl TimeHandle {
@@ -90,8 +106,27 @@ impl TimeHandle {
     }
 
     /// Return the current time.
+    ///
+    /// Inside a node, the time is shifted by the clock skew of the node.
     pub fn now_time(&self) -> SystemTime {
-        self.clock.now_time()
+        let time = self.clock.now_time();
+        let Some(task) = crate::context::try_current_task() else {
+            return time;
+        };
+        match self.skews.lock().get(&task.node.id) {
+            Some(ClockSkew::Ahead(skew)) => time + *skew,
+            Some(ClockSkew::Behind(skew)) => time - *skew,
+            None => time,
+        }
+    }
+
+    /// Set the wall clock skew of a node.
+    pub(crate) fn set_clock_skew(&self, id: NodeId, skew: ClockSkew) {
+        if skew == ClockSkew::ZERO {
+            self.skews.lock().remove(&id);
+        } else {
+            self.skews.lock().insert(id, skew);
+        }
     }
 
     /// Returns the amount of time elapsed since this handle was created.
@@ -109,21 +144,24 @@ impl TimeHandle {
     ///
     /// It will sleep for at least 1ms to be consistent with the behavior of `tokio::time::sleep`.
     pub fn sleep(&self, duration: Duration) -> Sleep {
+        let duration = duration.max(Duration::from_millis(1));
         self.sleep_until(self.clock.now_instant() + duration)
     }
 
     /// Waits until `deadline` is reached.
     ///
-    /// It will sleep for at least 1ms to be consistent with the behavior of `tokio::time::sleep_until`.
+    /// Like `tokio::time::sleep_until`, it completes on the first poll without advancing the
+    /// clock if `deadline` is not after now.
     pub fn sleep_until(&self, deadline: Instant) -> Sleep {
-        let min_deadline = self.clock.now_instant() + Duration::from_millis(1);
         Sleep {
             handle: self.clone(),
-            deadline: deadline.max(min_deadline),
+            deadline,
         }
     }
 
     /// Require a `Future` to complete before the specified duration has elapsed.
+    ///
+    /// See [`timeout`](crate::time::timeout) for how a tie with the deadline is broken.
     // TODO: make it Send
     pub fn timeout<T: Future>(
         &self,
@@ -131,10 +169,19 @@ impl TimeHandle {
         future: T,
     ) -> impl Future<Output = Result<T::Output, error::Elapsed>> {
         let timeout = self.sleep(duration);
+        let handle = self.clone();
         async move {
+            let mut future = pin!(future.fuse());
             select_biased! {
-                res = future.fuse() => Ok(res),
-                _ = timeout.fuse() => Err(error::Elapsed),
+                res = future => return Ok(res),
+                _ = timeout.fuse() => {}
+            }
+            // let tasks woken at the deadline run first, as they may complete the future
+            let (tx, rx) = oneshot::channel();
+            handle.add_timer(Duration::ZERO, move || _ = tx.send(()));
+            select_biased! {
+                res = future => Ok(res),
+                _ = rx.fuse() => Err(error::Elapsed),
             }
         }
     }
@@ -145,7 +192,12 @@ impl TimeHandle {
         callback: impl FnOnce() + Send + Sync + 'static,
     ) {
         let mut timer = self.timer.lock();
-        timer.add(deadline - self.clock.base_instant(), |_| callback());
+        timer.add(deadline - self.clock.base_instant(), callback);
+    }
+
+    /// Returns the time of the earliest pending timer since the start of the simulation.
+    pub(crate) fn next_timer(&self) -> Option<Duration> {
+        self.timer.lock().next()
     }
 
     pub(crate) fn add_timer(&self, dur: Duration, callback: impl FnOnce() + Send + Sync + 'static) {
@@ -154,6 +206,11 @@ impl TimeHandle {
 }
 
 /// Require a `Future` to complete before the specified duration has elapsed.
+///
+/// If the future completes at the same instant as the deadline, it wins and `Ok` is returned,
+/// like `tokio::time::timeout`. This holds even if the future is completed by another task
+/// woken at the deadline, e.g. a timer of the same deadline or a message arriving at that
+/// instant, no matter in which order the tasks are scheduled under the seed.
 pub fn timeout<T: Future>(
     duration: Duration,
     future: T,
@@ -172,6 +229,24 @@ pub fn advance(duration: Duration) {
     handle.advance(duration);
 }
 
+/// The offset of a node's wall clock from the simulated time.
+///
+/// Only `SystemTime` is affected. `Instant` and timers are driven by the global clock,
+/// as a skewed wall clock doesn't make a node run faster or slower.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClockSkew {
+    /// The wall clock is ahead of the simulated time.
+    Ahead(Duration),
+    /// The wall clock is behind the simulated time.
+    Behind(Duration),
+}
+
+impl ClockSkew {
+    /// No skew.
+    pub const ZERO: Self = ClockSkew::Ahead(Duration::ZERO);
+}
+
 struct Clock {
     inner: Mutex<ClockInner>,
 }
@@ -243,10 +318,6 @@ mod tests {
             sleep(Duration::default()).await;
             assert!(t0.elapsed() >= Duration::from_millis(1));
 
-            let t0 = Instant::now();
-            sleep_until(t0).await;
-            assert!(t0.elapsed() >= Duration::from_millis(1));
-
             let t0 = Instant::now();
 
             sleep(Duration::from_secs(1)).await;
@@ -268,6 +339,89 @@ mod tests {
         });
     }
 
+    #[test]
+    fn sleep_until() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            sleep(Duration::from_secs(1)).await;
+            // a deadline in the past returns immediately without advancing the clock
+            let t0 = Instant::now();
+            super::sleep_until(t0 - Duration::from_millis(500)).await;
+            assert_eq!(Instant::now(), t0);
+            super::sleep_until(t0).await;
+            assert_eq!(Instant::now(), t0);
+
+            // a deadline in the future wakes exactly then
+            let deadline = t0 + Duration::from_millis(1500);
+            let sleep = super::sleep_until(deadline);
+            assert_eq!(sleep.deadline(), deadline);
+            sleep.await;
+            // the clock overshoots timers by a few nanoseconds
+            let late = Instant::now() - deadline;
+            assert!(Instant::now() >= deadline && late < Duration::from_micros(1));
+        });
+    }
+
+    #[test]
+    fn timeout_at_deadline() {
+        // returns the outcome of an operation finishing `delay` after the deadline
+        let run = |seed, delay| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async move {
+                let (tx, rx) = oneshot::channel();
+                let node = crate::runtime::Handle::current().create_node().build();
+                node.spawn(async move {
+                    sleep(Duration::from_secs(1) + delay).await;
+                    _ = tx.send(());
+                });
+                timeout(Duration::from_secs(1), rx).await.is_ok()
+            })
+        };
+        for seed in 0..20 {
+            assert!(
+                run(seed, Duration::ZERO),
+                "seed {seed}: completion should win"
+            );
+            assert!(!run(seed, Duration::from_millis(1)), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn equal_deadlines() {
+        // returns the order in which timers fire and tasks run at the same deadline
+        let run = |seed| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                let handle = TimeHandle::current();
+                let deadline = Instant::now() + Duration::from_secs(1);
+                let fired = Arc::new(Mutex::new(vec![]));
+                let woken = Arc::new(Mutex::new(vec![]));
+                let mut tasks = vec![];
+                for i in 0..10 {
+                    let fired = fired.clone();
+                    handle.add_timer_at(deadline, move || fired.lock().push(i));
+                    let woken = woken.clone();
+                    tasks.push(crate::task::spawn(async move {
+                        sleep_until(deadline).await;
+                        woken.lock().push(i);
+                    }));
+                }
+                for task in tasks {
+                    task.await.unwrap();
+                }
+                let fired = fired.lock().clone();
+                let woken = woken.lock().clone();
+                (fired, woken)
+            })
+        };
+        let (fired, woken) = run(1);
+        // timers fire in insertion order
+        assert_eq!(fired, (0..10).collect::<Vec<_>>());
+        assert_eq!(woken.len(), 10);
+        // and the tasks run in the same order under the same seed
+        assert_eq!(run(1), (fired, woken));
+    }
+
     #[test]
     fn test_advance() {
         let runtime = Runtime::new();
@@ -277,4 +431,22 @@ mod tests {
             assert!(t0.elapsed() >= Duration::from_secs(1));
         });
     }
+
+    #[test]
+    fn handle_now() {
+        let runtime = Runtime::new();
+        let handle = runtime.handle().clone();
+        let t0 = handle.now();
+        let e0 = handle.elapsed();
+        let node = runtime.create_node().build();
+        let f = node.spawn(async move {
+            sleep(Duration::from_secs(3)).await;
+            Instant::now()
+        });
+        let t1 = runtime.block_on(f).unwrap();
+        assert!(handle.now() >= t1);
+        assert!(handle.now() - t0 >= Duration::from_secs(3));
+        assert!(handle.elapsed() - e0 >= Duration::from_secs(3));
+        assert!(handle.now() - t1 < Duration::from_millis(1));
+    }
 }
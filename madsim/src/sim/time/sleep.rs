@@ -2,12 +2,17 @@ use super::*;
 use std::{fmt, pin::Pin, task::Poll};
 
 /// Waits until `duration` has elapsed.
+///
+/// See the [module-level documentation](super) for the wake order of sleeps with the same
+/// deadline.
 pub fn sleep(duration: Duration) -> Sleep {
     let handle = TimeHandle::current();
     handle.sleep(duration)
 }
 
 /// Waits until `deadline` is reached.
+///
+/// Returns immediately if `deadline` has already been reached.
 pub fn sleep_until(deadline: Instant) -> Sleep {
     let handle = TimeHandle::current();
     handle.sleep_until(deadline)
@@ -87,6 +87,35 @@ unsafe extern "C" fn clock_gettime(
     }
 }
 
+/// Override the libc `nanosleep` function. For `std::thread::sleep`.
+///
+/// The real sleep blocks the whole simulation without advancing the simulated time,
+/// so it is reported in strict mode.
+#[no_mangle]
+#[inline(never)]
+unsafe extern "C" fn nanosleep(
+    req: *const libc::timespec,
+    rem: *mut libc::timespec,
+) -> libc::c_int {
+    if crate::task::report_strict_violation(
+        "`std::thread::sleep` called in simulation, which blocks all nodes and doesn't advance \
+         the simulated time. use `madsim::time::sleep` instead.",
+    ) {
+        return 0;
+    }
+    lazy_static::lazy_static! {
+        static ref NANOSLEEP: unsafe extern "C" fn(
+            req: *const libc::timespec,
+            rem: *mut libc::timespec,
+        ) -> libc::c_int = unsafe {
+            let ptr = libc::dlsym(libc::RTLD_NEXT, c"nanosleep".as_ptr() as _);
+            assert!(!ptr.is_null());
+            std::mem::transmute(ptr)
+        };
+    }
+    NANOSLEEP(req, rem)
+}
+
 /// Override the `mach_absolute_time` function. For `Instant` on macOS before Rust 1.75.
 #[no_mangle]
 #[inline(never)]
@@ -152,4 +181,23 @@ mod tests {
         }
         assert_eq!(times.len(), 1);
     }
+
+    #[test]
+    #[should_panic(expected = "use `madsim::time::sleep` instead")]
+    fn strict_thread_sleep() {
+        // the real sleep is allowed by default
+        let runtime = Runtime::new();
+        runtime.block_on(async { std::thread::sleep(Duration::from_millis(1)) });
+
+        let mut runtime = Runtime::new();
+        runtime.set_strict(true);
+        let node = runtime.create_node().build();
+        let f = node.spawn(async {
+            let t0 = Instant::now();
+            std::thread::sleep(Duration::from_secs(3600));
+            // the sleep returns at once
+            assert_eq!(t0.elapsed(), Duration::ZERO);
+        });
+        runtime.block_on(f).unwrap();
+    }
 }
@@ -0,0 +1,66 @@
+use std::{cmp::Ordering, collections::BinaryHeap, time::Duration};
+
+/// A timer queue that fires callbacks in order of deadline.
+///
+/// Timers with the same deadline fire in the order they were added.
+#[derive(Default)]
+pub(super) struct Timer {
+    events: BinaryHeap<Event>,
+    /// The sequence number of the next timer.
+    seq: u64,
+}
+
+struct Event {
+    deadline: Duration,
+    seq: u64,
+    callback: Box<dyn FnOnce() + Send + Sync>,
+}
+
+impl Timer {
+    /// Adds a timer firing `callback` at `deadline`.
+    pub fn add(&mut self, deadline: Duration, callback: impl FnOnce() + Send + Sync + 'static) {
+        self.events.push(Event {
+            deadline,
+            seq: self.seq,
+            callback: Box::new(callback),
+        });
+        self.seq += 1;
+    }
+
+    /// Returns the deadline of the earliest timer.
+    pub fn next(&self) -> Option<Duration> {
+        self.events.peek().map(|e| e.deadline)
+    }
+
+    /// Fires all timers whose deadline is not after `now`.
+    pub fn expire(&mut self, now: Duration) {
+        while let Some(event) = self.events.peek() {
+            if event.deadline > now {
+                break;
+            }
+            let event = self.events.pop().unwrap();
+            (event.callback)();
+        }
+    }
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    /// The earliest event is the greatest, as `BinaryHeap` is a max-heap.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
@@ -4,12 +4,12 @@
 use crate::rand::GlobalRng;
 use rand::Rng;
 use spin::Mutex;
-use std::{fmt, sync::Arc};
+use std::{collections::VecDeque, fmt, sync::Arc};
 
 /// Creates a new asynchronous channel, returning the sender/receiver halves.
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let inner = Arc::new(Inner {
-        queue: Mutex::new(Vec::new()),
+        queue: Mutex::new(VecDeque::new()),
     });
     let sender = Sender {
         inner: Arc::clone(&inner),
@@ -30,7 +30,7 @@ pub struct Receiver<T> {
 }
 
 struct Inner<T> {
-    queue: Mutex<Vec<T>>,
+    queue: Mutex<VecDeque<T>>,
 }
 
 impl<T> Clone for Sender<T> {
@@ -54,11 +54,16 @@ impl<T> Sender<T> {
     /// Attempts to send a value on this channel, returning it back if it could not be sent.
     pub fn send(&self, value: T) -> Result<(), SendError<T>> {
         if let Some(mut queue) = self.inner.queue.try_lock() {
-            queue.push(value);
+            queue.push_back(value);
             return Ok(());
         }
         Err(SendError(value))
     }
+
+    /// Returns the number of values in the queue.
+    pub fn len(&self) -> usize {
+        self.inner.queue.lock().len()
+    }
 }
 
 /// This enumeration is the list of the possible reasons
@@ -74,7 +79,19 @@ impl<T> Receiver<T> {
         let mut queue = self.inner.queue.lock();
         if !queue.is_empty() {
             let idx = rng.with(|rng| rng.gen_range(0..queue.len()));
-            Ok(queue.swap_remove(idx))
+            Ok(queue.swap_remove_back(idx).unwrap())
+        } else if Arc::weak_count(&self.inner) == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Attempts to return the earliest pending value on this receiver without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut queue = self.inner.queue.lock();
+        if !queue.is_empty() {
+            Ok(queue.pop_front().unwrap())
         } else if Arc::weak_count(&self.inner) == 0 {
             Err(TryRecvError::Disconnected)
         } else {
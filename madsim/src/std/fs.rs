@@ -6,7 +6,7 @@ use std::{
     path::Path,
 };
 
-pub use tokio::fs::{metadata, read};
+pub use tokio::fs::{metadata, read, rename};
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 /// A reference to an open file on the filesystem.
@@ -1,9 +1,14 @@
 pub mod buggify;
 pub mod fs;
 pub mod net;
+pub mod rand;
+pub mod runtime;
 pub mod signal;
+pub mod sync;
 pub mod time;
 
-pub use rand;
 pub use std::collections;
-pub use tokio::{main, task, test};
+pub use tokio::{main, process, task};
+
+#[cfg(feature = "macros")]
+pub use madsim_macros::std_test as test;
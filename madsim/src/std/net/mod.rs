@@ -40,6 +40,10 @@ pub use self::tcp::*;
 #[cfg(feature = "ucx")]
 pub use self::ucx::*;
 
+pub use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+pub use tokio::net::{UnixDatagram, UnixListener, UnixStream};
+
 #[cfg(feature = "rpc")]
 #[cfg_attr(docsrs, doc(cfg(feature = "rpc")))]
 pub mod rpc;
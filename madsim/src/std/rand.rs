@@ -0,0 +1,63 @@
+//! Utilities for random number generation.
+//!
+//! This module re-exports the [`rand`] crate, and adds the helpers of the simulated API drawing
+//! from [`thread_rng`].
+
+#[doc(no_inline)]
+pub use rand::*;
+
+/// Generates the bytes of a random (version 4) UUID using the thread-local random number
+/// generator. Convert the bytes with `uuid::Uuid::from_bytes`.
+pub fn gen_uuid_v4() -> [u8; 16] {
+    let mut bytes: [u8; 16] = random();
+    // version 4 and RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    bytes
+}
+
+/// Shuffles the slice in place using the thread-local random number generator.
+pub fn shuffle<T>(slice: &mut [T]) {
+    seq::SliceRandom::shuffle(slice, &mut thread_rng());
+}
+
+/// Returns the elements of the vector in a random order, see [`shuffle`].
+pub fn shuffled<T>(mut vec: Vec<T>) -> Vec<T> {
+    shuffle(&mut vec);
+    vec
+}
+
+/// Samplers of durations.
+///
+/// All functions draw from the thread-local random number generator.
+pub mod dist {
+    use super::{thread_rng, Rng};
+    use std::time::Duration;
+
+    /// Samples a duration uniformly from the range `[lo, hi)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lo >= hi`.
+    pub fn uniform_duration(lo: Duration, hi: Duration) -> Duration {
+        thread_rng().gen_range(lo..hi)
+    }
+
+    /// Samples a duration from the exponential distribution with the given mean.
+    pub fn exp(mean: Duration) -> Duration {
+        // inverse transform sampling, `1 - u` is in (0, 1]
+        let u = 1.0 - thread_rng().gen::<f64>();
+        mean.mul_f64(-u.ln())
+    }
+
+    /// Samples a duration from the normal distribution, clamped to zero if negative.
+    pub fn normal(mean: Duration, std_dev: Duration) -> Duration {
+        // Box-Muller transform
+        let mut rng = thread_rng();
+        let u1 = 1.0 - rng.gen::<f64>();
+        let u2 = rng.gen::<f64>();
+        let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+        let secs = mean.as_secs_f64() + z * std_dev.as_secs_f64();
+        Duration::from_secs_f64(secs.max(0.0))
+    }
+}
@@ -0,0 +1,33 @@
+//! The tokio runtime, which stands in for the simulator when built without `--cfg madsim`.
+
+use std::future::Future;
+
+/// A multi-threaded tokio runtime.
+///
+/// It provides the subset of the simulated `Runtime` API that is meaningful on a real machine,
+/// so that code driving a runtime builds both with and without `--cfg madsim`.
+pub struct Runtime {
+    rt: tokio::runtime::Runtime,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    /// Creates a multi-threaded tokio runtime with the I/O and time drivers enabled.
+    pub fn new() -> Self {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("failed to create tokio runtime");
+        Runtime { rt }
+    }
+
+    /// Runs a future to completion on the runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.rt.block_on(future)
+    }
+}
@@ -0,0 +1,13 @@
+//! Synchronization primitives.
+
+pub use tokio::sync::watch;
+
+// The channel only wraps tokio, so it is the same as in simulation.
+#[path = "../sim/sync/mpsc.rs"]
+pub mod mpsc;
+
+// The barrier only knows the node of a task in simulation, so the rest is the same.
+#[path = "../sim/sync/quorum.rs"]
+mod quorum;
+
+pub use self::quorum::QuorumBarrier;
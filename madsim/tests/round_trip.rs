@@ -0,0 +1,56 @@
+//! Tests that run on tokio by default and on the simulator with `--cfg madsim`.
+
+use madsim::{
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+    sync::QuorumBarrier,
+    time::{sleep, timeout, Duration, Instant},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[madsim::test(flavor = "multi_thread", time_limit = "60s", step_limit = 1_000_000)]
+async fn tcp_round_trip() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = madsim::task::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        stream.read_to_end(&mut request).await.unwrap();
+        stream.write_all(&request).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut response = vec![];
+    timeout(Duration::from_secs(10), stream.read_to_end(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response, b"ping");
+    server.await.unwrap();
+}
+
+#[test]
+fn runtime_block_on() {
+    let runtime = Runtime::new();
+    let elapsed = runtime.block_on(async {
+        let t0 = Instant::now();
+        sleep(Duration::from_millis(10)).await;
+        t0.elapsed()
+    });
+    assert!(elapsed >= Duration::from_millis(10));
+}
+
+#[madsim::test]
+async fn quorum_barrier() {
+    let barrier = QuorumBarrier::new(3);
+    let barrier1 = barrier.clone();
+    let task = madsim::task::spawn(async move { barrier1.wait().await });
+    sleep(Duration::from_millis(10)).await;
+    assert!(!barrier.is_released());
+    // 2 of 3 members is a majority
+    barrier.wait().await;
+    task.await.unwrap();
+}
@@ -1,5 +1,21 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed=proto/helloworld.proto");
     tonic_build::compile_protos("proto/helloworld.proto")?;
+
+    // services only, to check that the generated code doesn't depend on the prelude
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("no_prelude");
+    tonic_build::configure()
+        .out_dir(out_dir)
+        .extern_path(".helloworld", "::tonic_example::hello_world")
+        .compile_protos(&["proto/helloworld.proto"], &["proto"])?;
+
+    // server traits without `#[async_trait]`
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("native_async");
+    tonic_build::configure()
+        .out_dir(out_dir)
+        .build_client(false)
+        .use_native_async(true)
+        .extern_path(".helloworld", "::tonic_example::hello_world")
+        .compile_protos(&["proto/helloworld.proto"], &["proto"])?;
     Ok(())
 }
@@ -3,19 +3,33 @@
 use async_stream::stream;
 use futures_core::Stream;
 use madsim::{
-    net::NetSim,
+    net::{NetEventKind, NetSim},
     rand::{thread_rng, Rng},
     runtime::Handle,
     time::sleep,
 };
 use std::{
+    future::Future,
     net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
-use tonic::transport::{Endpoint, Server};
+use tonic::codec::CompressionEncoding;
+use tonic::health::{
+    pb::{health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest},
+    server::health_reporter,
+};
+use tonic::transport::{Channel, Endpoint, Server};
 use tonic_example::hello_world::{
-    another_greeter_client::AnotherGreeterClient, another_greeter_server::AnotherGreeterServer,
-    greeter_client::GreeterClient, greeter_server::GreeterServer, HelloRequest,
+    another_greeter_client::AnotherGreeterClient,
+    another_greeter_server::{AnotherGreeter, AnotherGreeterServer},
+    greeter_client::GreeterClient,
+    greeter_server::{Greeter, GreeterServer},
+    HelloReply, HelloRequest,
 };
 use tonic_example::MyGreeter;
 
@@ -137,6 +151,138 @@ fn request() -> tonic::Request<HelloRequest> {
     })
 }
 
+type ReplyStream = Pin<Box<dyn Stream<Item = Result<HelloReply, tonic::Status>> + Send>>;
+
+type ResponseFuture<Resp> =
+    Pin<Box<dyn Future<Output = Result<tonic::Response<Resp>, tonic::Status>> + Send>>;
+
+/// The handler of a method of [`TestGreeter`].
+type Handler<Req, Resp> = Arc<dyn Fn(tonic::Request<Req>) -> ResponseFuture<Resp> + Send + Sync>;
+
+/// Boxes a handler.
+fn handler<Req: 'static, Resp: 'static, F, Fut>(f: F) -> Option<Handler<Req, Resp>>
+where
+    F: Fn(tonic::Request<Req>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<tonic::Response<Resp>, tonic::Status>> + Send + 'static,
+{
+    Some(Arc::new(move |request| -> ResponseFuture<Resp> {
+        Box::pin(f(request))
+    }))
+}
+
+/// A greeter serving `Greeter` and `AnotherGreeter` with the handlers set by the test.
+///
+/// Methods without a handler fail with `Unimplemented`.
+#[derive(Clone, Default)]
+struct TestGreeter {
+    say_hello: Option<Handler<HelloRequest, HelloReply>>,
+    lots_of_replies: Option<Handler<HelloRequest, ReplyStream>>,
+    bidi_hello: Option<Handler<tonic::Streaming<HelloRequest>, ReplyStream>>,
+    delay: Option<Handler<HelloRequest, HelloReply>>,
+}
+
+impl TestGreeter {
+    fn say_hello<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(tonic::Request<HelloRequest>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<tonic::Response<HelloReply>, tonic::Status>> + Send + 'static,
+    {
+        self.say_hello = handler(f);
+        self
+    }
+
+    fn lots_of_replies<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(tonic::Request<HelloRequest>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<tonic::Response<ReplyStream>, tonic::Status>> + Send + 'static,
+    {
+        self.lots_of_replies = handler(f);
+        self
+    }
+
+    fn bidi_hello<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(tonic::Request<tonic::Streaming<HelloRequest>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<tonic::Response<ReplyStream>, tonic::Status>> + Send + 'static,
+    {
+        self.bidi_hello = handler(f);
+        self
+    }
+
+    fn delay<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(tonic::Request<HelloRequest>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<tonic::Response<HelloReply>, tonic::Status>> + Send + 'static,
+    {
+        self.delay = handler(f);
+        self
+    }
+}
+
+/// Runs the handler of a method, or fails with `Unimplemented` if there is none.
+async fn call<Req, Resp>(
+    handler: &Option<Handler<Req, Resp>>,
+    method: &str,
+    request: tonic::Request<Req>,
+) -> Result<tonic::Response<Resp>, tonic::Status> {
+    match handler {
+        Some(handler) => handler(request).await,
+        None => Err(tonic::Status::unimplemented(method)),
+    }
+}
+
+#[tonic::async_trait]
+impl Greeter for TestGreeter {
+    async fn say_hello(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> Result<tonic::Response<HelloReply>, tonic::Status> {
+        call(&self.say_hello, "say_hello", request).await
+    }
+
+    type LotsOfRepliesStream = ReplyStream;
+
+    async fn lots_of_replies(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> Result<tonic::Response<Self::LotsOfRepliesStream>, tonic::Status> {
+        call(&self.lots_of_replies, "lots_of_replies", request).await
+    }
+
+    async fn lots_of_greetings(
+        &self,
+        request: tonic::Request<tonic::Streaming<HelloRequest>>,
+    ) -> Result<tonic::Response<HelloReply>, tonic::Status> {
+        call(&None, "lots_of_greetings", request).await
+    }
+
+    type BidiHelloStream = ReplyStream;
+
+    async fn bidi_hello(
+        &self,
+        request: tonic::Request<tonic::Streaming<HelloRequest>>,
+    ) -> Result<tonic::Response<Self::BidiHelloStream>, tonic::Status> {
+        call(&self.bidi_hello, "bidi_hello", request).await
+    }
+}
+
+#[tonic::async_trait]
+impl AnotherGreeter for TestGreeter {
+    async fn say_hello(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> Result<tonic::Response<HelloReply>, tonic::Status> {
+        call(&self.say_hello, "say_hello", request).await
+    }
+
+    async fn delay(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> Result<tonic::Response<HelloReply>, tonic::Status> {
+        call(&self.delay, "delay", request).await
+    }
+}
+
 #[madsim::test]
 async fn invalid_address() {
     let handle = Handle::current();
@@ -151,6 +297,44 @@ async fn invalid_address() {
     task1.await.unwrap();
 }
 
+#[madsim::test]
+async fn connect_lazy() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    // the client is created before the server exists
+    let mut client = node1
+        .spawn(async move {
+            let channel = Endpoint::from_static("http://10.0.0.1:50051").connect_lazy();
+            let mut client = GreeterClient::new(channel);
+            // connection errors are returned by calls
+            let error = client.say_hello(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unavailable);
+            client
+        })
+        .await
+        .unwrap();
+
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    node1
+        .spawn(async move {
+            let response = client.say_hello(request()).await.unwrap();
+            assert_eq!(response.into_inner().message, "Hello Tonic! (10.0.0.2)");
+        })
+        .await
+        .unwrap();
+}
+
 // crash client and see whether server works as well
 #[madsim::test]
 async fn client_crash() {
@@ -230,6 +414,85 @@ async fn client_drops_response_stream() {
         .unwrap();
 }
 
+/// A greeter whose server stream is fed by a background task, which sends replies until the
+/// stream is closed.
+fn producer_greeter(produced: Arc<AtomicUsize>, cleaned_up: Arc<AtomicBool>) -> TestGreeter {
+    TestGreeter::default().lots_of_replies(move |_request| {
+        let produced = produced.clone();
+        let cleaned_up = cleaned_up.clone();
+        async move {
+            let (tx, mut rx) = madsim::sync::mpsc::unbounded_channel();
+            madsim::task::spawn(async move {
+                let mut i = 0;
+                while tx
+                    .send(HelloReply {
+                        message: i.to_string(),
+                    })
+                    .is_ok()
+                {
+                    produced.fetch_add(1, Ordering::SeqCst);
+                    i += 1;
+                    sleep(Duration::from_millis(100)).await;
+                }
+                cleaned_up.store(true, Ordering::SeqCst);
+            });
+            let stream = async_stream::stream! {
+                while let Some(reply) = rx.recv().await {
+                    yield Ok::<_, tonic::Status>(reply);
+                }
+            };
+            Ok(tonic::Response::new(Box::pin(stream) as ReplyStream))
+        }
+    })
+}
+
+#[madsim::test]
+async fn client_drops_stream_cancels_handler() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    let produced = Arc::new(AtomicUsize::new(0));
+    let cleaned_up = Arc::new(AtomicBool::new(false));
+    let greeter = producer_greeter(produced.clone(), cleaned_up.clone());
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(greeter))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+
+    node1
+        .spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let mut stream = client
+                .lots_of_replies(request())
+                .await
+                .unwrap()
+                .into_inner();
+            for i in 0..3 {
+                let reply = stream.message().await.unwrap().unwrap();
+                assert_eq!(reply.message, i.to_string());
+            }
+            drop(stream);
+        })
+        .await
+        .unwrap();
+
+    // the next send of the handler fails, and it stops producing
+    sleep(Duration::from_secs(1)).await;
+    assert!(cleaned_up.load(Ordering::SeqCst));
+    let count = produced.load(Ordering::SeqCst);
+    assert!((3..6).contains(&count), "{count}");
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(produced.load(Ordering::SeqCst), count);
+}
+
 #[madsim::test]
 async fn server_crash() {
     let handle = Handle::current();
@@ -406,3 +669,1432 @@ async fn request_timeout() {
         .unwrap();
     sleep(Duration::from_secs(10)).await;
 }
+
+#[madsim::test]
+async fn invalid_request_timeout() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(AnotherGreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let mut client = AnotherGreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            for timeout in ["", "1x", "abc", "123456789S"] {
+                let mut req = request();
+                req.metadata_mut()
+                    .insert("grpc-timeout", timeout.parse().unwrap());
+                let error = client.delay(req).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::InvalidArgument);
+            }
+
+            // the server is still alive
+            client.say_hello(request()).await.unwrap();
+        })
+        .await
+        .unwrap();
+}
+
+/// A service that works on a request, unless its deadline passes first.
+///
+/// The timeout seen by the handler and whether it completed are stored in `seen`.
+fn deadline_greeter(seen: Arc<std::sync::Mutex<Option<(Duration, bool)>>>) -> TestGreeter {
+    TestGreeter::default().delay(move |request| {
+        let seen = seen.clone();
+        async move {
+            let timeout = request.metadata().get("grpc-timeout").unwrap();
+            let timeout = timeout.to_str().unwrap();
+            let millis = if let Some(ms) = timeout.strip_suffix('m') {
+                ms.parse().unwrap()
+            } else {
+                timeout.strip_suffix('u').unwrap().parse::<u64>().unwrap() / 1000
+            };
+            let deadline = Instant::now() + Duration::from_millis(millis);
+            *seen.lock().unwrap() = Some((Duration::from_millis(millis), false));
+            // work for 5s, checking the deadline
+            for _ in 0..50 {
+                sleep(Duration::from_millis(100)).await;
+                if Instant::now() > deadline {
+                    return Err(tonic::Status::deadline_exceeded("handler gave up"));
+                }
+            }
+            seen.lock().unwrap().as_mut().unwrap().1 = true;
+            Ok(tonic::Response::new(HelloReply::default()))
+        }
+    })
+}
+
+#[madsim::test]
+async fn deadline_propagation() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let seen = Arc::new(std::sync::Mutex::new(None));
+    let greeter = deadline_greeter(seen.clone());
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(AnotherGreeterServer::new(greeter))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                .timeout(Duration::from_secs(1))
+                .connect()
+                .await
+                .unwrap();
+            let mut client = AnotherGreeterClient::new(channel);
+            let t0 = Instant::now();
+            let error = client.delay(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::DeadlineExceeded);
+            let elapsed = t0.elapsed();
+            assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(1100), "{elapsed:?}");
+        })
+        .await
+        .unwrap();
+
+    // the handler sees the deadline of the client and is cancelled when it passes
+    sleep(Duration::from_secs(10)).await;
+    let (timeout, completed) = seen.lock().unwrap().unwrap();
+    assert_eq!(timeout, Duration::from_secs(1));
+    assert!(!completed);
+}
+
+#[madsim::test]
+async fn circuit_breaker() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                .timeout(Duration::from_secs(1))
+                .circuit_breaker(3, Duration::from_secs(10))
+                .connect()
+                .await
+                .unwrap();
+            let mut client = GreeterClient::new(channel);
+            client.say_hello(request()).await.unwrap();
+
+            // fail enough calls to open the circuit
+            NetSim::current().clog_node(node0.id());
+            for _ in 0..3 {
+                let error = client.say_hello(request()).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::DeadlineExceeded);
+            }
+
+            // subsequent calls fail fast
+            NetSim::current().unclog_node(node0.id());
+            let t0 = Instant::now();
+            let error = client.say_hello(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unavailable);
+            assert_eq!(error.message(), "circuit breaker is open");
+            assert!(t0.elapsed() < Duration::from_millis(1));
+
+            // a cancelled trial call opens the circuit again
+            sleep(Duration::from_secs(10)).await;
+            NetSim::current().clog_node(node0.id());
+            let mut client1 = client.clone();
+            let trial = madsim::task::spawn(async move { client1.say_hello(request()).await });
+            sleep(Duration::from_millis(100)).await;
+            trial.abort();
+            NetSim::current().unclog_node(node0.id());
+            let error = client.say_hello(request()).await.unwrap_err();
+            assert_eq!(error.message(), "circuit breaker is open");
+
+            // recover after the cooldown
+            sleep(Duration::from_secs(10)).await;
+            client.say_hello(request()).await.unwrap();
+            client.say_hello(request()).await.unwrap();
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn method_rate_limit() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .method_rate_limit("/helloworld.Greeter/SayHello", 5, Duration::from_secs(1))
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .add_service(AnotherGreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                .connect()
+                .await
+                .unwrap();
+            let mut client = GreeterClient::new(channel.clone());
+            let mut another_client = AnotherGreeterClient::new(channel);
+
+            // flood the limited method
+            let mut limited = 0;
+            for _ in 0..20 {
+                match client.say_hello(request()).await {
+                    Ok(_) => {}
+                    Err(e) if e.code() == tonic::Code::ResourceExhausted => limited += 1,
+                    Err(e) => panic!("unexpected error: {e}"),
+                }
+            }
+            assert!(limited >= 10, "only {limited} calls are limited");
+
+            // other methods are unaffected
+            for _ in 0..20 {
+                another_client.say_hello(request()).await.unwrap();
+            }
+
+            // tokens are refilled as time goes by
+            sleep(Duration::from_secs(1)).await;
+            for _ in 0..5 {
+                client.say_hello(request()).await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn concurrency_limit() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let addr1 = "10.0.0.1:50052".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .concurrency_limit(3)
+            .add_service(AnotherGreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    node0.spawn(async move {
+        Server::builder()
+            .concurrency_limit(3)
+            .queue_overloaded(true)
+            .add_service(AnotherGreeterServer::new(MyGreeter::default()))
+            .serve(addr1)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node1
+        .spawn(async move {
+            // send 5 long-running calls at the same time and return their results and latencies
+            async fn flood(dst: &'static str) -> Vec<(Result<(), tonic::Code>, Duration)> {
+                let client = AnotherGreeterClient::connect(dst).await.unwrap();
+                let tasks: Vec<_> = (0..5)
+                    .map(|_| {
+                        let mut client = client.clone();
+                        madsim::task::spawn(async move {
+                            let t0 = Instant::now();
+                            let ret = client.delay(request()).await;
+                            (ret.map(|_| ()).map_err(|e| e.code()), t0.elapsed())
+                        })
+                    })
+                    .collect();
+                let mut results = vec![];
+                for task in tasks {
+                    results.push(task.await.unwrap());
+                }
+                results
+            }
+
+            // exactly the calls beyond the limit are rejected
+            let results = flood("http://10.0.0.1:50051").await;
+            let rejected = results.iter().filter(|(r, _)| r.is_err()).count();
+            assert_eq!(rejected, 2, "{results:?}");
+            for (ret, latency) in &results {
+                match ret {
+                    Ok(()) => assert!(*latency >= Duration::from_secs(10)),
+                    Err(code) => {
+                        assert_eq!(*code, tonic::Code::ResourceExhausted);
+                        assert!(*latency < Duration::from_secs(1));
+                    }
+                }
+            }
+            // the slots are released after the calls complete
+            let results = flood("http://10.0.0.1:50051").await;
+            assert_eq!(results.iter().filter(|(r, _)| r.is_err()).count(), 2);
+
+            // excess calls wait in the queue
+            let results = flood("http://10.0.0.1:50052").await;
+            assert!(results.iter().all(|(r, _)| r.is_ok()), "{results:?}");
+            let queued = results
+                .iter()
+                .filter(|(_, latency)| *latency >= Duration::from_secs(20))
+                .count();
+            assert_eq!(queued, 2, "{results:?}");
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn readiness_delay() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .readiness_delay(Duration::from_secs(3))
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            // connections are accepted before the server is ready
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let error = client.say_hello(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unavailable);
+            let error = client.lots_of_replies(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unavailable);
+
+            // calls succeed once the server is ready
+            sleep(Duration::from_secs(3)).await;
+            client.say_hello(request()).await.unwrap();
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn prewarm_connections() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let net = NetSim::current();
+            net.enable_trace(true);
+            let count_connects = || {
+                (net.take_trace().iter())
+                    .filter(|e| matches!(e.kind, NetEventKind::Connect { .. }))
+                    .count()
+            };
+            let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                .prewarm_connections(3)
+                .connect()
+                .await
+                .unwrap();
+            // a handshake to check the endpoint and one to warm each connection
+            assert_eq!(count_connects(), 4);
+
+            // each call only opens its own stream
+            let mut tasks = vec![];
+            for _ in 0..3 {
+                let mut client = GreeterClient::new(channel.clone());
+                tasks.push(madsim::task::spawn(async move {
+                    client.say_hello(request()).await.unwrap();
+                }));
+            }
+            for task in tasks {
+                task.await.unwrap();
+            }
+            assert_eq!(count_connects(), 3);
+
+            // without prewarming, every call pays a handshake
+            let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                .connect()
+                .await
+                .unwrap();
+            count_connects();
+            let mut client = GreeterClient::new(channel);
+            client.say_hello(request()).await.unwrap();
+            assert_eq!(count_connects(), 2);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn prewarm_pool_size() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    // the number of connections seen by the server
+    let conns = Arc::new(AtomicUsize::new(0));
+    let conns0 = conns.clone();
+    node0.spawn(async move {
+        Server::builder()
+            .connection_init(move |_addr, _metadata| {
+                conns0.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                .prewarm_connections(1)
+                .connect()
+                .await
+                .unwrap();
+            let concurrent_calls = || async {
+                let mut tasks = vec![];
+                for _ in 0..3 {
+                    let mut client = GreeterClient::new(channel.clone());
+                    tasks.push(madsim::task::spawn(async move {
+                        client.say_hello(request()).await.unwrap();
+                    }));
+                }
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            };
+            // one call takes the warm connection, the others connect on their own
+            concurrent_calls().await;
+            assert_eq!(conns.load(Ordering::Relaxed), 3);
+            // the extra connections are not pooled
+            concurrent_calls().await;
+            assert_eq!(conns.load(Ordering::Relaxed), 5);
+        })
+        .await
+        .unwrap();
+}
+
+/// A service that echoes the `traceparent` of requests, or forwards them to a downstream service.
+fn tracing_greeter(downstream: Option<&'static str>) -> TestGreeter {
+    TestGreeter::default().say_hello(move |request| async move {
+        let Some(downstream) = downstream else {
+            let traceparent = request.metadata().get("traceparent").unwrap();
+            return Ok(tonic::Response::new(HelloReply {
+                message: traceparent.to_str().unwrap().into(),
+            }));
+        };
+        // propagate the tracing context to the downstream call
+        let mut client = AnotherGreeterClient::connect(downstream).await.unwrap();
+        let mut downstream_request = tonic::Request::new(request.get_ref().clone());
+        for key in ["traceparent", "tracestate"] {
+            if let Some(value) = request.metadata().get(key) {
+                downstream_request.metadata_mut().insert(key, value.clone());
+            }
+        }
+        client.say_hello(downstream_request).await
+    })
+}
+
+#[madsim::test]
+async fn tracing_metadata_propagation() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let addr1 = "10.0.0.2:50051".parse::<SocketAddr>().unwrap();
+    let ip2 = "10.0.0.3".parse().unwrap();
+    let node0 = handle.create_node().name("frontend").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("backend").ip(addr1.ip()).build();
+    node0.spawn(async move {
+        let greeter = tracing_greeter(Some("http://10.0.0.2:50051"));
+        Server::builder()
+            .add_service(AnotherGreeterServer::new(greeter))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    node1.spawn(async move {
+        let greeter = tracing_greeter(None);
+        Server::builder()
+            .add_service(AnotherGreeterServer::new(greeter))
+            .serve(addr1)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node2 = handle.create_node().name("client").ip(ip2).build();
+    node2
+        .spawn(async move {
+            let mut client = AnotherGreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+            let mut req = request();
+            req.metadata_mut()
+                .insert("traceparent", traceparent.parse().unwrap());
+            let response = client.say_hello(req).await.unwrap();
+            assert_eq!(response.into_inner().message, traceparent);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn health_check() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let (mut reporter, health_service) = health_reporter();
+    let greeter = "helloworld.Greeter";
+    reporter
+        .set_service_status(greeter, tonic::health::ServingStatus::Serving)
+        .await;
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(health_service)
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let mut client = HealthClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let check = |service: &str| HealthCheckRequest {
+                service: service.into(),
+            };
+            let response = client.check(check("")).await.unwrap();
+            assert_eq!(response.into_inner().status(), ServingStatus::Serving);
+            let response = client.check(check(greeter)).await.unwrap();
+            assert_eq!(response.into_inner().status(), ServingStatus::Serving);
+            let mut watch = (client.watch(check(greeter)).await).unwrap().into_inner();
+            let response = watch.message().await.unwrap().unwrap();
+            assert_eq!(response.status(), ServingStatus::Serving);
+
+            reporter
+                .set_service_status(greeter, tonic::health::ServingStatus::NotServing)
+                .await;
+            let response = client.check(check(greeter)).await.unwrap();
+            assert_eq!(response.into_inner().status(), ServingStatus::NotServing);
+            let response = watch.message().await.unwrap().unwrap();
+            assert_eq!(response.status(), ServingStatus::NotServing);
+
+            let error = client.check(check("unknown")).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::NotFound);
+        })
+        .await
+        .unwrap();
+}
+
+/// A greeter whose bidi stream sends a welcome message before receiving any request.
+fn welcome_greeter() -> TestGreeter {
+    TestGreeter::default().bidi_hello(|request| async move {
+        let stream = async_stream::try_stream! {
+            yield HelloReply {
+                message: "welcome".into(),
+            };
+            let mut stream = request.into_inner();
+            while let Some(request) = stream.message().await? {
+                // replies are paced independently of requests
+                sleep(Duration::from_secs(1)).await;
+                yield HelloReply {
+                    message: format!("Hello {}!", request.name),
+                };
+            }
+        };
+        Ok(tonic::Response::new(Box::pin(stream) as ReplyStream))
+    })
+}
+
+#[madsim::test]
+async fn bidi_server_sends_first() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(welcome_greeter()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+
+    node1
+        .spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let (tx, mut rx) = madsim::sync::mpsc::unbounded_channel();
+            let requests = stream! {
+                while let Some(request) = rx.recv().await {
+                    yield request;
+                }
+            };
+            let mut replies = client.bidi_hello(requests).await.unwrap().into_inner();
+
+            // the server sends before any request is sent
+            let reply = replies.message().await.unwrap().unwrap();
+            assert_eq!(reply.message, "welcome");
+
+            // requests are not blocked by pending replies
+            let t0 = Instant::now();
+            for name in ["Alice", "Bob"] {
+                tx.send(HelloRequest { name: name.into() }).unwrap();
+            }
+            assert!(t0.elapsed() < Duration::from_millis(1));
+            for name in ["Alice", "Bob"] {
+                let reply = replies.message().await.unwrap().unwrap();
+                assert_eq!(reply.message, format!("Hello {name}!"));
+            }
+            assert!(t0.elapsed() >= Duration::from_secs(2));
+
+            // closing the request stream ends the response stream
+            drop(tx);
+            assert!(replies.message().await.unwrap().is_none());
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn compression_negotiation() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let addr1 = "10.0.0.2:50051".parse::<SocketAddr>().unwrap();
+    let ip2 = "10.0.0.3".parse().unwrap();
+    let node0 = handle.create_node().name("plain").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("gzip").ip(addr1.ip()).build();
+    let node2 = handle.create_node().name("client").ip(ip2).build();
+
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    node1.spawn(async move {
+        let greeter = GreeterServer::new(MyGreeter::default())
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip);
+        Server::builder()
+            .add_service(greeter)
+            .serve(addr1)
+            .await
+            .unwrap();
+    });
+
+    node2
+        .spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+
+            // the server doesn't accept gzip
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap()
+                .send_compressed(CompressionEncoding::Gzip);
+            let error = client.say_hello(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unimplemented);
+            assert_eq!(
+                error.message(),
+                "Content is compressed with `gzip` which isn't supported"
+            );
+            let error = client.lots_of_replies(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unimplemented);
+
+            // uncompressed requests are always accepted
+            let mut client = GreeterClient::connect("http://10.0.0.2:50051")
+                .await
+                .unwrap();
+            let response = client.say_hello(request()).await.unwrap();
+            assert!(response.metadata().get("grpc-encoding").is_none());
+
+            // the response is compressed only if the client accepts it
+            let mut client = client
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip);
+            let response = client.say_hello(request()).await.unwrap();
+            assert_eq!(response.metadata().get("grpc-encoding").unwrap(), "gzip");
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn max_message_size() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+
+    node0.spawn(async move {
+        let greeter = GreeterServer::new(MyGreeter::default()).max_decoding_message_size(16);
+        Server::builder()
+            .add_service(greeter)
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve("10.0.0.1:50052".parse().unwrap())
+            .await
+            .unwrap();
+    });
+
+    node1
+        .spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            // a request of `name` takes 2 more bytes when encoded
+            let hello = |len: usize| HelloRequest {
+                name: "x".repeat(len),
+            };
+
+            let response = client.say_hello(hello(14)).await.unwrap();
+            let message = response.into_inner().message;
+            assert!(message.starts_with(&format!("Hello {}!", "x".repeat(14))));
+
+            let error = client.say_hello(hello(15)).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::OutOfRange);
+            assert_eq!(
+                error.message(),
+                "Error, decoded message length too large: found 17 bytes, the limit is: 16 bytes"
+            );
+
+            // the client doesn't send an oversized request
+            let mut client = client.max_encoding_message_size(8);
+            let error = client.say_hello(hello(7)).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::OutOfRange);
+            assert!(error
+                .message()
+                .starts_with("Error, encoded message length too large"));
+
+            // decoded messages are limited to 4MB by default, as in tonic
+            let mut client = GreeterClient::connect("http://10.0.0.1:50052")
+                .await
+                .unwrap();
+            client.say_hello(hello(4 * 1024 * 1024 - 64)).await.unwrap();
+            let error = client.say_hello(hello(4 * 1024 * 1024)).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::OutOfRange);
+            assert!(error.message().ends_with("the limit is: 4194304 bytes"));
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn metadata_fault_injection() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let tenant_request = |tenant: &'static str| {
+                let mut request = request();
+                request
+                    .metadata_mut()
+                    .insert("tenant", tenant.parse().unwrap());
+                request
+            };
+
+            let id = tonic::fault::inject(
+                |metadata| metadata.get("tenant").is_some_and(|v| v == "foo"),
+                tonic::Status::unavailable("injected"),
+            );
+            // only calls of the matching tenant fail
+            let error = client.say_hello(tenant_request("foo")).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unavailable);
+            assert_eq!(error.message(), "injected");
+            let error = client
+                .lots_of_replies(tenant_request("foo"))
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unavailable);
+            client.say_hello(tenant_request("bar")).await.unwrap();
+            client.say_hello(request()).await.unwrap();
+
+            // removed faults no longer apply
+            tonic::fault::remove(id);
+            client.say_hello(tenant_request("foo")).await.unwrap();
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn drop_calls() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            tonic::fault::drop_calls("/helloworld.Greeter/SayHello", 1.0);
+            // every call to the method is dropped, while other methods are not affected
+            for _ in 0..10 {
+                let error = client.say_hello(request()).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::Unavailable);
+                client.lots_of_replies(request()).await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn intercept_frames() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            tonic::fault::set_interceptor(|frame| {
+                if !frame.is_response() {
+                    return;
+                }
+                match frame.path() {
+                    "/helloworld.Greeter/SayHello" => {
+                        if let Some(reply) = frame.message_mut::<HelloReply>() {
+                            reply.message = reply.message.to_uppercase();
+                        }
+                    }
+                    "/helloworld.Greeter/LotsOfReplies" if frame.metadata_mut().is_none() => {
+                        frame.set_status(tonic::Status::data_loss("corrupted"));
+                    }
+                    _ => {}
+                }
+            });
+            // the client observes the mutated reply
+            let reply = client.say_hello(request()).await.unwrap().into_inner();
+            assert_eq!(reply.message, "HELLO TONIC! (10.0.0.2)");
+            // messages of a stream are intercepted one by one
+            let mut stream = client
+                .lots_of_replies(request())
+                .await
+                .unwrap()
+                .into_inner();
+            let error = stream.message().await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::DataLoss);
+
+            tonic::fault::clear_interceptor();
+            let reply = client.say_hello(request()).await.unwrap().into_inner();
+            assert_eq!(reply.message, "Hello Tonic! (10.0.0.2)");
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn balance_list_failover() {
+    let handle = Handle::current();
+    let mut servers = vec![];
+    for i in 1..=3 {
+        let addr = format!("10.0.0.{i}:50051").parse::<SocketAddr>().unwrap();
+        let node = (handle.create_node())
+            .name(format!("server{i}"))
+            .ip(addr.ip())
+            .init(move || async move {
+                Server::builder()
+                    .add_service(GreeterServer::new(MyGreeter::default()))
+                    .serve(addr)
+                    .await
+                    .unwrap();
+            })
+            .build();
+        servers.push(node.id());
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    let ip1 = "10.0.0.4".parse().unwrap();
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let net = NetSim::current();
+            net.enable_trace(true);
+            let endpoints =
+                (1..=3).map(|i| Endpoint::from_shared(format!("http://10.0.0.{i}:50051")).unwrap());
+            let client = GreeterClient::new(Channel::balance_list(endpoints));
+            // make 6 calls and count the calls served by each backend
+            let spread = || {
+                let mut client = client.clone();
+                let net = net.clone();
+                async move {
+                    for _ in 0..6 {
+                        client.say_hello(request()).await.unwrap();
+                    }
+                    let mut served = [0; 3];
+                    for event in net.take_trace() {
+                        if let NetEventKind::Connect { dst, .. } = event.kind {
+                            let SocketAddr::V4(dst) = dst else {
+                                unreachable!()
+                            };
+                            // a handshake and a stream for each call
+                            served[dst.ip().octets()[3] as usize - 1] += 1;
+                        }
+                    }
+                    served.map(|n| n / 2)
+                }
+            };
+
+            // round robin
+            assert_eq!(spread().await, [2, 2, 2]);
+
+            // calls fail over to the remaining backends
+            Handle::current().kill(servers[2]);
+            let served = spread().await;
+            assert_eq!(served[2], 0);
+            assert!(served[0] > 0 && served[1] > 0, "{served:?}");
+
+            // the backend is back into rotation after it recovers
+            Handle::current().restart(servers[2]);
+            sleep(Duration::from_secs(2)).await;
+            let served = spread().await;
+            assert!(served.iter().all(|&n| n > 0), "{served:?}");
+        })
+        .await
+        .unwrap();
+}
+
+/// The identity of an authenticated connection.
+#[derive(Debug, Clone)]
+struct Identity(String);
+
+/// A service that replies with the identity of the connection.
+fn identity_greeter() -> TestGreeter {
+    TestGreeter::default().say_hello(|request| async move {
+        let Identity(user) = request.extensions().get::<Identity>().unwrap();
+        Ok(tonic::Response::new(HelloReply {
+            message: user.clone(),
+        }))
+    })
+}
+
+#[madsim::test]
+async fn connection_identity() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let auths = Arc::new(AtomicUsize::new(0));
+    let auths0 = auths.clone();
+    node0.spawn(async move {
+        Server::builder()
+            .connection_init(move |_addr, metadata| {
+                auths0.fetch_add(1, Ordering::Relaxed);
+                match metadata.get("authorization") {
+                    Some(token) => Ok(Identity(token.to_str().unwrap().into())),
+                    None => Err(tonic::Status::unauthenticated("no token")),
+                }
+            })
+            .add_service(AnotherGreeterServer::new(identity_greeter()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client1").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let connect = || async {
+                let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                    .prewarm_connections(1)
+                    .connect()
+                    .await
+                    .unwrap();
+                AnotherGreeterClient::new(channel)
+            };
+            let authorized = || {
+                let mut request = request();
+                (request.metadata_mut()).insert("authorization", "alice".parse().unwrap());
+                request
+            };
+
+            // the connection is authenticated by the first call
+            let mut client = connect().await;
+            let reply = client.say_hello(authorized()).await.unwrap();
+            assert_eq!(reply.into_inner().message, "alice");
+            // later calls on the connection read the same identity without credentials
+            for _ in 0..3 {
+                let reply = client.say_hello(request()).await.unwrap();
+                assert_eq!(reply.into_inner().message, "alice");
+            }
+            assert_eq!(auths.load(Ordering::Relaxed), 1);
+
+            // a new connection is authenticated again
+            let mut client = connect().await;
+            let error = client.say_hello(request()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Unauthenticated);
+            let reply = client.say_hello(authorized()).await.unwrap();
+            assert_eq!(reply.into_inner().message, "alice");
+            assert_eq!(auths.load(Ordering::Relaxed), 3);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn idle_timeout() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    // the number of connections seen by the server
+    let conns = Arc::new(AtomicUsize::new(0));
+    let conns0 = conns.clone();
+    node0.spawn(async move {
+        Server::builder()
+            .connection_init(move |_addr, _metadata| {
+                conns0.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node1
+        .spawn(async move {
+            let endpoint = Endpoint::from_static("http://10.0.0.1:50051")
+                .prewarm_connections(1)
+                .idle_timeout(Duration::from_secs(5));
+            let mut client = GreeterClient::new(endpoint.connect().await.unwrap());
+            client.say_hello(request()).await.unwrap();
+            sleep(Duration::from_secs(4)).await;
+            client.say_hello(request()).await.unwrap();
+            assert_eq!(conns.load(Ordering::Relaxed), 1);
+
+            // the connection is closed while idle, and the next call reconnects
+            sleep(Duration::from_secs(6)).await;
+            client.say_hello(request()).await.unwrap();
+            assert_eq!(conns.load(Ordering::Relaxed), 2);
+
+            // keep-alive pings keep an idle connection open
+            let endpoint = Endpoint::from_static("http://10.0.0.1:50051")
+                .prewarm_connections(1)
+                .idle_timeout(Duration::from_secs(5))
+                .http2_keep_alive_interval(Duration::from_secs(1))
+                .keep_alive_while_idle(true);
+            let mut client = GreeterClient::new(endpoint.connect().await.unwrap());
+            client.say_hello(request()).await.unwrap();
+            sleep(Duration::from_secs(10)).await;
+            client.say_hello(request()).await.unwrap();
+            assert_eq!(conns.load(Ordering::Relaxed), 3);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn flow_control_window() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node1
+        .spawn(async move {
+            // the reply echoes the name, so its size is about 64KB
+            let call = |window: u32| async move {
+                let channel = Endpoint::from_static("http://10.0.0.1:50051")
+                    .initial_stream_window_size(window)
+                    .initial_connection_window_size(window)
+                    .connect()
+                    .await
+                    .unwrap();
+                let mut client = GreeterClient::new(channel);
+                let request = tonic::Request::new(HelloRequest {
+                    name: "x".repeat(64 * 1024),
+                });
+                let t0 = Instant::now();
+                client.say_hello(request).await.unwrap();
+                t0.elapsed()
+            };
+            // a round trip is at least 2ms, and the small window pauses 64 times
+            let small = call(1024).await;
+            assert!(small >= Duration::from_millis(128), "{small:?}");
+            // the large window never pauses
+            let large = call(1024 * 1024).await;
+            assert!(large < Duration::from_millis(100), "{large:?}");
+        })
+        .await
+        .unwrap();
+}
+
+/// A greeter whose bidi stream acknowledges each request after processing it.
+///
+/// Processing takes 100ms, except for requests named "slow" which take 2s.
+fn ack_greeter() -> TestGreeter {
+    TestGreeter::default().bidi_hello(|request| async move {
+        let stream = async_stream::try_stream! {
+            let mut stream = request.into_inner();
+            while let Some(request) = stream.message().await? {
+                let delay = if request.name == "slow" { 2000 } else { 100 };
+                sleep(Duration::from_millis(delay)).await;
+                yield HelloReply {
+                    message: format!("ack {}", request.name),
+                };
+            }
+        };
+        Ok(tonic::Response::new(Box::pin(stream) as ReplyStream))
+    })
+}
+
+/// Send requests one by one, each after the previous one is acknowledged.
+///
+/// Returns the events seen by the sender and their times since the first request.
+fn send_with_acks(seed: u64) -> Vec<(String, Duration)> {
+    let runtime = madsim::runtime::Runtime::with_seed_and_config(seed, madsim::Config::default());
+    runtime.block_on(async {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(ack_greeter()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        node1
+            .spawn(async move {
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let (tx, mut rx) = madsim::sync::mpsc::unbounded_channel();
+                let requests = stream! {
+                    while let Some(request) = rx.recv().await {
+                        yield request;
+                    }
+                };
+                let mut acks = client.bidi_hello(requests).await.unwrap().into_inner();
+                let t0 = Instant::now();
+                let mut events = vec![];
+                for name in ["a", "b", "slow", "c"] {
+                    tx.send(HelloRequest { name: name.into() }).unwrap();
+                    let ack = loop {
+                        match madsim::time::timeout(Duration::from_secs(1), acks.message()).await {
+                            Ok(ack) => break ack.unwrap().unwrap(),
+                            // keep waiting for the ack after a timeout
+                            Err(_) => events.push((format!("timeout {name}"), t0.elapsed())),
+                        }
+                    };
+                    events.push((ack.message, t0.elapsed()));
+                }
+                drop(tx);
+                assert!(acks.message().await.unwrap().is_none());
+                events
+            })
+            .await
+            .unwrap()
+    })
+}
+
+#[test]
+fn bidi_ack_interleaving() {
+    let events = send_with_acks(1);
+    let names: Vec<_> = events.iter().map(|(e, _)| e.as_str()).collect();
+    assert_eq!(
+        names,
+        ["ack a", "ack b", "timeout slow", "ack slow", "ack c"]
+    );
+    // each request is sent only after the previous ack
+    let times: Vec<_> = events.iter().map(|(_, t)| *t).collect();
+    let ms = Duration::from_millis;
+    assert!(times[0] >= ms(100) && times[0] < ms(130), "{times:?}");
+    assert!(times[1] - times[0] >= ms(100), "{times:?}");
+    assert!(times[2] - times[1] >= ms(1000), "{times:?}");
+    assert!(times[3] - times[1] >= ms(2000), "{times:?}");
+    assert!(times[4] - times[3] >= ms(100), "{times:?}");
+    // the timeline is the same under the same seed
+    assert_eq!(send_with_acks(1), events);
+}
+
+/// Services generated for `helloworld.proto` with the messages of `tonic_example`.
+mod no_prelude {
+    #![no_implicit_prelude]
+    #![allow(dead_code)]
+
+    // names that unqualified paths in the generated code would pick up
+    type Result<T> = T;
+    type Option = ();
+    struct Box;
+    trait Send {}
+    trait Sync {}
+
+    ::std::include!(::std::concat!(
+        ::std::env!("OUT_DIR"),
+        "/no_prelude/sim/helloworld.rs"
+    ));
+}
+
+#[madsim::test]
+async fn generated_without_prelude() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(GreeterServer::new(MyGreeter::default()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+
+    let task1 = node1.spawn(async move {
+        sleep(Duration::from_secs(1)).await;
+        let mut client =
+            no_prelude::greeter_client::GreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+        let response = client.say_hello(request()).await.unwrap();
+        assert_eq!(response.into_inner().message, "Hello Tonic! (10.0.0.2)");
+    });
+    task1.await.unwrap();
+}
+
+mod native_async {
+    ::std::include!(::std::concat!(
+        ::std::env!("OUT_DIR"),
+        "/native_async/sim/helloworld.rs"
+    ));
+}
+
+// the same handlers serve the greeter generated with native `async fn`
+impl native_async::greeter_server::Greeter for TestGreeter {
+    async fn say_hello(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> Result<tonic::Response<HelloReply>, tonic::Status> {
+        call(&self.say_hello, "say_hello", request).await
+    }
+
+    type LotsOfRepliesStream = ReplyStream;
+
+    async fn lots_of_replies(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> Result<tonic::Response<Self::LotsOfRepliesStream>, tonic::Status> {
+        call(&self.lots_of_replies, "lots_of_replies", request).await
+    }
+
+    async fn lots_of_greetings(
+        &self,
+        request: tonic::Request<tonic::Streaming<HelloRequest>>,
+    ) -> Result<tonic::Response<HelloReply>, tonic::Status> {
+        call(&None, "lots_of_greetings", request).await
+    }
+
+    type BidiHelloStream = ReplyStream;
+
+    async fn bidi_hello(
+        &self,
+        request: tonic::Request<tonic::Streaming<HelloRequest>>,
+    ) -> Result<tonic::Response<Self::BidiHelloStream>, tonic::Status> {
+        call(&self.bidi_hello, "bidi_hello", request).await
+    }
+}
+
+#[madsim::test]
+async fn native_async_server() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    let greeter = TestGreeter::default().say_hello(|request| async move {
+        sleep(Duration::from_millis(10)).await;
+        let reply = HelloReply {
+            message: format!("Hello {}!", request.into_inner().name),
+        };
+        Ok(tonic::Response::new(reply))
+    });
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(native_async::greeter_server::GreeterServer::new(greeter))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+
+    let task1 = node1.spawn(async move {
+        sleep(Duration::from_secs(1)).await;
+        let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+            .await
+            .unwrap();
+        let response = client.say_hello(request()).await.unwrap();
+        assert_eq!(response.into_inner().message, "Hello Tonic!");
+        let error = client.lots_of_replies(request()).await.unwrap_err();
+        assert_eq!(error.code(), tonic::Code::Unimplemented);
+    });
+    task1.await.unwrap();
+}
+
+/// A service that panics on the name "panic".
+fn panicking_greeter() -> TestGreeter {
+    TestGreeter::default().say_hello(|request| async move {
+        let name = request.into_inner().name;
+        if name == "panic" {
+            panic!("bad request");
+        }
+        Ok(tonic::Response::new(HelloReply {
+            message: format!("Hello {name}!"),
+        }))
+    })
+}
+
+#[madsim::test]
+async fn handler_panic() {
+    let handle = Handle::current();
+    let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let ip1 = "10.0.0.2".parse().unwrap();
+    let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+    let node1 = handle.create_node().name("client").ip(ip1).build();
+    node0.spawn(async move {
+        Server::builder()
+            .add_service(AnotherGreeterServer::new(panicking_greeter()))
+            .serve(addr0)
+            .await
+            .unwrap();
+    });
+
+    let task1 = node1.spawn(async move {
+        sleep(Duration::from_secs(1)).await;
+        let mut client = AnotherGreeterClient::connect("http://10.0.0.1:50051")
+            .await
+            .unwrap();
+        let panic = tonic::Request::new(HelloRequest {
+            name: "panic".into(),
+        });
+        let error = client.say_hello(panic).await.unwrap_err();
+        assert_eq!(error.code(), tonic::Code::Internal);
+        assert!(error.message().contains("bad request"), "{error}");
+
+        // the server keeps serving other calls
+        for _ in 0..3 {
+            let response = client.say_hello(request()).await.unwrap();
+            assert_eq!(response.into_inner().message, "Hello Tonic!");
+        }
+    });
+    task1.await.unwrap();
+}